@@ -0,0 +1,186 @@
+// Concurrent list / download throughput against a running server.
+//
+//   cargo run --release --example bench
+//
+// Settings come from the environment: BENCH_URL (default http://127.0.0.1:8087),
+// CLIPBOARD_PASSWORD, BENCH_CONCURRENCY (default 32), BENCH_SECONDS (default 10) and
+// BENCH_ITEMS (default 200). The run adds BENCH_ITEMS text items and one 1 MiB file
+// before measuring, so point it at a scratch data directory.
+
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+
+type HttpClient = Client<HttpConnector, Full<Bytes>>;
+
+struct Target {
+    client: HttpClient,
+    base: String,
+    auth: String,
+}
+
+impl Target {
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        ctype: Option<&str>,
+        body: Vec<u8>,
+    ) -> anyhow::Result<Bytes> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base, path))
+            .header("Authorization", &self.auth);
+        if let Some(ct) = ctype {
+            req = req.header("Content-Type", ct);
+        }
+        let res = self
+            .client
+            .request(req.body(Full::new(Bytes::from(body)))?)
+            .await?;
+        let status = res.status();
+        let bytes = res.into_body().collect().await?.to_bytes();
+        anyhow::ensure!(status.is_success(), "{path}: {status}");
+        Ok(bytes)
+    }
+
+    async fn upload(
+        &self,
+        field: &str,
+        file_name: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<String> {
+        let boundary = "bench-boundary-7f3a";
+        let mut body = Vec::new();
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        match file_name {
+            Some(name) => body.extend_from_slice(
+                format!("Content-Disposition: form-data; name=\"{field}\"; filename=\"{name}\"\r\nContent-Type: application/octet-stream\r\n\r\n").as_bytes(),
+            ),
+            None => body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{field}\"\r\n\r\n").as_bytes()),
+        }
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        let ctype = format!("multipart/form-data; boundary={boundary}");
+        let res = self
+            .send(Method::POST, "/api/clipboard", Some(&ctype), body)
+            .await?;
+        let v: serde_json::Value = serde_json::from_slice(&res)?;
+        Ok(v["id"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+// Hammer `path` from `concurrency` tasks for `secs` seconds and print throughput and latency
+async fn run(target: &Arc<Target>, label: &str, path: String, concurrency: usize, secs: u64) {
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    let mut tasks = Vec::with_capacity(concurrency);
+    for _ in 0..concurrency {
+        let target = target.clone();
+        let path = path.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            let (mut bytes, mut errors) = (0u64, 0u64);
+            while Instant::now() < deadline {
+                let started = Instant::now();
+                match target.send(Method::GET, &path, None, Vec::new()).await {
+                    Ok(b) => {
+                        bytes += b.len() as u64;
+                        latencies.push(started.elapsed());
+                    }
+                    Err(_) => errors += 1,
+                }
+            }
+            (latencies, bytes, errors)
+        }));
+    }
+    let (mut latencies, mut bytes, mut errors) = (Vec::new(), 0u64, 0u64);
+    for t in tasks {
+        let (l, b, e) = t.await.unwrap();
+        latencies.extend(l);
+        bytes += b;
+        errors += e;
+    }
+    latencies.sort();
+    let pct = |p: usize| {
+        latencies
+            .get(latencies.len().saturating_sub(1) * p / 100)
+            .copied()
+            .unwrap_or_default()
+    };
+    println!(
+        "{label:<9} {:>9.0} req/s {:>8.1} MiB/s   p50 {:>7.2?}  p99 {:>7.2?}  errors {errors}",
+        latencies.len() as f64 / secs as f64,
+        bytes as f64 / secs as f64 / (1024.0 * 1024.0),
+        pct(50),
+        pct(99),
+    );
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let base = env::var("BENCH_URL").unwrap_or_else(|_| "http://127.0.0.1:8087".into());
+    let password = env::var("CLIPBOARD_PASSWORD").unwrap_or_default();
+    let concurrency = env_or("BENCH_CONCURRENCY", 32usize).max(1);
+    let secs = env_or("BENCH_SECONDS", 10u64).max(1);
+    let items = env_or("BENCH_ITEMS", 200usize);
+
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    let client = Client::builder(TokioExecutor::new())
+        .pool_max_idle_per_host(concurrency)
+        .build(connector);
+    let target = Arc::new(Target {
+        client,
+        base: base.trim_end_matches('/').to_string(),
+        auth: format!("Bearer {password}"),
+    });
+
+    println!("seeding {items} text items and a 1 MiB file on {base}");
+    for i in 0..items {
+        let text = format!("bench item {i} lorem ipsum dolor sit amet");
+        target.upload("content", None, text.as_bytes()).await?;
+    }
+    let file_id = target
+        .upload("file", Some("bench.bin"), &vec![0x5a; 1024 * 1024])
+        .await?;
+
+    println!("{concurrency} concurrent clients, {secs}s per scenario");
+    run(
+        &target,
+        "list",
+        "/api/clipboard?take=50".into(),
+        concurrency,
+        secs,
+    )
+    .await;
+    run(
+        &target,
+        "search",
+        "/api/clipboard?q=lorem".into(),
+        concurrency,
+        secs,
+    )
+    .await;
+    run(
+        &target,
+        "download",
+        format!("/api/files/{file_id}"),
+        concurrency,
+        secs,
+    )
+    .await;
+    Ok(())
+}
@@ -0,0 +1,631 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::crypt::{self, ItemKey, Keyring};
+use crate::db::Db;
+use crate::store::Conn;
+use crate::{epoch_to_iso, listing, now_unix, scrub, AppState};
+
+// -------------------- Archives --------------------
+//
+// Several items packed into one ZIP or tar.gz download, built while it streams. Text items
+// become .txt files, uploads keep their (sanitised) file name, bundles become a folder of
+// their files; duplicate names get a " (2)" suffix. A blocking task writes the archive
+// into a bounded channel and reads one item at a time (files from disk in chunks, text
+// and inline data straight from the database), so memory stays flat however large the
+// archive is. There is no Content-Length. Encrypted items are decrypted on the way out;
+// one whose key is unavailable is left out.
+
+const CHUNK: usize = 64 * 1024;
+// chunks buffered between the writer task and the response body
+const QUEUE: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "zip" => Some(Self::Zip),
+            "tar.gz" | "tgz" | "targz" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarGz => "tar.gz",
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+enum Source {
+    // text content or stored blob of this item, loaded when its turn comes
+    Db(String),
+    // stored blob of a bundle file
+    Child(String),
+    File(PathBuf),
+}
+
+pub(crate) struct Entry {
+    name: String,
+    source: Source,
+    // data key of the item, when it is encrypted at rest
+    key: Option<ItemKey>,
+    modified: i64,
+    // already-compressed formats are stored as-is in ZIPs
+    compressed: bool,
+    content_type: Option<String>,
+    // written without image metadata, as shares serve it
+    strip_metadata: bool,
+}
+
+/// Make a stored file name safe as an archive path: no separators, control characters or
+/// leading dots, and not empty.
+pub(crate) fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim();
+    let mut out: String = cleaned.chars().take(200).collect();
+    if out.is_empty() {
+        out = "file".into();
+    }
+    out
+}
+
+fn unique(name: String, seen: &mut HashSet<String>) -> String {
+    if seen.insert(name.to_lowercase()) {
+        return name;
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name.as_str(), ""),
+    };
+    (2..)
+        .map(|n| format!("{stem} ({n}){ext}"))
+        .find(|c| seen.insert(c.to_lowercase()))
+        .unwrap()
+}
+
+fn is_compressed(ctype: &str) -> bool {
+    (ctype.starts_with("image/") && ctype != "image/bmp" && ctype != "image/svg+xml")
+        || ctype.starts_with("video/")
+        || ctype.starts_with("audio/")
+        || matches!(
+            ctype,
+            "application/zip" | "application/gzip" | "application/x-7z-compressed"
+        )
+}
+
+/// Archive entries for the given live items, in the order given. Unknown ids are skipped.
+/// Only metadata is loaded here; contents are read while the archive is written.
+pub(crate) fn entries(
+    conn: &dyn Conn,
+    data_dir: &StdPath,
+    keys: Option<&Keyring>,
+    ids: &[String],
+) -> Vec<Entry> {
+    let mut stmt = conn
+        .prepare("SELECT type, fileName, contentType, filePath, updatedAt, keyId, dataKey, e2ee IS NOT NULL FROM ClipboardItem WHERE id=? AND deletedAt IS NULL")
+        .unwrap();
+    let mut seen = HashSet::new();
+    let mut out = vec![];
+    for id in ids {
+        let row = stmt.query_row([id], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, Option<String>>(1)?,
+                r.get::<_, Option<String>>(2)?,
+                r.get::<_, Option<String>>(3)?,
+                r.get::<_, i64>(4)?,
+                r.get::<_, Option<String>>(5)?,
+                r.get::<_, Option<Vec<u8>>>(6)?,
+                r.get::<_, bool>(7)?,
+            ))
+        });
+        let Ok((t, file_name, ctype, path, modified, key_id, wrapped, e2ee)) = row else {
+            continue;
+        };
+        let key = match crypt::item_key(keys, id, key_id, wrapped) {
+            Ok(key) => key,
+            Err(e) => {
+                tracing::warn!(%id, error = %e, "archive: skipping item that cannot be decrypted");
+                continue;
+            }
+        };
+        let short = &id[..id.len().min(8)];
+        if t == "BUNDLE" {
+            // the bundle's files go into a folder named after it
+            let display = sanitize(file_name.as_deref().unwrap_or(short));
+            let folder = unique(display.clone(), &mut seen);
+            let target = (display.as_str(), folder.as_str());
+            let bundle = (id.as_str(), key.as_ref());
+            push_bundle(
+                conn,
+                data_dir,
+                bundle,
+                None,
+                Some(target),
+                &mut seen,
+                &mut out,
+            );
+            continue;
+        }
+        // end-to-end encrypted text is a ciphertext file like any other
+        let (name, source) = if t == "TEXT" && !e2ee {
+            (format!("text-{short}.txt"), Source::Db(id.clone()))
+        } else {
+            let name = sanitize(file_name.as_deref().unwrap_or(short));
+            match path {
+                Some(p) => (name, Source::File(data_dir.join(p))),
+                None => (name, Source::Db(id.clone())),
+            }
+        };
+        out.push(Entry {
+            name: unique(name, &mut seen),
+            source,
+            key,
+            modified,
+            compressed: ctype.as_deref().is_some_and(is_compressed),
+            content_type: ctype,
+            strip_metadata: false,
+        });
+    }
+    out
+}
+
+/// Entries for the files of one bundle, or only those listed in `only` (ItemFile ids).
+pub(crate) fn bundle_entries(
+    conn: &dyn Conn,
+    data_dir: &StdPath,
+    keys: Option<&Keyring>,
+    item_id: &str,
+    only: Option<&[String]>,
+) -> Vec<Entry> {
+    let key = match crypt::load_item_key(conn, keys, item_id) {
+        Ok(Ok(key)) => key,
+        Ok(Err(e)) => {
+            tracing::warn!(%item_id, error = %e, "archive: bundle cannot be decrypted");
+            return vec![];
+        }
+        Err(_) => return vec![],
+    };
+    let mut out = vec![];
+    push_bundle(
+        conn,
+        data_dir,
+        (item_id, key.as_ref()),
+        only,
+        None,
+        &mut HashSet::new(),
+        &mut out,
+    );
+    out
+}
+
+fn push_bundle(
+    conn: &dyn Conn,
+    data_dir: &StdPath,
+    (item_id, key): (&str, Option<&ItemKey>),
+    only: Option<&[String]>,
+    // (bundle display name, folder to place the files in)
+    folder: Option<(&str, &str)>,
+    seen: &mut HashSet<String>,
+    out: &mut Vec<Entry>,
+) {
+    let mut stmt = conn
+        .prepare("SELECT id, path, contentType, filePath, createdAt FROM ItemFile WHERE itemId=? ORDER BY position")
+        .unwrap();
+    let rows = stmt
+        .query_map([item_id], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, Option<String>>(2)?,
+                r.get::<_, Option<String>>(3)?,
+                r.get::<_, i64>(4)?,
+            ))
+        })
+        .unwrap()
+        .flatten();
+    for (fid, path, ctype, file_path, modified) in rows {
+        if only.is_some_and(|o| !o.contains(&fid)) {
+            continue;
+        }
+        let name = match folder {
+            // paths usually already start with the shared top folder the bundle is named after
+            Some((display, f)) => {
+                match path.strip_prefix(display).and_then(|p| p.strip_prefix('/')) {
+                    Some(rest) => format!("{f}/{rest}"),
+                    None => format!("{f}/{path}"),
+                }
+            }
+            None => path,
+        };
+        out.push(Entry {
+            name: unique(name, seen),
+            source: match file_path {
+                Some(p) => Source::File(data_dir.join(p)),
+                None => Source::Child(fid),
+            },
+            key: key.cloned(),
+            modified,
+            compressed: ctype.as_deref().is_some_and(is_compressed),
+            content_type: ctype,
+            strip_metadata: false,
+        });
+    }
+}
+
+/// Have the images among `entries` written without metadata (see scrub).
+pub(crate) fn strip_metadata(entries: &mut [Entry]) {
+    for e in entries {
+        e.strip_metadata = e.content_type.as_deref().is_some_and(scrub::is_strippable);
+    }
+}
+
+// Open an entry's content along with its size; None when the file is gone or can't be
+// decrypted.
+fn open(db: &Db, e: &Entry) -> Option<(Box<dyn Read>, u64)> {
+    let (mut reader, len) = open_stored(db, e)?;
+    let (true, Some(ctype)) = (e.strip_metadata, e.content_type.as_deref()) else {
+        return Some((reader, len));
+    };
+    // images are small enough to strip in memory
+    let mut data = Vec::with_capacity(len as usize);
+    if let Err(err) = reader.read_to_end(&mut data) {
+        tracing::warn!(name = %e.name, error = %err, "archive: skipping unreadable file");
+        return None;
+    }
+    let data = scrub::strip(&data, ctype).unwrap_or_else(|err| {
+        // malformed container: the original is all we have
+        tracing::warn!(name = %e.name, error = %err, "archive: metadata stripping failed");
+        data
+    });
+    let len = data.len() as u64;
+    Some((Box::new(io::Cursor::new(data)), len))
+}
+
+fn open_stored(db: &Db, e: &Entry) -> Option<(Box<dyn Read>, u64)> {
+    let key = e.key.as_ref();
+    match &e.source {
+        Source::File(p) => {
+            let opened = match key {
+                Some(k) => crypt::SealedReader::open(p, k)
+                    .map(|(r, len)| (Box::new(r) as Box<dyn Read>, len)),
+                None => std::fs::File::open(p).map(|f| {
+                    let len = f.metadata().map(|m| m.len()).unwrap_or(0);
+                    (Box::new(f) as Box<dyn Read>, len)
+                }),
+            };
+            opened
+                .map_err(|err| {
+                    tracing::warn!(path = %p.display(), error = %err, "archive: skipping unreadable file");
+                })
+                .ok()
+        }
+        Source::Db(id) => load(
+            db,
+            "SELECT CASE WHEN type='TEXT' THEN content END, (SELECT data FROM Blob WHERE Blob.id = ClipboardItem.id) FROM ClipboardItem WHERE id=?",
+            id,
+            key,
+        ),
+        Source::Child(id) => load(
+            db,
+            "SELECT NULL, data FROM Blob WHERE id=?",
+            id,
+            key,
+        ),
+    }
+}
+
+// Content stored in the database, as (text, bytes); one item's worth is held in memory
+// at a time.
+fn load(db: &Db, sql: &str, id: &str, key: Option<&ItemKey>) -> Option<(Box<dyn Read>, u64)> {
+    let (text, bytes) = db
+        .reader()
+        .query_row(sql, [id], |r| {
+            Ok((
+                r.get::<_, Option<String>>(0)?,
+                r.get::<_, Option<Vec<u8>>>(1)?,
+            ))
+        })
+        .unwrap_or((None, None));
+    let data = match text {
+        Some(text) => crypt::open_text(key, Some(text)).map(|t| t.map(String::into_bytes)),
+        None => crypt::open_bytes(key, bytes),
+    };
+    let data = match data {
+        Ok(data) => data.unwrap_or_default(),
+        Err(e) => {
+            tracing::warn!(%id, error = %e, "archive: skipping entry that cannot be decrypted");
+            return None;
+        }
+    };
+    let len = data.len() as u64;
+    Some((Box::new(io::Cursor::new(data)), len))
+}
+
+// Write side of the response body. Fails once the client has gone away, which aborts
+// the writer.
+pub(crate) struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+fn zip_time(ts: i64) -> zip::DateTime {
+    let t = OffsetDateTime::from_unix_timestamp(ts).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    zip::DateTime::from_date_and_time(
+        t.year().clamp(1980, 2107) as u16,
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+    )
+    .unwrap_or_default()
+}
+
+fn write_zip(db: &Db, entries: &[Entry], out: ChannelWriter) -> io::Result<()> {
+    let mut zw = zip::ZipWriter::new_stream(out);
+    for e in entries {
+        let Some((mut reader, len)) = open(db, e) else {
+            continue;
+        };
+        let method = if e.compressed {
+            zip::CompressionMethod::Stored
+        } else {
+            zip::CompressionMethod::Deflated
+        };
+        let opts = zip::write::SimpleFileOptions::default()
+            .compression_method(method)
+            .last_modified_time(zip_time(e.modified))
+            .large_file(len >= u32::MAX as u64);
+        zw.start_file(e.name.as_str(), opts)?;
+        io::copy(&mut reader, &mut zw)?;
+    }
+    zw.finish()?.into_inner().flush()
+}
+
+fn write_tar_gz(db: &Db, entries: &[Entry], out: ChannelWriter) -> io::Result<()> {
+    let gz = flate2::write::GzEncoder::new(out, flate2::Compression::fast());
+    let mut tw = tar::Builder::new(gz);
+    for e in entries {
+        let Some((reader, len)) = open(db, e) else {
+            continue;
+        };
+        let mut h = tar::Header::new_gnu();
+        h.set_size(len);
+        h.set_mode(0o644);
+        h.set_mtime(e.modified.max(0) as u64);
+        h.set_entry_type(tar::EntryType::Regular);
+        // a file that shrank since open still yields exactly `len` bytes
+        tw.append_data(&mut h, &e.name, reader.chain(io::repeat(0)).take(len))?;
+    }
+    tw.into_inner()?.finish()?.flush()
+}
+
+/// Stream an archive of `entries` as an attachment named `<base>.<ext>`.
+pub(crate) fn stream_response(
+    db: Arc<Db>,
+    entries: Vec<Entry>,
+    format: Format,
+    base: &str,
+) -> Response {
+    let file_name = format!("{base}.{}", format.extension());
+    stream_blocking(format.mime(), &file_name, move |out| match format {
+        Format::Zip => write_zip(&db, &entries, out),
+        Format::TarGz => write_tar_gz(&db, &entries, out),
+    })
+}
+
+/// Respond with an attachment whose bytes `write` produces on a blocking thread, chunk by
+/// chunk, so nothing larger than the channel queue is held in memory.
+pub(crate) fn stream_blocking(
+    content_type: &'static str,
+    file_name: &str,
+    write: impl FnOnce(ChannelWriter) -> io::Result<()> + Send + 'static,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(QUEUE);
+    tokio::task::spawn_blocking(move || {
+        let out = ChannelWriter {
+            tx: tx.clone(),
+            buf: Vec::with_capacity(CHUNK),
+        };
+        if let Err(e) = write(out) {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                tracing::warn!(error = %e, "archive: write failed");
+                // surfaces as a truncated download rather than a valid-looking archive
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+    let mut hm = HeaderMap::new();
+    hm.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    hm.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!(
+            "attachment; filename*=UTF-8''{}",
+            urlencoding::encode(file_name)
+        ))
+        .unwrap(),
+    );
+    (
+        StatusCode::OK,
+        hm,
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+/// Default download name, e.g. clipboard-2024-05-01T10-00-00Z
+pub(crate) fn default_name(now: i64) -> String {
+    format!("clipboard-{}", epoch_to_iso(now).replace(':', "-"))
+}
+
+// GET /api/clipboard/archive?ids=a,b,c&format=zip|tar.gz — without ids, every item the
+// list would show for the remaining query parameters (q, type, tag, ...).
+pub(crate) async fn download(State(state): State<AppState>, uri: Uri) -> Response {
+    let mut ids: Vec<String> = vec![];
+    let mut format = Format::Zip;
+    // scoped: the serializer isn't Send and must be gone before the first await
+    let filter = {
+        let mut filter = form_urlencoded::Serializer::new(String::new());
+        for (k, v) in form_urlencoded::parse(uri.query().unwrap_or("").as_bytes()) {
+            match k.as_ref() {
+                "ids" | "id" => ids.extend(
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from),
+                ),
+                "format" => match Format::parse(&v) {
+                    Some(f) => format = f,
+                    None => {
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            Json(serde_json::json!({"error": format!("invalid format: {v}")})),
+                        )
+                            .into_response()
+                    }
+                },
+                _ => {
+                    filter.append_pair(&k, &v);
+                }
+            }
+        }
+        filter.finish()
+    };
+    let now = now_unix();
+    let (data_dir, keys) = (state.data_dir.clone(), state.keys.clone());
+    let entries = state
+        .db
+        .read(move |conn| {
+            if ids.is_empty() {
+                ids = listing::select_ids(conn, &filter, now)?;
+            }
+            Ok::<_, String>(entries(conn, &data_dir, keys.as_deref(), &ids))
+        })
+        .await;
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e})),
+            )
+                .into_response()
+        }
+    };
+    if entries.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"Not found"})),
+        )
+            .into_response();
+    }
+    stream_response(state.db.clone(), entries, format, &default_name(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(sanitize("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize("a\\b:c*d?e\"f<g>h|i"), "a_b_c_d_e_f_g_h_i");
+        assert_eq!(sanitize("tab\there\n.txt"), "tab_here_.txt");
+        assert_eq!(sanitize("  ..hidden "), "hidden");
+        assert_eq!(sanitize(""), "file");
+        assert_eq!(sanitize(" ... "), "file");
+        assert_eq!(sanitize("报告.pdf"), "报告.pdf");
+        assert_eq!(sanitize(&"ü".repeat(300)).chars().count(), 200);
+    }
+
+    #[test]
+    fn duplicate_names_get_a_counter() {
+        let mut seen = HashSet::new();
+        let names: Vec<String> = ["a.txt", "A.TXT", "a.txt", "a (2).txt", "README", "readme"]
+            .into_iter()
+            .map(|n| unique(n.to_string(), &mut seen))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "a.txt",
+                "A (2).TXT",
+                "a (3).txt",
+                "a (2) (2).txt",
+                "README",
+                "readme (2)"
+            ]
+        );
+        // a leading dot isn't an extension
+        assert_eq!(unique(".env".into(), &mut seen), ".env");
+        assert_eq!(unique(".env".into(), &mut seen), ".env (2)");
+    }
+
+    #[test]
+    fn formats_parse_leniently() {
+        assert!(Format::parse(" ZIP ") == Some(Format::Zip));
+        for s in ["tar.gz", "tgz", "TarGz"] {
+            assert!(Format::parse(s) == Some(Format::TarGz), "{s}");
+        }
+        assert!(Format::parse("rar").is_none());
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rusqlite::{Connection, DatabaseName};
+use serde::{Deserialize, Serialize};
+
+use crate::export::{self, Staging};
+use crate::store::Dialect;
+use crate::{db, epoch_to_iso, limits, now_unix, AppState};
+
+// -------------------- Scheduled backups --------------------
+//
+// With BACKUP_DIR set, a background task copies custom.db there with SQLite's online backup
+// API every BACKUP_INTERVAL and keeps the newest BACKUP_KEEP copies:
+//   BACKUP_DIR/backup-<time>/custom.db
+//   BACKUP_DIR/backup-<time>/manifest.json   upload files (path, size) the backup needs
+//   BACKUP_DIR/uploads/...                   upload files shared by all backups
+// Uploads never change once written, so each run only copies files the shared store lacks
+// and drops the ones no remaining backup lists. A backup only gets its final name after
+// PRAGMA integrity_check passes. `clip-relay restore` puts one back.
+
+const DEFAULT_INTERVAL: i64 = 86_400;
+const DEFAULT_KEEP: usize = 7;
+// wait before retrying after a failed run
+const RETRY: i64 = 3600;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Config {
+    pub(crate) dir: PathBuf,
+    /// Seconds
+    pub(crate) interval: i64,
+    pub(crate) keep: usize,
+}
+
+impl Config {
+    pub(crate) fn from_env() -> Option<Self> {
+        let dir = env::var("BACKUP_DIR")
+            .ok()
+            .filter(|v| !v.trim().is_empty())?;
+        Some(Self {
+            dir: PathBuf::from(dir),
+            interval: env::var("BACKUP_INTERVAL")
+                .ok()
+                .and_then(|v| limits::parse_duration(&v))
+                .filter(|s| *s > 0)
+                .unwrap_or(DEFAULT_INTERVAL),
+            keep: env::var("BACKUP_KEEP")
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(DEFAULT_KEEP),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileEntry {
+    path: String,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    created_at: i64,
+    items: i64,
+    bytes: u64,
+    files: Vec<FileEntry>,
+}
+
+/// Outcome of the last backup run, reported by /api/health.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Status {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    finished_at: String,
+    items: i64,
+    files: usize,
+    // upload files copied by this run; the rest were already in the shared store
+    copied: usize,
+    bytes: u64,
+    duration_ms: u64,
+}
+
+pub(crate) struct Backups {
+    config: Config,
+    last: Mutex<Option<Status>>,
+}
+
+impl Backups {
+    pub(crate) fn from_env(dialect: Dialect) -> Option<Self> {
+        let config = Config::from_env()?;
+        if dialect != Dialect::Sqlite {
+            tracing::warn!("BACKUP_DIR is ignored with PostgreSQL; back it up with pg_dump");
+            return None;
+        }
+        Some(Self::new(config))
+    }
+
+    pub(crate) fn new(config: Config) -> Self {
+        // pick up where the last process left off
+        let last = newest(&config.dir).map(|(name, m)| Status {
+            ok: true,
+            finished_at: epoch_to_iso(m.created_at),
+            items: m.items,
+            files: m.files.len(),
+            copied: 0,
+            bytes: m.bytes,
+            duration_ms: 0,
+            name: Some(name),
+            error: None,
+        });
+        Self {
+            config,
+            last: Mutex::new(last),
+        }
+    }
+
+    /// JSON for the health endpoint.
+    pub(crate) fn health(&self) -> serde_json::Value {
+        serde_json::json!({
+            "enabled": true,
+            "dir": self.config.dir,
+            "interval": self.config.interval,
+            "keep": self.config.keep,
+            "last": *self.last.lock().unwrap(),
+        })
+    }
+}
+
+// Backup directories, oldest first. The timestamped names sort chronologically.
+fn list(dir: &StdPath) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|n| n.starts_with("backup-") && !n.ends_with(".tmp"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn read_manifest(dir: &StdPath) -> Option<Manifest> {
+    serde_json::from_slice(&fs::read(dir.join("manifest.json")).ok()?).ok()
+}
+
+fn newest(dir: &StdPath) -> Option<(String, Manifest)> {
+    let name = list(dir).pop()?;
+    let m = read_manifest(&dir.join(&name))?;
+    Some((name, m))
+}
+
+fn io_err(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+// Move `from` to `to`, copying when they are on different filesystems.
+fn move_file(from: &StdPath, to: &StdPath) -> io::Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Run PRAGMA integrity_check on a backup's database. (Not opened read-only: checking the
+/// FTS5 index needs write access.)
+fn verify(db: &StdPath) -> io::Result<()> {
+    let conn = Connection::open(db).map_err(io_err)?;
+    let check: String = conn
+        .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+        .map_err(io_err)?;
+    if check != "ok" {
+        return Err(io_err(format!("integrity check failed: {check}")));
+    }
+    Ok(())
+}
+
+// Take one backup into `cfg.dir`. Returns the new backup's name, its manifest and how many
+// upload files had to be copied.
+fn take(state: &AppState, cfg: &Config) -> io::Result<(String, Manifest, usize)> {
+    let pool = cfg.dir.join("uploads");
+    fs::create_dir_all(&pool)?;
+    let now = now_unix();
+    let name = format!("backup-{}", epoch_to_iso(now).replace(':', "-"));
+    let staging = Staging::new(&state.data_dir, "backup")?;
+    let uploads = state.data_dir.join("uploads");
+    // Under the lock: copy the database and hard link the uploads the store lacks. Both are
+    // quick and local; the slower copy into BACKUP_DIR happens after the lock is released.
+    let (items, files, new) = {
+        let conn = state.db.writer();
+        conn.as_sqlite()
+            .ok_or_else(|| io_err("not a SQLite database"))?
+            .backup(DatabaseName::Main, staging.0.join("custom.db"), None)
+            .map_err(io_err)?;
+        let items: i64 = conn
+            .query_row("SELECT COUNT(*) FROM ClipboardItem", [], |r| r.get(0))
+            .map_err(io_err)?;
+        let mut files = vec![];
+        export::walk(&uploads, StdPath::new(""), &mut files)?;
+        let new: Vec<(PathBuf, u64)> = files
+            .iter()
+            .filter(|(rel, size)| fs::metadata(pool.join(rel)).map_or(true, |m| m.len() != *size))
+            .cloned()
+            .collect();
+        export::link_files(&uploads, &staging.0.join("uploads"), &new)?;
+        (items, files, new)
+    };
+
+    let tmp = cfg.dir.join(format!("{name}.tmp"));
+    fs::create_dir_all(&tmp)?;
+    let res = (|| {
+        move_file(&staging.0.join("custom.db"), &tmp.join("custom.db"))?;
+        for (rel, _) in &new {
+            let to = pool.join(rel);
+            let _ = fs::remove_file(&to);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            move_file(&staging.0.join("uploads").join(rel), &to)?;
+        }
+        verify(&tmp.join("custom.db"))?;
+        let manifest = Manifest {
+            created_at: now,
+            items,
+            bytes: files.iter().map(|(_, n)| n).sum(),
+            files: files
+                .into_iter()
+                .map(|(p, size)| FileEntry {
+                    path: p.to_string_lossy().replace('\\', "/"),
+                    size,
+                })
+                .collect(),
+        };
+        fs::write(
+            tmp.join("manifest.json"),
+            serde_json::to_vec_pretty(&manifest)?,
+        )?;
+        fs::rename(&tmp, cfg.dir.join(&name))?;
+        Ok(manifest)
+    })();
+    match res {
+        Ok(manifest) => Ok((name, manifest, new.len())),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&tmp);
+            Err(e)
+        }
+    }
+}
+
+// Drop backups beyond the newest `keep`, then upload files no remaining backup lists.
+fn rotate(cfg: &Config) {
+    let names = list(&cfg.dir);
+    let excess = names.len().saturating_sub(cfg.keep);
+    for name in &names[..excess] {
+        tracing::info!(backup = %name, "removing old backup");
+        let _ = fs::remove_dir_all(cfg.dir.join(name));
+    }
+    let mut wanted = HashSet::new();
+    for name in &names[excess..] {
+        let Some(m) = read_manifest(&cfg.dir.join(name)) else {
+            // can't tell what an unreadable backup needs; keep everything
+            return;
+        };
+        wanted.extend(m.files.into_iter().map(|f| f.path));
+    }
+    let pool = cfg.dir.join("uploads");
+    let mut stored = vec![];
+    let _ = export::walk(&pool, StdPath::new(""), &mut stored);
+    for (rel, _) in stored {
+        if !wanted.contains(&rel.to_string_lossy().replace('\\', "/")) {
+            let _ = fs::remove_file(pool.join(rel));
+        }
+    }
+}
+
+pub(crate) fn run_once(state: &AppState, backups: &Backups) -> Status {
+    let started = Instant::now();
+    let res = take(state, &backups.config);
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let status = match res {
+        Ok((name, m, copied)) => {
+            rotate(&backups.config);
+            tracing::info!(backup = %name, files = m.files.len(), copied, "backup done");
+            Status {
+                ok: true,
+                name: Some(name),
+                error: None,
+                finished_at: epoch_to_iso(now_unix()),
+                items: m.items,
+                files: m.files.len(),
+                copied,
+                bytes: m.bytes,
+                duration_ms,
+            }
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "backup failed");
+            Status {
+                ok: false,
+                name: None,
+                error: Some(e.to_string()),
+                finished_at: epoch_to_iso(now_unix()),
+                items: 0,
+                files: 0,
+                copied: 0,
+                bytes: 0,
+                duration_ms,
+            }
+        }
+    };
+    *backups.last.lock().unwrap() = Some(status.clone());
+    status
+}
+
+/// Background task taking a backup every BACKUP_INTERVAL.
+pub(crate) async fn run_task(state: AppState) {
+    let Some(backups) = state.backup.clone() else {
+        return;
+    };
+    let cfg = &backups.config;
+    tracing::info!(dir = %cfg.dir.display(), interval = cfg.interval, keep = cfg.keep, "backups enabled");
+    let mut next = newest(&cfg.dir).map_or(now_unix(), |(_, m)| m.created_at + cfg.interval);
+    loop {
+        let wait = (next - now_unix()).max(0) as u64;
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+        let (st, b) = (state.clone(), backups.clone());
+        let ok = tokio::task::spawn_blocking(move || run_once(&st, &b).ok)
+            .await
+            .unwrap_or(false);
+        next = now_unix()
+            + if ok {
+                cfg.interval
+            } else {
+                cfg.interval.min(RETRY)
+            };
+    }
+}
+
+/// `clip-relay restore [--list | NAME | PATH]`: replace the data with a backup, the newest
+/// one in BACKUP_DIR by default. Stop the server first.
+pub(crate) fn run_cli(
+    args: &[String],
+    data_dir: &StdPath,
+    db_path: &StdPath,
+) -> anyhow::Result<()> {
+    let dir = Config::from_env().map(|c| c.dir);
+    let arg = args.get(1).map(String::as_str);
+    if arg == Some("--list") {
+        let dir = dir.ok_or_else(|| anyhow::anyhow!("BACKUP_DIR is not set"))?;
+        for name in list(&dir) {
+            if let Some(m) = read_manifest(&dir.join(&name)) {
+                println!("{name}\t{} items\t{} files", m.items, m.files.len());
+            }
+        }
+        return Ok(());
+    }
+    let path = match (arg, &dir) {
+        (Some(a), Some(d)) if !StdPath::new(a).is_dir() => d.join(a),
+        (Some(a), _) => PathBuf::from(a),
+        (None, Some(d)) => d.join(
+            list(d)
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("no backups in {}", d.display()))?,
+        ),
+        (None, None) => {
+            anyhow::bail!("usage: clip-relay restore [--list | NAME | PATH] (set BACKUP_DIR)")
+        }
+    };
+    let manifest = read_manifest(&path)
+        .ok_or_else(|| anyhow::anyhow!("{} is not a backup", path.display()))?;
+    verify(&path.join("custom.db"))?;
+    // the shared upload store sits next to the backup directories
+    let pool = path.parent().map(|p| p.join("uploads")).unwrap_or_default();
+
+    // lay the backup out like an unpacked export and apply it as a replace
+    let staging = Staging::new(data_dir, "import")?;
+    let root = staging.0.join("data");
+    fs::create_dir_all(root.join("uploads"))?;
+    fs::copy(path.join("custom.db"), root.join("custom.db"))?;
+    let files: Vec<(PathBuf, u64)> = manifest
+        .files
+        .iter()
+        .map(|f| (PathBuf::from(&f.path), f.size))
+        .collect();
+    for (rel, size) in &files {
+        if fs::metadata(pool.join(rel)).map_or(true, |m| m.len() != *size) {
+            anyhow::bail!("backup is missing upload {}", rel.display());
+        }
+    }
+    export::link_files(&pool, &root.join("uploads"), &files)?;
+    let mut conn = db::open_sqlite(db_path)?;
+    export::apply(&mut conn, data_dir, &staging, export::Mode::Replace)
+        .map_err(anyhow::Error::msg)?;
+    eprintln!(
+        "restored {} ({} items, {} files)",
+        path.display(),
+        manifest.items,
+        files.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        let dir = env::temp_dir().join(format!("clip-relay-backups-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("uploads")).unwrap();
+        dir
+    }
+
+    // a backup directory whose manifest lists `files`, each also put in the shared store
+    fn fake_backup(dir: &StdPath, name: &str, files: &[&str]) {
+        fs::create_dir_all(dir.join(name)).unwrap();
+        for f in files {
+            fs::write(dir.join("uploads").join(f), f).unwrap();
+        }
+        let manifest = Manifest {
+            created_at: 0,
+            items: 0,
+            bytes: 0,
+            files: files
+                .iter()
+                .map(|f| FileEntry {
+                    path: f.to_string(),
+                    size: f.len() as u64,
+                })
+                .collect(),
+        };
+        fs::write(
+            dir.join(name).join("manifest.json"),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn stored(dir: &StdPath) -> Vec<String> {
+        let mut files = vec![];
+        export::walk(&dir.join("uploads"), StdPath::new(""), &mut files).unwrap();
+        let mut names: Vec<String> = files
+            .into_iter()
+            .map(|(p, _)| p.to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_and_the_uploads_they_list() {
+        let dir = scratch();
+        fake_backup(&dir, "backup-2024-01-01T00-00-00Z", &["a", "b"]);
+        fake_backup(&dir, "backup-2024-01-03T00-00-00Z", &["b", "c"]);
+        fake_backup(&dir, "backup-2024-01-02T00-00-00Z", &["a", "c"]);
+        fake_backup(&dir, "backup-2024-01-04T00-00-00Z", &["d"]);
+        // unfinished runs are neither counted nor removed
+        fs::create_dir_all(dir.join("backup-2024-01-05T00-00-00Z.tmp")).unwrap();
+        let cfg = Config {
+            dir: dir.clone(),
+            interval: DEFAULT_INTERVAL,
+            keep: 2,
+        };
+        rotate(&cfg);
+        assert_eq!(
+            list(&dir),
+            ["backup-2024-01-03T00-00-00Z", "backup-2024-01-04T00-00-00Z"]
+        );
+        assert!(dir.join("backup-2024-01-05T00-00-00Z.tmp").is_dir());
+        assert_eq!(stored(&dir), ["b", "c", "d"]);
+
+        // with a manifest it can't read, rotation keeps every upload
+        fs::write(dir.join("uploads").join("e"), "e").unwrap();
+        fs::write(dir.join("backup-2024-01-04T00-00-00Z/manifest.json"), "{").unwrap();
+        rotate(&cfg);
+        assert_eq!(stored(&dir), ["b", "c", "d", "e"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn verification_catches_damaged_databases() {
+        let dir = scratch();
+        let db = dir.join("custom.db");
+        let conn = Connection::open(&db).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT); CREATE INDEX t_v ON t (v);",
+        )
+        .unwrap();
+        for i in 0..500 {
+            conn.execute("INSERT INTO t (v) VALUES (?)", [format!("value {i}")])
+                .unwrap();
+        }
+        drop(conn);
+        verify(&db).unwrap();
+
+        // scribble over the pages after the schema
+        let mut bytes = fs::read(&db).unwrap();
+        assert!(bytes.len() > 8192);
+        for b in &mut bytes[4096 + 100..8192] {
+            *b = 0x5A;
+        }
+        fs::write(&db, &bytes).unwrap();
+        assert!(verify(&db).is_err());
+
+        fs::write(&db, b"not a database at all").unwrap();
+        assert!(verify(&db).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use rusqlite::{params, Connection};
+
+use crate::db::Db;
+use crate::store::{self, Conn, OptionalExtension};
+
+// -------------------- Blob storage --------------------
+//
+// Uploads under the inline limit are kept in the database instead of uploads/. Their
+// bytes live in Blob, one row per item or bundle file, so ClipboardItem and ItemFile rows
+// stay small and listing or share lookups never read blob pages. `id` is the item id, or
+// the ItemFile id for a file of a bundle; the row goes away with its item.
+
+pub(crate) fn put(conn: &dyn Conn, id: &str, item_id: &str, data: &[u8]) -> store::Result<()> {
+    conn.execute(
+        "INSERT INTO Blob (id, itemId, data) VALUES (?, ?, ?)",
+        params![id, item_id, data],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get(conn: &dyn Conn, id: &str) -> Option<Vec<u8>> {
+    conn.query_row("SELECT data FROM Blob WHERE id=?", [id], |r| r.get(0))
+        .optional()
+        .ok()
+        .flatten()
+}
+
+/// `get` on a pooled reader, for handlers that only need the bytes once they've checked
+/// access.
+pub(crate) async fn load(db: &Arc<Db>, id: &str) -> Option<Vec<u8>> {
+    let id = id.to_string();
+    db.read(move |conn| get(conn, &id)).await
+}
+
+// Bytes of the pages (overflow pages included) holding the item tables.
+fn item_table_bytes(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(pgsize), 0) FROM dbstat WHERE name IN ('ClipboardItem', 'ItemFile')",
+        [],
+        |r| r.get(0),
+    )
+}
+
+/// Migration: create Blob and move the `inlineData` columns of ClipboardItem and
+/// ItemFile into it.
+pub(crate) fn init(conn: &Connection) -> rusqlite::Result<()> {
+    let before = item_table_bytes(conn)?;
+    conn.execute_batch(
+        r"
+        CREATE TABLE Blob (
+          id TEXT PRIMARY KEY NOT NULL,
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          data BLOB NOT NULL
+        );
+        CREATE INDEX Blob_item ON Blob(itemId);
+        INSERT INTO Blob (id, itemId, data)
+          SELECT id, id, inlineData FROM ClipboardItem WHERE inlineData IS NOT NULL;
+        INSERT INTO Blob (id, itemId, data)
+          SELECT id, itemId, inlineData FROM ItemFile WHERE inlineData IS NOT NULL;
+        ALTER TABLE ClipboardItem DROP COLUMN inlineData;
+        ALTER TABLE ItemFile DROP COLUMN inlineData;
+        ",
+    )?;
+    let (moved, bytes): (i64, i64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(length(data)), 0) FROM Blob",
+        [],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )?;
+    let after = item_table_bytes(conn)?;
+    tracing::info!(
+        moved,
+        bytes,
+        item_tables_before = before,
+        item_tables_after = after,
+        saved = before - after,
+        "moved inline data out of item rows"
+    );
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::store::{self, Conn};
+use crate::{archive, epoch_to_iso, expiry, listing, now_unix, tags, trash, AppState, ServerEvent};
+
+// -------------------- Bulk operations --------------------
+//
+// POST /api/clipboard/bulk applies one action to many items: either an explicit `ids`
+// list or every item matching `filter`, a list query string such as
+// `type=IMAGE&createdTo=2024-01-01`. All changes run in a single transaction under one
+// lock, each item gets its own result, and clients receive one clipboard:bulk event
+// instead of one per item.
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Action {
+    Delete,
+    Archive,
+    Unarchive,
+    Tag,
+    Untag,
+    Expire,
+    RevokeShares,
+    Download,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BulkReq {
+    action: Action,
+    ids: Option<Vec<String>>,
+    filter: Option<String>,
+    // tag / untag
+    #[serde(default)]
+    tags: Vec<String>,
+    // expire; neither set clears the expiry
+    expires_in: Option<String>,
+    expires_at: Option<String>,
+    // download: zip (default) or tar.gz
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ItemResult {
+    id: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+fn bad_request(msg: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": msg })),
+    )
+        .into_response()
+}
+
+// Apply `action` to one item inside the bulk transaction. Err is the per-item error.
+fn apply(
+    conn: &dyn Conn,
+    action: Action,
+    id: &str,
+    tag_ids: &[String],
+    expires_at: Option<i64>,
+    now: i64,
+) -> Result<(), &'static str> {
+    if !tags::item_exists(conn, id) {
+        return Err("Not found");
+    }
+    let db = |r: store::Result<usize>| r.map(drop).map_err(|_| "Update failed");
+    match action {
+        Action::Delete => trash::soft_delete(conn, id)
+            .then_some(())
+            .ok_or("Not found"),
+        Action::Archive => db(conn.execute(
+            "UPDATE ClipboardItem SET archivedAt=COALESCE(archivedAt, ?) WHERE id=?",
+            params![now, id],
+        )),
+        Action::Unarchive => {
+            db(conn.execute("UPDATE ClipboardItem SET archivedAt=NULL WHERE id=?", [id]))
+        }
+        Action::Tag => tag_ids.iter().try_for_each(|t| {
+            db(conn.execute(
+                "INSERT INTO ItemTag (itemId, tagId, createdAt) VALUES (?,?,?) ON CONFLICT DO NOTHING",
+                params![id, t, now],
+            ))
+        }),
+        Action::Untag => tag_ids.iter().try_for_each(|t| {
+            db(conn.execute(
+                "DELETE FROM ItemTag WHERE itemId=? AND tagId=?",
+                params![id, t],
+            ))
+        }),
+        Action::Expire => expiry::set(conn, id, expires_at)
+            .then_some(())
+            .ok_or("Not found"),
+        Action::RevokeShares => db(conn.execute(
+            "UPDATE ShareLink SET revoked=1, updatedAt=? WHERE itemId=? AND revoked=0",
+            params![now, id],
+        )),
+        Action::Download => Ok(()),
+    }
+}
+
+// POST /api/clipboard/bulk
+pub(crate) async fn bulk(State(state): State<AppState>, Json(req): Json<BulkReq>) -> Response {
+    let now = now_unix();
+    let expires_at = match req.action {
+        Action::Expire => {
+            match expiry::parse(req.expires_in.as_deref(), req.expires_at.as_deref(), now) {
+                Ok(v) => v,
+                Err(e) => return bad_request(&e),
+            }
+        }
+        _ => None,
+    };
+    let format = match req.format.as_deref().map(archive::Format::parse) {
+        None => archive::Format::Zip,
+        Some(Some(f)) => f,
+        Some(None) => return bad_request("invalid format"),
+    };
+    let names = match req.action {
+        Action::Tag | Action::Untag => match tags::clean_names(&req.tags) {
+            Ok(n) if !n.is_empty() => n,
+            Ok(_) => return bad_request("tags is required"),
+            Err(e) => return bad_request(e),
+        },
+        _ => vec![],
+    };
+    let ids = match (req.ids, req.filter) {
+        (Some(ids), None) => ids,
+        (None, Some(f)) => {
+            match state
+                .db
+                .read(move |conn| listing::select_ids(conn, &f, now))
+                .await
+            {
+                Ok(ids) => ids,
+                Err(e) => return bad_request(&e),
+            }
+        }
+        _ => return bad_request("exactly one of ids or filter is required"),
+    };
+
+    if req.action == Action::Download {
+        let (data_dir, keys) = (state.data_dir.clone(), state.keys.clone());
+        let entries = state
+            .db
+            .read(move |conn| archive::entries(conn, &data_dir, keys.as_deref(), &ids))
+            .await;
+        if entries.is_empty() {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error":"Not found"})),
+            )
+                .into_response();
+        }
+        return archive::stream_response(
+            state.db.clone(),
+            entries,
+            format,
+            &archive::default_name(now),
+        );
+    }
+
+    let applied = state
+        .db
+        .write(move |conn| {
+            let tx = conn.transaction()?;
+            let (tag_refs, created) = match req.action {
+                Action::Tag => tags::ensure_tags(&tx, &names),
+                Action::Untag => (
+                    names
+                        .iter()
+                        .filter_map(|n| tags::find_tag_by_name(&tx, n))
+                        .collect(),
+                    vec![],
+                ),
+                _ => (vec![], vec![]),
+            };
+            let tag_ids: Vec<String> = tag_refs.iter().map(|t| t.id.clone()).collect();
+            let mut results = Vec::with_capacity(ids.len());
+            let mut done: Vec<String> = vec![];
+            for id in ids {
+                let res = apply(&tx, req.action, &id, &tag_ids, expires_at, now);
+                if res.is_ok() {
+                    done.push(id.clone());
+                }
+                results.push(ItemResult {
+                    ok: res.is_ok(),
+                    error: res.err(),
+                    id,
+                });
+            }
+            tx.commit()?;
+            let item_tags: Option<HashMap<String, Vec<tags::TagRef>>> =
+                matches!(req.action, Action::Tag | Action::Untag).then(|| {
+                    let mut map = tags::tags_for_items(conn, &done);
+                    for id in &done {
+                        map.entry(id.clone()).or_default();
+                    }
+                    map
+                });
+            Ok::<_, store::Error>((results, done, created, item_tags))
+        })
+        .await;
+    let (results, done, created, item_tags) = match applied {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error":"db write failed","detail": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+
+    tags::announce_created(&state, created);
+    if req.action == Action::Expire && expires_at.is_some() && !done.is_empty() {
+        state.expiry_wake.notify_one();
+    }
+    if !done.is_empty() {
+        let mut data = serde_json::json!({ "action": req.action, "ids": &done });
+        match req.action {
+            Action::Delete => data["trashed"] = true.into(),
+            Action::Expire => data["expiresAt"] = serde_json::json!(expires_at.map(epoch_to_iso)),
+            _ => {}
+        }
+        if let Some(t) = &item_tags {
+            data["tags"] = serde_json::json!(t);
+        }
+        let _ = state.tx.send(ServerEvent {
+            name: "clipboard:bulk".into(),
+            data,
+        });
+    }
+    let failed = results.iter().filter(|r| !r.ok).count();
+    Json(serde_json::json!({
+        "action": req.action,
+        "succeeded": done.len(),
+        "failed": failed,
+        "results": results,
+    }))
+    .into_response()
+}
@@ -0,0 +1,477 @@
+use std::path::{Path as StdPath, PathBuf};
+
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rusqlite::{params, Connection};
+use uuid::Uuid;
+
+use crate::crypt::{self, ItemKey};
+use crate::ranges::{self, FileSource, Validators};
+use crate::store::{self, Conn, OptionalExtension};
+use crate::{
+    archive, blob, count_share_download, ensure_content_hash, epoch_to_iso, finish_share_download,
+    insert_file_headers, now_unix, strip_for_share, AppState,
+};
+
+// -------------------- Bundles --------------------
+//
+// An item of type BUNDLE holds several files, e.g. a dropped folder or a batch of photos.
+// The item row carries a display name and the total size; every file is an ItemFile row
+// with its relative path. Uploaded files live under uploads/bundles/<item id>/ (small ones
+// inline), so removing the item's files removes the whole bundle. Files can be fetched one
+// at a time or together as an archive, by the owner and through the item's share link.
+
+/// One received file of a multipart upload.
+pub(crate) struct Part {
+    pub(crate) path: String,
+    pub(crate) content_type: String,
+    pub(crate) size: i64,
+    pub(crate) hash: String,
+    pub(crate) rel_path: Option<String>,
+    pub(crate) inline: Option<Vec<u8>>,
+}
+
+pub(crate) fn init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r"
+        CREATE TABLE IF NOT EXISTS ItemFile (
+          id TEXT PRIMARY KEY,
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          position INTEGER NOT NULL,
+          path TEXT NOT NULL,
+          fileSize INTEGER NOT NULL,
+          contentType TEXT,
+          contentHash TEXT,
+          filePath TEXT,
+          inlineData BLOB,
+          createdAt INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS ItemFile_item ON ItemFile(itemId, position);
+        ",
+    )
+}
+
+/// Directory holding a bundle's uploaded files.
+pub(crate) fn dir(data_dir: &StdPath, item_id: &str) -> PathBuf {
+    data_dir.join("uploads").join("bundles").join(item_id)
+}
+
+/// Normalise a client-supplied relative path: `/` separators, no empty, `.` or `..`
+/// segments, each segment sanitised like an archive file name.
+pub(crate) fn clean_path(raw: &str) -> String {
+    let segs: Vec<String> = raw
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+        .take(32)
+        .map(archive::sanitize)
+        .collect();
+    if segs.is_empty() {
+        "file".into()
+    } else {
+        segs.join("/")
+    }
+}
+
+/// Name shown for a bundle: the folder all files share, else "N files".
+pub(crate) fn display_name(paths: &[String]) -> String {
+    let top = |p: &String| p.split_once('/').map(|(t, _)| t.to_string());
+    match paths.first().and_then(top) {
+        Some(t) if paths.iter().all(|p| top(p).as_deref() == Some(t.as_str())) => t,
+        _ => format!("{} files", paths.len()),
+    }
+}
+
+/// Remove received files of an upload that is being rejected.
+pub(crate) async fn discard(data_dir: &StdPath, parts: &[Part]) {
+    for p in parts {
+        if let Some(rel) = &p.rel_path {
+            let _ = tokio::fs::remove_file(data_dir.join(rel)).await;
+        }
+    }
+}
+
+/// Move uploaded parts into the bundle's directory, updating their stored paths.
+pub(crate) async fn store(
+    data_dir: &StdPath,
+    item_id: &str,
+    parts: &mut [Part],
+) -> std::io::Result<()> {
+    let dir = dir(data_dir, item_id);
+    tokio::fs::create_dir_all(&dir).await?;
+    for p in parts.iter_mut() {
+        let Some(rel) = p.rel_path.as_mut() else {
+            continue;
+        };
+        let name = StdPath::new(rel.as_str())
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        tokio::fs::rename(data_dir.join(&*rel), dir.join(&name)).await?;
+        *rel = format!("uploads/bundles/{item_id}/{name}");
+    }
+    Ok(())
+}
+
+pub(crate) fn insert_files(
+    conn: &dyn Conn,
+    item_id: &str,
+    parts: &[Part],
+    now: i64,
+    key: Option<&ItemKey>,
+) -> store::Result<()> {
+    for (i, p) in parts.iter().enumerate() {
+        let id = Uuid::new_v4().to_string();
+        conn.execute(
+            "INSERT INTO ItemFile (id, itemId, position, path, fileSize, contentType, contentHash, filePath, createdAt) VALUES (?,?,?,?,?,?,?,?,?)",
+            params![id, item_id, i as i64, p.path, p.size, p.content_type, p.hash, p.rel_path, now],
+        )?;
+        if let Some(data) = &p.inline {
+            blob::put(conn, &id, item_id, &crypt::seal_bytes(key, data))?;
+        }
+    }
+    Ok(())
+}
+
+/// A bundle's files, in upload order.
+pub(crate) fn list(conn: &dyn Conn, item_id: &str) -> Vec<serde_json::Value> {
+    let mut stmt = conn
+        .prepare("SELECT id, path, fileSize, contentType, createdAt FROM ItemFile WHERE itemId=? ORDER BY position")
+        .unwrap();
+    stmt.query_map([item_id], |r| {
+        Ok(serde_json::json!({
+            "id": r.get::<_, String>(0)?,
+            "path": r.get::<_, String>(1)?,
+            "fileSize": r.get::<_, i64>(2)?,
+            "contentType": r.get::<_, Option<String>>(3)?,
+            "createdAt": epoch_to_iso(r.get::<_, i64>(4)?),
+        }))
+    })
+    .unwrap()
+    .flatten()
+    .collect()
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({"error":"Not found"})),
+    )
+        .into_response()
+}
+
+fn is_bundle(conn: &dyn Conn, item_id: &str) -> bool {
+    conn.query_row(
+        "SELECT 1 FROM ClipboardItem WHERE id=? AND type='BUNDLE' AND deletedAt IS NULL",
+        [item_id],
+        |_| Ok(()),
+    )
+    .is_ok()
+}
+
+fn want(uri: &Uri, key: &str) -> Option<String> {
+    form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.into_owned())
+}
+
+// Serve one file of a bundle with range support; `share` serves what share recipients get.
+async fn serve_file(
+    state: &AppState,
+    item_id: &str,
+    file_id: &str,
+    req_headers: &HeaderMap,
+    attachment: bool,
+    share: bool,
+) -> Option<ranges::Served> {
+    type Row = (
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<Vec<u8>>,
+        i64,
+        Option<String>,
+        Option<Vec<u8>>,
+    );
+    let (fid, id) = (file_id.to_string(), item_id.to_string());
+    let row: Option<Row> = state
+        .db
+        .read(move |conn| {
+            conn.query_row(
+                "SELECT f.path, f.contentType, f.contentHash, f.filePath, (SELECT data FROM Blob WHERE Blob.id = f.id), f.createdAt, c.keyId, c.dataKey FROM ItemFile f JOIN ClipboardItem c ON c.id = f.itemId WHERE f.id=? AND f.itemId=?",
+                [fid, id],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?, r.get(6)?, r.get(7)?)),
+            )
+            .optional()
+            .ok()
+            .flatten()
+        })
+        .await;
+    let (path, ctype, hash, file_path, inline, created_at, key_id, wrapped) = row?;
+    let stored = crypt::item_key(state.keys.as_deref(), item_id, key_id, wrapped).and_then(|key| {
+        Ok((
+            FileSource::stored(&state.data_dir, file_path, inline, key.clone())?,
+            key,
+        ))
+    });
+    let (src, key) = match stored {
+        Ok((src, key)) => (src?, key),
+        Err(e) => {
+            return Some(ranges::Served {
+                response: crypt::failure(item_id, e),
+                counts_as_download: false,
+            })
+        }
+    };
+    let hash = ensure_content_hash(state, "ItemFile", file_id, hash, &src, key.as_ref()).await;
+    let name = path.rsplit('/').next().unwrap_or("download").to_string();
+    let ctype = ctype.unwrap_or_else(|| "application/octet-stream".into());
+    let (src, etag) = if share {
+        strip_for_share(state, &format!("{item_id}/{file_id}"), &ctype, src, hash).await
+    } else {
+        (src, hash.map(|h| ranges::strong_etag(&h)))
+    };
+    let mut headers = HeaderMap::new();
+    insert_file_headers(&mut headers, &ctype, &name, attachment);
+    let validators = Validators {
+        etag,
+        last_modified: Some(created_at),
+    };
+    Some(ranges::serve(req_headers, headers, src, &validators).await)
+}
+
+// Archive of a bundle's files, all or only `files=a,b` (ItemFile ids).
+// `share` strips image metadata from the entries as share_file does.
+async fn archive_response(state: &AppState, item_id: &str, uri: &Uri, share: bool) -> Response {
+    let format = match want(uri, "format").map(|f| archive::Format::parse(&f)) {
+        None => archive::Format::Zip,
+        Some(Some(f)) => f,
+        Some(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error":"invalid format"})),
+            )
+                .into_response()
+        }
+    };
+    let only: Option<Vec<String>> = want(uri, "files").map(|v| {
+        v.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    });
+    let (data_dir, keys, item_id) = (
+        state.data_dir.clone(),
+        state.keys.clone(),
+        item_id.to_string(),
+    );
+    let (mut entries, name) = state
+        .db
+        .read(move |conn| {
+            let name: String = conn
+                .query_row(
+                    "SELECT fileName FROM ClipboardItem WHERE id=?",
+                    [&item_id],
+                    |r| r.get::<_, Option<String>>(0),
+                )
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| "bundle".into());
+            (
+                archive::bundle_entries(
+                    conn,
+                    &data_dir,
+                    keys.as_deref(),
+                    &item_id,
+                    only.as_deref(),
+                ),
+                archive::sanitize(&name),
+            )
+        })
+        .await;
+    if entries.is_empty() {
+        return not_found();
+    }
+    if share && state.strip_share_metadata {
+        archive::strip_metadata(&mut entries);
+    }
+    archive::stream_response(state.db.clone(), entries, format, &name)
+}
+
+// GET /api/clipboard/:id/files
+pub(crate) async fn list_files(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    state
+        .db
+        .read(move |conn| {
+            if !is_bundle(conn, &id) {
+                return not_found();
+            }
+            Json(serde_json::json!({ "files": list(conn, &id) })).into_response()
+        })
+        .await
+}
+
+// GET /api/clipboard/:id/files/:file_id[?download=1]
+pub(crate) async fn get_file(
+    State(state): State<AppState>,
+    Path((id, file_id)): Path<(String, String)>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if !bundle_exists(&state, &id).await {
+        return not_found();
+    }
+    let attachment =
+        want(&uri, "download").is_some_and(|v| matches!(v.as_str(), "1" | "true" | "yes"));
+    match serve_file(&state, &id, &file_id, &headers, attachment, false).await {
+        Some(served) => served.response,
+        None => not_found(),
+    }
+}
+
+// GET /api/clipboard/:id/archive[?files=a,b&format=zip|tar.gz]
+pub(crate) async fn get_archive(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    uri: Uri,
+) -> Response {
+    if !bundle_exists(&state, &id).await {
+        return not_found();
+    }
+    archive_response(&state, &id, &uri, false).await
+}
+
+async fn bundle_exists(state: &AppState, id: &str) -> bool {
+    let id = id.to_string();
+    state.db.read(move |conn| is_bundle(conn, &id)).await
+}
+
+// -------- Share link access --------
+
+struct ShareAccess {
+    token: String,
+    item_id: String,
+    max: Option<i64>,
+    count: i64,
+    /// Spent: only ranges continuing its last transfer are served.
+    exhausted: bool,
+}
+
+// Same validity and password rules as the other share endpoints, for bundle items only.
+async fn open_share(
+    state: &AppState,
+    token: &str,
+    headers: &HeaderMap,
+) -> Result<ShareAccess, StatusCode> {
+    type Row = (String, Option<i64>, Option<i64>, i64, i64, Option<String>);
+    let t = token.to_string();
+    let row: Option<Row> = state
+        .db
+        .read(move |conn| {
+            conn.query_row(
+                "SELECT s.itemId, s.expiresAt, s.maxDownloads, s.downloadCount, s.revoked, s.passwordHash FROM ShareLink s JOIN ClipboardItem c ON s.itemId=c.id WHERE s.token=? AND c.deletedAt IS NULL AND c.type='BUNDLE'",
+                [t],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?)),
+            )
+            .optional()
+            .ok()
+            .flatten()
+        })
+        .await;
+    let (item_id, exp, max, count, revoked, pwd_hash) = row.ok_or(StatusCode::NOT_FOUND)?;
+    let is_expired = exp.is_some_and(|e| e < now_unix());
+    let is_exhausted = max.is_some_and(|m| m >= 0 && count >= m);
+    if revoked != 0 || is_expired || (is_exhausted && !headers.contains_key(header::RANGE)) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if let Some(ph) = pwd_hash {
+        let ok = headers
+            .get(axum::http::header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|c| {
+                c.split(';')
+                    .any(|p| p.trim() == format!("share_auth_{token}={ph}"))
+            });
+        if !ok {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+    Ok(ShareAccess {
+        token: token.to_string(),
+        item_id,
+        max,
+        count,
+        exhausted: is_exhausted,
+    })
+}
+
+fn share_error(code: StatusCode) -> Response {
+    let msg = if code == StatusCode::UNAUTHORIZED {
+        "unauthorized"
+    } else {
+        "not found"
+    };
+    (code, Json(serde_json::json!({ "error": msg }))).into_response()
+}
+
+// GET /api/share/:token/files/:file_id — one file of a shared bundle; counts as a download
+pub(crate) async fn share_file(
+    state: AppState,
+    token: String,
+    file_id: String,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    let access = match open_share(&state, &token, &headers).await {
+        Ok(a) => a,
+        Err(code) => return share_error(code),
+    };
+    let Some(served) = serve_file(&state, &access.item_id, &file_id, &headers, true, true).await
+    else {
+        return share_error(StatusCode::NOT_FOUND);
+    };
+    finish_share_download(
+        &state,
+        &access.token,
+        &access.item_id,
+        (access.max, access.count),
+        &method,
+        served,
+    )
+    .await
+}
+
+/// Archive download of a shared bundle (all files or `files=a,b`); counts as one download.
+pub(crate) async fn share_archive(
+    state: &AppState,
+    token: &str,
+    method: &Method,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> Response {
+    let access = match open_share(state, token, headers).await {
+        Ok(a) => a,
+        Err(code) => return share_error(code),
+    };
+    if access.exhausted {
+        return share_error(StatusCode::NOT_FOUND);
+    }
+    let resp = archive_response(state, &access.item_id, uri, true).await;
+    if resp.status() == StatusCode::OK && method == Method::GET {
+        return count_share_download(
+            state,
+            &access.token,
+            &access.item_id,
+            access.max,
+            access.count,
+            resp,
+        )
+        .await;
+    }
+    resp
+}
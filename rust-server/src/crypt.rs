@@ -0,0 +1,957 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, SeekFrom, Write};
+use std::path::{Path as StdPath, PathBuf};
+
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::STANDARD as B64;
+use base64::Engine as _;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use futures_util::Stream;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rusqlite::params;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+
+use crate::db::{self, Db};
+use crate::store::{self, Conn};
+use crate::{scrub, thumbs};
+
+// -------------------- Encryption at rest --------------------
+//
+// With ENCRYPTION_KEY (or ENCRYPTION_KEY_FILE) set, every new item gets its own random
+// data key, stored in ClipboardItem.dataKey wrapped by the master key named in keyId. The
+// data key seals the item's text (content and revisions), its blobs and its files in
+// uploads/, as well as the thumbnails and metadata-stripped copies made from them. All of
+// it uses XChaCha20-Poly1305.
+//
+// Text is stored as base64(nonce | ciphertext) and blobs as nonce | ciphertext. Files are
+// sealed in chunks of 64 KiB so downloads and ranges decrypt while they stream: an 8 byte
+// magic and a 19 byte nonce prefix, then each chunk with its tag. A chunk's nonce is the
+// prefix, its index and a flag marking the last chunk, so chunks can't be reordered and a
+// file cut short at a chunk boundary doesn't decrypt.
+//
+// Several master keys may be configured; the first one wraps new data keys and the others
+// only unwrap. `clip-relay encrypt` encrypts items stored before a key was set and rewraps
+// data keys under the first key, which is how keys are rotated.
+
+const MAGIC: &[u8; 8] = b"CRSEAL01";
+const PREFIX_LEN: usize = 19;
+const HEADER_LEN: u64 = (MAGIC.len() + PREFIX_LEN) as u64;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Plaintext bytes per file chunk.
+const CHUNK: usize = 64 * 1024;
+const SEALED_CHUNK: u64 = (CHUNK + TAG_LEN) as u64;
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// The item's data key is wrapped by a master key that isn't configured.
+    NoKey(String),
+    /// Wrong key, or the stored data was altered or cut short.
+    Corrupt,
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoKey(id) => write!(f, "encryption key {id} is not configured"),
+            Self::Corrupt => f.write_str("decryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// 500 response for an item that can't be decrypted.
+pub(crate) fn failure(id: &str, e: Error) -> Response {
+    tracing::error!(%id, error = %e, "cannot decrypt item");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({"error":"cannot decrypt item","detail": e.to_string()})),
+    )
+        .into_response()
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut buf = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut buf);
+    buf
+}
+
+// -------- Master keys --------
+
+struct MasterKey {
+    id: String,
+    cipher: XChaCha20Poly1305,
+}
+
+/// The configured master keys; the first one wraps new data keys.
+pub(crate) struct Keyring {
+    keys: Vec<MasterKey>,
+}
+
+impl Keyring {
+    /// Keys from ENCRYPTION_KEY or the file ENCRYPTION_KEY_FILE names: base64 of 32 random
+    /// bytes each, separated by commas, spaces or newlines. None when neither is set.
+    pub(crate) fn from_env() -> anyhow::Result<Option<Self>> {
+        let inline = std::env::var("ENCRYPTION_KEY")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        let file = std::env::var("ENCRYPTION_KEY_FILE")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        let text = match (inline, file) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                anyhow::bail!("set either ENCRYPTION_KEY or ENCRYPTION_KEY_FILE, not both")
+            }
+            (Some(v), None) => v,
+            (None, Some(path)) => std::fs::read_to_string(path.trim())
+                .map_err(|e| anyhow::anyhow!("ENCRYPTION_KEY_FILE {path}: {e}"))?,
+        };
+        Self::parse(&text).map(Some)
+    }
+
+    pub(crate) fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut keys: Vec<MasterKey> = vec![];
+        for (i, word) in text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|w| !w.is_empty())
+            .enumerate()
+        {
+            let raw = B64
+                .decode(word)
+                .ok()
+                .filter(|k| k.len() == 32)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("encryption key {} is not 32 bytes of base64", i + 1)
+                })?;
+            let id: String = Sha256::digest(&raw)[..4]
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            if keys.iter().all(|k| k.id != id) {
+                keys.push(MasterKey {
+                    id,
+                    cipher: XChaCha20Poly1305::new(Key::from_slice(&raw)),
+                });
+            }
+        }
+        anyhow::ensure!(!keys.is_empty(), "no encryption key given");
+        Ok(Self { keys })
+    }
+
+    /// Id of the key new data keys are wrapped with.
+    pub(crate) fn current_id(&self) -> &str {
+        &self.keys[0].id
+    }
+
+    /// Whether data keys wrapped under `key_id` can be unwrapped.
+    pub(crate) fn knows(&self, key_id: &str) -> bool {
+        self.keys.iter().any(|k| k.id == key_id)
+    }
+
+    /// A fresh data key for item `item_id`, with the keyId and dataKey to store.
+    pub(crate) fn new_key(&self, item_id: &str) -> (ItemKey, String, Vec<u8>) {
+        let key = ItemKey::new(random::<32>().into());
+        let (key_id, wrapped) = self.wrap(&key, item_id);
+        (key, key_id, wrapped)
+    }
+
+    // Wrapped keys are bound to their item, so one can't be copied onto another row.
+    fn wrap(&self, key: &ItemKey, item_id: &str) -> (String, Vec<u8>) {
+        let master = &self.keys[0];
+        let nonce = random::<NONCE_LEN>();
+        let sealed = master
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &key.raw,
+                    aad: item_id.as_bytes(),
+                },
+            )
+            .expect("key fits in one message");
+        (master.id.clone(), [&nonce[..], &sealed].concat())
+    }
+
+    fn unwrap(&self, key_id: &str, wrapped: &[u8], item_id: &str) -> Result<ItemKey> {
+        let master = self
+            .keys
+            .iter()
+            .find(|k| k.id == key_id)
+            .ok_or_else(|| Error::NoKey(key_id.to_string()))?;
+        if wrapped.len() < NONCE_LEN {
+            return Err(Error::Corrupt);
+        }
+        let (nonce, sealed) = wrapped.split_at(NONCE_LEN);
+        let raw = master
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: item_id.as_bytes(),
+                },
+            )
+            .map_err(|_| Error::Corrupt)?;
+        if raw.len() != 32 {
+            return Err(Error::Corrupt);
+        }
+        Ok(ItemKey::new(*Key::from_slice(&raw)))
+    }
+}
+
+/// The data key of an item from its keyId and dataKey columns; None for a plaintext item.
+pub(crate) fn item_key(
+    keys: Option<&Keyring>,
+    item_id: &str,
+    key_id: Option<String>,
+    wrapped: Option<Vec<u8>>,
+) -> Result<Option<ItemKey>> {
+    let Some(wrapped) = wrapped else {
+        return Ok(None);
+    };
+    let key_id = key_id.unwrap_or_default();
+    let keys = keys.ok_or_else(|| Error::NoKey(key_id.clone()))?;
+    keys.unwrap(&key_id, &wrapped, item_id).map(Some)
+}
+
+/// `item_key` for an item looked up by id.
+pub(crate) fn load_item_key(
+    conn: &dyn Conn,
+    keys: Option<&Keyring>,
+    item_id: &str,
+) -> store::Result<Result<Option<ItemKey>>> {
+    let (key_id, wrapped) = conn.query_row(
+        "SELECT keyId, dataKey FROM ClipboardItem WHERE id=?",
+        [item_id],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )?;
+    Ok(item_key(keys, item_id, key_id, wrapped))
+}
+
+// -------- Data keys --------
+
+#[derive(Clone)]
+pub(crate) struct ItemKey {
+    raw: Key,
+    cipher: XChaCha20Poly1305,
+}
+
+impl ItemKey {
+    fn new(raw: Key) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(&raw),
+            raw,
+        }
+    }
+
+    /// HMAC-SHA256 of a content hash, so a sealed item's contentHash can't be used to
+    /// confirm guesses of its content.
+    fn keyed_hash(&self, hex: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.raw).expect("any key length");
+        mac.update(b"clip-relay contentHash\0");
+        mac.update(hex.as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    /// nonce | ciphertext
+    pub(crate) fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let nonce = random::<NONCE_LEN>();
+        let sealed = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), data)
+            .expect("blob fits in one message");
+        [&nonce[..], &sealed].concat()
+    }
+
+    pub(crate) fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN + TAG_LEN {
+            return Err(Error::Corrupt);
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), sealed)
+            .map_err(|_| Error::Corrupt)
+    }
+
+    pub(crate) fn seal_text(&self, text: &str) -> String {
+        B64.encode(self.seal(text.as_bytes()))
+    }
+
+    pub(crate) fn open_text(&self, sealed: &str) -> Result<String> {
+        let data = B64.decode(sealed).map_err(|_| Error::Corrupt)?;
+        String::from_utf8(self.open(&data)?).map_err(|_| Error::Corrupt)
+    }
+
+    /// Sealer for one file.
+    pub(crate) fn sealer(&self) -> Sealer {
+        Sealer {
+            cipher: self.cipher.clone(),
+            prefix: random::<PREFIX_LEN>(),
+            index: 0,
+            buf: Vec::with_capacity(CHUNK),
+            started: false,
+        }
+    }
+}
+
+/// Content hashes stored before they were keyed: dropped for sealed items, and made again
+/// on their next download. Run by the migrations of both backends.
+pub(crate) const CLEAR_SEALED_HASHES: &str = "
+    UPDATE ItemFile SET contentHash = NULL
+      WHERE itemId IN (SELECT id FROM ClipboardItem WHERE dataKey IS NOT NULL);
+    UPDATE ClipboardItem SET contentHash = NULL WHERE dataKey IS NOT NULL;
+";
+
+/// The contentHash stored for content whose plaintext hashes to `hex` (sha256): keyed
+/// with the data key when the item is sealed.
+pub(crate) fn stored_hash(key: Option<&ItemKey>, hex: String) -> String {
+    match key {
+        Some(k) => k.keyed_hash(&hex),
+        None => hex,
+    }
+}
+
+/// Text column as stored: sealed when the item has a key.
+pub(crate) fn seal_text(key: Option<&ItemKey>, text: Option<String>) -> Option<String> {
+    match key {
+        Some(k) => text.map(|t| k.seal_text(&t)),
+        None => text,
+    }
+}
+
+/// Text column as read back.
+pub(crate) fn open_text(key: Option<&ItemKey>, text: Option<String>) -> Result<Option<String>> {
+    match (key, text) {
+        (Some(k), Some(t)) => k.open_text(&t).map(Some),
+        (_, text) => Ok(text),
+    }
+}
+
+/// Text column of a listed item. One that can't be decrypted is listed without it.
+pub(crate) fn open_listed(
+    keys: Option<&Keyring>,
+    item_id: &str,
+    key_id: Option<String>,
+    wrapped: Option<Vec<u8>>,
+    text: Option<String>,
+) -> Option<String> {
+    match item_key(keys, item_id, key_id, wrapped).and_then(|k| open_text(k.as_ref(), text)) {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::warn!(id = %item_id, error = %e, "cannot decrypt item");
+            None
+        }
+    }
+}
+
+/// Blob as stored: sealed when the item has a key.
+pub(crate) fn seal_bytes(key: Option<&ItemKey>, data: &[u8]) -> Vec<u8> {
+    match key {
+        Some(k) => k.seal(data),
+        None => data.to_vec(),
+    }
+}
+
+/// Blob as read back.
+pub(crate) fn open_bytes(key: Option<&ItemKey>, data: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    match (key, data) {
+        (Some(k), Some(d)) => k.open(&d).map(Some),
+        (_, data) => Ok(data),
+    }
+}
+
+// -------- Chunked files --------
+
+fn chunk_nonce(prefix: &[u8; PREFIX_LEN], index: u64, last: bool) -> XNonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&(index as u32).to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce.into()
+}
+
+/// Seals a file as it is written: feed it with `push`, end with `finish`, and write out
+/// whatever they return.
+pub(crate) struct Sealer {
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; PREFIX_LEN],
+    index: u64,
+    buf: Vec<u8>,
+    started: bool,
+}
+
+impl Sealer {
+    fn start(&mut self) -> Vec<u8> {
+        if std::mem::replace(&mut self.started, true) {
+            return vec![];
+        }
+        [&MAGIC[..], &self.prefix].concat()
+    }
+
+    fn seal_chunk(&mut self, chunk: &[u8], last: bool, out: &mut Vec<u8>) {
+        let nonce = chunk_nonce(&self.prefix, self.index, last);
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, chunk)
+            .expect("chunk fits in one message");
+        out.extend_from_slice(&sealed);
+        self.index += 1;
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = self.start();
+        self.buf.extend_from_slice(data);
+        // the last chunk is held back: only `finish` knows it is the last
+        while self.buf.len() > CHUNK {
+            let rest = self.buf.split_off(CHUNK);
+            let chunk = std::mem::replace(&mut self.buf, rest);
+            self.seal_chunk(&chunk, false, &mut out);
+        }
+        out
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let mut out = self.start();
+        let chunk = std::mem::take(&mut self.buf);
+        self.seal_chunk(&chunk, true, &mut out);
+        out
+    }
+}
+
+/// Bytes to write for `data`: sealed when there is a sealer.
+pub(crate) fn push<'a>(sealer: &mut Option<Sealer>, data: &'a [u8]) -> Cow<'a, [u8]> {
+    match sealer {
+        Some(s) => Cow::Owned(s.push(data)),
+        None => Cow::Borrowed(data),
+    }
+}
+
+// Chunks in a sealed file of `body` bytes after the header. The last one is always there,
+// if only as an empty chunk.
+fn chunk_count(body: u64) -> u64 {
+    body.div_ceil(SEALED_CHUNK).max(1)
+}
+
+/// Plaintext length of a sealed file of `file_len` bytes.
+pub(crate) fn plain_len(file_len: u64) -> u64 {
+    let body = file_len.saturating_sub(HEADER_LEN);
+    body.saturating_sub(TAG_LEN as u64 * chunk_count(body))
+}
+
+// Where the chunks of one sealed file are and how to open them.
+struct Layout {
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; PREFIX_LEN],
+    body: u64,
+    count: u64,
+}
+
+impl Layout {
+    fn new(key: &ItemKey, header: &[u8; HEADER_LEN as usize], file_len: u64) -> Result<Self> {
+        if &header[..MAGIC.len()] != MAGIC || file_len < HEADER_LEN + TAG_LEN as u64 {
+            return Err(Error::Corrupt);
+        }
+        let body = file_len - HEADER_LEN;
+        let count = chunk_count(body);
+        if body - (count - 1) * SEALED_CHUNK < TAG_LEN as u64 {
+            return Err(Error::Corrupt);
+        }
+        Ok(Self {
+            cipher: key.cipher.clone(),
+            prefix: header[MAGIC.len()..].try_into().unwrap(),
+            body,
+            count,
+        })
+    }
+
+    fn sealed_len(&self, index: u64) -> usize {
+        (self.body - index * SEALED_CHUNK).min(SEALED_CHUNK) as usize
+    }
+
+    fn offset(index: u64) -> u64 {
+        HEADER_LEN + index * SEALED_CHUNK
+    }
+
+    fn open(&self, index: u64, sealed: &[u8]) -> Result<Vec<u8>> {
+        let nonce = chunk_nonce(&self.prefix, index, index + 1 == self.count);
+        self.cipher
+            .decrypt(&nonce, sealed)
+            .map_err(|_| Error::Corrupt)
+    }
+}
+
+/// Reads the plaintext of a sealed file from start to end.
+pub(crate) struct SealedReader {
+    file: File,
+    layout: Layout,
+    index: u64,
+    plain: Vec<u8>,
+    pos: usize,
+}
+
+impl SealedReader {
+    /// The reader and the plaintext length.
+    pub(crate) fn open(path: &StdPath, key: &ItemKey) -> io::Result<(Self, u64)> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header)?;
+        let layout = Layout::new(key, &header, file_len)?;
+        let reader = Self {
+            file,
+            layout,
+            index: 0,
+            plain: vec![],
+            pos: 0,
+        };
+        Ok((reader, plain_len(file_len)))
+    }
+}
+
+impl Read for SealedReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.index == self.layout.count {
+                return Ok(0);
+            }
+            let mut sealed = vec![0u8; self.layout.sealed_len(self.index)];
+            self.file.read_exact(&mut sealed)?;
+            self.plain = self.layout.open(self.index, &sealed)?;
+            self.pos = 0;
+            self.index += 1;
+        }
+        let n = out.len().min(self.plain.len() - self.pos);
+        out[..n].copy_from_slice(&self.plain[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// `len` plaintext bytes of a sealed file from offset `start`, decrypted chunk by chunk.
+pub(crate) fn stream(
+    path: PathBuf,
+    key: ItemKey,
+    start: u64,
+    len: u64,
+) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    async_stream::try_stream! {
+        let end = start + len;
+        let mut f = tokio::fs::File::open(&path).await?;
+        let file_len = f.metadata().await?.len();
+        let mut header = [0u8; HEADER_LEN as usize];
+        f.read_exact(&mut header).await?;
+        let layout = Layout::new(&key, &header, file_len)?;
+        let mut index = start / CHUNK as u64;
+        f.seek(SeekFrom::Start(Layout::offset(index))).await?;
+        let mut sealed = vec![0u8; SEALED_CHUNK as usize];
+        let mut pos = index * CHUNK as u64;
+        while pos < end && index < layout.count {
+            let n = layout.sealed_len(index);
+            f.read_exact(&mut sealed[..n]).await?;
+            let plain = layout.open(index, &sealed[..n])?;
+            let from = start.saturating_sub(pos) as usize;
+            let to = ((end - pos) as usize).min(plain.len());
+            yield Bytes::copy_from_slice(&plain[from..to]);
+            pos += CHUNK as u64;
+            index += 1;
+        }
+    }
+}
+
+/// A stored file read whole, decrypted when `key` is given. For images and the caches
+/// made from them; downloads stream instead.
+pub(crate) fn read(path: &StdPath, key: Option<&ItemKey>) -> io::Result<Vec<u8>> {
+    let Some(key) = key else {
+        return std::fs::read(path);
+    };
+    let (mut reader, len) = SealedReader::open(path, key)?;
+    let mut out = Vec::with_capacity(len as usize);
+    reader.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Write a file, sealed when `key` is given.
+pub(crate) fn write(path: &StdPath, key: Option<&ItemKey>, data: &[u8]) -> io::Result<()> {
+    match key {
+        Some(key) => {
+            let mut sealer = key.sealer();
+            let mut f = File::create(path)?;
+            for chunk in data.chunks(CHUNK) {
+                f.write_all(&sealer.push(chunk))?;
+            }
+            f.write_all(&sealer.finish())
+        }
+        None => std::fs::write(path, data),
+    }
+}
+
+// -------- clip-relay encrypt --------
+
+// An item's sealable state; files are relative paths under the data directory.
+struct Stored {
+    id: String,
+    content: Option<String>,
+    file_path: Option<String>,
+    key: Option<ItemKey>,
+    // (rev, content)
+    revisions: Vec<(i64, Option<String>)>,
+    // (id, data)
+    blobs: Vec<(String, Vec<u8>)>,
+    // (ItemFile id, filePath)
+    files: Vec<(String, String)>,
+}
+
+fn load_stored(conn: &dyn Conn, keys: Option<&Keyring>, id: &str) -> anyhow::Result<Stored> {
+    let (content, file_path, key_id, wrapped) = conn.query_row(
+        "SELECT content, filePath, keyId, dataKey FROM ClipboardItem WHERE id=?",
+        [id],
+        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+    )?;
+    let key = item_key(keys, id, key_id, wrapped)?;
+    let content = open_text(key.as_ref(), content)?;
+    let mut revisions = vec![];
+    for row in conn
+        .prepare("SELECT rev, content FROM ItemRevision WHERE itemId=?")?
+        .query_map([id], |r| Ok((r.get(0)?, r.get(1)?)))?
+    {
+        let (rev, text): (i64, Option<String>) = row?;
+        revisions.push((rev, open_text(key.as_ref(), text)?));
+    }
+    let mut blobs = vec![];
+    for row in conn
+        .prepare("SELECT id, data FROM Blob WHERE itemId=?")?
+        .query_map([id], |r| Ok((r.get(0)?, r.get(1)?)))?
+    {
+        let (blob_id, data): (String, Vec<u8>) = row?;
+        let data = open_bytes(key.as_ref(), Some(data))?.unwrap_or_default();
+        blobs.push((blob_id, data));
+    }
+    let files = conn
+        .prepare("SELECT id, filePath FROM ItemFile WHERE itemId=? AND filePath IS NOT NULL")?
+        .query_map([id], |r| Ok((r.get(0)?, r.get(1)?)))?
+        .collect::<store::Result<_>>()?;
+    Ok(Stored {
+        id: id.to_string(),
+        content,
+        file_path,
+        key,
+        revisions,
+        blobs,
+        files,
+    })
+}
+
+// Copy `rel` to a new file next to it, re-sealed with `to` (plaintext when None). Returns
+// the new relative path and the hex sha256 of the plaintext.
+fn reseal_file(
+    data_dir: &StdPath,
+    rel: &str,
+    from: Option<&ItemKey>,
+    to: Option<&ItemKey>,
+) -> anyhow::Result<(String, String)> {
+    let src = data_dir.join(rel);
+    let mut reader: Box<dyn Read> = match from {
+        Some(k) => Box::new(SealedReader::open(&src, k)?.0),
+        None => Box::new(File::open(&src)?),
+    };
+    let ext = StdPath::new(rel)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| format!(".{e}"))
+        .unwrap_or_default();
+    let new_rel = match rel.rsplit_once('/') {
+        Some((dir, _)) => format!("{dir}/{}{ext}", Uuid::new_v4()),
+        None => format!("{}{ext}", Uuid::new_v4()),
+    };
+    let mut out = File::create(data_dir.join(&new_rel))?;
+    let mut sealer = to.map(ItemKey::sealer);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        match sealer.as_mut() {
+            Some(s) => out.write_all(&s.push(&buf[..n]))?,
+            None => out.write_all(&buf[..n])?,
+        }
+    }
+    if let Some(s) = sealer {
+        out.write_all(&s.finish())?;
+    }
+    out.sync_all()?;
+    Ok((new_rel, format!("{:x}", hasher.finalize())))
+}
+
+// Store `item` sealed with `to` (None decrypts it). Files are written next to the old ones
+// first and the old ones removed once the rows point at the new ones.
+fn reseal(
+    conn: &dyn Conn,
+    data_dir: &StdPath,
+    item: Stored,
+    to: Option<(&ItemKey, &str, &[u8])>,
+) -> anyhow::Result<()> {
+    let key = to.map(|(k, _, _)| k);
+    let mut written = vec![];
+    let result = (|| {
+        // content hashes are keyed with the data key, so they're made again for the new one
+        let blob_hash = |id: &str| {
+            item.blobs
+                .iter()
+                .find(|(b, _)| b == id)
+                .map(|(_, data)| stored_hash(key, format!("{:x}", Sha256::digest(data))))
+        };
+        let mut file_path = None;
+        let mut hash = blob_hash(&item.id);
+        if let Some(rel) = &item.file_path {
+            let (new_rel, h) = reseal_file(data_dir, rel, item.key.as_ref(), key)?;
+            written.push(new_rel.clone());
+            file_path = Some(new_rel);
+            hash = Some(stored_hash(key, h));
+        }
+        // ItemFile rows: (id, new filePath if on disk, hash)
+        let mut files = vec![];
+        for (fid, rel) in &item.files {
+            let (new_rel, h) = reseal_file(data_dir, rel, item.key.as_ref(), key)?;
+            written.push(new_rel.clone());
+            files.push((fid.as_str(), Some(new_rel), stored_hash(key, h)));
+        }
+        for (bid, _) in item.blobs.iter().filter(|(b, _)| *b != item.id) {
+            files.extend(blob_hash(bid).map(|h| (bid.as_str(), None, h)));
+        }
+        let tx = conn.transaction()?;
+        // a text item's size is its plaintext length once content is sealed
+        let text_size = key.and(item.content.as_ref()).map(|c| c.len() as i64);
+        tx.execute(
+            "UPDATE ClipboardItem SET content=?, filePath=COALESCE(?, filePath), contentHash=?, keyId=?, dataKey=?, fileSize=CASE WHEN type='TEXT' AND content IS NOT NULL THEN ? ELSE fileSize END, extractedText=NULL, extractStatus=CASE WHEN type='FILE' THEN ? END WHERE id=?",
+            params![
+                seal_text(key, item.content.clone()),
+                file_path,
+                hash,
+                to.map(|(_, id, _)| id),
+                to.map(|(_, _, wrapped)| wrapped),
+                text_size,
+                // documents are re-extracted once decrypted; sealed ones are never indexed
+                if key.is_some() { "skipped" } else { "pending" },
+                item.id,
+            ],
+        )?;
+        for (rev, text) in &item.revisions {
+            tx.execute(
+                "UPDATE ItemRevision SET content=? WHERE itemId=? AND rev=?",
+                params![seal_text(key, text.clone()), item.id, rev],
+            )?;
+        }
+        for (blob_id, data) in &item.blobs {
+            tx.execute(
+                "UPDATE Blob SET data=? WHERE id=?",
+                params![seal_bytes(key, data), blob_id],
+            )?;
+        }
+        for (fid, rel, hash) in &files {
+            tx.execute(
+                "UPDATE ItemFile SET filePath=COALESCE(?, filePath), contentHash=? WHERE id=?",
+                params![rel, hash, fid],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        for rel in written {
+            let _ = std::fs::remove_file(data_dir.join(rel));
+        }
+        return Err(e);
+    }
+    let old = item
+        .file_path
+        .iter()
+        .chain(item.files.iter().map(|(_, r)| r));
+    for rel in old {
+        let _ = std::fs::remove_file(data_dir.join(rel));
+    }
+    // cached renditions are made again, in the item's new form, on their next request
+    thumbs::remove(data_dir, &item.id);
+    scrub::remove(data_dir, &item.id);
+    Ok(())
+}
+
+/// `clip-relay encrypt [--status | --decrypt]`: encrypt the items stored in plaintext and
+/// rewrap every data key under the first configured key; `--decrypt` turns everything
+/// back into plaintext. Run it with the server stopped.
+pub(crate) fn run_cli(
+    args: &[String],
+    data_dir: &StdPath,
+    target: &db::Target,
+) -> anyhow::Result<()> {
+    let keys = Keyring::from_env()?;
+    let db = Db::open(target)?;
+    let conn = db.writer();
+    let rows: Vec<(String, Option<String>, bool)> = conn
+        .prepare("SELECT id, keyId, dataKey IS NOT NULL FROM ClipboardItem ORDER BY createdAt, id")?
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+        .collect::<store::Result<_>>()?;
+    let mode = args.get(1).map(String::as_str);
+    if mode == Some("--status") {
+        let plain = rows.iter().filter(|(_, _, sealed)| !sealed).count();
+        println!("{plain} items in plaintext");
+        let mut by_key: Vec<(&str, usize)> = vec![];
+        for (_, key_id, _) in rows.iter().filter(|(_, _, sealed)| *sealed) {
+            let key_id = key_id.as_deref().unwrap_or("?");
+            match by_key.iter_mut().find(|(k, _)| *k == key_id) {
+                Some((_, n)) => *n += 1,
+                None => by_key.push((key_id, 1)),
+            }
+        }
+        for (key_id, n) in by_key {
+            let state = match &keys {
+                Some(k) if k.current_id() == key_id => "current key",
+                Some(k) if k.knows(key_id) => "older key",
+                _ => "key not configured",
+            };
+            println!("{n} items encrypted with key {key_id} ({state})");
+        }
+        return Ok(());
+    }
+    let decrypt = match mode {
+        None => false,
+        Some("--decrypt") => true,
+        Some(_) => anyhow::bail!("usage: clip-relay encrypt [--status | --decrypt]"),
+    };
+    let keys = keys.ok_or_else(|| anyhow::anyhow!("set ENCRYPTION_KEY or ENCRYPTION_KEY_FILE"))?;
+    let (mut sealed, mut rewrapped, mut opened) = (0, 0, 0);
+    for (id, key_id, is_sealed) in rows {
+        if decrypt {
+            if is_sealed {
+                let item = load_stored(&*conn, Some(&keys), &id)?;
+                reseal(&*conn, data_dir, item, None)?;
+                opened += 1;
+            }
+        } else if !is_sealed {
+            let item = load_stored(&*conn, Some(&keys), &id)?;
+            let (key, key_id, wrapped) = keys.new_key(&id);
+            reseal(&*conn, data_dir, item, Some((&key, &key_id, &wrapped)))?;
+            sealed += 1;
+        } else if key_id.as_deref() != Some(keys.current_id()) {
+            // rotation: same data key, wrapped by the current master key
+            let wrapped: Vec<u8> =
+                conn.query_row("SELECT dataKey FROM ClipboardItem WHERE id=?", [&id], |r| {
+                    r.get(0)
+                })?;
+            let key = keys.unwrap(key_id.as_deref().unwrap_or_default(), &wrapped, &id)?;
+            let (new_id, new_wrapped) = keys.wrap(&key, &id);
+            conn.execute(
+                "UPDATE ClipboardItem SET keyId=?, dataKey=? WHERE id=?",
+                params![new_id, new_wrapped, id],
+            )?;
+            rewrapped += 1;
+        }
+    }
+    if decrypt {
+        println!("decrypted {opened} items");
+    } else {
+        println!(
+            "encrypted {sealed} items, rewrapped {rewrapped} under key {}",
+            keys.current_id()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a sealed file of `data` in the temp dir; the caller removes it
+    fn sealed_file(key: &ItemKey, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("clip-relay-sealed-{}", Uuid::new_v4()));
+        write(&path, Some(key), data).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn files_round_trip_whole_and_in_ranges() {
+        use futures_util::StreamExt;
+        let key = ItemKey::new(random::<32>().into());
+        for len in [0, 1, CHUNK, CHUNK + 1, 3 * CHUNK + 17] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let path = sealed_file(&key, &data);
+            let file_len = std::fs::metadata(&path).unwrap().len();
+            assert_eq!(plain_len(file_len), len as u64);
+            assert_eq!(read(&path, Some(&key)).unwrap(), data, "len {len}");
+            let (start, n) = (
+                len.saturating_sub(CHUNK + 3) as u64,
+                len.min(CHUNK + 2) as u64,
+            );
+            let part: Vec<u8> = stream(path.clone(), key.clone(), start, n)
+                .map(|b| b.unwrap().to_vec())
+                .concat()
+                .await;
+            assert_eq!(
+                part,
+                &data[start as usize..(start + n) as usize],
+                "len {len}"
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn tampering_and_truncation_are_detected() {
+        let key = ItemKey::new(random::<32>().into());
+        let path = sealed_file(&key, &vec![7u8; 2 * CHUNK + 5]);
+        let mut bytes = std::fs::read(&path).unwrap();
+        // drop the last chunk: the one before it wasn't sealed as the last
+        std::fs::write(&path, &bytes[..(HEADER_LEN + 2 * SEALED_CHUNK) as usize]).unwrap();
+        assert!(read(&path, Some(&key)).is_err());
+        bytes[HEADER_LEN as usize + 3] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(read(&path, Some(&key)).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn data_keys_unwrap_only_for_their_item() {
+        let old = Keyring::parse(&B64.encode([1u8; 32])).unwrap();
+        let both = Keyring::parse(&format!(
+            "{},{}",
+            B64.encode([2u8; 32]),
+            B64.encode([1u8; 32])
+        ))
+        .unwrap();
+        let (key, key_id, wrapped) = old.new_key("a");
+        let sealed = key.seal_text("secret");
+        let opened = both.unwrap(&key_id, &wrapped, "a").unwrap();
+        assert_eq!(opened.open_text(&sealed).unwrap(), "secret");
+        assert!(matches!(
+            both.unwrap(&key_id, &wrapped, "b"),
+            Err(Error::Corrupt)
+        ));
+        // rotated: wrapped by the new first key, which the old keyring doesn't have
+        let (new_id, rewrapped) = both.wrap(&opened, "a");
+        assert!(matches!(
+            old.unwrap(&new_id, &rewrapped, "a"),
+            Err(Error::NoKey(_))
+        ));
+    }
+}
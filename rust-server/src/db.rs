@@ -0,0 +1,336 @@
+use std::env;
+use std::ops::{Deref, DerefMut};
+use std::path::Path as StdPath;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusqlite::{Connection, OpenFlags};
+
+use crate::pg::{self, PgConn};
+use crate::store::{Conn, Dialect};
+use crate::{migrations, search};
+
+// -------------------- Connection pool --------------------
+//
+// DATABASE_URL picks the backend: unset (or sqlite:<path>) is data/custom.db, a
+// postgres:// URL a PostgreSQL database. Either way Db holds one writer connection behind
+// a mutex and DB_READERS (default 4) reader connections handed out from a small pool, so
+// writes stay serialized as they are on SQLite. custom.db runs in WAL mode so readers
+// never wait on the writer. Async handlers go through `read` / `write`, which run the
+// closure on tokio's blocking pool; code that is already on a blocking thread (archive
+// streaming, background jobs, the CLI) can take `reader()` / `writer()` directly.
+
+const DEFAULT_READERS: usize = 4;
+// How long a connection waits on SQLite's lock before giving up with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct Db {
+    dialect: Dialect,
+    writer: Mutex<Box<dyn Conn + Send>>,
+    readers: Mutex<Vec<Box<dyn Conn + Send>>>,
+    returned: Condvar,
+}
+
+/// Where DATABASE_URL points.
+pub(crate) enum Target {
+    Sqlite(std::path::PathBuf),
+    Postgres(Box<postgres::Config>),
+}
+
+impl Target {
+    pub(crate) fn from_env(data_dir: &StdPath) -> anyhow::Result<Self> {
+        let url = env::var("DATABASE_URL").unwrap_or_default();
+        let url = url.trim();
+        if url.is_empty() {
+            return Ok(Self::Sqlite(data_dir.join("custom.db")));
+        }
+        if let Some(path) = url.strip_prefix("sqlite:") {
+            return Ok(Self::Sqlite(path.trim_start_matches("//").into()));
+        }
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            return Ok(Self::Postgres(Box::new(url.parse()?)));
+        }
+        anyhow::bail!("DATABASE_URL must be a postgres:// URL or sqlite:<path>")
+    }
+}
+
+/// A pooled reader connection; goes back to the pool when dropped.
+pub(crate) struct Reader<'a> {
+    db: &'a Db,
+    conn: Option<Box<dyn Conn + Send>>,
+}
+
+impl Deref for Reader<'_> {
+    type Target = dyn Conn;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            lock(&self.db.readers).push(conn);
+            self.db.returned.notify_one();
+        }
+    }
+}
+
+/// The writer connection, held until dropped.
+pub(crate) struct Writer<'a>(MutexGuard<'a, Box<dyn Conn + Send>>);
+
+impl Deref for Writer<'_> {
+    type Target = dyn Conn;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.0
+    }
+}
+
+impl DerefMut for Writer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut **self.0
+    }
+}
+
+// A panic while a connection is held must not take every later request down with it
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Settings every SQLite connection needs, reader or writer.
+pub(crate) fn configure(conn: &Connection) -> rusqlite::Result<()> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    search::register_functions(conn)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")
+}
+
+/// Open and migrate a SQLite database file.
+pub(crate) fn open_sqlite(path: &StdPath) -> anyhow::Result<Connection> {
+    let conn = Connection::open(path)?;
+    configure(&conn)?;
+    migrations::run(&conn)?;
+    Ok(conn)
+}
+
+fn reader_count() -> usize {
+    env::var("DB_READERS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_READERS)
+}
+
+impl Db {
+    /// Open (and migrate) the database DATABASE_URL points at. Blocks; call it off the
+    /// async runtime.
+    pub(crate) fn open(target: &Target) -> anyhow::Result<Self> {
+        match target {
+            Target::Sqlite(path) => Self::sqlite(open_sqlite(path)?, path),
+            Target::Postgres(config) => Self::postgres(config),
+        }
+    }
+
+    /// Wrap an already migrated SQLite writer connection and open the reader pool next
+    /// to it.
+    pub(crate) fn sqlite(writer: Connection, path: &StdPath) -> anyhow::Result<Self> {
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        // WAL keeps commits durable across crashes with NORMAL; only power loss can drop
+        // the last transactions
+        writer.pragma_update(None, "synchronous", "NORMAL")?;
+        let n = reader_count();
+        let mut readers: Vec<Box<dyn Conn + Send>> = Vec::with_capacity(n);
+        for _ in 0..n {
+            let conn = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            configure(&conn)?;
+            readers.push(Box::new(conn));
+        }
+        tracing::info!(readers = n, "database opened in WAL mode");
+        Ok(Self::new(Dialect::Sqlite, Box::new(writer), readers))
+    }
+
+    fn postgres(config: &postgres::Config) -> anyhow::Result<Self> {
+        pg::migrate(config)?;
+        let n = reader_count();
+        let readers = (0..n)
+            .map(|_| Ok(Box::new(PgConn::connect(config)?) as Box<dyn Conn + Send>))
+            .collect::<anyhow::Result<_>>()?;
+        tracing::info!(readers = n, "connected to PostgreSQL");
+        Ok(Self::new(
+            Dialect::Postgres,
+            Box::new(PgConn::connect(config)?),
+            readers,
+        ))
+    }
+
+    fn new(
+        dialect: Dialect,
+        writer: Box<dyn Conn + Send>,
+        readers: Vec<Box<dyn Conn + Send>>,
+    ) -> Self {
+        Self {
+            dialect,
+            writer: Mutex::new(writer),
+            readers: Mutex::new(readers),
+            returned: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// The single writer connection. Blocks until it is free.
+    pub(crate) fn writer(&self) -> Writer<'_> {
+        Writer(lock(&self.writer))
+    }
+
+    /// A reader connection from the pool. Blocks until one is free.
+    pub(crate) fn reader(&self) -> Reader<'_> {
+        let mut pool = lock(&self.readers);
+        loop {
+            if let Some(conn) = pool.pop() {
+                return Reader {
+                    db: self,
+                    conn: Some(conn),
+                };
+            }
+            pool = self.returned.wait(pool).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Run `f` with a reader on the blocking pool.
+    pub(crate) async fn read<T, F>(self: &Arc<Self>, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Conn) -> T + Send + 'static,
+    {
+        let db = self.clone();
+        run(move || f(&*db.reader())).await
+    }
+
+    /// Run `f` with the writer on the blocking pool.
+    pub(crate) async fn write<T, F>(self: &Arc<Self>, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn Conn) -> T + Send + 'static,
+    {
+        let db = self.clone();
+        run(move || f(&mut *db.writer())).await
+    }
+}
+
+async fn run<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(v) => v,
+        // surface the closure's panic in the calling task, as if it had run inline
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    // a fresh database with a counter table; the caller removes the directory
+    fn scratch_db() -> (Arc<Db>, std::path::PathBuf) {
+        let dir = env::temp_dir().join(format!("clip-relay-db-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::open(&Target::Sqlite(dir.join("custom.db"))).unwrap();
+        db.writer()
+            .exec_batch(
+                "CREATE TABLE counter (n INTEGER NOT NULL); INSERT INTO counter VALUES (0);",
+            )
+            .unwrap();
+        (Arc::new(db), dir)
+    }
+
+    fn count(conn: &dyn Conn) -> i64 {
+        conn.query_row("SELECT n FROM counter", [], |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn readers_are_not_blocked_by_an_open_write() {
+        let (db, dir) = scratch_db();
+        let writer = db.writer();
+        let tx = writer.transaction().unwrap();
+        tx.execute("UPDATE counter SET n = 1", []).unwrap();
+        // another thread reads the last committed state without waiting
+        let seen = std::thread::scope(|s| s.spawn(|| count(&*db.reader())).join().unwrap());
+        assert_eq!(seen, 0);
+        tx.commit().unwrap();
+        drop(writer);
+        assert_eq!(count(&*db.reader()), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn readers_wait_for_a_free_connection() {
+        let (db, dir) = scratch_db();
+        let mut held: Vec<Reader> = (0..reader_count()).map(|_| db.reader()).collect();
+        let (tx, rx) = mpsc::channel();
+        std::thread::scope(|s| {
+            s.spawn(|| tx.send(count(&*db.reader())).unwrap());
+            assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+            held.pop();
+            assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(0));
+        });
+        drop(held);
+        assert_eq!(lock(&db.readers).len(), reader_count());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writes_are_serialized() {
+        let (db, dir) = scratch_db();
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    // read-modify-write: lost updates would show if two ran at once
+                    db.write(|conn| {
+                        let n = count(conn);
+                        std::thread::sleep(Duration::from_millis(1));
+                        conn.execute("UPDATE counter SET n = ?", [n + 1]).unwrap();
+                    })
+                    .await;
+                    db.read(count).await
+                })
+            })
+            .collect();
+        for t in tasks {
+            assert!(t.await.unwrap() >= 1);
+        }
+        assert_eq!(db.read(count).await, 50);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_panicking_closure_leaves_the_pool_usable() {
+        let (db, dir) = scratch_db();
+        for _ in 0..reader_count() + 1 {
+            let (r, w) = (db.clone(), db.clone());
+            let read = tokio::spawn(async move { r.read(|_| panic!("boom")).await });
+            let write = tokio::spawn(async move { w.write(|_| panic!("boom")).await });
+            assert!(read.await.unwrap_err().is_panic());
+            assert!(write.await.unwrap_err().is_panic());
+        }
+        assert_eq!(lock(&db.readers).len(), reader_count());
+        db.write(|conn| conn.execute("UPDATE counter SET n = 7", []).unwrap())
+            .await;
+        assert_eq!(db.read(count).await, 7);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use base64::engine::general_purpose::STANDARD as B64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+// -------------------- End-to-end encrypted items --------------------
+//
+// The client encrypts before uploading and sends the ciphertext as the item's one `file`
+// field, with an `e2ee` field describing how to decrypt it: algorithm, nonce and the data
+// key wrapped by a key the server never sees. The metadata is kept verbatim in
+// ClipboardItem.e2ee and handed back with the item and on its share page; for a share link
+// the client puts the key in the URL fragment (/s/?token=...#key=...), which browsers don't
+// send. The server treats the ciphertext as an opaque file: no type sniffing, thumbnails,
+// text extraction, metadata stripping or edits, and sizes and quotas count ciphertext bytes.
+
+const ALGORITHMS: &[(&str, usize)] = &[("AES-256-GCM", 12), ("XChaCha20-Poly1305", 24)];
+
+// upper bound on a wrapped key, generous for any key wrapping scheme
+const MAX_WRAPPED_KEY: usize = 1024;
+
+/// Content type stored for every end-to-end encrypted item.
+pub(crate) const CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Meta {
+    alg: String,
+    /// base64
+    nonce: String,
+    /// base64; absent when the key only ever travels in share links
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wrapped_key: Option<String>,
+}
+
+/// Check the `e2ee` field of an upload and return it normalised for storage.
+pub(crate) fn parse(raw: &str) -> Result<String, String> {
+    let meta: Meta = serde_json::from_str(raw).map_err(|e| format!("invalid e2ee: {e}"))?;
+    let Some(&(_, nonce_len)) = ALGORITHMS.iter().find(|(a, _)| *a == meta.alg) else {
+        let known: Vec<&str> = ALGORITHMS.iter().map(|(a, _)| *a).collect();
+        return Err(format!("e2ee.alg must be one of {}", known.join(", ")));
+    };
+    match B64.decode(&meta.nonce) {
+        Ok(n) if n.len() == nonce_len => {}
+        _ => return Err(format!("e2ee.nonce must be {nonce_len} bytes of base64")),
+    }
+    if let Some(k) = &meta.wrapped_key {
+        if !B64
+            .decode(k)
+            .is_ok_and(|k| !k.is_empty() && k.len() <= MAX_WRAPPED_KEY)
+        {
+            return Err("e2ee.wrappedKey must be base64, at most 1024 bytes".into());
+        }
+    }
+    Ok(serde_json::to_string(&meta).unwrap())
+}
+
+/// The stored metadata as it goes into item and share responses.
+pub(crate) fn json(stored: Option<String>) -> Option<serde_json::Value> {
+    stored.and_then(|s| serde_json::from_str(&s).ok())
+}
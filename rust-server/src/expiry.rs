@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rusqlite::params;
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::store::{self, Conn};
+use crate::{epoch_to_iso, limits, listing, now_unix, trash, AppState, ServerEvent};
+
+// -------------------- Item expiry --------------------
+//
+// An item can carry its own expiresAt, independent of any share link expiry. A background
+// task sleeps until the earliest expiresAt and then deletes the item for good (no trash),
+// announcing it with clipboard:expired. Setting or changing an expiry wakes the task so it
+// re-plans; it also re-checks every MAX_SLEEP in case the clock jumped.
+
+const MAX_SLEEP: Duration = Duration::from_secs(3600);
+
+/// Resolve an expiry from `expiresIn` (duration like `1h`) or `expiresAt` (unix seconds or
+/// RFC 3339). Empty / `0` / `never` mean no expiry.
+pub(crate) fn parse(
+    expires_in: Option<&str>,
+    expires_at: Option<&str>,
+    now: i64,
+) -> Result<Option<i64>, String> {
+    let off = |v: &str| matches!(v.trim(), "" | "0" | "never" | "off");
+    let ts = if let Some(v) = expires_in.filter(|v| !off(v)) {
+        let secs = limits::parse_duration(v).ok_or_else(|| format!("invalid expiresIn: {v}"))?;
+        now.checked_add(secs)
+            .ok_or_else(|| format!("expiresIn is too far off: {v}"))?
+    } else if let Some(v) = expires_at.filter(|v| !off(v)) {
+        let ts = listing::parse_time(v, false)?;
+        if ts <= now {
+            return Err("expiresAt must be in the future".into());
+        }
+        ts
+    } else {
+        return Ok(None);
+    };
+    // has to be a date we can show
+    if OffsetDateTime::from_unix_timestamp(ts).is_err() {
+        return Err("expiry is too far off".into());
+    }
+    Ok(Some(ts))
+}
+
+/// Set or clear an item's expiry. Returns false when the item doesn't exist or is trashed.
+pub(crate) fn set(conn: &dyn Conn, id: &str, expires_at: Option<i64>) -> bool {
+    conn.execute(
+        "UPDATE ClipboardItem SET expiresAt=? WHERE id=? AND deletedAt IS NULL",
+        params![expires_at, id],
+    )
+    .unwrap_or(0)
+        > 0
+}
+
+// Delete every item whose expiry has passed, trashed or not. Returns the removed ids.
+async fn burn_due(state: &AppState, now: i64) -> Vec<String> {
+    let removed = state
+        .db
+        .write(move |conn| {
+            let tx = conn.transaction()?;
+            let due: Vec<(String, Option<String>)> = {
+                let mut stmt = tx.prepare(
+                    "SELECT id, filePath FROM ClipboardItem WHERE expiresAt IS NOT NULL AND expiresAt <= ?",
+                )?;
+                stmt.query_map([now], |r| Ok((r.get(0)?, r.get(1)?)))?
+                    .collect::<store::Result<_>>()?
+            };
+            for (id, _) in &due {
+                tx.execute("DELETE FROM ClipboardItem WHERE id=?", [id])?;
+            }
+            tx.commit()?;
+            Ok::<_, store::Error>(due)
+        })
+        .await;
+    let removed = removed.unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "deleting expired items failed");
+        vec![]
+    });
+    for (id, fp) in &removed {
+        trash::remove_files(&state.data_dir, id, fp.as_deref());
+        let _ = state.tx.send(ServerEvent {
+            name: "clipboard:expired".into(),
+            data: serde_json::json!({ "id": id }),
+        });
+    }
+    removed.into_iter().map(|(id, _)| id).collect()
+}
+
+fn next_due(conn: &dyn Conn) -> Option<i64> {
+    conn.query_row("SELECT MIN(expiresAt) FROM ClipboardItem", [], |r| r.get(0))
+        .ok()
+        .flatten()
+}
+
+/// Background task deleting items as their expiresAt passes.
+pub(crate) async fn run_task(state: AppState) {
+    loop {
+        let now = now_unix();
+        let burned = burn_due(&state, now).await;
+        if !burned.is_empty() {
+            tracing::info!(count = burned.len(), "deleted expired items");
+        }
+        let next = state.db.read(next_due).await;
+        let sleep = next
+            .map(|ts| Duration::from_secs((ts - now).max(1) as u64))
+            .unwrap_or(MAX_SLEEP)
+            .min(MAX_SLEEP);
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = state.expiry_wake.notified() => {}
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ExpiryRequest {
+    expires_in: Option<String>,
+    expires_at: Option<String>,
+}
+
+// PUT /api/clipboard/:id/expiry — {expiresIn} or {expiresAt}; both missing/null clears it
+pub(crate) async fn update_expiry(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<ExpiryRequest>,
+) -> Response {
+    let expires_at = match parse(
+        req.expires_in.as_deref(),
+        req.expires_at.as_deref(),
+        now_unix(),
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e})),
+            )
+                .into_response()
+        }
+    };
+    let updated = state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| set(conn, &id, expires_at)
+        })
+        .await;
+    if !updated {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"Not found"})),
+        )
+            .into_response();
+    }
+    state.expiry_wake.notify_one();
+    let data = serde_json::json!({ "id": id, "expiresAt": expires_at.map(epoch_to_iso) });
+    let _ = state.tx.send(ServerEvent {
+        name: "clipboard:expiry".into(),
+        data: data.clone(),
+    });
+    Json(data).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn expiries_parse_from_durations_or_times() {
+        assert_eq!(parse(Some("1h"), None, NOW), Ok(Some(NOW + 3600)));
+        // expiresIn wins over expiresAt
+        assert_eq!(parse(Some("30m"), Some("1"), NOW), Ok(Some(NOW + 1800)));
+        assert_eq!(parse(None, Some("1700003600"), NOW), Ok(Some(NOW + 3600)));
+        assert_eq!(
+            parse(None, Some("2023-11-15T00:00:00Z"), NOW),
+            Ok(Some(1_700_006_400))
+        );
+        for off in ["", "0", "never", "off"] {
+            assert_eq!(parse(Some(off), Some(off), NOW), Ok(None), "{off}");
+        }
+        assert_eq!(parse(None, None, NOW), Ok(None));
+    }
+
+    #[test]
+    fn bad_or_out_of_range_expiries_are_rejected() {
+        assert!(parse(Some("soon"), None, NOW).is_err());
+        assert!(parse(None, Some("yesterday"), NOW).is_err());
+        assert!(parse(None, Some("1600000000"), NOW).is_err());
+        assert!(parse(None, Some(&NOW.to_string()), NOW).is_err());
+        // would overflow, or lands past what a timestamp can show
+        assert!(parse(Some("9223372036854775807"), None, NOW).is_err());
+        assert!(parse(Some("9223372036854775807"), None, 1).is_err());
+        assert!(parse(Some("100000000w"), None, NOW).is_err());
+        assert!(parse(None, Some("9223372036854775807"), NOW).is_err());
+    }
+}
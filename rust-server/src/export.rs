@@ -0,0 +1,575 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path as StdPath, PathBuf};
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures_util::StreamExt;
+use rusqlite::backup::Progress;
+use rusqlite::{Connection, DatabaseName};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::crypt::Keyring;
+use crate::store::Dialect;
+use crate::{
+    archive, db, epoch_to_iso, migrations, now_unix, queue_pending_extractions, scrub, search,
+    thumbs, AppState, ServerEvent,
+};
+
+// -------------------- Export / import --------------------
+//
+// GET /api/admin/export streams a tar.gz holding manifest.json, custom.db and uploads/.
+// The database is copied with SQLite's online backup API and the upload files are hard
+// linked next to it under the same lock, so the archive is a consistent snapshot even
+// while the server keeps writing. POST /api/admin/import restores such an archive, merged
+// into the current data (items already present are left alone) or replacing it.
+// `clip-relay export` and `clip-relay import` do the same without a running server.
+
+const FORMAT: &str = "clip-relay-export";
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Manifest {
+    format: String,
+    version: u32,
+    created_at: String,
+    items: i64,
+    files: usize,
+    bytes: u64,
+}
+
+/// A scratch directory under the data dir (same filesystem, so renames are cheap),
+/// removed with everything in it when dropped.
+pub(crate) struct Staging(pub(crate) PathBuf);
+
+impl Staging {
+    pub(crate) fn new(data_dir: &StdPath, kind: &str) -> io::Result<Self> {
+        let dir = data_dir.join(format!(".{kind}-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+}
+
+/// Remove staging directories left behind by a crash.
+pub(crate) fn clean_stale(data_dir: &StdPath) {
+    for entry in fs::read_dir(data_dir).into_iter().flatten().flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if [".export-", ".import-", ".backup-"]
+            .iter()
+            .any(|p| name.starts_with(p))
+        {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A consistent copy of the database and uploads, ready to be archived.
+pub(crate) struct Snapshot {
+    staging: Staging,
+    manifest: Manifest,
+    // upload paths relative to uploads/, with sizes
+    files: Vec<(PathBuf, u64)>,
+}
+
+fn db_err(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Files under `root`/`rel`, as paths relative to `root` with their sizes.
+pub(crate) fn walk(root: &StdPath, rel: &StdPath, out: &mut Vec<(PathBuf, u64)>) -> io::Result<()> {
+    let Ok(dir) = fs::read_dir(root.join(rel)) else {
+        return Ok(());
+    };
+    for entry in dir.flatten() {
+        let path = rel.join(entry.file_name());
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            walk(root, &path, out)?;
+        } else if meta.is_file() {
+            out.push((path, meta.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Hard link (or copy, across filesystems) `files` from `src` into `dst`.
+pub(crate) fn link_files(src: &StdPath, dst: &StdPath, files: &[(PathBuf, u64)]) -> io::Result<()> {
+    for (rel, _) in files {
+        let (from, to) = (src.join(rel), dst.join(rel));
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::hard_link(&from, &to).is_err() {
+            fs::copy(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the live database with the online backup API and link the uploads beside it.
+/// `conn` must be the guarded server connection so no write lands in between.
+pub(crate) fn snapshot(conn: &Connection, data_dir: &StdPath) -> io::Result<Snapshot> {
+    let staging = Staging::new(data_dir, "export")?;
+    conn.backup(DatabaseName::Main, staging.0.join("custom.db"), None)
+        .map_err(db_err)?;
+    let items: i64 = conn
+        .query_row("SELECT COUNT(*) FROM ClipboardItem", [], |r| r.get(0))
+        .map_err(db_err)?;
+    let uploads = data_dir.join("uploads");
+    let mut files = vec![];
+    walk(&uploads, StdPath::new(""), &mut files)?;
+    link_files(&uploads, &staging.0.join("uploads"), &files)?;
+    let bytes = files.iter().map(|(_, n)| n).sum();
+    Ok(Snapshot {
+        staging,
+        manifest: Manifest {
+            format: FORMAT.into(),
+            version: VERSION,
+            created_at: epoch_to_iso(now_unix()),
+            items,
+            files: files.len(),
+            bytes,
+        },
+        files,
+    })
+}
+
+/// Write a snapshot as tar.gz: manifest.json first, then custom.db and uploads/.
+pub(crate) fn write_archive<W: Write>(snap: &Snapshot, out: W) -> io::Result<W> {
+    let mut tw = tar::Builder::new(GzEncoder::new(out, Compression::fast()));
+    let manifest = serde_json::to_vec_pretty(&snap.manifest)?;
+    let mut h = tar::Header::new_gnu();
+    h.set_size(manifest.len() as u64);
+    h.set_mode(0o644);
+    h.set_mtime(now_unix().max(0) as u64);
+    h.set_entry_type(tar::EntryType::Regular);
+    tw.append_data(&mut h, "manifest.json", &manifest[..])?;
+    tw.append_path_with_name(snap.staging.0.join("custom.db"), "custom.db")?;
+    let uploads = snap.staging.0.join("uploads");
+    for (rel, _) in &snap.files {
+        tw.append_path_with_name(uploads.join(rel), StdPath::new("uploads").join(rel))?;
+    }
+    tw.into_inner()?.finish()
+}
+
+fn export_name(now: i64) -> String {
+    format!(
+        "clip-relay-export-{}.tar.gz",
+        epoch_to_iso(now).replace(':', "-")
+    )
+}
+
+/// Export and import move a SQLite file; a PostgreSQL database is dumped with its own tools.
+fn sqlite_only(state: &AppState) -> Option<Response> {
+    (state.db.dialect() != Dialect::Sqlite).then(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(serde_json::json!({"error":"export and import need the SQLite backend; use pg_dump / pg_restore"})),
+        )
+            .into_response()
+    })
+}
+
+// GET /api/admin/export
+pub(crate) async fn export(State(state): State<AppState>) -> Response {
+    if let Some(r) = sqlite_only(&state) {
+        return r;
+    }
+    let db = state.db.clone();
+    let data_dir = state.data_dir.clone();
+    let snap = tokio::task::spawn_blocking(move || {
+        let conn = db.writer();
+        let conn = conn
+            .as_sqlite()
+            .ok_or_else(|| io::Error::other("not a SQLite database"))?;
+        snapshot(conn, &data_dir)
+    })
+    .await;
+    let snap = match snap {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "export: snapshot failed");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error":"snapshot failed","detail": e.to_string()})),
+            )
+                .into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    // the snapshot (and its staging dir) goes away once the archive is written
+    archive::stream_blocking("application/gzip", &export_name(now_unix()), move |out| {
+        write_archive(&snap, out)?.flush()
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Mode {
+    Merge,
+    Replace,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportReport {
+    mode: Mode,
+    // items added; on replace, all items of the archive
+    imported: i64,
+    // items of the archive left out because an item with the same id exists (merge only)
+    skipped: i64,
+    files: usize,
+}
+
+/// Unpack an export archive into `staging` and check its manifest and database. The
+/// database is migrated to the current schema so older exports can be applied. Archives
+/// with items sealed under a master key missing from `keys` are refused, as those items
+/// could never be opened here.
+fn unpack(
+    staging: &Staging,
+    reader: impl Read,
+    keys: Option<&Keyring>,
+) -> Result<Manifest, String> {
+    let root = staging.0.join("data");
+    // unpack() refuses entries that would land outside `root`
+    tar::Archive::new(GzDecoder::new(reader))
+        .unpack(&root)
+        .map_err(|e| format!("invalid archive: {e}"))?;
+    let manifest: Manifest = fs::read(root.join("manifest.json"))
+        .ok()
+        .and_then(|b| serde_json::from_slice(&b).ok())
+        .ok_or("invalid archive: missing manifest.json")?;
+    if manifest.format != FORMAT {
+        return Err("invalid archive: not a clip-relay export".into());
+    }
+    if manifest.version > VERSION {
+        return Err(format!(
+            "unsupported export version {} (this server reads up to {VERSION})",
+            manifest.version
+        ));
+    }
+    let db_path = root.join("custom.db");
+    if !db_path.is_file() {
+        return Err("invalid archive: missing custom.db".into());
+    }
+    let conn = Connection::open(&db_path).map_err(|e| e.to_string())?;
+    let check: String = conn
+        .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+        .map_err(|e| e.to_string())?;
+    if check != "ok" {
+        return Err(format!("database in archive is corrupt: {check}"));
+    }
+    search::register_functions(&conn).map_err(|e| e.to_string())?;
+    migrations::run(&conn).map_err(|e| e.to_string())?;
+    let missing = missing_keys(&conn, keys).map_err(|e| e.to_string())?;
+    if !missing.is_empty() {
+        return Err(format!(
+            "archive has items encrypted with keys that aren't configured: {}",
+            missing.join(", ")
+        ));
+    }
+    fs::create_dir_all(root.join("uploads")).map_err(|e| e.to_string())?;
+    Ok(manifest)
+}
+
+// keyIds of sealed items in `conn` that `keys` can't unwrap.
+fn missing_keys(conn: &Connection, keys: Option<&Keyring>) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT COALESCE(keyId, '') FROM ClipboardItem WHERE dataKey IS NOT NULL ORDER BY 1",
+    )?;
+    let ids = stmt.query_map([], |r| r.get::<_, String>(0))?;
+    let mut missing = vec![];
+    for id in ids {
+        let id = id?;
+        if !keys.is_some_and(|k| k.knows(&id)) {
+            missing.push(id);
+        }
+    }
+    Ok(missing)
+}
+
+fn column_list(conn: &Connection, table: &str) -> rusqlite::Result<String> {
+    let mut stmt = conn.prepare(&format!("PRAGMA main.table_info({table})"))?;
+    let cols: Vec<String> = stmt
+        .query_map([], |r| r.get::<_, String>(1))?
+        .flatten()
+        .map(|c| format!("\"{c}\""))
+        .collect();
+    Ok(cols.join(", "))
+}
+
+// Rows of the attached `import` database belonging to items that weren't here before.
+fn copy_rows(conn: &Connection, table: &str, filter: &str) -> rusqlite::Result<usize> {
+    let cols = column_list(conn, table)?;
+    conn.execute(
+        &format!("INSERT OR IGNORE INTO main.{table} ({cols}) SELECT {cols} FROM import.{table} WHERE {filter} IN (SELECT id FROM temp.import_new)"),
+        [],
+    )
+}
+
+fn merge_db(conn: &Connection) -> rusqlite::Result<(i64, i64)> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(
+        r"
+        DROP TABLE IF EXISTS temp.import_new;
+        CREATE TEMP TABLE import_new AS
+          SELECT id FROM import.ClipboardItem WHERE id NOT IN (SELECT id FROM main.ClipboardItem);
+        ",
+    )?;
+    let imported = copy_rows(&tx, "ClipboardItem", "id")? as i64;
+    let total: i64 = tx.query_row("SELECT COUNT(*) FROM import.ClipboardItem", [], |r| {
+        r.get(0)
+    })?;
+    for table in ["ShareLink", "ItemRevision", "ItemFile", "Blob"] {
+        copy_rows(&tx, table, "itemId")?;
+    }
+    // tags and collections are matched by name, so an existing "work" tag is reused
+    tx.execute_batch(
+        r"
+        INSERT OR IGNORE INTO main.Tag (id, name, color, createdAt, updatedAt)
+          SELECT id, name, color, createdAt, updatedAt FROM import.Tag;
+        INSERT OR IGNORE INTO main.ItemTag (itemId, tagId, createdAt)
+          SELECT it.itemId, t.id, it.createdAt FROM import.ItemTag it
+          JOIN import.Tag s ON s.id = it.tagId JOIN main.Tag t ON t.name = s.name
+          WHERE it.itemId IN (SELECT id FROM temp.import_new);
+        INSERT OR IGNORE INTO main.Collection (id, name, description, createdAt, updatedAt)
+          SELECT id, name, description, createdAt, updatedAt FROM import.Collection;
+        INSERT OR IGNORE INTO main.CollectionItem (collectionId, itemId, addedAt)
+          SELECT c.id, ci.itemId, ci.addedAt FROM import.CollectionItem ci
+          JOIN import.Collection s ON s.id = ci.collectionId JOIN main.Collection c ON c.name = s.name
+          WHERE ci.itemId IN (SELECT id FROM temp.import_new);
+        DROP TABLE temp.import_new;
+        ",
+    )?;
+    tx.commit()?;
+    Ok((imported, total - imported))
+}
+
+// Move files of the unpacked uploads/ that don't exist yet into place.
+fn merge_uploads(src: &StdPath, dst: &StdPath) -> io::Result<usize> {
+    let mut files = vec![];
+    walk(src, StdPath::new(""), &mut files)?;
+    let mut moved = 0;
+    for (rel, _) in files {
+        let to = dst.join(&rel);
+        if to.exists() {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(src.join(&rel), &to)?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Apply an unpacked export (`staging`/data/{custom.db,uploads}) to the live data. `conn`
+/// is the guarded server connection.
+pub(crate) fn apply(
+    conn: &mut Connection,
+    data_dir: &StdPath,
+    staging: &Staging,
+    mode: Mode,
+) -> Result<ImportReport, String> {
+    let root = staging.0.join("data");
+    let db_path = root.join("custom.db");
+    let err = |e: &dyn std::fmt::Display| e.to_string();
+    match mode {
+        Mode::Merge => {
+            // files first: if the database part fails, unused files are harmless
+            let files = merge_uploads(&root.join("uploads"), &data_dir.join("uploads"))
+                .map_err(|e| err(&e))?;
+            conn.execute("ATTACH DATABASE ? AS import", [db_path.to_string_lossy()])
+                .map_err(|e| err(&e))?;
+            let merged = merge_db(conn);
+            let _ = conn.execute("DETACH DATABASE import", []);
+            let (imported, skipped) = merged.map_err(|e| err(&e))?;
+            Ok(ImportReport {
+                mode,
+                imported,
+                skipped,
+                files,
+            })
+        }
+        Mode::Replace => {
+            conn.restore(DatabaseName::Main, &db_path, None::<fn(Progress)>)
+                .map_err(|e| err(&e))?;
+            migrations::run(conn).map_err(|e| err(&e))?;
+            let uploads = data_dir.join("uploads");
+            // the old uploads end up in the staging dir and are removed with it
+            fs::rename(&uploads, staging.0.join("uploads.old")).map_err(|e| err(&e))?;
+            fs::rename(root.join("uploads"), &uploads).map_err(|e| err(&e))?;
+            let _ = fs::remove_dir_all(thumbs::thumbs_dir(data_dir));
+            let _ = fs::remove_dir_all(scrub::scrubbed_dir(data_dir));
+            let mut files = vec![];
+            walk(&uploads, StdPath::new(""), &mut files).map_err(|e| err(&e))?;
+            let imported = conn
+                .query_row("SELECT COUNT(*) FROM ClipboardItem", [], |r| r.get(0))
+                .map_err(|e| err(&e))?;
+            Ok(ImportReport {
+                mode,
+                imported,
+                skipped: 0,
+                files: files.len(),
+            })
+        }
+    }
+}
+
+fn parse_mode(uri: &Uri) -> Option<Mode> {
+    let q = uri.query().unwrap_or("");
+    let mode = form_urlencoded::parse(q.as_bytes())
+        .find(|(k, _)| k == "mode")
+        .map(|(_, v)| v.into_owned());
+    match mode.as_deref() {
+        None | Some("merge") => Some(Mode::Merge),
+        Some("replace") => Some(Mode::Replace),
+        _ => None,
+    }
+}
+
+// POST /api/admin/import?mode=merge|replace — body: an archive from /api/admin/export
+pub(crate) async fn import(State(state): State<AppState>, uri: Uri, body: Body) -> Response {
+    if let Some(r) = sqlite_only(&state) {
+        return r;
+    }
+    let Some(mode) = parse_mode(&uri) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error":"mode must be merge or replace"})),
+        )
+            .into_response();
+    };
+    let staging = match Staging::new(&state.data_dir, "import") {
+        Ok(s) => s,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    // spool the upload to disk; archives can be far larger than memory
+    let upload = staging.0.join("upload.tar.gz");
+    let spooled = async {
+        let mut f = tokio::fs::File::create(&upload).await?;
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&chunk.map_err(io::Error::other)?).await?;
+        }
+        f.flush().await
+    };
+    if let Err(e) = spooled.await {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error":"upload failed","detail": e.to_string()})),
+        )
+            .into_response();
+    }
+    let st = state.clone();
+    let res = tokio::task::spawn_blocking(move || {
+        let file = fs::File::open(&upload)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        unpack(&staging, io::BufReader::new(file), st.keys.as_deref())
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        let mut conn = st.db.writer();
+        let conn = conn.as_sqlite_mut().ok_or((
+            StatusCode::NOT_IMPLEMENTED,
+            "not a SQLite database".to_string(),
+        ))?;
+        apply(conn, &st.data_dir, &staging, mode)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+    })
+    .await
+    .unwrap_or_else(|e| Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())));
+    let report = match res {
+        Ok(r) => r,
+        Err((code, e)) => {
+            tracing::warn!(error = %e, "import failed");
+            return (code, Json(serde_json::json!({"error": e}))).into_response();
+        }
+    };
+    tracing::info!(
+        imported = report.imported,
+        files = report.files,
+        "import done"
+    );
+    queue_pending_extractions(&state).await;
+    state.expiry_wake.notify_one();
+    let _ = state.tx.send(ServerEvent {
+        name: "clipboard:imported".into(),
+        data: serde_json::json!({ "mode": report.mode, "imported": report.imported }),
+    });
+    Json(report).into_response()
+}
+
+/// `clip-relay export [FILE|-]` and `clip-relay import FILE [--replace]`, run against the
+/// data directory without starting the server.
+pub(crate) fn run_cli(
+    args: &[String],
+    data_dir: &StdPath,
+    db_path: &StdPath,
+) -> anyhow::Result<()> {
+    let mut conn = db::open_sqlite(db_path)?;
+    match args.first().map(String::as_str) {
+        Some("export") => {
+            let snap = snapshot(&conn, data_dir)?;
+            match args.get(1).map(String::as_str) {
+                Some("-") => {
+                    write_archive(&snap, io::stdout().lock())?.flush()?;
+                }
+                target => {
+                    let name = target.map_or_else(|| export_name(now_unix()), str::to_string);
+                    let file = fs::File::create(&name)?;
+                    write_archive(&snap, io::BufWriter::new(file))?.flush()?;
+                    eprintln!(
+                        "exported {} items and {} files to {name}",
+                        snap.manifest.items, snap.manifest.files
+                    );
+                }
+            }
+            Ok(())
+        }
+        Some("import") => {
+            let Some(path) = args.get(1).filter(|a| !a.starts_with("--")) else {
+                anyhow::bail!("usage: clip-relay import FILE [--replace]");
+            };
+            let mode = if args.iter().any(|a| a == "--replace") {
+                Mode::Replace
+            } else {
+                Mode::Merge
+            };
+            let staging = Staging::new(data_dir, "import")?;
+            let reader: Box<dyn Read> = if path == "-" {
+                Box::new(io::stdin().lock())
+            } else {
+                Box::new(io::BufReader::new(fs::File::open(path)?))
+            };
+            let keys = Keyring::from_env()?;
+            unpack(&staging, reader, keys.as_ref()).map_err(anyhow::Error::msg)?;
+            let report = apply(&mut conn, data_dir, &staging, mode).map_err(anyhow::Error::msg)?;
+            eprintln!(
+                "imported {} items ({} skipped) and {} files",
+                report.imported, report.skipped, report.files
+            );
+            Ok(())
+        }
+        _ => anyhow::bail!("usage: clip-relay export [FILE|-] | import FILE [--replace] | restore [--list | NAME | PATH]"),
+    }
+}
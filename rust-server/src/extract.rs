@@ -0,0 +1,300 @@
+use std::io::Read;
+
+// -------------------- Document text extraction --------------------
+//
+// Uploaded files are turned into plain text for the search index by a background worker.
+// The outcome is kept on the item: extractStatus is pending, done, failed (extractError
+// says why) or skipped for formats we don't read.
+
+/// Larger files are skipped rather than loaded into memory.
+pub(crate) const MAX_SOURCE_BYTES: u64 = 64 * 1024 * 1024;
+/// Extracted text beyond this is cut off; it is only used for search.
+const MAX_TEXT_BYTES: usize = 2 * 1024 * 1024;
+const DOCX_XML_LIMIT: u64 = 32 * 1024 * 1024;
+
+const DOCX_TYPE: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Text,
+    Pdf,
+    Docx,
+}
+
+/// Which extractor applies to an upload, if any.
+pub(crate) fn kind(content_type: &str, file_name: Option<&str>) -> Option<Kind> {
+    let ct = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    if ct == "application/pdf" {
+        return Some(Kind::Pdf);
+    }
+    if ct == DOCX_TYPE {
+        return Some(Kind::Docx);
+    }
+    if ct.starts_with("text/")
+        || matches!(
+            ct.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/x-sh"
+                | "application/xml"
+                | "application/toml"
+                | "application/yaml"
+                | "application/x-yaml"
+                | "application/sql"
+        )
+    {
+        return Some(Kind::Text);
+    }
+    // Source files the sniffer could only call octet-stream (e.g. unusual encodings)
+    let ext = file_name
+        .and_then(|n| std::path::Path::new(n).extension())
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)?;
+    matches!(
+        ext.as_str(),
+        "txt"
+            | "md"
+            | "markdown"
+            | "rst"
+            | "log"
+            | "csv"
+            | "tsv"
+            | "json"
+            | "yaml"
+            | "yml"
+            | "toml"
+            | "ini"
+            | "rs"
+            | "py"
+            | "js"
+            | "ts"
+            | "tsx"
+            | "jsx"
+            | "go"
+            | "java"
+            | "kt"
+            | "c"
+            | "h"
+            | "cpp"
+            | "hpp"
+            | "cs"
+            | "rb"
+            | "php"
+            | "swift"
+            | "sh"
+            | "sql"
+            | "lua"
+    )
+    .then_some(Kind::Text)
+}
+
+/// Extract searchable text. Blocking; run on a blocking thread.
+pub(crate) fn extract(kind: Kind, data: &[u8]) -> Result<String, String> {
+    let text = match kind {
+        Kind::Text => {
+            if data.contains(&0) {
+                return Err("binary content".into());
+            }
+            String::from_utf8_lossy(data).into_owned()
+        }
+        Kind::Pdf => pdf_extract::extract_text_from_mem(data).map_err(|e| format!("pdf: {e}"))?,
+        Kind::Docx => docx_text(data)?,
+    };
+    Ok(truncate(normalize(&text)))
+}
+
+fn docx_text(data: &[u8]) -> Result<String, String> {
+    let mut zip =
+        zip::ZipArchive::new(std::io::Cursor::new(data)).map_err(|e| format!("docx: {e}"))?;
+    let mut xml = String::new();
+    zip.by_name("word/document.xml")
+        .map_err(|e| format!("docx: {e}"))?
+        .take(DOCX_XML_LIMIT)
+        .read_to_string(&mut xml)
+        .map_err(|e| format!("docx: {e}"))?;
+    Ok(wordml_text(&xml))
+}
+
+// Collect <w:t> runs; paragraphs, breaks and tabs become whitespace.
+fn wordml_text(xml: &str) -> String {
+    let mut out = String::with_capacity(xml.len() / 4);
+    let mut rest = xml;
+    while let Some(lt) = rest.find('<') {
+        let Some(gt) = rest[lt..].find('>') else {
+            break;
+        };
+        let tag = &rest[lt + 1..lt + gt];
+        rest = &rest[lt + gt + 1..];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        match name {
+            "w:t" if !tag.starts_with('/') && !tag.ends_with('/') => {
+                let end = rest.find("</w:t>").unwrap_or(rest.len());
+                out.push_str(&unescape_xml(&rest[..end]));
+                rest = &rest[end..];
+            }
+            "w:p" if tag.starts_with('/') => out.push('\n'),
+            "w:br" | "w:cr" => out.push('\n'),
+            "w:tab" => out.push('\t'),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn unescape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            e => e
+                .strip_prefix("#x")
+                .map(|h| u32::from_str_radix(h, 16))
+                .or_else(|| e.strip_prefix('#').map(str::parse::<u32>))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Collapse runs of blank lines and trailing spaces that PDF layout tends to produce.
+fn normalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank += 1;
+            if blank > 1 {
+                continue;
+            }
+        } else {
+            blank = 0;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.trim().to_string()
+}
+
+fn truncate(mut s: String) -> String {
+    if s.len() > MAX_TEXT_BYTES {
+        let mut cut = MAX_TEXT_BYTES;
+        while !s.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        s.truncate(cut);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn docx(document_xml: &str) -> Vec<u8> {
+        let mut zw = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zw.start_file(
+            "word/document.xml",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        zw.write_all(document_xml.as_bytes()).unwrap();
+        zw.finish().unwrap().into_inner()
+    }
+
+    fn docx_without_body() -> Vec<u8> {
+        let mut zw = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zw.start_file(
+            "[Content_Types].xml",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        zw.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn kinds_follow_content_type_then_extension() {
+        assert_eq!(kind("application/pdf", None), Some(Kind::Pdf));
+        assert_eq!(kind(DOCX_TYPE, Some("a.bin")), Some(Kind::Docx));
+        assert_eq!(kind("text/plain; charset=utf-8", None), Some(Kind::Text));
+        assert_eq!(kind("application/json", None), Some(Kind::Text));
+        assert_eq!(
+            kind("application/octet-stream", Some("main.RS")),
+            Some(Kind::Text)
+        );
+        assert_eq!(kind("application/octet-stream", Some("photo.raw")), None);
+        assert_eq!(kind("image/png", None), None);
+    }
+
+    #[test]
+    fn docx_runs_become_text() {
+        let xml = r#"<w:document><w:body>
+            <w:p><w:r><w:t>Quarterly</w:t></w:r><w:r><w:t xml:space="preserve"> report</w:t></w:r></w:p>
+            <w:p><w:r><w:t>R&amp;D</w:t><w:tab/><w:t>&#x263A;&#65;</w:t><w:br/><w:t/><w:t>end</w:t></w:r></w:p>
+            </w:body></w:document>"#;
+        assert_eq!(
+            extract(Kind::Docx, &docx(xml)).unwrap(),
+            "Quarterly report\nR&D\t\u{263A}A\nend"
+        );
+    }
+
+    #[test]
+    fn unreadable_documents_report_why() {
+        let err = extract(Kind::Docx, b"PK\x03\x04 not really a zip").unwrap_err();
+        assert!(err.starts_with("docx:"), "{err}");
+        let err = extract(Kind::Docx, &docx_without_body()).unwrap_err();
+        assert!(err.starts_with("docx:"), "{err}");
+        let err = extract(Kind::Pdf, b"%PDF-1.4\ngarbage").unwrap_err();
+        assert!(err.starts_with("pdf:"), "{err}");
+        assert_eq!(
+            extract(Kind::Text, b"text\0with nul").unwrap_err(),
+            "binary content"
+        );
+    }
+
+    #[test]
+    fn text_is_normalized_and_capped() {
+        let text = extract(Kind::Text, b"  one  \n\n\n\ntwo\t\r\n\n").unwrap();
+        assert_eq!(text, "one\n\ntwo");
+        // cut on a char boundary
+        let big = "é".repeat(MAX_TEXT_BYTES / 2 + 10);
+        let text = extract(Kind::Text, big.as_bytes()).unwrap();
+        assert_eq!(text.len(), MAX_TEXT_BYTES);
+        let big = format!("x{big}");
+        let text = extract(Kind::Text, big.as_bytes()).unwrap();
+        assert_eq!(text.len(), MAX_TEXT_BYTES - 1);
+    }
+}
@@ -0,0 +1,258 @@
+use std::env;
+
+// -------------------- Upload limits & content policy --------------------
+
+const DEFAULT_MAX_BODY: usize = 210 * 1024 * 1024;
+const DEFAULT_MAX_INLINE: usize = 256 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuotaPolicy {
+    /// Refuse new uploads once the quota would be exceeded.
+    Reject,
+    /// Delete the oldest items until the new upload fits.
+    EvictOldest,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct UploadLimits {
+    /// Maximum request body size (multipart envelope included).
+    pub(crate) max_body_bytes: usize,
+    /// Per-item cap on file/text size; None => only the body limit applies.
+    pub(crate) max_file_bytes: Option<u64>,
+    /// Files up to this size are stored inline in SQLite instead of data/uploads.
+    pub(crate) max_inline_bytes: usize,
+    /// Total storage quota across all items (file sizes + text lengths).
+    pub(crate) quota_bytes: Option<u64>,
+    pub(crate) quota_policy: QuotaPolicy,
+    allowed_mime: Vec<String>,
+    denied_mime: Vec<String>,
+    allowed_ext: Vec<String>,
+    denied_ext: Vec<String>,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: DEFAULT_MAX_BODY,
+            max_file_bytes: None,
+            max_inline_bytes: DEFAULT_MAX_INLINE,
+            quota_bytes: None,
+            quota_policy: QuotaPolicy::Reject,
+            allowed_mime: vec![],
+            denied_mime: vec![],
+            allowed_ext: vec![],
+            denied_ext: vec![],
+        }
+    }
+}
+
+impl UploadLimits {
+    pub(crate) fn from_env() -> Self {
+        let mut l = Self::default();
+        if let Some(n) = env_bytes("MAX_BODY_BYTES") {
+            l.max_body_bytes = n as usize;
+        }
+        l.max_file_bytes = env_bytes("MAX_FILE_BYTES");
+        if let Some(n) = env_bytes("INLINE_MAX_BYTES") {
+            l.max_inline_bytes = n as usize;
+        }
+        l.quota_bytes = env_bytes("STORAGE_QUOTA_BYTES");
+        l.quota_policy = match env::var("STORAGE_QUOTA_POLICY")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "evict" | "evict-oldest" | "evict_oldest" => QuotaPolicy::EvictOldest,
+            _ => QuotaPolicy::Reject,
+        };
+        l.allowed_mime = env_list("ALLOWED_MIME_TYPES");
+        l.denied_mime = env_list("DENIED_MIME_TYPES");
+        l.allowed_ext = env_ext_list("ALLOWED_EXTENSIONS");
+        l.denied_ext = env_ext_list("DENIED_EXTENSIONS");
+        l
+    }
+
+    /// Check content type and file extension against the allow/deny lists.
+    /// Deny rules win over allow rules. Returns a human readable reason on rejection.
+    pub(crate) fn check_type(
+        &self,
+        content_type: Option<&str>,
+        file_name: Option<&str>,
+    ) -> Result<(), String> {
+        let mime = content_type
+            .map(|c| {
+                c.split(';')
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        if self.denied_mime.iter().any(|p| mime_matches(p, &mime)) {
+            return Err(format!("content type {mime} is not allowed"));
+        }
+        if !self.allowed_mime.is_empty()
+            && !self.allowed_mime.iter().any(|p| mime_matches(p, &mime))
+        {
+            return Err(format!("content type {mime} is not allowed"));
+        }
+        let ext = file_name
+            .and_then(|n| std::path::Path::new(n).extension())
+            .and_then(|s| s.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        if !ext.is_empty() && self.denied_ext.contains(&ext) {
+            return Err(format!("file extension .{ext} is not allowed"));
+        }
+        if !self.allowed_ext.is_empty() && !self.allowed_ext.contains(&ext) {
+            if ext.is_empty() {
+                return Err("files without an extension are not allowed".to_string());
+            }
+            return Err(format!("file extension .{ext} is not allowed"));
+        }
+        Ok(())
+    }
+}
+
+/// Match a MIME pattern like `image/*`, `*/*` or `application/pdf`.
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => mime.split_once('/').is_some_and(|(top, _)| top == prefix),
+        None => pattern == mime,
+    }
+}
+
+/// Parse a byte size such as `1048576`, `512K`, `200M` or `2G` (binary units).
+pub(crate) fn parse_bytes(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    let upper = s.to_ascii_uppercase();
+    let digits = upper.trim_end_matches(['B', 'I']);
+    let (num, mul) = match digits.chars().last()? {
+        'K' => (&digits[..digits.len() - 1], 1u64 << 10),
+        'M' => (&digits[..digits.len() - 1], 1u64 << 20),
+        'G' => (&digits[..digits.len() - 1], 1u64 << 30),
+        'T' => (&digits[..digits.len() - 1], 1u64 << 40),
+        _ => (digits, 1u64),
+    };
+    num.trim().parse::<u64>().ok()?.checked_mul(mul)
+}
+
+/// Parse a duration in seconds such as `3600`, `90m`, `12h`, `30d` or `2w`.
+pub(crate) fn parse_duration(s: &str) -> Option<i64> {
+    let s = s.trim().to_ascii_lowercase();
+    let (num, mul) = match s.chars().last()? {
+        's' => (&s[..s.len() - 1], 1i64),
+        'm' => (&s[..s.len() - 1], 60),
+        'h' => (&s[..s.len() - 1], 3600),
+        'd' => (&s[..s.len() - 1], 86_400),
+        'w' => (&s[..s.len() - 1], 7 * 86_400),
+        _ => (s.as_str(), 1),
+    };
+    num.trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n >= 0)?
+        .checked_mul(mul)
+}
+
+fn env_bytes(key: &str) -> Option<u64> {
+    env::var(key)
+        .ok()
+        .and_then(|v| parse_bytes(&v))
+        .filter(|n| *n > 0)
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().to_ascii_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn env_ext_list(key: &str) -> Vec<String> {
+    env_list(key)
+        .into_iter()
+        .map(|s| s.trim_start_matches('.').to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_sizes_parse_with_binary_units() {
+        assert_eq!(parse_bytes("1048576"), Some(1_048_576));
+        assert_eq!(parse_bytes(" 512K "), Some(512 << 10));
+        assert_eq!(parse_bytes("200m"), Some(200 << 20));
+        assert_eq!(parse_bytes("2GB"), Some(2 << 30));
+        assert_eq!(parse_bytes("2GiB"), Some(2 << 30));
+        assert_eq!(parse_bytes("1T"), Some(1 << 40));
+        assert_eq!(parse_bytes("10B"), Some(10));
+        for bad in [
+            "",
+            "K",
+            "-1",
+            "1.5M",
+            "ten",
+            "99999999999999999999",
+            "20000000T",
+        ] {
+            assert_eq!(parse_bytes(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn durations_parse_to_seconds() {
+        assert_eq!(parse_duration("3600"), Some(3600));
+        assert_eq!(parse_duration("45s"), Some(45));
+        assert_eq!(parse_duration("90m"), Some(5400));
+        assert_eq!(parse_duration(" 12H "), Some(43_200));
+        assert_eq!(parse_duration("30d"), Some(30 * 86_400));
+        assert_eq!(parse_duration("2w"), Some(14 * 86_400));
+        assert_eq!(parse_duration("0"), Some(0));
+        for bad in ["", "d", "-5m", "1.5h", "soon", "9223372036854775807w"] {
+            assert_eq!(parse_duration(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn mime_patterns_match_exactly_or_by_top_level_type() {
+        assert!(mime_matches("*", "text/html"));
+        assert!(mime_matches("*/*", "application/pdf"));
+        assert!(mime_matches("image/*", "image/png"));
+        assert!(!mime_matches("image/*", "imagex/png"));
+        assert!(!mime_matches("image/*", "application/image"));
+        assert!(mime_matches("application/pdf", "application/pdf"));
+        assert!(!mime_matches("application/pdf", "application/pdfx"));
+    }
+
+    #[test]
+    fn deny_rules_win_over_allow_rules() {
+        let limits = UploadLimits {
+            allowed_mime: vec!["image/*".into()],
+            denied_mime: vec!["image/svg+xml".into()],
+            denied_ext: vec!["exe".into()],
+            ..UploadLimits::default()
+        };
+        assert!(limits
+            .check_type(Some("image/png; q=1"), Some("a.png"))
+            .is_ok());
+        assert!(limits
+            .check_type(Some("IMAGE/SVG+XML"), Some("a.svg"))
+            .is_err());
+        assert!(limits.check_type(Some("image/png"), Some("a.EXE")).is_err());
+        assert!(limits.check_type(None, Some("a.png")).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use crate::search;
+use crate::store::{Conn, Dialect, Value};
+
+// -------------------- List filters & sort orders --------------------
+//
+// Every sort order is a keyset: (sort key, createdAt, id). The page cursor carries the
+// last row's values so the next page starts strictly after it, whatever the order.
+
+/// Bytes of an item: file size for uploads, UTF-8 length for text.
+pub(crate) const SIZE_EXPR: &str = "COALESCE(c.fileSize, octet_length(c.content), 0)";
+
+/// Substring match for searches FTS can't express; case-insensitive for ASCII like
+/// SQLite's LIKE, and blind to sealed content. Takes `like_pattern(term)` twice.
+pub(crate) const LIKE_SEARCH: &str = "((c.dataKey IS NULL AND lower(c.content) LIKE lower(?) ESCAPE '\\') OR lower(c.fileName) LIKE lower(?) ESCAPE '\\')";
+
+const PINNED_EXPR: &str = "(c.pinnedAt IS NOT NULL)";
+
+const DOWNLOADS_EXPR: &str =
+    "COALESCE((SELECT SUM(s.downloadCount) FROM ShareLink s WHERE s.itemId = c.id), 0)";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SortOrder {
+    /// Drag-and-drop order (sortWeight), newest first within equal weights.
+    Manual,
+    /// bm25 rank; only meaningful together with a search.
+    Relevance,
+    Newest,
+    Oldest,
+    Largest,
+    Smallest,
+    MostDownloaded,
+}
+
+impl SortOrder {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "manual" => Self::Manual,
+            "relevance" => Self::Relevance,
+            "newest" => Self::Newest,
+            "oldest" => Self::Oldest,
+            "largest" => Self::Largest,
+            "smallest" => Self::Smallest,
+            "downloads" | "most-downloaded" => Self::MostDownloaded,
+            _ => return None,
+        })
+    }
+
+    /// Expression of the primary sort key, None when ordering by createdAt alone.
+    pub(crate) fn key_expr(self, d: Dialect) -> Option<&'static str> {
+        match self {
+            Self::Manual => Some("c.sortWeight"),
+            Self::Relevance => Some(search::rank(d)),
+            Self::Newest | Self::Oldest => None,
+            Self::Largest | Self::Smallest => Some(SIZE_EXPR),
+            Self::MostDownloaded => Some(DOWNLOADS_EXPR),
+        }
+    }
+
+    fn key_desc(self) -> bool {
+        // bm25 scores (and the negated ts_rank) are negative; lower is more relevant
+        !matches!(self, Self::Relevance | Self::Smallest)
+    }
+
+    fn tiebreak_desc(self) -> bool {
+        self != Self::Oldest
+    }
+
+    /// Pinned items stay above everything else in the manual order.
+    pub(crate) fn pins_first(self) -> bool {
+        self == Self::Manual
+    }
+
+    pub(crate) fn order_by(self) -> String {
+        let dir = |desc: bool| if desc { "DESC" } else { "ASC" };
+        let t = dir(self.tiebreak_desc());
+        let order = match self {
+            Self::Newest | Self::Oldest => format!("c.createdAt {t}, c.id {t}"),
+            _ => format!(
+                "sortKey {}, c.createdAt {t}, c.id {t}",
+                dir(self.key_desc())
+            ),
+        };
+        if self.pins_first() {
+            format!("{PINNED_EXPR} DESC, {order}")
+        } else {
+            order
+        }
+    }
+
+    /// Condition selecting rows strictly after the cursor row.
+    pub(crate) fn after_cursor(
+        self,
+        d: Dialect,
+        cursor: Cursor,
+        where_clauses: &mut Vec<String>,
+        params: &mut Vec<Value>,
+    ) {
+        let Cursor {
+            pinned,
+            key,
+            created_at,
+            id,
+        } = cursor;
+        let cmp = |desc: bool| if desc { "<" } else { ">" };
+        let t = cmp(self.tiebreak_desc());
+        let tail = format!("(c.createdAt {t} ? OR (c.createdAt = ? AND c.id {t} ?))");
+        let mut cond = match (self.key_expr(d), &key) {
+            (Some(expr), Some(_)) => {
+                let op = cmp(self.key_desc());
+                format!("({expr} {op} ? OR ({expr} = ? AND {tail}))")
+            }
+            _ => tail,
+        };
+        let pinned = pinned.filter(|_| self.pins_first());
+        if let Some(p) = pinned {
+            cond = format!("({PINNED_EXPR} < ? OR ({PINNED_EXPR} = ? AND {cond}))");
+            params.push(i64::from(p).into());
+            params.push(i64::from(p).into());
+        }
+        where_clauses.push(cond);
+        if let (Some(_), Some(k)) = (self.key_expr(d), key) {
+            params.push(k.clone());
+            params.push(k);
+        }
+        params.push(created_at.into());
+        params.push(created_at.into());
+        params.push(id.into());
+    }
+}
+
+/// Where the previous page ended. `pinned` is the last row's pin state, only used by
+/// orders that put pinned items first.
+pub(crate) struct Cursor {
+    pub(crate) pinned: Option<bool>,
+    pub(crate) key: Option<Value>,
+    pub(crate) created_at: i64,
+    pub(crate) id: String,
+}
+
+#[derive(Default, Debug)]
+pub(crate) struct ListFilter {
+    /// TEXT, IMAGE and/or FILE
+    pub(crate) types: Vec<String>,
+    pub(crate) content_type_prefix: Option<String>,
+    pub(crate) created_from: Option<i64>,
+    pub(crate) created_to: Option<i64>,
+    pub(crate) min_size: Option<u64>,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) has_share: Option<bool>,
+    pub(crate) password_protected: Option<bool>,
+    /// Tag ids or names; an item must carry all of them
+    pub(crate) tags: Vec<String>,
+    /// Collection id or name
+    pub(crate) collection: Option<String>,
+    pub(crate) pinned: Option<bool>,
+    pub(crate) favorite: Option<bool>,
+    pub(crate) archived: Archived,
+}
+
+/// Archived items are left out unless asked for.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Archived {
+    #[default]
+    Hide,
+    Only,
+    Include,
+}
+
+impl ListFilter {
+    /// Apply a query parameter; unknown keys are ignored. Err carries a message for
+    /// malformed values.
+    pub(crate) fn accept(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "type" => {
+                for t in value.split(',').map(|t| t.trim().to_ascii_uppercase()) {
+                    if !matches!(t.as_str(), "TEXT" | "IMAGE" | "FILE" | "BUNDLE") {
+                        return Err(format!("invalid type: {t}"));
+                    }
+                    self.types.push(t);
+                }
+            }
+            "contentType" => {
+                let v = value.trim().to_ascii_lowercase();
+                self.content_type_prefix = (!v.is_empty()).then_some(v);
+            }
+            "createdFrom" => self.created_from = Some(parse_time(value, false)?),
+            "createdTo" => self.created_to = Some(parse_time(value, true)?),
+            "minSize" => self.min_size = Some(parse_size(value)?),
+            "maxSize" => self.max_size = Some(parse_size(value)?),
+            "hasShare" => self.has_share = Some(parse_flag(value)?),
+            "passwordProtected" => self.password_protected = Some(parse_flag(value)?),
+            "tag" => self.tags.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from),
+            ),
+            "pinned" => self.pinned = Some(parse_flag(value)?),
+            "favorite" => self.favorite = Some(parse_flag(value)?),
+            "archived" => {
+                self.archived = match value.trim() {
+                    "all" => Archived::Include,
+                    v if parse_flag(v)? => Archived::Only,
+                    _ => Archived::Hide,
+                }
+            }
+            "collection" => {
+                let v = value.trim();
+                self.collection = (!v.is_empty()).then(|| v.to_string());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub(crate) fn push_sql(
+        &self,
+        now: i64,
+        where_clauses: &mut Vec<String>,
+        params: &mut Vec<Value>,
+    ) {
+        if !self.types.is_empty() {
+            let marks = vec!["?"; self.types.len()].join(",");
+            where_clauses.push(format!("c.type IN ({marks})"));
+            params.extend(self.types.iter().map(|t| Value::from(t.clone())));
+        }
+        if let Some(p) = &self.content_type_prefix {
+            where_clauses.push("lower(c.contentType) LIKE ? ESCAPE '\\'".into());
+            params.push(format!("{}%", escape_like(p)).into());
+        }
+        if let Some(t) = self.created_from {
+            where_clauses.push("c.createdAt >= ?".into());
+            params.push(t.into());
+        }
+        if let Some(t) = self.created_to {
+            where_clauses.push("c.createdAt <= ?".into());
+            params.push(t.into());
+        }
+        if let Some(n) = self.min_size {
+            where_clauses.push(format!("{SIZE_EXPR} >= ?"));
+            params.push((n as i64).into());
+        }
+        if let Some(n) = self.max_size {
+            where_clauses.push(format!("{SIZE_EXPR} <= ?"));
+            params.push((n as i64).into());
+        }
+        // A share is active while it is not revoked, expired or used up
+        let active = "s.itemId = c.id AND s.revoked = 0 AND (s.expiresAt IS NULL OR s.expiresAt > ?) AND (s.maxDownloads IS NULL OR s.maxDownloads < 0 OR s.downloadCount < s.maxDownloads)";
+        if let Some(want) = self.has_share {
+            let not = if want { "" } else { "NOT " };
+            where_clauses.push(format!(
+                "{not}EXISTS (SELECT 1 FROM ShareLink s WHERE {active})"
+            ));
+            params.push(now.into());
+        }
+        if let Some(want) = self.password_protected {
+            let not = if want { "" } else { "NOT " };
+            where_clauses.push(format!(
+                "{not}EXISTS (SELECT 1 FROM ShareLink s WHERE {active} AND s.passwordHash IS NOT NULL)"
+            ));
+            params.push(now.into());
+        }
+        if let Some(want) = self.pinned {
+            let not = if want { "NOT " } else { "" };
+            where_clauses.push(format!("c.pinnedAt IS {not}NULL"));
+        }
+        if let Some(want) = self.favorite {
+            where_clauses.push("c.favorite = ?".into());
+            params.push(i64::from(want).into());
+        }
+        match self.archived {
+            Archived::Hide => where_clauses.push("c.archivedAt IS NULL".into()),
+            Archived::Only => where_clauses.push("c.archivedAt IS NOT NULL".into()),
+            Archived::Include => {}
+        }
+        for tag in &self.tags {
+            where_clauses.push("EXISTS (SELECT 1 FROM ItemTag it JOIN Tag t ON t.id = it.tagId WHERE it.itemId = c.id AND (t.id = ? OR lower(t.name) = lower(?)))".into());
+            params.push(tag.clone().into());
+            params.push(tag.clone().into());
+        }
+        if let Some(col) = &self.collection {
+            where_clauses.push("EXISTS (SELECT 1 FROM CollectionItem ci JOIN Collection co ON co.id = ci.collectionId WHERE ci.itemId = c.id AND (co.id = ? OR lower(co.name) = lower(?)))".into());
+            params.push(col.clone().into());
+            params.push(col.clone().into());
+        }
+    }
+}
+
+/// Ids of live items matching a list query string (`q` plus the filters above), in manual
+/// order. Lets bulk endpoints target "everything the list shows" without sending ids.
+pub(crate) fn select_ids(conn: &dyn Conn, query: &str, now: i64) -> Result<Vec<String>, String> {
+    let mut filter = ListFilter::default();
+    let mut search: Option<String> = None;
+    for (k, v) in form_urlencoded::parse(query.trim_start_matches('?').as_bytes()) {
+        match k.as_ref() {
+            "q" | "search" => search = Some(v.into_owned()),
+            _ => filter.accept(&k, &v)?,
+        }
+    }
+    let d = conn.dialect();
+    let fts = search.as_deref().and_then(|s| search::match_query(d, s));
+    let order = SortOrder::Manual;
+    let cols = format!("c.id, {} AS sortKey", order.key_expr(d).unwrap_or("NULL"));
+    let mut sql = if fts.is_some() {
+        format!("SELECT {cols} FROM {}", search::source(d))
+    } else {
+        format!("SELECT {cols} FROM ClipboardItem c")
+    };
+    let mut where_clauses: Vec<String> = vec!["c.deletedAt IS NULL".into()];
+    let mut params: Vec<Value> = vec![];
+    if let Some(m) = fts {
+        where_clauses.push(search::condition(d).into());
+        params.push(m.into());
+    } else if let Some(s) = search.filter(|s| !s.trim().is_empty()) {
+        where_clauses.push(LIKE_SEARCH.into());
+        let like = like_pattern(&s);
+        params.push(like.clone().into());
+        params.push(like.into());
+    }
+    filter.push_sql(now, &mut where_clauses, &mut params);
+    sql.push_str(" WHERE ");
+    sql.push_str(&where_clauses.join(" AND "));
+    sql.push_str(&format!(" ORDER BY {}", order.order_by()));
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let ids = stmt
+        .query_map(params, |r| r.get(0))
+        .map_err(|e| e.to_string())?
+        .flatten()
+        .collect();
+    Ok(ids)
+}
+
+/// Unix seconds, RFC 3339, or a plain `YYYY-MM-DD` date (start or end of that day, UTC).
+pub(crate) fn parse_time(s: &str, end_of_day: bool) -> Result<i64, String> {
+    let s = s.trim();
+    if let Ok(ts) = s.parse::<i64>() {
+        return Ok(ts);
+    }
+    if let Ok(dt) = time::OffsetDateTime::parse(s, &time::format_description::well_known::Rfc3339) {
+        return Ok(dt.unix_timestamp());
+    }
+    let ymd: Vec<&str> = s.split('-').collect();
+    if let [y, m, d] = ymd[..] {
+        let date = (|| {
+            let month = time::Month::try_from(m.parse::<u8>().ok()?).ok()?;
+            time::Date::from_calendar_date(y.parse().ok()?, month, d.parse().ok()?).ok()
+        })();
+        if let Some(d) = date {
+            let start = d.midnight().assume_utc().unix_timestamp();
+            return Ok(if end_of_day { start + 86_399 } else { start });
+        }
+    }
+    Err(format!("invalid date: {s}"))
+}
+
+/// Cursor sort keys are integers except for relevance scores.
+pub(crate) fn parse_cursor_key(s: &str) -> Option<Value> {
+    s.parse::<i64>()
+        .map(Value::from)
+        .ok()
+        .or_else(|| s.parse::<f64>().ok().map(Value::from))
+}
+
+pub(crate) fn cursor_key_json(v: &Value) -> serde_json::Value {
+    match v {
+        Value::Integer(i) => serde_json::json!(i),
+        Value::Real(f) => serde_json::json!(f),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    crate::limits::parse_bytes(s).ok_or_else(|| format!("invalid size: {s}"))
+}
+
+fn parse_flag(s: &str) -> Result<bool, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(format!("invalid boolean: {s}")),
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// LIKE pattern matching `s` literally anywhere in a value.
+pub(crate) fn like_pattern(s: &str) -> String {
+    format!("%{}%", escape_like(s))
+}
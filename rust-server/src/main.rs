@@ -4,12 +4,12 @@ use axum::body::Body;
 use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
 use axum::{
     extract::DefaultBodyLimit,
-    extract::{Multipart, Path, Request, State},
+    extract::{multipart::MultipartError, Multipart, Path, Request, State},
     http::{HeaderMap, HeaderValue, Method, StatusCode, Uri},
     middleware::from_fn_with_state,
     response::sse::{Event, KeepAlive, Sse},
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use base64::engine::general_purpose::URL_SAFE_NO_PAD as B64_URL_SAFE_NO_PAD;
@@ -17,17 +17,21 @@ use base64::Engine as _;
 use futures_util::{stream, Stream, StreamExt};
 use mime_guess::from_path as guess_mime;
 use rand::RngCore;
-use rusqlite::{params, Connection};
+use rusqlite::params;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::convert::Infallible;
 use std::fs as stdfs;
 use std::path::{Path as StdPath, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::UNIX_EPOCH;
 use time::OffsetDateTime;
 use tokio::io::AsyncWriteExt;
-use tokio::{net::TcpListener, sync::broadcast, time as tokio_time};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc},
+    time as tokio_time,
+};
 use tokio_util::io::ReaderStream;
 use tower_http::{
     cors::{Any, CorsLayer},
@@ -35,12 +39,59 @@ use tower_http::{
 };
 use uuid::Uuid;
 
+mod archive;
+mod backup;
+mod blob;
+mod bulk;
+mod bundle;
+mod crypt;
+mod db;
+mod e2ee;
+mod expiry;
+mod export;
+mod extract;
+mod limits;
+mod listing;
+mod migrations;
+mod pg;
+mod ranges;
+mod retention;
+mod revisions;
+mod scrub;
+mod search;
+mod sniff;
+mod store;
+mod tags;
+#[cfg(test)]
+mod tests;
+mod thumbs;
+mod trash;
+
+use limits::{QuotaPolicy, UploadLimits};
+use ranges::{FileSource, Validators};
+use store::{Conn, Value};
+
 #[derive(Clone)]
 struct AppState {
     tx: broadcast::Sender<ServerEvent>,
     password: Option<String>,
-    db: Arc<Mutex<Connection>>,
+    db: Arc<db::Db>,
     data_dir: PathBuf,
+    limits: Arc<UploadLimits>,
+    // Serve share recipients a copy of JPEG/PNG/WebP images without EXIF/XMP metadata
+    strip_share_metadata: bool,
+    // Item ids queued for document text extraction
+    extract_tx: mpsc::UnboundedSender<String>,
+    // Seconds trashed items are kept before purge; None keeps them until emptied by hand
+    trash_retention: Option<i64>,
+    // Automatic removal rules (RETENTION_MAX_AGE / _ITEMS / _BYTES)
+    retention: retention::Policy,
+    // Wakes the expiry task when an item's expiresAt is set or changed
+    expiry_wake: Arc<tokio::sync::Notify>,
+    // Scheduled backups (BACKUP_DIR); None when disabled
+    backup: Option<Arc<backup::Backups>>,
+    // Master keys for encryption at rest (ENCRYPTION_KEY / _FILE); None stores plaintext
+    keys: Option<Arc<crypt::Keyring>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -59,36 +110,167 @@ async fn main() -> anyhow::Result<()> {
         let _ = dotenvy::from_filename("../.env");
     }
 
+    // offline subcommands: clip-relay export / import / restore / migrate
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(cmd) = args.first() {
+        let data_dir = ensure_data_dirs()?;
+        let target = db::Target::from_env(&data_dir)?;
+        // the postgres client runs its own runtime, which can't start on a runtime thread
+        return tokio::task::block_in_place(|| match (cmd.as_str(), &target) {
+            ("migrate", db::Target::Sqlite(path)) => migrations::run_cli(&args, path),
+            ("migrate", db::Target::Postgres(config)) => pg::run_cli(&args, config),
+            ("encrypt", target) => crypt::run_cli(&args, &data_dir, target),
+            (_, db::Target::Postgres(_)) => anyhow::bail!(
+                "`clip-relay {cmd}` only works with SQLite; use pg_dump / pg_restore for PostgreSQL"
+            ),
+            ("restore", db::Target::Sqlite(path)) => backup::run_cli(&args, &data_dir, path),
+            (_, db::Target::Sqlite(path)) => export::run_cli(&args, &data_dir, path),
+        });
+    }
+
     let (tx, _rx) = broadcast::channel::<ServerEvent>(1024);
     let password = env::var("CLIPBOARD_PASSWORD").ok();
     let data_dir = ensure_data_dirs()?;
-    let db = init_db(&data_dir)?;
+    export::clean_stale(&data_dir);
+    let target = db::Target::from_env(&data_dir)?;
+    let keys = crypt::Keyring::from_env()?.map(Arc::new);
+    let db = tokio::task::spawn_blocking(move || db::Db::open(&target)).await??;
+    let limits = UploadLimits::from_env();
+    let (extract_tx, extract_rx) = mpsc::unbounded_channel::<String>();
+    let strip_share_metadata = !matches!(
+        env::var("SHARE_STRIP_METADATA")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .as_str(),
+        "0" | "false" | "no" | "off"
+    );
     let state = AppState {
         tx,
         password,
-        db: Arc::new(Mutex::new(db)),
+        backup: backup::Backups::from_env(db.dialect()).map(Arc::new),
+        db: Arc::new(db),
         data_dir,
+        limits: Arc::new(limits),
+        strip_share_metadata,
+        extract_tx,
+        trash_retention: trash::retention_from_env(),
+        retention: retention::Policy::from_env(),
+        expiry_wake: Arc::new(tokio::sync::Notify::new()),
+        keys,
     };
+    let sealed: i64 = state
+        .db
+        .read(|conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM ClipboardItem WHERE dataKey IS NOT NULL",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap_or(0)
+        })
+        .await;
+    match &state.keys {
+        Some(k) => tracing::info!(key = k.current_id(), "encryption at rest enabled"),
+        None if sealed > 0 => tracing::warn!(
+            items = sealed,
+            "encrypted items exist but ENCRYPTION_KEY is not set; they can't be read"
+        ),
+        None => {}
+    }
+    tokio::spawn(run_extract_worker(state.clone(), extract_rx));
+    queue_pending_extractions(&state).await;
+    tokio::spawn(trash::run_purge_task(state.clone()));
+    tokio::spawn(retention::run_task(state.clone()));
+    tokio::spawn(expiry::run_task(state.clone()));
+    tokio::spawn(backup::run_task(state.clone()));
+
+    let app = router(state);
+
+    let port: u16 = env::var("PORT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(8087);
+    let addr: SocketAddr = ([0, 0, 0, 0], port).into();
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(%addr, "Rust API listening");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
 
+/// All routes: the API under /api, public share endpoints and the static front end.
+fn router(state: AppState) -> Router {
     let protected = Router::new()
         .route("/events", get(sse_events))
         .route("/dev/broadcast", post(dev_broadcast))
-        .route("/health", get(health))
+        .route("/health", get(health_detail))
         // Clipboard core
         .route("/clipboard", get(list_clipboard).post(create_clipboard))
         .route(
             "/clipboard/:id",
-            get(get_clipboard).delete(delete_clipboard),
+            get(get_clipboard)
+                .patch(revisions::update_clipboard)
+                .delete(delete_clipboard),
         )
         .route(
             "/clipboard/:id/share",
             get(get_item_share).put(update_item_share),
         )
+        .route("/clipboard/:id/tags", put(tags::set_item_tags))
+        .route("/clipboard/:id/flags", put(update_item_flags))
+        .route("/clipboard/:id/expiry", put(expiry::update_expiry))
+        .route("/clipboard/:id/files", get(bundle::list_files))
+        .route("/clipboard/:id/files/:file_id", get(bundle::get_file))
+        .route("/clipboard/:id/archive", get(bundle::get_archive))
+        .route("/clipboard/:id/revisions", get(revisions::list_revisions))
+        .route(
+            "/clipboard/:id/revisions/diff",
+            get(revisions::diff_revisions),
+        )
+        .route(
+            "/clipboard/:id/revisions/:rev",
+            get(revisions::get_revision),
+        )
+        .route(
+            "/clipboard/:id/revisions/:rev/restore",
+            post(revisions::restore_revision),
+        )
         .route("/clipboard/reorder", post(reorder_clipboard))
+        .route("/clipboard/bulk", post(bulk::bulk))
+        .route("/clipboard/archive", get(archive::download))
+        // Trash
+        .route("/trash", get(trash::list_trash).delete(trash::empty_trash))
+        .route("/trash/:id", delete(trash::purge_item))
+        .route("/trash/:id/restore", post(trash::restore_item))
+        .route("/retention/preview", get(retention::preview))
+        // Admin: full export / import
+        .route("/admin/export", get(export::export))
+        .route("/admin/import", post(export::import))
+        // Tags & collections
+        .route("/tags", get(tags::list_tags).post(tags::create_tag))
+        .route(
+            "/tags/:id",
+            patch(tags::update_tag).delete(tags::delete_tag),
+        )
+        .route(
+            "/collections",
+            get(tags::list_collections).post(tags::create_collection),
+        )
+        .route(
+            "/collections/:id",
+            get(tags::get_collection)
+                .patch(tags::update_collection)
+                .delete(tags::delete_collection),
+        )
+        .route("/collections/:id/items", post(tags::add_collection_items))
+        .route(
+            "/collections/:id/items/:item_id",
+            delete(tags::remove_collection_item),
+        )
         // Files
         .route("/files/:id", get(get_file))
-        // Allow large multipart bodies (up to 210MB)
-        .layer(DefaultBodyLimit::max(210 * 1024 * 1024))
+        .route("/files/:id/thumb", get(get_thumbnail))
+        // Allow large multipart bodies (MAX_BODY_BYTES, default 210MB)
+        .layer(DefaultBodyLimit::max(state.limits.max_body_bytes))
         .layer(from_fn_with_state(state.clone(), auth_mw));
 
     let public = Router::new()
@@ -134,92 +316,96 @@ async fn main() -> anyhow::Result<()> {
     };
     let share_index_path = share_entry_path.clone();
 
-    let app =
-        Router::new()
-            .merge(api)
-            // Public share endpoints (no global auth) - use closures to avoid Handler type inference pitfalls
-            .route("/api/share/:token", get(share_meta))
-            .route("/api/share/:token/verify", post(share_verify))
-            .route("/api/share/:token/qr", get(share_qr))
-            .route(
-                "/api/share/:token/file",
-                get(
-                    |State(state): State<AppState>,
-                     Path(token): Path<String>,
-                     headers: HeaderMap| async move {
-                        share_file_inner(state, token, headers).await
-                    },
-                ),
-            )
-            .route(
-                "/api/share/:token/download",
-                get(
-                    |State(state): State<AppState>,
-                     Path(token): Path<String>,
-                     headers: HeaderMap| async move {
-                        share_download_inner(state, token, headers).await
-                    },
-                ),
-            )
-            // Legacy POST /api/share removed; item share managed via /api/clipboard/:id/share
-            // Prefer precompressed .br for share entry as well
-            .route(
-                "/s",
-                get({
-                    let p = share_index_path.clone();
-                    move |headers: HeaderMap| {
-                        let p2 = p.clone();
-                        async move { serve_file_prefer_br(p2, headers).await }
-                    }
-                }),
-            )
-            .route(
-                "/s/",
-                get({
-                    let p = share_index_path.clone();
-                    move |headers: HeaderMap| {
-                        let p2 = p.clone();
-                        async move { serve_file_prefer_br(p2, headers).await }
-                    }
-                }),
-            )
-            // Root path
-            .route(
-                "/",
-                get({
-                    let idx = spa_index_path.clone();
-                    move |headers: HeaderMap| {
-                        let idx2 = idx.clone();
-                        async move { serve_file_prefer_br(idx2, headers).await }
-                    }
-                }),
-            )
-            // Static files with SPA fallback, prefer .br if available
-            .route(
-                "/*path",
-                get({
-                    let root = static_root.clone();
-                    let idx = spa_index_path.clone();
-                    move |Path(path): Path<String>, headers: HeaderMap| {
-                        let root2 = root.clone();
-                        let idx2 = idx.clone();
-                        async move { static_handler(root2, idx2, path, headers).await }
-                    }
-                }),
-            )
-            .with_state(state)
-            .layer(TraceLayer::new_for_http())
-            .layer(build_cors());
-
-    let port: u16 = env::var("PORT")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(8087);
-    let addr: SocketAddr = ([0, 0, 0, 0], port).into();
-    let listener = TcpListener::bind(addr).await?;
-    tracing::info!(%addr, "Rust API listening");
-    axum::serve(listener, app).await?;
-    Ok(())
+    Router::new()
+        .merge(api)
+        // Public share endpoints (no global auth) - use closures to avoid Handler type inference pitfalls
+        .route("/api/share/:token", get(share_meta))
+        .route("/api/share/:token/verify", post(share_verify))
+        .route("/api/share/:token/qr", get(share_qr))
+        .route("/api/share/:token/thumb", get(share_thumb))
+        .route(
+            "/api/share/:token/file",
+            get(
+                |State(state): State<AppState>,
+                 Path(token): Path<String>,
+                 method: Method,
+                 headers: HeaderMap| async move {
+                    share_file_inner(state, token, method, headers).await
+                },
+            ),
+        )
+        .route(
+            "/api/share/:token/download",
+            get(
+                |State(state): State<AppState>,
+                 Path(token): Path<String>,
+                 method: Method,
+                 headers: HeaderMap,
+                 uri: Uri| async move {
+                    share_download_inner(state, token, method, headers, uri).await
+                },
+            ),
+        )
+        .route(
+            "/api/share/:token/files/:file_id",
+            get(
+                |State(state): State<AppState>,
+                 Path((token, file_id)): Path<(String, String)>,
+                 method: Method,
+                 headers: HeaderMap| async move {
+                    bundle::share_file(state, token, file_id, method, headers).await
+                },
+            ),
+        )
+        // Legacy POST /api/share removed; item share managed via /api/clipboard/:id/share
+        // Prefer precompressed .br for share entry as well
+        .route(
+            "/s",
+            get({
+                let p = share_index_path.clone();
+                move |headers: HeaderMap| {
+                    let p2 = p.clone();
+                    async move { serve_file_prefer_br(p2, headers).await }
+                }
+            }),
+        )
+        .route(
+            "/s/",
+            get({
+                let p = share_index_path.clone();
+                move |headers: HeaderMap| {
+                    let p2 = p.clone();
+                    async move { serve_file_prefer_br(p2, headers).await }
+                }
+            }),
+        )
+        // Root path
+        .route(
+            "/",
+            get({
+                let idx = spa_index_path.clone();
+                move |headers: HeaderMap| {
+                    let idx2 = idx.clone();
+                    async move { serve_file_prefer_br(idx2, headers).await }
+                }
+            }),
+        )
+        // Static files with SPA fallback, prefer .br if available
+        .route(
+            "/*path",
+            get({
+                let root = static_root.clone();
+                let idx = spa_index_path.clone();
+                move |Path(path): Path<String>, headers: HeaderMap| {
+                    let root2 = root.clone();
+                    let idx2 = idx.clone();
+                    async move { static_handler(root2, idx2, path, headers).await }
+                }
+            }),
+        )
+        .with_state(state)
+        .layer(TraceLayer::new_for_http())
+        .layer(build_cors())
 }
 
 fn init_tracing() {
@@ -244,6 +430,7 @@ fn build_cors() -> CorsLayer {
                 Method::POST,
                 Method::DELETE,
                 Method::PUT,
+                Method::PATCH,
                 Method::OPTIONS,
             ])
             .allow_headers([ACCEPT, CONTENT_TYPE, AUTHORIZATION])
@@ -257,6 +444,7 @@ fn build_cors() -> CorsLayer {
                 Method::POST,
                 Method::DELETE,
                 Method::PUT,
+                Method::PATCH,
                 Method::OPTIONS,
             ])
             .allow_headers([ACCEPT, CONTENT_TYPE, AUTHORIZATION])
@@ -269,6 +457,15 @@ async fn health() -> impl IntoResponse {
     Json(serde_json::json!({ "message": "Good!" }))
 }
 
+// GET /api/health — like /api/healthz, plus the state of scheduled backups
+async fn health_detail(State(state): State<AppState>) -> impl IntoResponse {
+    let backup = state
+        .backup
+        .as_ref()
+        .map_or_else(|| serde_json::json!({ "enabled": false }), |b| b.health());
+    Json(serde_json::json!({ "message": "Good!", "backup": backup }))
+}
+
 fn accept_br(headers: &HeaderMap) -> bool {
     headers
         .get(axum::http::header::ACCEPT_ENCODING)
@@ -666,6 +863,8 @@ async fn auth_mw(
                 || (tail == "verify" && method == Method::POST)
                 || (tail == "file" && method == Method::GET)
                 || (tail == "download" && method == Method::GET)
+                || (tail == "files" && method == Method::GET)
+                || (tail == "thumb" && method == Method::GET)
             {
                 return next.run(req).await;
             }
@@ -804,6 +1003,7 @@ enum ItemType {
     Text,
     Image,
     File,
+    Bundle,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -822,6 +1022,34 @@ struct ClipboardItem {
     file_path: Option<String>,
     created_at: String,
     updated_at: String,
+    // highlighted match context (HTML), only present in search results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    favorite: bool,
+    #[serde(default)]
+    archived: bool,
+    // item expiry (RFC 3339), independent of share expiry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+    // how the client decrypts an end-to-end encrypted item (alg, nonce, wrappedKey)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e2ee: Option<serde_json::Value>,
+    // document text extraction outcome (detail view only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extract_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extract_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<tags::TagRef>>,
+    // collections the item belongs to (detail view only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collections: Option<Vec<serde_json::Value>>,
+    // files of a BUNDLE item (detail view only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<serde_json::Value>>,
 }
 
 fn ensure_data_dirs() -> anyhow::Result<PathBuf> {
@@ -842,68 +1070,23 @@ fn ensure_data_dirs() -> anyhow::Result<PathBuf> {
     Ok(data_dir)
 }
 
-fn init_db(data_dir: &StdPath) -> anyhow::Result<Connection> {
-    let db_path = data_dir.join("custom.db");
-    let conn = Connection::open(db_path)?;
-    conn.execute_batch(
-        r"
-        PRAGMA foreign_keys = ON;
-        CREATE TABLE IF NOT EXISTS ClipboardItem (
-          id TEXT PRIMARY KEY NOT NULL,
-          type TEXT NOT NULL,
-          content TEXT,
-          fileName TEXT,
-          fileSize INTEGER,
-          sortWeight INTEGER NOT NULL DEFAULT 0,
-          contentType TEXT,
-          inlineData BLOB,
-          filePath TEXT,
-          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
-          updatedAt INTEGER NOT NULL DEFAULT (unixepoch())
-        );
-        CREATE INDEX IF NOT EXISTS clipboard_created_idx ON ClipboardItem (createdAt, id);
-
-        CREATE TABLE IF NOT EXISTS ShareLink (
-          token TEXT PRIMARY KEY NOT NULL,
-          itemId TEXT NOT NULL,
-          expiresAt INTEGER,
-          maxDownloads INTEGER,
-          downloadCount INTEGER NOT NULL DEFAULT 0,
-          revoked INTEGER NOT NULL DEFAULT 0,
-          passwordHash TEXT,
-          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
-          updatedAt INTEGER NOT NULL DEFAULT (unixepoch()),
-          CONSTRAINT share_item_fk FOREIGN KEY (itemId) REFERENCES ClipboardItem(id) ON DELETE CASCADE
-        );
-        CREATE INDEX IF NOT EXISTS share_item_idx ON ShareLink (itemId);
-        CREATE INDEX IF NOT EXISTS share_created_idx ON ShareLink (createdAt);
-        ",
-    )?;
-    // best-effort schema migration: add passwordPlain column if missing
-    let need_add_password_plain = conn
-        .prepare("SELECT passwordPlain FROM ShareLink LIMIT 1")
-        .map(|_| false)
-        .unwrap_or(true);
-    if need_add_password_plain {
-        let _ = conn.execute("ALTER TABLE ShareLink ADD COLUMN passwordPlain TEXT", []);
-    }
-    Ok(conn)
-}
-
 fn now_unix() -> i64 {
     OffsetDateTime::now_utc().unix_timestamp()
 }
 
 // -------------------- Clipboard Handlers --------------------
 
-async fn list_clipboard(State(state): State<AppState>, uri: Uri) -> impl IntoResponse {
+async fn list_clipboard(State(state): State<AppState>, uri: Uri) -> Response {
     let q = uri.query().unwrap_or("");
     let params: Vec<(String, String)> = form_urlencoded::parse(q.as_bytes()).into_owned().collect();
     let mut search: Option<String> = None;
     let mut take: usize = 24;
+    let mut sort: Option<listing::SortOrder> = None;
+    let mut filter = listing::ListFilter::default();
     let mut cursor_created_at: Option<i64> = None;
     let mut cursor_id: Option<String> = None;
-    let mut cursor_sort: Option<i64> = None;
+    let mut cursor_key: Option<Value> = None;
+    let mut cursor_pinned: Option<bool> = None;
     for (k, v) in params {
         match k.as_str() {
             "search" => search = Some(v),
@@ -914,6 +1097,16 @@ async fn list_clipboard(State(state): State<AppState>, uri: Uri) -> impl IntoRes
                     .map(|n| n.clamp(1, 48))
                     .unwrap_or(24)
             }
+            "sort" => match listing::SortOrder::parse(&v) {
+                Some(s) => sort = Some(s),
+                None => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({"error": format!("invalid sort: {v}")})),
+                    )
+                        .into_response()
+                }
+            },
             "cursorCreatedAt" => {
                 // Try parse as i64 (unix ts) or RFC3339 string
                 if let Ok(ts) = v.parse::<i64>() {
@@ -925,84 +1118,158 @@ async fn list_clipboard(State(state): State<AppState>, uri: Uri) -> impl IntoRes
                 }
             }
             "cursorId" => cursor_id = Some(v),
-            "cursorSortWeight" => cursor_sort = v.parse::<i64>().ok(),
-            _ => {}
-        }
-    }
-    let conn = state.db.lock().unwrap();
-    let mut sql = String::from("SELECT id,type,content,fileName,fileSize,sortWeight,contentType,inlineData,filePath,createdAt,updatedAt FROM ClipboardItem");
-    let mut where_clauses: Vec<String> = vec![];
-    let mut params_vec: Vec<rusqlite::types::Value> = vec![];
-    if let Some(s) = &search {
-        where_clauses.push("(content LIKE ? OR fileName LIKE ?)".into());
-        let like = format!("%{}%", s);
-        params_vec.push(like.clone().into());
-        params_vec.push(like.into());
-    }
-    if let (Some(ca), Some(cid)) = (cursor_created_at, cursor_id.as_ref()) {
-        if let Some(cs) = cursor_sort {
-            where_clauses.push("(sortWeight < ? OR (sortWeight = ? AND (createdAt < ? OR (createdAt = ? AND id < ?))))".into());
-            params_vec.push(cs.into());
-            params_vec.push(cs.into());
-            params_vec.push(ca.into());
-            params_vec.push(ca.into());
-            params_vec.push(cid.clone().into());
-        } else {
-            where_clauses.push("(createdAt < ? OR (createdAt = ? AND id < ?))".into());
-            params_vec.push(ca.into());
-            params_vec.push(ca.into());
-            params_vec.push(cid.clone().into());
+            "cursorPinned" => cursor_pinned = Some(matches!(v.as_str(), "1" | "true")),
+            // cursorSortWeight/cursorScore are the older names of cursorKey
+            "cursorKey" | "cursorSortWeight" | "cursorScore" => {
+                cursor_key = listing::parse_cursor_key(&v)
+            }
+            _ => {
+                if let Err(e) = filter.accept(&k, &v) {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({"error": e})),
+                    )
+                        .into_response();
+                }
+            }
         }
     }
-    if !where_clauses.is_empty() {
-        sql.push_str(" WHERE ");
-        sql.push_str(&where_clauses.join(" AND "));
-    }
-    sql.push_str(" ORDER BY sortWeight DESC, createdAt DESC, id DESC LIMIT ?");
-    params_vec.push(((take as i64) + 1).into());
-
-    let mut stmt = conn.prepare(&sql).unwrap();
-    let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
-        .iter()
-        .map(|v| v as &dyn rusqlite::ToSql)
-        .collect();
-    let rows = stmt
-        .query_map(params_refs.as_slice(), |r| {
-            Ok(ClipboardItem {
-                id: r.get(0)?,
-                item_type: match r.get::<_, String>(1)?.as_str() {
-                    "TEXT" => ItemType::Text,
-                    "IMAGE" => ItemType::Image,
-                    _ => ItemType::File,
-                },
-                content: r.get(2).ok(),
-                file_name: r.get(3).ok(),
-                file_size: r.get(4).ok(),
-                sort_weight: r.get(5).unwrap_or(0),
-                content_type: r.get(6).ok(),
-                inline_data: None,
-                file_path: None,
-                created_at: epoch_to_iso(r.get::<_, i64>(9).unwrap_or(0)),
-                updated_at: epoch_to_iso(r.get::<_, i64>(10).unwrap_or(0)),
-            })
+    let keys = state.keys.clone();
+    state
+        .db
+        .read(move |conn| {
+            // Full-text search ranks by relevance; punctuation-only input falls back to LIKE
+            let d = conn.dialect();
+            let fts = search.as_deref().and_then(|s| search::match_query(d, s));
+            let sort = match sort {
+                Some(listing::SortOrder::Relevance) if fts.is_none() => listing::SortOrder::Manual,
+                Some(s) => s,
+                None if fts.is_some() => listing::SortOrder::Relevance,
+                None => listing::SortOrder::Manual,
+            };
+            let cols = format!(
+                "c.id,c.type,c.content,c.fileName,c.fileSize,c.sortWeight,c.contentType,c.filePath,c.createdAt,c.updatedAt,c.pinnedAt IS NOT NULL,c.favorite,c.archivedAt IS NOT NULL,c.expiresAt,c.keyId,c.dataKey,c.e2ee,{} AS sortKey",
+                sort.key_expr(d).unwrap_or("NULL")
+            );
+            let mut sql = if fts.is_some() {
+                format!(
+                    "SELECT {cols}, {} FROM {}",
+                    search::snippet(d),
+                    search::source(d)
+                )
+            } else {
+                format!("SELECT {cols} FROM ClipboardItem c")
+            };
+            let mut where_clauses: Vec<String> = vec!["c.deletedAt IS NULL".into()];
+            let mut params_vec: Vec<Value> = vec![];
+            if let Some(m) = &fts {
+                where_clauses.push(search::condition(d).into());
+                params_vec.push(m.clone().into());
+            } else if let Some(s) = search.as_ref().filter(|s| !s.trim().is_empty()) {
+                where_clauses.push(listing::LIKE_SEARCH.into());
+                let like = listing::like_pattern(s);
+                params_vec.push(like.clone().into());
+                params_vec.push(like.into());
+            }
+            filter.push_sql(now_unix(), &mut where_clauses, &mut params_vec);
+            if let (Some(created_at), Some(id)) = (cursor_created_at, cursor_id) {
+                let cursor = listing::Cursor {
+                    pinned: cursor_pinned,
+                    key: cursor_key,
+                    created_at,
+                    id,
+                };
+                sort.after_cursor(d, cursor, &mut where_clauses, &mut params_vec);
+            }
+            if !where_clauses.is_empty() {
+                sql.push_str(" WHERE ");
+                sql.push_str(&where_clauses.join(" AND "));
+            }
+            sql.push_str(&format!(" ORDER BY {} LIMIT ?", sort.order_by()));
+            params_vec.push(((take as i64) + 1).into());
+
+            let mut stmt = conn.prepare(&sql).unwrap();
+            let ranked = fts.is_some();
+            let rows = stmt
+                .query_map(params_vec, |r| {
+                    let key: Value = r.get(17)?;
+                    let id: String = r.get(0)?;
+                    let content = crypt::open_listed(
+                        keys.as_deref(),
+                        &id,
+                        r.get(14)?,
+                        r.get(15)?,
+                        r.get(2)?,
+                    );
+                    let item = ClipboardItem {
+                        id,
+                        item_type: match r.get::<_, String>(1)?.as_str() {
+                            "TEXT" => ItemType::Text,
+                            "IMAGE" => ItemType::Image,
+                            "BUNDLE" => ItemType::Bundle,
+                            _ => ItemType::File,
+                        },
+                        content,
+                        file_name: r.get(3).ok(),
+                        file_size: r.get(4).ok(),
+                        sort_weight: r.get(5).unwrap_or(0),
+                        content_type: r.get(6).ok(),
+                        inline_data: None,
+                        file_path: None,
+                        created_at: epoch_to_iso(r.get::<_, i64>(8).unwrap_or(0)),
+                        updated_at: epoch_to_iso(r.get::<_, i64>(9).unwrap_or(0)),
+                        snippet: if ranked {
+                            r.get::<_, Option<String>>(18)
+                                .ok()
+                                .flatten()
+                                .map(|s| search::snippet_html(&s))
+                        } else {
+                            None
+                        },
+                        pinned: r.get(10).unwrap_or(false),
+                        favorite: r.get(11).unwrap_or(false),
+                        archived: r.get(12).unwrap_or(false),
+                        expires_at: r.get::<_, Option<i64>>(13).ok().flatten().map(epoch_to_iso),
+                        e2ee: e2ee::json(r.get(16)?),
+                        extract_status: None,
+                        extract_error: None,
+                        tags: None,
+                        collections: None,
+                        files: None,
+                    };
+                    Ok((item, key))
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let has_more = rows.len() > take;
+            let page = &rows[..rows.len().min(take)];
+            let next_cursor = if has_more {
+                let (last, key) = page.last().unwrap();
+                let mut cur = serde_json::json!({"id": last.id, "createdAt": last.created_at, "sortWeight": last.sort_weight });
+                if sort.key_expr(d).is_some() {
+                    cur["key"] = listing::cursor_key_json(key);
+                }
+                if sort.pins_first() {
+                    cur["pinned"] = serde_json::json!(last.pinned);
+                }
+                Some(cur)
+            } else {
+                None
+            };
+            let ids: Vec<String> = page.iter().map(|(i, _)| i.id.clone()).collect();
+            let mut tag_map = tags::tags_for_items(conn, &ids);
+            let items: Vec<ClipboardItem> = page
+                .iter()
+                .map(|(i, _)| ClipboardItem {
+                    tags: Some(tag_map.remove(&i.id).unwrap_or_default()),
+                    ..i.clone()
+                })
+                .collect();
+            Json(serde_json::json!({"items": items, "nextCursor": next_cursor, "hasMore": has_more}))
+                .into_response()
         })
-        .unwrap()
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
-    let has_more = rows.len() > take;
-    let items = if has_more {
-        rows[..take].to_vec()
-    } else {
-        rows.clone()
-    };
-    let next_cursor = if has_more {
-        Some(
-            serde_json::json!({"id": items.last().unwrap().id, "createdAt": items.last().unwrap().created_at, "sortWeight": items.last().unwrap().sort_weight }),
-        )
-    } else {
-        None
-    };
-    Json(serde_json::json!({"items": items, "nextCursor": next_cursor, "hasMore": has_more}))
+        .await
 }
 
 fn epoch_to_iso(ts: i64) -> String {
@@ -1024,7 +1291,17 @@ async fn create_clipboard(
     State(state): State<AppState>,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
-    const MAX_INLINE: usize = 256 * 1024;
+    let max_inline = state.limits.max_inline_bytes;
+    let max_file = state.limits.max_file_bytes;
+    let id = Uuid::new_v4().to_string();
+    // with encryption at rest: the item's data key and its keyId / dataKey columns
+    let (key, key_id, wrapped) = match &state.keys {
+        Some(keys) => {
+            let (k, key_id, wrapped) = keys.new_key(&id);
+            (Some(k), Some(key_id), Some(wrapped))
+        }
+        None => (None, None, None),
+    };
     let mut content: Option<String> = None;
     let mut in_type: Option<InType> = None;
     let mut file_name: Option<String> = None;
@@ -1032,11 +1309,29 @@ async fn create_clipboard(
     let mut file_size: Option<i64> = None;
     let mut inline_data: Option<Vec<u8>> = None;
     let mut file_path_rel: Option<String> = None;
+    let mut detected_type: Option<InType> = None;
+    let mut content_hash: Option<String> = None;
+    // end-to-end encrypted upload: the client's metadata, and the file is ciphertext
+    let mut e2ee_meta: Option<String> = None;
+    // earlier files when several `file` fields are sent; the item becomes a BUNDLE
+    let mut parts: Vec<bundle::Part> = vec![];
     // share params (unified flow: every item is a share)
     let mut share_expires_in: Option<i64> = None; // seconds; None => default never expire
     let mut share_max_downloads: Option<i64> = None;
     let mut share_password: Option<String> = None;
-    while let Some(field) = multipart.next_field().await.unwrap_or(None) {
+    // item expiry, independent of the share link
+    let mut expires_in: Option<String> = None;
+    let mut expires_at_raw: Option<String> = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+                bundle::discard(&state.data_dir, &parts).await;
+                return multipart_error(e, &state.limits);
+            }
+        };
         let name = field.name().map(|s| s.to_string());
         match name.as_deref() {
             Some("content") => {
@@ -1051,22 +1346,100 @@ async fn create_clipboard(
                     _ => None,
                 };
             }
+            Some("e2ee") => {
+                let v = field.text().await.unwrap_or_default();
+                // the file's handling depends on it, so it has to arrive first
+                let parsed = if file_size.is_some() || !parts.is_empty() {
+                    Err("e2ee must be sent before the file".to_string())
+                } else {
+                    e2ee::parse(&v)
+                };
+                match parsed {
+                    Ok(meta) => e2ee_meta = Some(meta),
+                    Err(e) => {
+                        discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+                        bundle::discard(&state.data_dir, &parts).await;
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            Json(serde_json::json!({"error": e})),
+                        )
+                            .into_response();
+                    }
+                }
+            }
             Some("file") => {
+                if e2ee_meta.is_some() && file_size.is_some() {
+                    discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({"error":"an end-to-end encrypted item holds one file"})),
+                    )
+                        .into_response();
+                }
+                if let Some(size) = file_size.take() {
+                    parts.push(bundle::Part {
+                        path: bundle::clean_path(file_name.as_deref().unwrap_or_default()),
+                        content_type: content_type.take().unwrap_or_default(),
+                        size,
+                        hash: content_hash.take().unwrap_or_default(),
+                        rel_path: file_path_rel.take(),
+                        inline: inline_data.take(),
+                    });
+                }
                 let fname = field.file_name().map(|s| s.to_string());
-                let ctype = field.content_type().map(|s| s.to_string());
+                let ctype = match e2ee_meta {
+                    Some(_) => Some(e2ee::CONTENT_TYPE.to_string()),
+                    None => field.content_type().map(|s| s.to_string()),
+                };
+                if let Err(reason) = state.limits.check_type(ctype.as_deref(), fname.as_deref()) {
+                    bundle::discard(&state.data_dir, &parts).await;
+                    return (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        Json(
+                            serde_json::json!({"error":"Unsupported media type","detail": reason}),
+                        ),
+                    )
+                        .into_response();
+                }
                 file_name = fname;
-                content_type = ctype;
 
                 let mut total: usize = 0;
                 let mut buf: Vec<u8> = Vec::new();
                 let mut fh: Option<tokio::fs::File> = None;
                 let mut rel_path: Option<String> = None;
+                let mut head: Vec<u8> = Vec::new();
+                let mut hasher = Sha256::new();
+                let mut sealer = key.as_ref().map(crypt::ItemKey::sealer);
 
                 let mut field_stream = field;
-                while let Some(chunk) = field_stream.chunk().await.unwrap_or(None) {
+                loop {
+                    let chunk = match field_stream.chunk().await {
+                        Ok(Some(chunk)) => chunk,
+                        Ok(None) => break,
+                        Err(e) => {
+                            // a truncated upload (client gone, body limit hit) is not stored
+                            drop(fh.take());
+                            discard_upload(&state.data_dir, rel_path.as_deref()).await;
+                            bundle::discard(&state.data_dir, &parts).await;
+                            return multipart_error(e, &state.limits);
+                        }
+                    };
                     total += chunk.len();
+                    hasher.update(&chunk);
+                    if head.len() < sniff::SNIFF_LEN {
+                        let n = (sniff::SNIFF_LEN - head.len()).min(chunk.len());
+                        head.extend_from_slice(&chunk[..n]);
+                    }
+                    if max_file.is_some_and(|m| total as u64 > m) {
+                        drop(fh.take());
+                        if let Some(rp) = rel_path.as_ref() {
+                            let _ = tokio::fs::remove_file(state.data_dir.join(rp)).await;
+                        }
+                        bundle::discard(&state.data_dir, &parts).await;
+                        return too_large_response("File too large", max_file);
+                    }
 
-                    if fh.is_none() && total <= MAX_INLINE {
+                    if fh.is_none() && total <= max_inline {
                         buf.extend_from_slice(&chunk);
                     } else {
                         if fh.is_none() {
@@ -1087,7 +1460,10 @@ async fn create_clipboard(
                             match tokio::fs::File::create(&abs).await {
                                 Ok(mut f) => {
                                     if !buf.is_empty() {
-                                        if let Err(_e) = f.write_all(&buf).await {
+                                        if let Err(_e) =
+                                            f.write_all(&crypt::push(&mut sealer, &buf)).await
+                                        {
+                                            bundle::discard(&state.data_dir, &parts).await;
                                             return (
                                                 StatusCode::INTERNAL_SERVER_ERROR,
                                                 Json(serde_json::json!({"error":"write failed"})),
@@ -1099,6 +1475,7 @@ async fn create_clipboard(
                                     rel_path = Some(format!("uploads/{}", gen));
                                 }
                                 Err(_e) => {
+                                    bundle::discard(&state.data_dir, &parts).await;
                                     return (
                                         StatusCode::INTERNAL_SERVER_ERROR,
                                         Json(serde_json::json!({"error":"open failed"})),
@@ -1108,7 +1485,8 @@ async fn create_clipboard(
                             }
                         }
                         if let Some(f) = fh.as_mut() {
-                            if let Err(_e) = f.write_all(&chunk).await {
+                            if let Err(_e) = f.write_all(&crypt::push(&mut sealer, &chunk)).await {
+                                bundle::discard(&state.data_dir, &parts).await;
                                 return (
                                     StatusCode::INTERNAL_SERVER_ERROR,
                                     Json(serde_json::json!({"error":"write failed"})),
@@ -1119,6 +1497,53 @@ async fn create_clipboard(
                     }
                 }
 
+                if let Some(mut f) = fh.take() {
+                    if let Some(s) = sealer.take() {
+                        if let Err(_e) = f.write_all(&s.finish()).await {
+                            discard_upload(&state.data_dir, rel_path.as_deref()).await;
+                            bundle::discard(&state.data_dir, &parts).await;
+                            return (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                Json(serde_json::json!({"error":"write failed"})),
+                            )
+                                .into_response();
+                        }
+                    }
+                    let _ = f.flush().await;
+                }
+
+                // Never trust the client's content type: sniff the real one and re-check policy.
+                // Ciphertext has no type to sniff.
+                let sniffed = match e2ee_meta {
+                    Some(_) => e2ee::CONTENT_TYPE.to_string(),
+                    None => sniff::detect(&head, file_name.as_deref()),
+                };
+                if let Err(reason) = state
+                    .limits
+                    .check_type(Some(&sniffed), file_name.as_deref())
+                {
+                    discard_upload(&state.data_dir, rel_path.as_deref()).await;
+                    bundle::discard(&state.data_dir, &parts).await;
+                    return (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        Json(
+                            serde_json::json!({"error":"Unsupported media type","detail": reason}),
+                        ),
+                    )
+                        .into_response();
+                }
+                detected_type = match e2ee_meta {
+                    // the client's `type` says what the plaintext is
+                    Some(_) => None,
+                    None if sniff::is_previewable_image(&sniffed) => Some(InType::Image),
+                    None => Some(InType::File),
+                };
+                content_type = Some(sniffed);
+                content_hash = Some(crypt::stored_hash(
+                    key.as_ref(),
+                    format!("{:x}", hasher.finalize()),
+                ));
+
                 file_size = Some(total as i64);
                 if let Some(rp) = rel_path {
                     file_path_rel = Some(rp);
@@ -1146,44 +1571,172 @@ async fn create_clipboard(
                     share_password = Some(v);
                 }
             }
+            Some("expiresIn") => {
+                expires_in = Some(field.text().await.unwrap_or_default());
+            }
+            Some("expiresAt") => {
+                expires_at_raw = Some(field.text().await.unwrap_or_default());
+            }
             _ => {}
         }
     }
-    if content.is_none() && inline_data.is_none() && file_path_rel.is_none() {
+    let is_bundle = !parts.is_empty();
+    if is_bundle {
+        if let Some(size) = file_size.take() {
+            parts.push(bundle::Part {
+                path: bundle::clean_path(file_name.as_deref().unwrap_or_default()),
+                content_type: content_type.take().unwrap_or_default(),
+                size,
+                hash: content_hash.take().unwrap_or_default(),
+                rel_path: file_path_rel.take(),
+                inline: inline_data.take(),
+            });
+        }
+        let paths: Vec<String> = parts.iter().map(|p| p.path.clone()).collect();
+        file_name = Some(bundle::display_name(&paths));
+        file_size = Some(parts.iter().map(|p| p.size).sum());
+    }
+    if e2ee_meta.is_some() && content.is_some() {
+        discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error":"end-to-end encrypted text is sent as the file"})),
+        )
+            .into_response();
+    }
+    if content.is_none() && inline_data.is_none() && file_path_rel.is_none() && !is_bundle {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({"error":"Content or file is required"})),
         )
             .into_response();
     }
-    let id = Uuid::new_v4().to_string();
-    let t = match in_type.unwrap_or(InType::Text) {
+    let expires_at =
+        match expiry::parse(expires_in.as_deref(), expires_at_raw.as_deref(), now_unix()) {
+            Ok(v) => v,
+            Err(e) => {
+                discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+                bundle::discard(&state.data_dir, &parts).await;
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": e})),
+                )
+                    .into_response();
+            }
+        };
+    let new_bytes = file_size.map(|n| n as u64).unwrap_or(0)
+        + content.as_ref().map(|c| c.len() as u64).unwrap_or(0);
+    // bundles are limited per file, while receiving
+    if !is_bundle && max_file.is_some_and(|m| new_bytes > m) {
+        discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+        bundle::discard(&state.data_dir, &parts).await;
+        return too_large_response("Item too large", max_file);
+    }
+    let quota = state.limits.quota_bytes;
+    if quota.is_some_and(|q| new_bytes > q) {
+        discard_upload(&state.data_dir, file_path_rel.as_deref()).await;
+        bundle::discard(&state.data_dir, &parts).await;
+        return too_large_response("Item exceeds storage quota", quota);
+    }
+    let quota_policy = state.limits.quota_policy;
+    // Uploaded files are classified from their sniffed type, not the client's `type` field
+    let default_type = match e2ee_meta {
+        Some(_) => InType::File,
+        None => InType::Text,
+    };
+    let t = match detected_type.or(in_type).unwrap_or(default_type) {
+        _ if is_bundle => "BUNDLE",
         InType::Text => "TEXT",
         InType::Image => "IMAGE",
         InType::File => "FILE",
     };
+    // sealed text keeps its plaintext length here, for sizes and the quota
+    if t == "TEXT" && key.is_some() && e2ee_meta.is_none() {
+        file_size = content.as_ref().map(|c| c.len() as i64);
+    }
+    // documents of encrypted items are never extracted, so their text stays out of the index
+    let extract_status =
+        (t == "FILE" && (key.is_some() || e2ee_meta.is_some())).then_some("skipped");
     let now = now_unix();
-    // Assign new items the highest sortWeight so they always appear first
-    let new_weight: i64 = {
-        let conn = state.db.lock().unwrap();
-        let max: i64 = conn
-            .query_row(
-                "SELECT COALESCE(MAX(sortWeight),0) FROM ClipboardItem",
-                [],
-                |r| r.get(0),
+    if is_bundle {
+        if let Err(e) = bundle::store(&state.data_dir, &id, &mut parts).await {
+            bundle::discard(&state.data_dir, &parts).await;
+            trash::remove_files(&state.data_dir, &id, None);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error":"write failed","detail": e.to_string()})),
             )
-            .unwrap_or(0);
-        let w = max + 1;
-        if let Err(e) = conn.execute(
-            "INSERT INTO ClipboardItem (id,type,content,fileName,fileSize,sortWeight,contentType,inlineData,filePath,createdAt,updatedAt) VALUES (?,?,?,?,?,?,?, ?, ?, ?, ?)",
-            params![id, t, content, file_name, file_size, w, content_type, inline_data, file_path_rel, now, now]
-        ) {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error":"db write failed","detail": e.to_string()}))).into_response();
+                .into_response();
+        }
+    }
+    // Assign new items the highest sortWeight so they always appear first
+    let inserted = state
+        .db
+        .write({
+            let (id, content, file_name, content_type) = (
+                id.clone(),
+                content.clone(),
+                file_name.clone(),
+                content_type.clone(),
+            );
+            let (inline_data, file_path_rel) = (inline_data.clone(), file_path_rel.clone());
+            let (key, e2ee_meta) = (key.clone(), e2ee_meta.clone());
+            move |conn| {
+                let max: i64 = conn
+                    .query_row(
+                        "SELECT COALESCE(MAX(sortWeight),0) FROM ClipboardItem",
+                        [],
+                        |r| r.get(0),
+                    )
+                    .unwrap_or(0);
+                let w = max + 1;
+                let tx = conn.transaction()?;
+                let evicted = match quota {
+                    Some(q) => make_room(&tx, q, quota_policy, new_bytes)?,
+                    None => vec![],
+                };
+                tx.execute(
+                    "INSERT INTO ClipboardItem (id,type,content,fileName,fileSize,sortWeight,contentType,filePath,contentHash,createdAt,updatedAt,expiresAt,extractStatus,keyId,dataKey,e2ee) VALUES (?,?,?,?,?,?,?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                    params![id, t, crypt::seal_text(key.as_ref(), content), file_name, file_size, w, content_type, file_path_rel, content_hash, now, now, expires_at, extract_status, key_id, wrapped, e2ee_meta]
+                )?;
+                if let Some(data) = &inline_data {
+                    blob::put(&tx, &id, &id, &crypt::seal_bytes(key.as_ref(), data))?;
+                }
+                bundle::insert_files(&tx, &id, &parts, now, key.as_ref())?;
+                tx.commit()?;
+                Ok::<_, InsertError>((w, evicted))
+            }
+        })
+        .await;
+    let new_weight = match inserted {
+        Ok((w, evicted)) => {
+            for (id, fp) in evicted {
+                trash::remove_files(&state.data_dir, &id, fp.as_deref());
+                tracing::info!(%id, "evicted item to satisfy storage quota");
+                let _ = state.tx.send(ServerEvent {
+                    name: "clipboard:deleted".into(),
+                    data: serde_json::json!({"id": id}),
+                });
+            }
+            w
+        }
+        Err(e) => {
+            trash::remove_files(&state.data_dir, &id, file_path_rel.as_deref());
+            return match e {
+                InsertError::Quota(used) => (
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    Json(serde_json::json!({"error":"Storage quota exceeded","limit": quota, "used": used})),
+                ),
+                InsertError::Db(e) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({"error":"db write failed","detail": e.to_string()})),
+                ),
+            }
+            .into_response();
         }
-        w
     };
     // select minimal fields for broadcast/response
-    let item = serde_json::json!({
+    let mut item = serde_json::json!({
         "id": id,
         "type": t,
         "content": content,
@@ -1192,11 +1745,30 @@ async fn create_clipboard(
         "sortWeight": new_weight,
         "createdAt": OffsetDateTime::from_unix_timestamp(now).unwrap().format(&time::format_description::well_known::Rfc3339).unwrap_or_default(),
         "updatedAt": OffsetDateTime::from_unix_timestamp(now).unwrap().format(&time::format_description::well_known::Rfc3339).unwrap_or_default(),
+        "expiresAt": expires_at.map(epoch_to_iso),
     });
+    if let Some(meta) = e2ee::json(e2ee_meta.clone()) {
+        item["e2ee"] = meta;
+    }
     let _ = state.tx.send(ServerEvent {
         name: "clipboard:created".into(),
         data: item.clone(),
     });
+    if expires_at.is_some() {
+        state.expiry_wake.notify_one();
+    }
+    if t == "IMAGE" && e2ee_meta.is_none() {
+        spawn_thumbnail_job(
+            &state,
+            &id,
+            file_path_rel.clone(),
+            inline_data.clone(),
+            key.clone(),
+        );
+    }
+    if t == "FILE" && extract_status.is_none() {
+        let _ = state.extract_tx.send(id.clone());
+    }
     // Auto-create share for this item (never expire by default, unless provided)
     let (token, expires_at_abs, requires_password) = {
         // token 18 random bytes -> base64url no pad
@@ -1226,13 +1798,18 @@ async fn create_clipboard(
                 Some(p.clone())
             }
         });
-        {
-            let conn = state.db.lock().unwrap();
-            let _ = conn.execute(
-                "INSERT INTO ShareLink (token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,passwordPlain,createdAt,updatedAt) VALUES (?,?,?,?,0,0,?,?,?,?)",
-                params![token, id, expires_at_abs, share_max_downloads, password_hash, password_plain, now, now]
-            );
-        }
+        state
+            .db
+            .write({
+                let (token, id) = (token.clone(), id.clone());
+                move |conn| {
+                    let _ = conn.execute(
+                        "INSERT INTO ShareLink (token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,passwordPlain,createdAt,updatedAt) VALUES (?,?,?,?,0,0,?,?,?,?)",
+                        params![token, id, expires_at_abs, share_max_downloads, password_hash, password_plain, now, now]
+                    );
+                }
+            })
+            .await;
         (token, expires_at_abs, share_password.is_some())
     };
 
@@ -1257,77 +1834,217 @@ async fn create_clipboard(
         .into_response()
 }
 
-async fn get_clipboard(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
-    let conn = state.db.lock().unwrap();
-    let mut stmt = conn.prepare("SELECT id,type,content,fileName,fileSize,sortWeight,contentType,inlineData,filePath,createdAt,updatedAt FROM ClipboardItem WHERE id=? LIMIT 1").unwrap();
-    let row = stmt.query_row([id.clone()], |r| {
-        Ok(ClipboardItem {
-            id: r.get(0)?,
-            item_type: match r.get::<_, String>(1)?.as_str() {
-                "TEXT" => ItemType::Text,
-                "IMAGE" => ItemType::Image,
-                _ => ItemType::File,
-            },
-            content: r.get(2).ok(),
-            file_name: r.get(3).ok(),
-            file_size: r.get(4).ok(),
-            sort_weight: r.get(5).unwrap_or(0),
-            content_type: r.get(6).ok(),
-            inline_data: r.get(7).ok(),
-            file_path: r.get(8).ok(),
-            created_at: epoch_to_iso(r.get::<_, i64>(9).unwrap_or(0)),
-            updated_at: epoch_to_iso(r.get::<_, i64>(10).unwrap_or(0)),
-        })
-    });
-    match row {
-        Ok(mut item) => {
-            item.inline_data = None;
-            Json(item).into_response()
-        }
-        Err(_) => (
-            StatusCode::NOT_FOUND,
-            Json(serde_json::json!({"error":"Not found"})),
-        )
-            .into_response(),
+fn too_large_response(msg: &str, limit: Option<u64>) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(serde_json::json!({"error": msg, "limit": limit})),
+    )
+        .into_response()
+}
+
+// A multipart body that fails mid-way: 413 when it ran into the body limit, else 400
+fn multipart_error(e: MultipartError, limits: &UploadLimits) -> Response {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return too_large_response("Request too large", Some(limits.max_body_bytes as u64));
     }
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({"error": format!("invalid upload: {}", e.body_text())})),
+    )
+        .into_response()
 }
 
-#[derive(Deserialize)]
-struct ReorderReq {
-    ids: Vec<String>,
+async fn discard_upload(data_dir: &StdPath, rel: Option<&str>) {
+    if let Some(rel) = rel {
+        let _ = tokio::fs::remove_file(data_dir.join(rel)).await;
+    }
 }
 
-async fn reorder_clipboard(
-    State(state): State<AppState>,
-    Json(req): Json<ReorderReq>,
-) -> impl IntoResponse {
-    if req.ids.is_empty() {
-        return Json(serde_json::json!({"ok": true}));
+// Bytes counted against STORAGE_QUOTA_BYTES: file sizes plus text lengths (kept in
+// fileSize once the text is sealed).
+fn storage_usage(conn: &dyn Conn) -> u64 {
+    conn.query_row(
+        "SELECT COALESCE(SUM(COALESCE(fileSize, octet_length(content), 0)),0) FROM ClipboardItem",
+        [],
+        |r| r.get::<_, i64>(0),
+    )
+    .map(|n| n.max(0) as u64)
+    .unwrap_or(0)
+}
+
+// Why a new item couldn't be stored
+enum InsertError {
+    /// Over the storage quota, with this many bytes in use
+    Quota(u64),
+    Db(store::Error),
+}
+
+impl From<store::Error> for InsertError {
+    fn from(e: store::Error) -> Self {
+        Self::Db(e)
     }
-    let now = now_unix();
-    let conn = state.db.lock().unwrap();
-    let max: i64 = conn
-        .query_row(
-            "SELECT COALESCE(MAX(sortWeight),0) FROM ClipboardItem",
-            [],
-            |r| r.get(0),
-        )
-        .unwrap_or(0);
-    let base = max + (req.ids.len() as i64);
-    let tx = conn.unchecked_transaction().unwrap();
-    let mut weights: Vec<(String, i64)> = Vec::with_capacity(req.ids.len());
-    for (i, id) in req.ids.iter().enumerate() {
-        let new_weight = base - (i as i64);
-        tx.execute(
-            "UPDATE ClipboardItem SET sortWeight=?, updatedAt=? WHERE id=?",
-            params![new_weight, now, id],
-        )
-        .ok();
-        weights.push((id.clone(), new_weight));
+}
+
+// Make room for `new_bytes` under `quota`, in the transaction that inserts the item so
+// concurrent uploads can't both fit in the same space. Depending on the policy either
+// rejects or deletes the oldest items, returning (id, filePath) of those; their files go
+// once the transaction has committed. Pinned and favorited items are never evicted unless
+// they're in the trash.
+fn make_room(
+    conn: &dyn Conn,
+    quota: u64,
+    policy: QuotaPolicy,
+    new_bytes: u64,
+) -> Result<Vec<(String, Option<String>)>, InsertError> {
+    let used = storage_usage(conn);
+    if used + new_bytes <= quota {
+        return Ok(vec![]);
     }
-    tx.commit().ok();
-    // Build weights mapping for SSE so clients can update local state precisely
-    let mut weights_map = serde_json::Map::new();
+    if policy == QuotaPolicy::Reject {
+        return Err(InsertError::Quota(used));
+    }
+    let mut need = (used + new_bytes) - quota;
+    let mut stmt = conn.prepare("SELECT id, filePath, COALESCE(fileSize, octet_length(content), 0) FROM ClipboardItem WHERE deletedAt IS NOT NULL OR (pinnedAt IS NULL AND favorite = 0) ORDER BY deletedAt IS NULL, createdAt ASC, id ASC")?;
+    let rows = stmt.query_map([], |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            r.get::<_, Option<String>>(1)?,
+            r.get::<_, i64>(2).unwrap_or(0),
+        ))
+    })?;
+    let mut victims: Vec<(String, Option<String>)> = vec![];
+    for row in rows {
+        if need == 0 {
+            break;
+        }
+        let (id, fp, size) = row?;
+        need = need.saturating_sub(size.max(0) as u64);
+        victims.push((id, fp));
+    }
+    drop(stmt);
+    // what's left is pinned or favorited: reject rather than evict part-way
+    if need > 0 {
+        return Err(InsertError::Quota(used));
+    }
+    for (id, _) in &victims {
+        conn.execute("DELETE FROM ClipboardItem WHERE id=?", [id])?;
+    }
+    Ok(victims)
+}
+
+async fn get_clipboard(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
+    let keys = state.keys.clone();
+    state.db.read(move |conn| {
+        let mut stmt = conn.prepare("SELECT id,type,content,fileName,fileSize,sortWeight,contentType,filePath,createdAt,updatedAt,extractStatus,extractError,pinnedAt IS NOT NULL,favorite,archivedAt IS NOT NULL,expiresAt,keyId,dataKey,e2ee FROM ClipboardItem WHERE id=? AND deletedAt IS NULL LIMIT 1").unwrap();
+        let row = stmt.query_row([id.clone()], |r| {
+            let item = ClipboardItem {
+                id: r.get(0)?,
+                item_type: match r.get::<_, String>(1)?.as_str() {
+                    "TEXT" => ItemType::Text,
+                    "IMAGE" => ItemType::Image,
+                    "BUNDLE" => ItemType::Bundle,
+                    _ => ItemType::File,
+                },
+                content: r.get(2).ok(),
+                file_name: r.get(3).ok(),
+                file_size: r.get(4).ok(),
+                sort_weight: r.get(5).unwrap_or(0),
+                content_type: r.get(6).ok(),
+                inline_data: None,
+                file_path: r.get(7).ok(),
+                created_at: epoch_to_iso(r.get::<_, i64>(8).unwrap_or(0)),
+                updated_at: epoch_to_iso(r.get::<_, i64>(9).unwrap_or(0)),
+                snippet: None,
+                pinned: r.get(12).unwrap_or(false),
+                favorite: r.get(13).unwrap_or(false),
+                archived: r.get(14).unwrap_or(false),
+                expires_at: r.get::<_, Option<i64>>(15).ok().flatten().map(epoch_to_iso),
+                e2ee: e2ee::json(r.get(18)?),
+                extract_status: r.get(10).ok().flatten(),
+                extract_error: r.get(11).ok().flatten(),
+                tags: None,
+                collections: None,
+                files: None,
+            };
+            Ok((item, r.get(16)?, r.get(17)?))
+        });
+        match row {
+            Ok((mut item, key_id, wrapped)) => {
+                let content = crypt::item_key(keys.as_deref(), &item.id, key_id, wrapped)
+                    .and_then(|k| crypt::open_text(k.as_ref(), item.content.take()));
+                item.content = match content {
+                    Ok(c) => c,
+                    Err(e) => return crypt::failure(&item.id, e),
+                };
+                item.tags = Some(
+                    tags::tags_for_items(conn, std::slice::from_ref(&item.id))
+                        .remove(&item.id)
+                        .unwrap_or_default(),
+                );
+                item.collections = Some(tags::collections_for_item(conn, &item.id));
+                if matches!(item.item_type, ItemType::Bundle) {
+                    item.files = Some(bundle::list(conn, &item.id));
+                }
+                Json(item).into_response()
+            }
+            Err(_) => (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error":"Not found"})),
+            )
+                .into_response(),
+        }
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct ReorderReq {
+    ids: Vec<String>,
+}
+
+async fn reorder_clipboard(State(state): State<AppState>, Json(req): Json<ReorderReq>) -> Response {
+    if req.ids.is_empty() {
+        return Json(serde_json::json!({"ok": true})).into_response();
+    }
+    let now = now_unix();
+    let ids = req.ids.clone();
+    let weights = state
+        .db
+        .write(move |conn| {
+            let max: i64 = conn
+                .query_row(
+                    "SELECT COALESCE(MAX(sortWeight),0) FROM ClipboardItem",
+                    [],
+                    |r| r.get(0),
+                )
+                .unwrap_or(0);
+            let base = max + (ids.len() as i64);
+            let tx = conn.transaction()?;
+            let mut weights: Vec<(String, i64)> = Vec::with_capacity(ids.len());
+            for (i, id) in ids.iter().enumerate() {
+                let new_weight = base - (i as i64);
+                tx.execute(
+                    "UPDATE ClipboardItem SET sortWeight=?, updatedAt=? WHERE id=?",
+                    params![new_weight, now, id],
+                )?;
+                weights.push((id.clone(), new_weight));
+            }
+            tx.commit()?;
+            Ok::<_, store::Error>(weights)
+        })
+        .await;
+    let weights = match weights {
+        Ok(w) => w,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error":"db write failed","detail": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    // Build weights mapping for SSE so clients can update local state precisely
+    let mut weights_map = serde_json::Map::new();
     for (id, w) in &weights {
         weights_map.insert(id.clone(), serde_json::json!(*w));
     }
@@ -1339,34 +2056,87 @@ async fn reorder_clipboard(
         name: "clipboard:reordered".into(),
         data,
     });
-    Json(serde_json::json!({"ok": true}))
+    Json(serde_json::json!({"ok": true})).into_response()
 }
 
-async fn delete_clipboard(
+#[derive(Deserialize)]
+struct FlagsReq {
+    pinned: Option<bool>,
+    favorite: Option<bool>,
+    archived: Option<bool>,
+}
+
+// PUT /api/clipboard/:id/flags — pin, favorite and archive; omitted fields are left as is
+async fn update_item_flags(
     State(state): State<AppState>,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    let file_path: Option<String> = {
-        let conn = state.db.lock().unwrap();
-        let mut stmt = conn
-            .prepare("SELECT filePath FROM ClipboardItem WHERE id=?")
-            .unwrap();
-        let fp = stmt
-            .query_row([id.clone()], |r| r.get::<_, Option<String>>(0))
+    Json(req): Json<FlagsReq>,
+) -> Response {
+    let now = now_unix();
+    let flags = state
+        .db
+        .write(move |conn| {
+            // COALESCE keeps the original timestamp when re-pinning an already pinned item
+            let changed = conn
+                .execute(
+                    "UPDATE ClipboardItem SET
+                       pinnedAt = CASE CAST(?1 AS INTEGER) WHEN 1 THEN COALESCE(pinnedAt, ?4) WHEN 0 THEN NULL ELSE pinnedAt END,
+                       favorite = COALESCE(?2, favorite),
+                       archivedAt = CASE CAST(?3 AS INTEGER) WHEN 1 THEN COALESCE(archivedAt, ?4) WHEN 0 THEN NULL ELSE archivedAt END
+                     WHERE id = ?5 AND deletedAt IS NULL",
+                    params![req.pinned, req.favorite, req.archived, now, id],
+                )
+                .unwrap_or(0);
+            if changed == 0 {
+                return None;
+            }
+            conn.query_row(
+                "SELECT pinnedAt IS NOT NULL, favorite, archivedAt IS NOT NULL FROM ClipboardItem WHERE id=?",
+                [&id],
+                |r| {
+                    Ok(serde_json::json!({
+                        "id": id,
+                        "pinned": r.get::<_, bool>(0)?,
+                        "favorite": r.get::<_, bool>(1)?,
+                        "archived": r.get::<_, bool>(2)?,
+                    }))
+                },
+            )
             .ok()
-            .flatten();
-        conn.execute("DELETE FROM ClipboardItem WHERE id=?", [id.clone()])
-            .ok();
-        fp
+        })
+        .await;
+    let Some(flags) = flags else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"Not found"})),
+        )
+            .into_response();
     };
-    if let Some(rel) = file_path {
-        let abs = state.data_dir.join(rel);
-        let _ = stdfs::remove_file(abs);
-    }
     let _ = state.tx.send(ServerEvent {
-        name: "clipboard:deleted".into(),
-        data: serde_json::json!({"id": id}),
+        name: "clipboard:flags".into(),
+        data: flags.clone(),
     });
+    Json(flags).into_response()
+}
+
+// Moves the item to the trash; files are removed when it is purged (see trash.rs)
+async fn delete_clipboard(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let trashed = state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| trash::soft_delete(conn, &id)
+        })
+        .await;
+    if trashed {
+        let _ = state.tx.send(ServerEvent {
+            name: "clipboard:deleted".into(),
+            data: serde_json::json!({"id": id, "trashed": true}),
+        });
+    }
     Json(serde_json::json!({"ok": true}))
 }
 
@@ -1375,25 +2145,44 @@ type DbFileRow = (
     Option<Vec<u8>>,
     Option<String>,
     Option<String>,
+    Option<String>,
+    i64,
+    Option<String>,
+    Option<Vec<u8>>,
 );
 
 async fn get_file(
     State(state): State<AppState>,
     Path(id): Path<String>,
     uri: Uri,
+    req_headers: HeaderMap,
 ) -> impl IntoResponse {
     let q = uri.query().unwrap_or("");
     let want_download = form_urlencoded::parse(q.as_bytes())
         .into_owned()
         .any(|(k, v)| k == "download" && matches!(v.as_str(), "1" | "true" | "yes"));
-    let row: Option<DbFileRow> = {
-        let conn = state.db.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT filePath, inlineData, fileName, contentType FROM ClipboardItem WHERE id=? LIMIT 1").unwrap();
-        stmt.query_row([id.clone()], |r| {
-            Ok((r.get(0).ok(), r.get(1).ok(), r.get(2).ok(), r.get(3).ok()))
+    let row: Option<DbFileRow> = state
+        .db
+        .read({
+            let id = id.clone();
+            move |conn| {
+                let mut stmt = conn.prepare("SELECT filePath, (SELECT data FROM Blob WHERE Blob.id = ClipboardItem.id), fileName, contentType, contentHash, createdAt, keyId, dataKey FROM ClipboardItem WHERE id=? AND deletedAt IS NULL LIMIT 1").unwrap();
+                stmt.query_row([id], |r| {
+                    Ok((
+                        r.get(0).ok(),
+                        r.get(1).ok(),
+                        r.get(2).ok(),
+                        r.get(3).ok(),
+                        r.get(4).ok().flatten(),
+                        r.get(5).unwrap_or(0),
+                        r.get(6).ok().flatten(),
+                        r.get(7).ok().flatten(),
+                    ))
+                })
+                .ok()
+            }
         })
-        .ok()
-    };
+        .await;
     if row.is_none() {
         return (
             StatusCode::NOT_FOUND,
@@ -1401,60 +2190,444 @@ async fn get_file(
         )
             .into_response();
     }
-    let (file_path, inline, file_name, content_type) = row.unwrap();
+    let (file_path, inline, file_name, content_type, hash, created_at, key_id, wrapped) =
+        row.unwrap();
     let filename = file_name.unwrap_or_else(|| "download".into());
     let ctype = content_type.unwrap_or_else(|| "application/octet-stream".into());
     let mut headers = axum::http::HeaderMap::new();
+    insert_file_headers(&mut headers, &ctype, &filename, want_download);
+    // Strong caching: file content is immutable by id; allow long-lived cache to speed up subsequent fetches
     headers.insert(
+        axum::http::header::CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=31536000, immutable"),
+    );
+    let key = match crypt::item_key(state.keys.as_deref(), &id, key_id, wrapped) {
+        Ok(key) => key,
+        Err(e) => return crypt::failure(&id, e),
+    };
+    let src = match FileSource::stored(&state.data_dir, file_path, inline, key.clone()) {
+        Ok(Some(src)) => src,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error":"missing content"})),
+            )
+                .into_response()
+        }
+        Err(e) => return crypt::failure(&id, e),
+    };
+    let validators = Validators {
+        etag: ensure_content_hash(&state, "ClipboardItem", &id, hash, &src, key.as_ref())
+            .await
+            .map(|h| ranges::strong_etag(&h)),
+        last_modified: Some(created_at),
+    };
+    ranges::serve(&req_headers, headers, src, &validators)
+        .await
+        .response
+}
+
+// Rows of `table` (ClipboardItem or ItemFile) stored without a content hash get it computed
+// on first download, keyed with the item's data key when it is sealed.
+pub(crate) async fn ensure_content_hash(
+    state: &AppState,
+    table: &'static str,
+    id: &str,
+    stored: Option<String>,
+    src: &FileSource,
+    key: Option<&crypt::ItemKey>,
+) -> Option<String> {
+    if stored.is_some() {
+        return stored;
+    }
+    let hash = match src {
+        FileSource::Inline(buf) => format!("{:x}", Sha256::digest(buf)),
+        FileSource::Path(p) | FileSource::Sealed(p, _) => {
+            let (p, key) = (p.clone(), src.key().cloned());
+            tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+                let mut f: Box<dyn std::io::Read> = match &key {
+                    Some(k) => Box::new(crypt::SealedReader::open(&p, k)?.0),
+                    None => Box::new(stdfs::File::open(p)?),
+                };
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut f, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            })
+            .await
+            .ok()?
+            .ok()?
+        }
+    };
+    let hash = crypt::stored_hash(key, hash);
+    let (h, id) = (hash.clone(), id.to_string());
+    state
+        .db
+        .write(move |conn| {
+            let _ = conn.execute(
+                &format!("UPDATE {table} SET contentHash=? WHERE id=?"),
+                params![h, id],
+            );
+        })
+        .await;
+    Some(hash)
+}
+
+// Pick what a share recipient receives: with SHARE_STRIP_METADATA (default on) JPEG, PNG and
+// WebP images are served from a metadata-stripped copy with its own ETag.
+async fn share_file_source(
+    state: &AppState,
+    item_id: &str,
+    ctype: &str,
+    src: FileSource,
+    hash: Option<String>,
+    key: Option<&crypt::ItemKey>,
+) -> (FileSource, Option<String>) {
+    let hash = ensure_content_hash(state, "ClipboardItem", item_id, hash, &src, key).await;
+    strip_for_share(state, item_id, ctype, src, hash).await
+}
+
+// The stripping half of share_file_source, for content whose hash is already known.
+// Stripped copies of stored files are cached as `cache_id`.
+pub(crate) async fn strip_for_share(
+    state: &AppState,
+    cache_id: &str,
+    ctype: &str,
+    src: FileSource,
+    hash: Option<String>,
+) -> (FileSource, Option<String>) {
+    if !state.strip_share_metadata || !scrub::is_strippable(ctype) {
+        return (src, hash.map(|h| ranges::strong_etag(&h)));
+    }
+    let stripped = match &src {
+        FileSource::Inline(buf) => scrub::strip(buf, ctype).map(FileSource::Inline),
+        FileSource::Path(p) | FileSource::Sealed(p, _) => {
+            let key = src.key().cloned();
+            let cached = scrub::cached_path(&state.data_dir, cache_id);
+            if cached.is_file() {
+                Ok(FileSource::file(cached, key))
+            } else {
+                let (data_dir, id, p, ct, k) = (
+                    state.data_dir.clone(),
+                    cache_id.to_string(),
+                    p.clone(),
+                    ctype.to_string(),
+                    key.clone(),
+                );
+                tokio::task::spawn_blocking(move || {
+                    scrub::write_cached(&data_dir, &id, &p, &ct, k.as_ref())
+                })
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|r| r)
+                .map(|p| FileSource::file(p, key))
+            }
+        }
+    };
+    match stripped {
+        Ok(s) => (s, hash.map(|h| ranges::strong_etag(&format!("{h}-s")))),
+        Err(e) => {
+            // Malformed container: the original is all we have
+            tracing::warn!(id = %cache_id, error = %e, "metadata stripping failed");
+            (src, hash.map(|h| ranges::strong_etag(&h)))
+        }
+    }
+}
+
+// Extract document text one item at a time so a burst of PDFs can't starve the runtime.
+async fn run_extract_worker(state: AppState, mut rx: mpsc::UnboundedReceiver<String>) {
+    while let Some(id) = rx.recv().await {
+        extract_item_text(&state, &id).await;
+    }
+}
+
+// Queue FILE items that were never processed, or were interrupted by a restart.
+async fn queue_pending_extractions(state: &AppState) {
+    let ids: Vec<String> = state
+        .db
+        .read(|conn| {
+            let mut stmt = conn
+                .prepare("SELECT id FROM ClipboardItem WHERE type='FILE' AND dataKey IS NULL AND (extractStatus IS NULL OR extractStatus='pending') ORDER BY createdAt DESC")
+                .unwrap();
+            stmt.query_map([], |r| r.get(0))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect()
+        })
+        .await;
+    for id in ids {
+        let _ = state.extract_tx.send(id);
+    }
+}
+
+async fn extract_item_text(state: &AppState, id: &str) {
+    type ExtractRow = (
+        Option<String>,
+        Option<Vec<u8>>,
+        Option<String>,
+        Option<String>,
+    );
+    let row: Option<ExtractRow> = state
+        .db
+        .read({
+            let id = id.to_string();
+            move |conn| {
+                conn.query_row(
+                    "SELECT filePath, (SELECT data FROM Blob WHERE Blob.id = ClipboardItem.id), contentType, fileName FROM ClipboardItem WHERE id=? AND dataKey IS NULL",
+                    [id],
+                    |r| {
+                        Ok((
+                            r.get(0).ok().flatten(),
+                            r.get(1).ok().flatten(),
+                            r.get(2).ok().flatten(),
+                            r.get(3).ok().flatten(),
+                        ))
+                    },
+                )
+                .ok()
+            }
+        })
+        .await;
+    let Some((file_path, inline, ctype, fname)) = row else {
+        return;
+    };
+    let set_status = |status: &'static str, text: Option<String>, error: Option<String>| {
+        let id = id.to_string();
+        state.db.write(move |conn| {
+            let _ = conn.execute(
+                "UPDATE ClipboardItem SET extractedText=?, extractStatus=?, extractError=? WHERE id=?",
+                params![text, status, error, id],
+            );
+        })
+    };
+    let Some(kind) = extract::kind(ctype.as_deref().unwrap_or(""), fname.as_deref()) else {
+        set_status("skipped", None, None).await;
+        return;
+    };
+    let path = file_path.map(|rel| state.data_dir.join(rel));
+    if let Some(p) = &path {
+        if stdfs::metadata(p).is_ok_and(|m| m.len() > extract::MAX_SOURCE_BYTES) {
+            set_status(
+                "skipped",
+                None,
+                Some("file too large for text extraction".into()),
+            )
+            .await;
+            return;
+        }
+    }
+    set_status("pending", None, None).await;
+    let res = tokio::task::spawn_blocking(move || {
+        let data = match (path, inline) {
+            (Some(p), _) => stdfs::read(p).map_err(|e| format!("read: {e}"))?,
+            (None, Some(buf)) => buf,
+            (None, None) => return Err("missing content".to_string()),
+        };
+        extract::extract(kind, &data)
+    })
+    .await
+    .unwrap_or_else(|_| Err("extractor crashed".into()));
+    match res {
+        Ok(text) => set_status("done", Some(text), None).await,
+        Err(e) => {
+            tracing::warn!(%id, error = %e, "text extraction failed");
+            set_status("failed", None, Some(e)).await;
+        }
+    }
+}
+
+// Generate thumbnails in the background right after an IMAGE upload.
+fn spawn_thumbnail_job(
+    state: &AppState,
+    id: &str,
+    file_path: Option<String>,
+    inline: Option<Vec<u8>>,
+    key: Option<crypt::ItemKey>,
+) {
+    let data_dir = state.data_dir.clone();
+    let id = id.to_string();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = generate_thumbnails(&data_dir, &id, file_path, inline, key.as_ref()) {
+            tracing::warn!(%id, error = %e, "thumbnail generation failed");
+        }
+    });
+}
+
+// `inline` is the plaintext; a file on disk is sealed when `key` is given.
+fn generate_thumbnails(
+    data_dir: &StdPath,
+    id: &str,
+    file_path: Option<String>,
+    inline: Option<Vec<u8>>,
+    key: Option<&crypt::ItemKey>,
+) -> anyhow::Result<()> {
+    match (file_path, inline) {
+        (Some(rel), _) if key.is_some() => {
+            let data = crypt::read(&data_dir.join(rel), key)?;
+            thumbs::generate(data_dir, id, thumbs::ImageSource::Bytes(&data), key)
+        }
+        (Some(rel), _) => thumbs::generate(
+            data_dir,
+            id,
+            thumbs::ImageSource::Path(&data_dir.join(rel)),
+            key,
+        ),
+        (None, Some(buf)) => thumbs::generate(data_dir, id, thumbs::ImageSource::Bytes(&buf), key),
+        (None, None) => Err(anyhow::anyhow!("missing content")),
+    }
+}
+
+fn parse_thumb_size(uri: &Uri) -> u32 {
+    let q = uri.query().unwrap_or("");
+    let requested = form_urlencoded::parse(q.as_bytes())
+        .into_owned()
+        .find(|(k, _)| k == "size")
+        .and_then(|(_, v)| v.parse::<u32>().ok());
+    thumbs::snap_size(requested)
+}
+
+// GET /api/files/:id/thumb?size=256|1024
+async fn get_thumbnail(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    uri: Uri,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    let size = parse_thumb_size(&uri);
+    serve_thumbnail(&state, &id, size, &req_headers).await
+}
+
+// Serve a thumbnail, generating it on demand for items uploaded before thumbnails existed.
+async fn serve_thumbnail(
+    state: &AppState,
+    id: &str,
+    size: u32,
+    req_headers: &HeaderMap,
+) -> Response {
+    type ThumbRow = (
+        String,
+        Option<String>,
+        Option<Vec<u8>>,
+        Option<String>,
+        i64,
+        Option<String>,
+        Option<Vec<u8>>,
+    );
+    let row: Option<ThumbRow> = state.db.read({
+        let id = id.to_string();
+        move |conn| {
+            conn.query_row(
+                "SELECT type, filePath, (SELECT data FROM Blob WHERE Blob.id = ClipboardItem.id), contentHash, createdAt, keyId, dataKey FROM ClipboardItem WHERE id=? AND deletedAt IS NULL AND e2ee IS NULL",
+                [id],
+                |r| {
+                    Ok((
+                        r.get(0)?,
+                        r.get(1).ok().flatten(),
+                        r.get(2).ok().flatten(),
+                        r.get(3).ok().flatten(),
+                        r.get(4).unwrap_or(0),
+                        r.get(5)?,
+                        r.get(6)?,
+                    ))
+                },
+            )
+            .ok()
+        }
+    }).await;
+    let Some((itype, file_path, inline, hash, created_at, key_id, wrapped)) = row else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"Not found"})),
+        )
+            .into_response();
+    };
+    let key = match crypt::item_key(state.keys.as_deref(), id, key_id, wrapped) {
+        Ok(k) => k,
+        Err(e) => return crypt::failure(id, e),
+    };
+    if itype != "IMAGE" {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"not an image"})),
+        )
+            .into_response();
+    }
+    if thumbs::find(&state.data_dir, id, size).is_none() {
+        let data_dir = state.data_dir.clone();
+        let id2 = id.to_string();
+        let key = key.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            let inline = crypt::open_bytes(key.as_ref(), inline)?;
+            generate_thumbnails(&data_dir, &id2, file_path, inline, key.as_ref())
+        })
+        .await;
+        if !matches!(res, Ok(Ok(()))) {
+            return (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(serde_json::json!({"error":"thumbnail not available"})),
+            )
+                .into_response();
+        }
+    }
+    let Some((path, ctype)) = thumbs::find(&state.data_dir, id, size) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"missing"})),
+        )
+            .into_response();
+    };
+    let mut hm = axum::http::HeaderMap::new();
+    hm.insert(
+        axum::http::header::CONTENT_TYPE,
+        HeaderValue::from_static(ctype),
+    );
+    hm.insert(
+        axum::http::header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    hm.insert(
+        axum::http::header::CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=31536000, immutable"),
+    );
+    let validators = Validators {
+        etag: hash.map(|h| ranges::strong_etag(&format!("{h}-t{size}"))),
+        last_modified: Some(created_at),
+    };
+    ranges::serve(req_headers, hm, FileSource::file(path, key), &validators)
+        .await
+        .response
+}
+
+// Content-Type/Content-Disposition for stored uploads. Active content (HTML, SVG, XML, ...)
+// is always forced to download and sandboxed by CSP so it can never run on our origin.
+fn insert_file_headers(hm: &mut HeaderMap, ctype: &str, filename: &str, attachment: bool) {
+    let active = sniff::is_active(ctype);
+    hm.insert(
         axum::http::header::CONTENT_TYPE,
-        HeaderValue::from_str(&ctype).unwrap(),
+        HeaderValue::from_str(ctype)
+            .unwrap_or(HeaderValue::from_static("application/octet-stream")),
     );
     let disp = format!(
         "{}; filename*=UTF-8''{}",
-        if want_download {
+        if attachment || active {
             "attachment"
         } else {
             "inline"
         },
-        urlencoding::encode(&filename)
+        urlencoding::encode(filename)
     );
-    headers.insert(
+    hm.insert(
         axum::http::header::CONTENT_DISPOSITION,
         HeaderValue::from_str(&disp).unwrap(),
     );
-    // Strong caching: file content is immutable by id; allow long-lived cache to speed up subsequent fetches
-    headers.insert(
-        axum::http::header::CACHE_CONTROL,
-        HeaderValue::from_static("public, max-age=31536000, immutable"),
+    hm.insert(
+        axum::http::header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
     );
-    if let Some(rel) = file_path {
-        let abs = state.data_dir.join(rel);
-        if let Ok(meta) = tokio::fs::metadata(&abs).await {
-            headers.insert(
-                axum::http::header::CONTENT_LENGTH,
-                HeaderValue::from_str(&meta.len().to_string()).unwrap(),
-            );
-        }
-        match tokio::fs::File::open(&abs).await {
-            Ok(f) => {
-                let stream = ReaderStream::new(f);
-                let body = Body::from_stream(stream);
-                (StatusCode::OK, headers, body).into_response()
-            }
-            Err(_) => (
-                StatusCode::NOT_FOUND,
-                Json(serde_json::json!({"error":"missing"})),
-            )
-                .into_response(),
-        }
-    } else if let Some(buf) = inline {
-        (StatusCode::OK, headers, buf).into_response()
-    } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(serde_json::json!({"error":"missing content"})),
-        )
-            .into_response()
+    if active {
+        hm.insert(
+            axum::http::header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox; default-src 'none'; style-src 'unsafe-inline'"),
+        );
     }
 }
 
@@ -1464,13 +2637,24 @@ async fn get_file(
 
 // share_valid_row removed (no longer used)
 
+type ShareItemRow = (
+    String,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<Vec<u8>>,
+    Option<String>,
+);
+
 async fn share_meta(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path(token): Path<String>,
 ) -> impl IntoResponse {
     // Query share row and item meta under one lock scope
-    let (
+    let Some((
         token_s,
         item_id,
         exp,
@@ -1483,67 +2667,72 @@ async fn share_meta(
         fsize,
         ctype,
         content,
-    ) = {
-        let conn = state.db.lock().unwrap();
-        let mut st = conn.prepare("SELECT token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,createdAt,updatedAt FROM ShareLink WHERE token=? LIMIT 1").unwrap();
-        let row_opt = st
-            .query_row([token.clone()], |r| {
-                Ok((
-                    r.get::<_, String>(0)?,
-                    r.get::<_, String>(1)?,
-                    r.get::<_, Option<i64>>(2).ok().flatten(),
-                    r.get::<_, Option<i64>>(3).ok().flatten(),
-                    r.get::<_, i64>(4).unwrap_or(0),
-                    r.get::<_, i64>(5).unwrap_or(0),
-                    r.get::<_, Option<String>>(6).ok().flatten(),
-                    r.get::<_, i64>(7).unwrap_or(0),
-                    r.get::<_, i64>(8).unwrap_or(0),
-                ))
-            })
-            .ok();
-        if row_opt.is_none() {
-            return (
-                StatusCode::NOT_FOUND,
-                Json(serde_json::json!({"error":"not found"})),
-            )
-                .into_response();
-        }
-        let (token_s, item_id, exp, max, dcnt, revoked, pwd_hash, _created, _updated) =
-            row_opt.unwrap();
-        let (itype, fname, fsize, ctype, content): (
-            String,
-            Option<String>,
-            Option<i64>,
-            Option<String>,
-            Option<String>,
-        ) = conn
-            .query_row(
-                "SELECT type,fileName,fileSize,contentType,content FROM ClipboardItem WHERE id=?",
-                [item_id.clone()],
-                |rr| {
+        e2ee_meta,
+    )) = state
+        .db
+        .read({
+            let keys = state.keys.clone();
+            move |conn| {
+            let mut st = conn.prepare(&format!("SELECT token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,createdAt,updatedAt FROM ShareLink s WHERE token=? AND {} LIMIT 1", trash::SHARE_ITEM_LIVE)).unwrap();
+            let row_opt = st
+                .query_row([token], |r| {
                     Ok((
-                        rr.get(0)?,
-                        rr.get(1).ok(),
-                        rr.get(2).ok(),
-                        rr.get(3).ok(),
-                        rr.get(4).ok(),
+                        r.get::<_, String>(0)?,
+                        r.get::<_, String>(1)?,
+                        r.get::<_, Option<i64>>(2).ok().flatten(),
+                        r.get::<_, Option<i64>>(3).ok().flatten(),
+                        r.get::<_, i64>(4).unwrap_or(0),
+                        r.get::<_, i64>(5).unwrap_or(0),
+                        r.get::<_, Option<String>>(6).ok().flatten(),
+                        r.get::<_, i64>(7).unwrap_or(0),
+                        r.get::<_, i64>(8).unwrap_or(0),
                     ))
-                },
-            )
-            .unwrap_or(("FILE".into(), None, None, None, None));
-        (
-            token_s, item_id, exp, max, dcnt, revoked, pwd_hash, itype, fname, fsize, ctype,
-            content,
+                })
+                .ok();
+            let (token_s, item_id, exp, max, dcnt, revoked, pwd_hash, _created, _updated) =
+                row_opt?;
+            let (itype, fname, fsize, ctype, content, key_id, wrapped, e2ee_meta): ShareItemRow = conn
+                .query_row(
+                    "SELECT type,fileName,fileSize,contentType,content,keyId,dataKey,e2ee FROM ClipboardItem WHERE id=?",
+                    [item_id.clone()],
+                    |rr| {
+                        Ok((
+                            rr.get(0)?,
+                            rr.get(1).ok(),
+                            rr.get(2).ok(),
+                            rr.get(3).ok(),
+                            rr.get(4).ok(),
+                            rr.get(5).ok().flatten(),
+                            rr.get(6).ok().flatten(),
+                            rr.get(7).ok().flatten(),
+                        ))
+                    },
+                )
+                .unwrap_or(("FILE".into(), None, None, None, None, None, None, None));
+            let content = crypt::item_key(keys.as_deref(), &item_id, key_id, wrapped)
+                .and_then(|key| crypt::open_text(key.as_ref(), content));
+            Some((
+                token_s, item_id, exp, max, dcnt, revoked, pwd_hash, itype, fname, fsize, ctype,
+                content, e2ee_meta,
+            ))
+        }})
+        .await
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"not found"})),
         )
+            .into_response();
     };
     // validity check and cleanup
     let is_expired = exp.is_some_and(|e| e < now_unix());
     let is_exhausted = max.is_some_and(|m| m >= 0 && dcnt >= m);
     if revoked != 0 || is_expired || is_exhausted {
-        // Delete expired/exhausted items automatically
-        if is_expired || is_exhausted {
-            let conn2 = state.db.lock().unwrap();
-            let _ = conn2.execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
+        // Delete expired items; spent ones go once their last transfer had time to finish
+        if is_expired {
+            delete_share_item(&state, item_id).await;
+        } else if is_exhausted {
+            retire_share_item(&state, &item_id).await;
         }
         return (
             StatusCode::NOT_FOUND,
@@ -1565,25 +2754,51 @@ async fn share_meta(
             })
             .unwrap_or(false);
     }
+    // end-to-end encrypted text is fetched from /file like any file, and counted there
+    let inline_text = itype == "TEXT" && e2ee_meta.is_none();
+    let content = match content {
+        Ok(content) => content,
+        Err(e) if authorized && inline_text => return crypt::failure(&item_id, e),
+        Err(_) => None,
+    };
     // If TEXT and authorized, count this access
-    if authorized && itype == "TEXT" {
-        let conn2 = state.db.lock().unwrap();
-        let _ = conn2.execute(
-            "UPDATE ShareLink SET downloadCount=downloadCount+1, updatedAt=? WHERE token=?",
-            params![now_unix(), token_s.clone()],
-        );
+    if authorized && inline_text {
         // Keep response count in sync optimistically
         dcnt += 1;
-        // Check if exhausted after increment
-        if let Some(m) = max {
-            if m >= 0 && dcnt >= m {
-                let _ = conn2.execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
-            }
+        let (token_s, id) = (token_s.clone(), item_id.clone());
+        let removed = state
+            .db
+            .write(move |conn| {
+                let _ = conn.execute(
+                    "UPDATE ShareLink SET downloadCount=downloadCount+1, updatedAt=? WHERE token=?",
+                    params![now_unix(), token_s],
+                );
+                // Check if exhausted after increment
+                match max {
+                    Some(m) if m >= 0 && dcnt >= m => delete_item_row(conn, &id),
+                    _ => None,
+                }
+            })
+            .await;
+        if let Some(file_path) = removed {
+            trash::remove_files(&state.data_dir, &item_id, file_path.as_deref());
         }
     }
+    // file list of a bundle, for recipients to pick from
+    let files = if authorized && itype == "BUNDLE" {
+        let item_id = item_id.clone();
+        Some(
+            state
+                .db
+                .read(move |conn| bundle::list(conn, &item_id))
+                .await,
+        )
+    } else {
+        None
+    };
     Json(serde_json::json!({
         "token": token_s,
-        "item": {"id": item_id, "type": itype, "fileName": fname, "fileSize": fsize, "contentType": ctype, "content": if authorized && itype=="TEXT" { content } else { None } },
+        "item": {"id": item_id, "type": itype, "fileName": fname, "fileSize": fsize, "contentType": ctype, "content": if authorized && inline_text { content } else { None }, "files": files, "e2ee": if authorized { e2ee::json(e2ee_meta) } else { None } },
         "expiresAt": exp.map(epoch_to_iso),
         "maxDownloads": max,
         "downloadCount": dcnt,
@@ -1603,14 +2818,23 @@ async fn share_verify(
     Path(token): Path<String>,
     Json(body): Json<ShareVerifyReq>,
 ) -> impl IntoResponse {
-    let conn = state.db.lock().unwrap();
-    let pwd_hash: Option<String> = conn
-        .query_row(
-            "SELECT passwordHash FROM ShareLink WHERE token=?",
-            [token.clone()],
-            |r| r.get(0),
-        )
-        .ok();
+    let pwd_hash: Option<String> = state
+        .db
+        .read({
+            let token = token.clone();
+            move |conn| {
+                conn.query_row(
+                    &format!(
+                        "SELECT passwordHash FROM ShareLink s WHERE token=? AND {}",
+                        trash::SHARE_ITEM_LIVE
+                    ),
+                    [token],
+                    |r| r.get(0),
+                )
+                .ok()
+            }
+        })
+        .await;
     if pwd_hash.is_none() {
         return (
             StatusCode::BAD_REQUEST,
@@ -1656,57 +2880,56 @@ async fn get_item_share(
 ) -> impl IntoResponse {
     let now = now_unix();
     // Try find latest share
-    let row2 = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
+    state.db.write(move |conn| {
+        let row2 = conn.query_row(
             "SELECT token, expiresAt, maxDownloads, downloadCount, revoked, passwordHash, passwordPlain, createdAt FROM ShareLink WHERE itemId=? ORDER BY createdAt DESC LIMIT 1",
             [id.clone()],
             |r| Ok((
                 r.get::<_,String>(0)?, r.get::<_,Option<i64>>(1).ok().flatten(), r.get::<_,Option<i64>>(2).ok().flatten(), r.get::<_,i64>(3).unwrap_or(0), r.get::<_,i64>(4).unwrap_or(0), r.get::<_,Option<String>>(5).ok().flatten(), r.get::<_,Option<String>>(6).ok().flatten(), r.get::<_,i64>(7).unwrap_or(0)
             ))
-        ).ok()
-    };
-    let (token, exp, max, dcnt, revoked, pwd_hash, pwd_plain) =
-        if let Some((t, e, m, d, rev, ph, pp, _created)) = row2 {
-            (t, e, m, d, rev, ph, pp)
-        } else {
-            (String::new(), None, None, 0, 0, None, None)
-        };
-    let mut token_out = token;
-    let mut exp_out = exp;
-    let mut max_out = max;
-    let mut dcnt_out = dcnt;
-    let mut pwd_out = pwd_hash;
-    let invalid = token_out.is_empty()
-        || revoked != 0
-        || exp_out.map(|e| e < now).unwrap_or(false)
-        || max_out.map(|m| m >= 0 && dcnt_out >= m).unwrap_or(false);
-    if invalid {
-        // Auto provision
-        let mut buf = [0u8; 18];
-        rand::thread_rng().fill_bytes(&mut buf);
-        let new_token = B64_URL_SAFE_NO_PAD.encode(buf);
-        let conn = state.db.lock().unwrap();
-        let _ = conn.execute(
-            "INSERT INTO ShareLink (token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,createdAt,updatedAt) VALUES (?,?,?,?,0,0,?,?,?)",
-            params![new_token, id.clone(), Option::<i64>::None, Option::<i64>::None, Option::<String>::None, now, now]
-        );
-        token_out = new_token;
-        exp_out = None;
-        max_out = None;
-        dcnt_out = 0;
-        pwd_out = None;
-    }
-    let url = format!("/s/?token={}", token_out);
-    Json(serde_json::json!({
-        "token": token_out,
-        "url": url,
-        "expiresAt": exp_out.map(epoch_to_iso),
-        "maxDownloads": max_out,
-        "downloadCount": dcnt_out,
-        "requiresPassword": pwd_out.is_some(),
-        "password": pwd_plain,
-    }))
+        ).ok();
+        let (token, exp, max, dcnt, revoked, pwd_hash, pwd_plain) =
+            if let Some((t, e, m, d, rev, ph, pp, _created)) = row2 {
+                (t, e, m, d, rev, ph, pp)
+            } else {
+                (String::new(), None, None, 0, 0, None, None)
+            };
+        let mut token_out = token;
+        let mut exp_out = exp;
+        let mut max_out = max;
+        let mut dcnt_out = dcnt;
+        let mut pwd_out = pwd_hash;
+        let invalid = token_out.is_empty()
+            || revoked != 0
+            || exp_out.map(|e| e < now).unwrap_or(false)
+            || max_out.map(|m| m >= 0 && dcnt_out >= m).unwrap_or(false);
+        if invalid {
+            // Auto provision
+            let mut buf = [0u8; 18];
+            rand::thread_rng().fill_bytes(&mut buf);
+            let new_token = B64_URL_SAFE_NO_PAD.encode(buf);
+            let _ = conn.execute(
+                "INSERT INTO ShareLink (token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,createdAt,updatedAt) VALUES (?,?,?,?,0,0,?,?,?)",
+                params![new_token, id.clone(), Option::<i64>::None, Option::<i64>::None, Option::<String>::None, now, now]
+            );
+            token_out = new_token;
+            exp_out = None;
+            max_out = None;
+            dcnt_out = 0;
+            pwd_out = None;
+        }
+        let url = format!("/s/?token={}", token_out);
+        Json(serde_json::json!({
+            "token": token_out,
+            "url": url,
+            "expiresAt": exp_out.map(epoch_to_iso),
+            "maxDownloads": max_out,
+            "downloadCount": dcnt_out,
+            "requiresPassword": pwd_out.is_some(),
+            "password": pwd_plain,
+        }))
+    })
+    .await
 }
 
 #[derive(Deserialize)]
@@ -1725,60 +2948,60 @@ async fn update_item_share(
     Json(req): Json<ShareUpdateReq>,
 ) -> impl IntoResponse {
     let now = now_unix();
-    let conn = state.db.lock().unwrap();
-    if matches!(req.disable, Some(true)) {
-        let _ = conn.execute(
-            "UPDATE ShareLink SET revoked=1, updatedAt=? WHERE itemId=? AND revoked=0",
-            params![now, id],
-        );
-        return Json(serde_json::json!({"ok": true}));
-    }
-    // get latest row for item or create
-    type ShareSnapshot = (String, Option<i64>, Option<i64>, i64, Option<String>);
-    let current: Option<ShareSnapshot> = conn.query_row(
-        "SELECT token, expiresAt, maxDownloads, downloadCount, passwordHash FROM ShareLink WHERE itemId=? ORDER BY createdAt DESC LIMIT 1",
-        [id.clone()], |r| Ok((r.get(0)?, r.get(1).ok().flatten(), r.get(2).ok().flatten(), r.get(3).unwrap_or(0), r.get(4).ok().flatten()))
-    ).ok();
-    let mut token = if let Some((t, _, _, _, _)) = current.as_ref() {
-        t.clone()
-    } else {
-        let mut buf = [0u8; 18];
-        rand::thread_rng().fill_bytes(&mut buf);
-        let t = B64_URL_SAFE_NO_PAD.encode(buf);
-        let _ = conn.execute("INSERT INTO ShareLink (token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,createdAt,updatedAt) VALUES (?,?,?,?,0,0,?,?,?)", params![t, id.clone(), Option::<i64>::None, Option::<i64>::None, Option::<String>::None, now, now]);
-        t
-    };
-    let expires_abs: Option<i64> =
-        req.expires_in
-            .and_then(|sec| if sec > 0 { Some(now + sec) } else { None });
-    let max = req
-        .max_downloads
-        .or_else(|| current.as_ref().and_then(|c| c.2));
-    if matches!(req.reset, Some(true)) {
-        let mut buf = [0u8; 18];
-        rand::thread_rng().fill_bytes(&mut buf);
-        let new_token = B64_URL_SAFE_NO_PAD.encode(buf);
-        let new_hash: Option<String> = req.password.as_ref().and_then(|p| {
-            if p.trim().is_empty() {
-                None
-            } else {
-                let mut h = Sha256::new();
-                h.update(p.as_bytes());
-                h.update(b"|");
-                h.update(new_token.as_bytes());
-                Some(format!("{:x}", h.finalize()))
-            }
-        });
-        let new_plain: Option<String> = req.password.as_ref().and_then(|p| {
-            if p.trim().is_empty() {
-                None
-            } else {
-                Some(p.clone())
-            }
-        });
-        let _ = conn.execute("UPDATE ShareLink SET token=?, expiresAt=?, maxDownloads=?, passwordHash=?, passwordPlain=?, updatedAt=? WHERE token=?", params![new_token, expires_abs, max, new_hash, new_plain, now, token]);
-        token = new_token;
-    } else if req.password.is_some() || req.expires_in.is_some() || req.max_downloads.is_some() {
+    state.db.write(move |conn| {
+        if matches!(req.disable, Some(true)) {
+            let _ = conn.execute(
+                "UPDATE ShareLink SET revoked=1, updatedAt=? WHERE itemId=? AND revoked=0",
+                params![now, id],
+            );
+            return Json(serde_json::json!({"ok": true}));
+        }
+        // get latest row for item or create
+        type ShareSnapshot = (String, Option<i64>, Option<i64>, i64, Option<String>);
+        let current: Option<ShareSnapshot> = conn.query_row(
+            "SELECT token, expiresAt, maxDownloads, downloadCount, passwordHash FROM ShareLink WHERE itemId=? ORDER BY createdAt DESC LIMIT 1",
+            [id.clone()], |r| Ok((r.get(0)?, r.get(1).ok().flatten(), r.get(2).ok().flatten(), r.get(3).unwrap_or(0), r.get(4).ok().flatten()))
+        ).ok();
+        let mut token = if let Some((t, _, _, _, _)) = current.as_ref() {
+            t.clone()
+        } else {
+            let mut buf = [0u8; 18];
+            rand::thread_rng().fill_bytes(&mut buf);
+            let t = B64_URL_SAFE_NO_PAD.encode(buf);
+            let _ = conn.execute("INSERT INTO ShareLink (token,itemId,expiresAt,maxDownloads,downloadCount,revoked,passwordHash,createdAt,updatedAt) VALUES (?,?,?,?,0,0,?,?,?)", params![t, id.clone(), Option::<i64>::None, Option::<i64>::None, Option::<String>::None, now, now]);
+            t
+        };
+        let expires_abs: Option<i64> =
+            req.expires_in
+                .and_then(|sec| if sec > 0 { Some(now + sec) } else { None });
+        let max = req
+            .max_downloads
+            .or_else(|| current.as_ref().and_then(|c| c.2));
+        if matches!(req.reset, Some(true)) {
+            let mut buf = [0u8; 18];
+            rand::thread_rng().fill_bytes(&mut buf);
+            let new_token = B64_URL_SAFE_NO_PAD.encode(buf);
+            let new_hash: Option<String> = req.password.as_ref().and_then(|p| {
+                if p.trim().is_empty() {
+                    None
+                } else {
+                    let mut h = Sha256::new();
+                    h.update(p.as_bytes());
+                    h.update(b"|");
+                    h.update(new_token.as_bytes());
+                    Some(format!("{:x}", h.finalize()))
+                }
+            });
+            let new_plain: Option<String> = req.password.as_ref().and_then(|p| {
+                if p.trim().is_empty() {
+                    None
+                } else {
+                    Some(p.clone())
+                }
+            });
+            let _ = conn.execute("UPDATE ShareLink SET token=?, expiresAt=?, maxDownloads=?, passwordHash=?, passwordPlain=?, updatedAt=? WHERE token=?", params![new_token, expires_abs, max, new_hash, new_plain, now, token]);
+            token = new_token;
+        } else if req.password.is_some() || req.expires_in.is_some() || req.max_downloads.is_some() {
             let hash: Option<String> = req.password.as_ref().and_then(|p| {
                 if p.trim().is_empty() {
                     None
@@ -1798,22 +3021,24 @@ async fn update_item_share(
                 }
             });
             let _ = conn.execute("UPDATE ShareLink SET expiresAt=?, maxDownloads=?, passwordHash=?, passwordPlain=?, updatedAt=? WHERE token=?", params![expires_abs, max, hash, plain, now, token]);
-    }
-    let (exp, maxd, dcnt, pwdhash, pwdplain):(Option<i64>, Option<i64>, i64, Option<String>, Option<String>) = conn.query_row(
-        "SELECT expiresAt, maxDownloads, downloadCount, passwordHash, passwordPlain FROM ShareLink WHERE token=?",
-        [token.clone()],
-        |r| Ok((r.get(0).ok().flatten(), r.get(1).ok().flatten(), r.get(2).unwrap_or(0), r.get(3).ok().flatten(), r.get(4).ok().flatten()))
-    ).unwrap_or((None,None,0,None,None));
-    let url = format!("/s/?token={}", token);
-    Json(serde_json::json!({
-        "token": token,
-        "url": url,
-        "expiresAt": exp.map(epoch_to_iso),
-        "maxDownloads": maxd,
-        "downloadCount": dcnt,
-        "requiresPassword": pwdhash.is_some(),
-        "password": pwdplain,
-    }))
+        }
+        let (exp, maxd, dcnt, pwdhash, pwdplain):(Option<i64>, Option<i64>, i64, Option<String>, Option<String>) = conn.query_row(
+            "SELECT expiresAt, maxDownloads, downloadCount, passwordHash, passwordPlain FROM ShareLink WHERE token=?",
+            [token.clone()],
+            |r| Ok((r.get(0).ok().flatten(), r.get(1).ok().flatten(), r.get(2).unwrap_or(0), r.get(3).ok().flatten(), r.get(4).ok().flatten()))
+        ).unwrap_or((None,None,0,None,None));
+        let url = format!("/s/?token={}", token);
+        Json(serde_json::json!({
+            "token": token,
+            "url": url,
+            "expiresAt": exp.map(epoch_to_iso),
+            "maxDownloads": maxd,
+            "downloadCount": dcnt,
+            "requiresPassword": pwdhash.is_some(),
+            "password": pwdplain,
+        }))
+    })
+    .await
 }
 
 // Generate QR code SVG for a share link. No auth required.
@@ -1825,22 +3050,28 @@ async fn share_qr(
     uri: Uri,
 ) -> impl IntoResponse {
     // Validate share is still valid (not revoked/expired/exhausted)
-    let row = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
-            "SELECT expiresAt, maxDownloads, downloadCount, revoked FROM ShareLink WHERE token=?",
-            [token.clone()],
-            |r| {
-                Ok((
-                    r.get::<_, Option<i64>>(0).ok().flatten(),
-                    r.get::<_, Option<i64>>(1).ok().flatten(),
-                    r.get::<_, i64>(2).unwrap_or(0),
-                    r.get::<_, i64>(3).unwrap_or(0),
-                ))
-            },
-        )
-        .ok()
-    };
+    let row = state.db.read({
+        let token = token.clone();
+        move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT expiresAt, maxDownloads, downloadCount, revoked, itemId FROM ShareLink s WHERE token=? AND {}",
+                    trash::SHARE_ITEM_LIVE
+                ),
+                [token.clone()],
+                |r| {
+                    Ok((
+                        r.get::<_, Option<i64>>(0).ok().flatten(),
+                        r.get::<_, Option<i64>>(1).ok().flatten(),
+                        r.get::<_, i64>(2).unwrap_or(0),
+                        r.get::<_, i64>(3).unwrap_or(0),
+                        r.get::<_, String>(4)?,
+                    ))
+                },
+            )
+            .ok()
+        }
+    }).await;
     if row.is_none() {
         return (
             StatusCode::NOT_FOUND,
@@ -1848,26 +3079,16 @@ async fn share_qr(
         )
             .into_response();
     }
-    let (exp, max, dcnt, revoked) = row.unwrap();
+    let (exp, max, dcnt, revoked, item_id) = row.unwrap();
     let now = now_unix();
     let is_expired = exp.map(|e| e < now).unwrap_or(false);
     let is_exhausted = max.map(|m| m >= 0 && dcnt >= m).unwrap_or(false);
     if revoked != 0 || is_expired || is_exhausted {
-        // Delete expired/exhausted items automatically
-        if is_expired || is_exhausted {
-            let item_id_opt: Option<String> = {
-                let conn = state.db.lock().unwrap();
-                conn.query_row(
-                    "SELECT itemId FROM ShareLink WHERE token=?",
-                    [token.clone()],
-                    |r| r.get(0),
-                )
-                .ok()
-            };
-            if let Some(iid) = item_id_opt {
-                let conn2 = state.db.lock().unwrap();
-                let _ = conn2.execute("DELETE FROM ClipboardItem WHERE id=?", [iid]);
-            }
+        // Delete expired items; spent ones go once their last transfer had time to finish
+        if is_expired {
+            delete_share_item(&state, item_id).await;
+        } else if is_exhausted {
+            retire_share_item(&state, &item_id).await;
         }
         return (
             StatusCode::NOT_FOUND,
@@ -1933,13 +3154,85 @@ async fn share_qr(
         let fname = format!("share-{}.svg", &token[..std::cmp::min(8, token.len())]);
         hm.insert(
             axum::http::header::CONTENT_DISPOSITION,
-            HeaderValue::from_str(&format!("attachment; filename=\"{fname}\""))
-                .unwrap(),
+            HeaderValue::from_str(&format!("attachment; filename=\"{fname}\"")).unwrap(),
         );
     }
     (StatusCode::OK, hm, svg).into_response()
 }
 
+// GET /api/share/:token/thumb?size=256|1024 — preview rendition for the share page.
+// Does not count as a download.
+async fn share_thumb(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    uri: Uri,
+) -> impl IntoResponse {
+    let row = state.db.read({
+        let token = token.clone();
+        move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT itemId, expiresAt, maxDownloads, downloadCount, revoked, passwordHash FROM ShareLink s WHERE token=? AND {}",
+                    trash::SHARE_ITEM_LIVE
+                ),
+                [token.clone()],
+                |r| {
+                    Ok((
+                        r.get::<_, String>(0)?,
+                        r.get::<_, Option<i64>>(1).ok().flatten(),
+                        r.get::<_, Option<i64>>(2).ok().flatten(),
+                        r.get::<_, i64>(3).unwrap_or(0),
+                        r.get::<_, i64>(4).unwrap_or(0),
+                        r.get::<_, Option<String>>(5).ok().flatten(),
+                    ))
+                },
+            )
+            .ok()
+        }
+    }).await;
+    let Some((item_id, exp, max, dcnt, revoked, pwd_hash)) = row else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"not found"})),
+        )
+            .into_response();
+    };
+    let is_expired = exp.is_some_and(|e| e < now_unix());
+    let is_exhausted = max.is_some_and(|m| m >= 0 && dcnt >= m);
+    if revoked != 0 || is_expired || is_exhausted {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"not found"})),
+        )
+            .into_response();
+    }
+    if let Some(ph) = pwd_hash.as_ref() {
+        let ok = headers
+            .get(axum::http::header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|c| {
+                c.split(';')
+                    .any(|p| p.trim() == format!("share_auth_{token}={ph}"))
+            });
+        if !ok {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error":"unauthorized"})),
+            )
+                .into_response();
+        }
+    }
+    let size = parse_thumb_size(&uri);
+    let mut res = serve_thumbnail(&state, &item_id, size, &headers).await;
+    // Share links can be revoked, so keep the preview out of shared caches
+    res.headers_mut().insert(
+        axum::http::header::CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=3600"),
+    );
+    res
+}
+
 fn make_qr_svg(text: &str, size_px: u32, margin_modules: u32) -> Result<String, ()> {
     use qrcodegen::{QrCode, QrCodeEcc};
     let qr = QrCode::encode_text(text, QrCodeEcc::Medium).map_err(|_| ())?;
@@ -1966,20 +3259,30 @@ fn make_qr_svg(text: &str, size_px: u32, margin_modules: u32) -> Result<String,
     Ok(s)
 }
 
-async fn share_file_inner(state: AppState, token: String, headers: HeaderMap) -> impl IntoResponse {
-    let row = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
-            "SELECT s.token, s.passwordHash, s.maxDownloads, s.downloadCount, s.expiresAt, s.revoked, s.itemId, c.type, c.content, c.fileName, c.fileSize, c.contentType, c.filePath, c.inlineData FROM ShareLink s LEFT JOIN ClipboardItem c ON s.itemId=c.id WHERE s.token=?",
-            [token.clone()],
-            |r| Ok((
-                r.get::<_,String>(0)?, r.get::<_,Option<String>>(1).ok().flatten(), r.get::<_,Option<i64>>(2).ok().flatten(), r.get::<_,i64>(3).unwrap_or(0),
-                r.get::<_,Option<i64>>(4).ok().flatten(), r.get::<_,i64>(5).unwrap_or(0), r.get::<_,String>(6)?,
-                r.get::<_,String>(7)?, r.get::<_,Option<String>>(8).ok().flatten(), r.get::<_,Option<String>>(9).ok().flatten(), r.get::<_,Option<i64>>(10).ok().flatten(),
-                r.get::<_,Option<String>>(11).ok().flatten(), r.get::<_,Option<String>>(12).ok().flatten(), r.get::<_,Option<Vec<u8>>>(13).ok().flatten()
-            ))
-        ).ok()
-    };
+async fn share_file_inner(
+    state: AppState,
+    token: String,
+    method: Method,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let row = state.db.read({
+        let token = token.clone();
+        move |conn| {
+            conn.query_row(
+                "SELECT s.token, s.passwordHash, s.maxDownloads, s.downloadCount, s.expiresAt, s.revoked, s.itemId, c.type, c.content, c.fileName, c.fileSize, c.contentType, c.filePath, c.contentHash, c.createdAt, c.keyId, c.dataKey, c.e2ee FROM ShareLink s LEFT JOIN ClipboardItem c ON s.itemId=c.id WHERE s.token=? AND c.deletedAt IS NULL",
+                [token.clone()],
+                |r| Ok((
+                    r.get::<_,String>(0)?, r.get::<_,Option<String>>(1).ok().flatten(), r.get::<_,Option<i64>>(2).ok().flatten(), r.get::<_,i64>(3).unwrap_or(0),
+                    r.get::<_,Option<i64>>(4).ok().flatten(), r.get::<_,i64>(5).unwrap_or(0), r.get::<_,String>(6)?,
+                    r.get::<_,String>(7)?, r.get::<_,Option<String>>(8).ok().flatten(), r.get::<_,Option<String>>(9).ok().flatten(), r.get::<_,Option<i64>>(10).ok().flatten(),
+                    r.get::<_,Option<String>>(11).ok().flatten(), r.get::<_,Option<String>>(12).ok().flatten(),
+                    r.get::<_,Option<String>>(13).ok().flatten(), r.get::<_,i64>(14).unwrap_or(0),
+                    r.get::<_,Option<String>>(15).ok().flatten(), r.get::<_,Option<Vec<u8>>>(16).ok().flatten(),
+                    r.get::<_,Option<String>>(17).ok().flatten()
+                ))
+            ).ok()
+        }
+    }).await;
     if row.is_none() {
         return (
             StatusCode::NOT_FOUND,
@@ -2001,15 +3304,24 @@ async fn share_file_inner(state: AppState, token: String, headers: HeaderMap) ->
         _fsize,
         ctype,
         fpath,
-        inline,
+        hash,
+        created_at,
+        key_id,
+        wrapped,
+        e2ee_meta,
     ) = row.unwrap();
     // validity check and cleanup
     let is_expired = exp.is_some_and(|e| e < now_unix());
     let is_exhausted = max.is_some_and(|m| m >= 0 && dcnt >= m);
-    if revoked != 0 || is_expired || is_exhausted {
-        if is_expired || is_exhausted {
-            let conn2 = state.db.lock().unwrap();
-            let _ = conn2.execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
+    // a spent share may still serve ranges continuing its last transfer
+    let continuing = is_exhausted
+        && headers.contains_key(axum::http::header::RANGE)
+        && !(itype == "TEXT" && e2ee_meta.is_none());
+    if revoked != 0 || is_expired || (is_exhausted && !continuing) {
+        if is_expired {
+            delete_share_item(&state, item_id).await;
+        } else if is_exhausted {
+            retire_share_item(&state, &item_id).await;
         }
         return (
             StatusCode::NOT_FOUND,
@@ -2034,8 +3346,16 @@ async fn share_file_inner(state: AppState, token: String, headers: HeaderMap) ->
                 .into_response();
         }
     }
-    if itype == "TEXT" {
-        let text = content.unwrap_or_default();
+    let key = match crypt::item_key(state.keys.as_deref(), &item_id, key_id, wrapped) {
+        Ok(key) => key,
+        Err(e) => return crypt::failure(&item_id, e).into_response(),
+    };
+    // end-to-end encrypted text is ciphertext, served like a file
+    if itype == "TEXT" && e2ee_meta.is_none() {
+        let text = match crypt::open_text(key.as_ref(), content) {
+            Ok(text) => text.unwrap_or_default(),
+            Err(e) => return crypt::failure(&item_id, e).into_response(),
+        };
         // Count access for TEXT as part of meta endpoint, avoid double-count here
         return (
             StatusCode::OK,
@@ -2050,92 +3370,155 @@ async fn share_file_inner(state: AppState, token: String, headers: HeaderMap) ->
     let filename = fname.unwrap_or_else(|| "download".into());
     let ctype = ctype.unwrap_or_else(|| "application/octet-stream".into());
     let mut headers_out = axum::http::HeaderMap::new();
-    headers_out.insert(
-        axum::http::header::CONTENT_TYPE,
-        HeaderValue::from_str(&ctype).unwrap(),
-    );
-    headers_out.insert(
-        axum::http::header::CONTENT_DISPOSITION,
-        HeaderValue::from_str(&format!(
-            "inline; filename*=UTF-8''{}",
-            urlencoding::encode(&filename)
-        ))
-        .unwrap(),
-    );
-    if let Some(rel) = fpath {
-        let abs = state.data_dir.join(rel);
-        if let Ok(meta) = tokio::fs::metadata(&abs).await {
-            headers_out.insert(
-                axum::http::header::CONTENT_LENGTH,
-                HeaderValue::from_str(&meta.len().to_string()).unwrap(),
-            );
-        }
-        match tokio::fs::File::open(&abs).await {
-            Ok(f) => {
-                // increment access count and check if exhausted after increment
-                {
-                    let conn = state.db.lock().unwrap();
-                    let _= conn.execute("UPDATE ShareLink SET downloadCount=downloadCount+1, updatedAt=? WHERE token=?", params![now_unix(), token_s.clone()]);
-                    if let Some(m) = max {
-                        if m >= 0 && (dcnt + 1) >= m {
-                            let _ = conn
-                                .execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
-                        }
-                    }
-                }
-                let stream = ReaderStream::new(f);
-                let body = Body::from_stream(stream);
-                return (StatusCode::OK, headers_out, body).into_response();
-            }
-            Err(_) => {
-                return (
-                    StatusCode::NOT_FOUND,
-                    Json(serde_json::json!({"error":"missing"})),
-                )
-                    .into_response()
-            }
+    insert_file_headers(&mut headers_out, &ctype, &filename, false);
+    let inline = match fpath {
+        Some(_) => None,
+        None => blob::load(&state.db, &item_id).await,
+    };
+    let src = match FileSource::stored(&state.data_dir, fpath, inline, key.clone()) {
+        Ok(Some(src)) => src,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error":"missing content"})),
+            )
+                .into_response()
         }
+        Err(e) => return crypt::failure(&item_id, e).into_response(),
+    };
+    let (src, etag) = share_file_source(&state, &item_id, &ctype, src, hash, key.as_ref()).await;
+    let validators = Validators {
+        etag,
+        last_modified: Some(created_at),
+    };
+    let served = ranges::serve(&headers, headers_out, src, &validators).await;
+    // seeks, revalidations and HEAD requests are not new downloads
+    finish_share_download(&state, &token_s, &item_id, (max, dcnt), &method, served).await
+}
+
+// A spent share's item is kept this long (seconds), so the transfer that used up its last
+// download can still seek and resume; the expiry task deletes it afterwards.
+const SHARE_TRANSFER_GRACE: i64 = 600;
+
+// Count a share file response that starts a transfer. A spent share only serves requests
+// continuing its last transfer (ranges past the first byte); new transfers get 404.
+pub(crate) async fn finish_share_download(
+    state: &AppState,
+    token: &str,
+    item_id: &str,
+    (max, dcnt): (Option<i64>, i64),
+    method: &Method,
+    served: ranges::Served,
+) -> Response {
+    if !served.counts_as_download {
+        return served.response;
     }
-    if let Some(buf) = inline {
-        {
-            let conn = state.db.lock().unwrap();
+    if max.is_some_and(|m| m >= 0 && dcnt >= m) {
+        retire_share_item(state, item_id).await;
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"not found"})),
+        )
+            .into_response();
+    }
+    if *method != Method::GET {
+        return served.response;
+    }
+    count_share_download(state, token, item_id, max, dcnt, served.response).await
+}
+
+// increment access count; once max downloads is reached the item is retired
+pub(crate) async fn count_share_download(
+    state: &AppState,
+    token: &str,
+    item_id: &str,
+    max: Option<i64>,
+    dcnt: i64,
+    response: Response,
+) -> Response {
+    let token = token.to_string();
+    state
+        .db
+        .write(move |conn| {
             let _ = conn.execute(
                 "UPDATE ShareLink SET downloadCount=downloadCount+1, updatedAt=? WHERE token=?",
-                params![now_unix(), token_s.clone()],
+                params![now_unix(), token],
             );
-            if let Some(m) = max {
-                if m >= 0 && (dcnt + 1) >= m {
-                    let _ = conn.execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
-                }
-            }
-        }
-        return (StatusCode::OK, headers_out, buf).into_response();
+        })
+        .await;
+    if max.is_some_and(|m| m >= 0 && (dcnt + 1) >= m) {
+        retire_share_item(state, item_id).await;
+    }
+    response
+}
+
+// have the expiry task delete a spent share's item once SHARE_TRANSFER_GRACE is over
+async fn retire_share_item(state: &AppState, item_id: &str) {
+    let id = item_id.to_string();
+    let at = now_unix() + SHARE_TRANSFER_GRACE;
+    state
+        .db
+        .write(move |conn| {
+            let _ = conn.execute(
+                "UPDATE ClipboardItem SET expiresAt=? WHERE id=? AND (expiresAt IS NULL OR expiresAt > ?)",
+                params![at, id, at],
+            );
+        })
+        .await;
+    state.expiry_wake.notify_one();
+}
+
+// Delete an item row; Some(filePath) when it existed
+fn delete_item_row(conn: &dyn Conn, id: &str) -> Option<Option<String>> {
+    let file_path: Option<String> = conn
+        .query_row("SELECT filePath FROM ClipboardItem WHERE id=?", [id], |r| {
+            r.get(0)
+        })
+        .ok()?;
+    conn.execute("DELETE FROM ClipboardItem WHERE id=?", [id])
+        .ok()
+        .map(|_| file_path)
+}
+
+// Expired share: the item goes with it
+async fn delete_share_item(state: &AppState, item_id: String) {
+    let removed = state
+        .db
+        .write({
+            let item_id = item_id.clone();
+            move |conn| delete_item_row(conn, &item_id)
+        })
+        .await;
+    if let Some(file_path) = removed {
+        trash::remove_files(&state.data_dir, &item_id, file_path.as_deref());
     }
-    (
-        StatusCode::NOT_FOUND,
-        Json(serde_json::json!({"error":"missing content"})),
-    )
-        .into_response()
 }
 
 async fn share_download_inner(
     state: AppState,
     token: String,
+    method: Method,
     headers: HeaderMap,
+    uri: Uri,
 ) -> impl IntoResponse {
-    let row = {
-        let conn = state.db.lock().unwrap();
-        conn.query_row(
-            "SELECT s.token, s.passwordHash, s.maxDownloads, s.downloadCount, s.expiresAt, s.revoked, s.itemId, c.type, c.content, c.fileName, c.fileSize, c.contentType, c.filePath, c.inlineData FROM ShareLink s LEFT JOIN ClipboardItem c ON s.itemId=c.id WHERE s.token=?",
-            [token.clone()],
-            |r| Ok((
-                r.get::<_,String>(0)?, r.get::<_,Option<String>>(1).ok().flatten(), r.get::<_,Option<i64>>(2).ok().flatten(), r.get::<_,i64>(3).unwrap_or(0),
-                r.get::<_,Option<i64>>(4).ok().flatten(), r.get::<_,i64>(5).unwrap_or(0), r.get::<_,String>(6)?,
-                r.get::<_,String>(7)?, r.get::<_,Option<String>>(8).ok().flatten(), r.get::<_,Option<String>>(9).ok().flatten(), r.get::<_,Option<i64>>(10).ok().flatten(),
-                r.get::<_,Option<String>>(11).ok().flatten(), r.get::<_,Option<String>>(12).ok().flatten(), r.get::<_,Option<Vec<u8>>>(13).ok().flatten()
-            ))
-        ).ok()
-    };
+    let row = state.db.read({
+        let token = token.clone();
+        move |conn| {
+            conn.query_row(
+                "SELECT s.token, s.passwordHash, s.maxDownloads, s.downloadCount, s.expiresAt, s.revoked, s.itemId, c.type, c.content, c.fileName, c.fileSize, c.contentType, c.filePath, c.contentHash, c.createdAt, c.keyId, c.dataKey, c.e2ee FROM ShareLink s LEFT JOIN ClipboardItem c ON s.itemId=c.id WHERE s.token=? AND c.deletedAt IS NULL",
+                [token.clone()],
+                |r| Ok((
+                    r.get::<_,String>(0)?, r.get::<_,Option<String>>(1).ok().flatten(), r.get::<_,Option<i64>>(2).ok().flatten(), r.get::<_,i64>(3).unwrap_or(0),
+                    r.get::<_,Option<i64>>(4).ok().flatten(), r.get::<_,i64>(5).unwrap_or(0), r.get::<_,String>(6)?,
+                    r.get::<_,String>(7)?, r.get::<_,Option<String>>(8).ok().flatten(), r.get::<_,Option<String>>(9).ok().flatten(), r.get::<_,Option<i64>>(10).ok().flatten(),
+                    r.get::<_,Option<String>>(11).ok().flatten(), r.get::<_,Option<String>>(12).ok().flatten(),
+                    r.get::<_,Option<String>>(13).ok().flatten(), r.get::<_,i64>(14).unwrap_or(0),
+                    r.get::<_,Option<String>>(15).ok().flatten(), r.get::<_,Option<Vec<u8>>>(16).ok().flatten(),
+                    r.get::<_,Option<String>>(17).ok().flatten()
+                ))
+            ).ok()
+        }
+    }).await;
     if row.is_none() {
         return (
             StatusCode::NOT_FOUND,
@@ -2157,15 +3540,24 @@ async fn share_download_inner(
         _fsize,
         ctype,
         fpath,
-        inline,
+        hash,
+        created_at,
+        key_id,
+        wrapped,
+        e2ee_meta,
     ) = row.unwrap();
     // validity check and cleanup
     let is_expired = exp.is_some_and(|e| e < now_unix());
     let is_exhausted = max.is_some_and(|m| m >= 0 && dcnt >= m);
-    if revoked != 0 || is_expired || is_exhausted {
-        if is_expired || is_exhausted {
-            let conn2 = state.db.lock().unwrap();
-            let _ = conn2.execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
+    // a spent share may still serve ranges continuing its last transfer
+    let continuing = is_exhausted
+        && headers.contains_key(axum::http::header::RANGE)
+        && !(itype == "TEXT" && e2ee_meta.is_none());
+    if revoked != 0 || is_expired || (is_exhausted && !continuing) {
+        if is_expired {
+            delete_share_item(&state, item_id).await;
+        } else if is_exhausted {
+            retire_share_item(&state, &item_id).await;
         }
         return (
             StatusCode::NOT_FOUND,
@@ -2189,23 +3581,22 @@ async fn share_download_inner(
                 .into_response();
         }
     }
-    // increment downloadCount and check if exhausted after increment
-    {
-        let conn = state.db.lock().unwrap();
-        let _ = conn.execute(
-            "UPDATE ShareLink SET downloadCount=downloadCount+1, updatedAt=? WHERE token=?",
-            params![now_unix(), token.clone()],
-        );
-        if let Some(m) = max {
-            if m >= 0 && (dcnt + 1) >= m {
-                let _ = conn.execute("DELETE FROM ClipboardItem WHERE id=?", [item_id.clone()]);
-            }
-        }
+    if itype == "BUNDLE" {
+        // all files, or the ones picked with files=a,b, as one archive
+        return bundle::share_archive(&state, &token_s, &method, &headers, &uri).await;
     }
-    if itype == "TEXT" {
-        let text = content.unwrap_or_default();
+    let key = match crypt::item_key(state.keys.as_deref(), &item_id, key_id, wrapped) {
+        Ok(key) => key,
+        Err(e) => return crypt::failure(&item_id, e),
+    };
+    // end-to-end encrypted text is ciphertext, served like a file
+    if itype == "TEXT" && e2ee_meta.is_none() {
+        let text = match crypt::open_text(key.as_ref(), content) {
+            Ok(text) => text.unwrap_or_default(),
+            Err(e) => return crypt::failure(&item_id, e),
+        };
         let filename = format!("{}.txt", fname.unwrap_or_else(|| "download".into()));
-        return (
+        let response = (
             StatusCode::OK,
             [
                 (
@@ -2224,51 +3615,35 @@ async fn share_download_inner(
             text,
         )
             .into_response();
+        if method == Method::GET {
+            return count_share_download(&state, &token_s, &item_id, max, dcnt, response).await;
+        }
+        return response;
     }
     let filename = fname.unwrap_or_else(|| "download".into());
     let ctype = ctype.unwrap_or_else(|| "application/octet-stream".into());
     let mut headers_out = axum::http::HeaderMap::new();
-    headers_out.insert(
-        axum::http::header::CONTENT_TYPE,
-        HeaderValue::from_str(&ctype).unwrap(),
-    );
-    headers_out.insert(
-        axum::http::header::CONTENT_DISPOSITION,
-        HeaderValue::from_str(&format!(
-            "attachment; filename*=UTF-8''{}",
-            urlencoding::encode(&filename)
-        ))
-        .unwrap(),
-    );
-    if let Some(rel) = fpath {
-        let abs = state.data_dir.join(rel);
-        if let Ok(meta) = tokio::fs::metadata(&abs).await {
-            headers_out.insert(
-                axum::http::header::CONTENT_LENGTH,
-                HeaderValue::from_str(&meta.len().to_string()).unwrap(),
-            );
-        }
-        match tokio::fs::File::open(&abs).await {
-            Ok(f) => {
-                let stream = ReaderStream::new(f);
-                let body = Body::from_stream(stream);
-                return (StatusCode::OK, headers_out, body).into_response();
-            }
-            Err(_) => {
-                return (
-                    StatusCode::NOT_FOUND,
-                    Json(serde_json::json!({"error":"missing"})),
-                )
-                    .into_response()
-            }
+    insert_file_headers(&mut headers_out, &ctype, &filename, true);
+    let inline = match fpath {
+        Some(_) => None,
+        None => blob::load(&state.db, &item_id).await,
+    };
+    let src = match FileSource::stored(&state.data_dir, fpath, inline, key.clone()) {
+        Ok(Some(src)) => src,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error":"missing content"})),
+            )
+                .into_response()
         }
-    }
-    if let Some(buf) = inline {
-        return (StatusCode::OK, headers_out, buf).into_response();
-    }
-    (
-        StatusCode::NOT_FOUND,
-        Json(serde_json::json!({"error":"missing content"})),
-    )
-        .into_response()
+        Err(e) => return crypt::failure(&item_id, e),
+    };
+    let (src, etag) = share_file_source(&state, &item_id, &ctype, src, hash, key.as_ref()).await;
+    let validators = Validators {
+        etag,
+        last_modified: Some(created_at),
+    };
+    let served = ranges::serve(&headers, headers_out, src, &validators).await;
+    finish_share_download(&state, &token_s, &item_id, (max, dcnt), &method, served).await
 }
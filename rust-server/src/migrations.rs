@@ -0,0 +1,415 @@
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{blob, bundle, crypt, search};
+
+// -------------------- Schema migrations --------------------
+//
+// The schema version is kept in PRAGMA user_version: version N means the first N entries
+// of MIGRATIONS have been applied. Each migration runs in its own transaction together
+// with the version bump, so a failed one leaves the database at the previous version.
+//
+// Databases created before versioning report version 0 but may already have any of the
+// early tables and columns, so migrations up to 11 only create what is missing. Later
+// ones can assume the schema of the version before them. Append new migrations at the
+// end and never change one that has shipped.
+
+struct Migration {
+    name: &'static str,
+    up: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "clipboard items and share links",
+        up: initial,
+    },
+    Migration {
+        name: "ShareLink.passwordPlain",
+        up: |c| add_column(c, "ShareLink", "passwordPlain", "TEXT"),
+    },
+    Migration {
+        // hex sha256 backing strong ETags; filled lazily for older rows
+        name: "ClipboardItem.contentHash",
+        up: |c| add_column(c, "ClipboardItem", "contentHash", "TEXT"),
+    },
+    Migration {
+        name: "extracted document text",
+        up: |c| {
+            add_column(c, "ClipboardItem", "extractedText", "TEXT")?;
+            add_column(c, "ClipboardItem", "extractStatus", "TEXT")?;
+            add_column(c, "ClipboardItem", "extractError", "TEXT")
+        },
+    },
+    Migration {
+        name: "full-text search index",
+        up: search::init,
+    },
+    Migration {
+        name: "tags and collections",
+        up: tags_and_collections,
+    },
+    Migration {
+        name: "item revisions",
+        up: revisions,
+    },
+    Migration {
+        // pinnedAt and archivedAt are NULL when unset
+        name: "pin, favorite and archive flags",
+        up: |c| {
+            add_column(c, "ClipboardItem", "pinnedAt", "INTEGER")?;
+            add_column(c, "ClipboardItem", "favorite", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column(c, "ClipboardItem", "archivedAt", "INTEGER")
+        },
+    },
+    Migration {
+        // set while the item is in the trash
+        name: "ClipboardItem.deletedAt",
+        up: |c| add_column(c, "ClipboardItem", "deletedAt", "INTEGER"),
+    },
+    Migration {
+        name: "ClipboardItem.expiresAt",
+        up: |c| add_column(c, "ClipboardItem", "expiresAt", "INTEGER"),
+    },
+    Migration {
+        name: "multi-file items",
+        up: bundle::init,
+    },
+    Migration {
+        name: "inline data moved to Blob",
+        up: blob::init,
+    },
+    Migration {
+        // per-item data key (wrapped) and the master key that wraps it
+        name: "encryption at rest",
+        up: |c| {
+            add_column(c, "ClipboardItem", "keyId", "TEXT")?;
+            add_column(c, "ClipboardItem", "dataKey", "BLOB")?;
+            search::skip_sealed_content(c)
+        },
+    },
+    Migration {
+        name: "end-to-end encrypted items",
+        up: |c| add_column(c, "ClipboardItem", "e2ee", "TEXT"),
+    },
+    Migration {
+        name: "keyed content hashes",
+        up: |c| c.execute_batch(crypt::CLEAR_SEALED_HASHES),
+    },
+];
+
+/// Schema version this build writes.
+pub(crate) const LATEST: u32 = MIGRATIONS.len() as u32;
+
+fn initial(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r"
+        CREATE TABLE IF NOT EXISTS ClipboardItem (
+          id TEXT PRIMARY KEY NOT NULL,
+          type TEXT NOT NULL,
+          content TEXT,
+          fileName TEXT,
+          fileSize INTEGER,
+          sortWeight INTEGER NOT NULL DEFAULT 0,
+          contentType TEXT,
+          inlineData BLOB,
+          filePath TEXT,
+          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          updatedAt INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        CREATE INDEX IF NOT EXISTS clipboard_created_idx ON ClipboardItem (createdAt, id);
+
+        CREATE TABLE IF NOT EXISTS ShareLink (
+          token TEXT PRIMARY KEY NOT NULL,
+          itemId TEXT NOT NULL,
+          expiresAt INTEGER,
+          maxDownloads INTEGER,
+          downloadCount INTEGER NOT NULL DEFAULT 0,
+          revoked INTEGER NOT NULL DEFAULT 0,
+          passwordHash TEXT,
+          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          updatedAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          CONSTRAINT share_item_fk FOREIGN KEY (itemId) REFERENCES ClipboardItem(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS share_item_idx ON ShareLink (itemId);
+        CREATE INDEX IF NOT EXISTS share_created_idx ON ShareLink (createdAt);
+        ",
+    )
+}
+
+fn tags_and_collections(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r"
+        CREATE TABLE IF NOT EXISTS Tag (
+          id TEXT PRIMARY KEY NOT NULL,
+          name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+          color TEXT,
+          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          updatedAt INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        CREATE TABLE IF NOT EXISTS ItemTag (
+          itemId TEXT NOT NULL,
+          tagId TEXT NOT NULL,
+          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          PRIMARY KEY (itemId, tagId),
+          CONSTRAINT item_tag_item_fk FOREIGN KEY (itemId) REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          CONSTRAINT item_tag_tag_fk FOREIGN KEY (tagId) REFERENCES Tag(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS item_tag_tag_idx ON ItemTag (tagId);
+
+        CREATE TABLE IF NOT EXISTS Collection (
+          id TEXT PRIMARY KEY NOT NULL,
+          name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+          description TEXT,
+          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          updatedAt INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        CREATE TABLE IF NOT EXISTS CollectionItem (
+          collectionId TEXT NOT NULL,
+          itemId TEXT NOT NULL,
+          addedAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          PRIMARY KEY (collectionId, itemId),
+          CONSTRAINT collection_item_collection_fk FOREIGN KEY (collectionId) REFERENCES Collection(id) ON DELETE CASCADE,
+          CONSTRAINT collection_item_item_fk FOREIGN KEY (itemId) REFERENCES ClipboardItem(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS collection_item_item_idx ON CollectionItem (itemId);
+        ",
+    )
+}
+
+fn revisions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r"
+        CREATE TABLE IF NOT EXISTS ItemRevision (
+          itemId TEXT NOT NULL,
+          rev INTEGER NOT NULL,
+          content TEXT,
+          fileName TEXT,
+          contentType TEXT,
+          createdAt INTEGER NOT NULL DEFAULT (unixepoch()),
+          PRIMARY KEY (itemId, rev),
+          CONSTRAINT item_revision_item_fk FOREIGN KEY (itemId) REFERENCES ClipboardItem(id) ON DELETE CASCADE
+        );
+        ",
+    )
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|r| r.is_some())
+}
+
+// For the migrations that pre-versioning databases may already have applied.
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |r| r.get(0))
+}
+
+/// Bring the database up to LATEST. Refuses databases written by a newer build, which
+/// this one can't know how to read.
+pub(crate) fn run(conn: &Connection) -> anyhow::Result<()> {
+    let current = version(conn)?;
+    if current > LATEST {
+        anyhow::bail!(
+            "database schema version {current} is newer than this build supports ({LATEST}); upgrade clip-relay"
+        );
+    }
+    for (v, m) in (1..).zip(MIGRATIONS).skip(current as usize) {
+        let tx = conn.unchecked_transaction()?;
+        (m.up)(&tx).with_context(|| format!("migration {v} ({}) failed", m.name))?;
+        tx.pragma_update(None, "user_version", v)?;
+        tx.commit()?;
+        tracing::info!(version = v, name = m.name, "applied schema migration");
+    }
+    Ok(())
+}
+
+/// `clip-relay migrate [--status]`: apply pending migrations, or only list them.
+pub(crate) fn run_cli(args: &[String], db_path: &std::path::Path) -> anyhow::Result<()> {
+    let conn = Connection::open(db_path)?;
+    search::register_functions(&conn)?;
+    if args.get(1).map(String::as_str) == Some("--status") {
+        let current = version(&conn)?;
+        println!("schema version {current} (this build: {LATEST})");
+        for (v, m) in (1..).zip(MIGRATIONS) {
+            let state = if v <= current { "applied" } else { "pending" };
+            println!("{v:>4}  {state:<8} {}", m.name);
+        }
+        if current > LATEST {
+            println!("database is newer than this build");
+        }
+        return Ok(());
+    }
+    let before = version(&conn)?;
+    run(&conn)?;
+    println!("schema version {before} -> {LATEST}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        search::register_functions(&conn).unwrap();
+        conn
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")
+            .unwrap();
+        stmt.query_map([table], |r| r.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    // The schema as the first release created it, with no user_version.
+    fn legacy(conn: &Connection) {
+        initial(conn).unwrap();
+        conn.execute(
+            "INSERT INTO ClipboardItem (id, type, content) VALUES ('a', 'TEXT', 'hello world')",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO ShareLink (token, itemId) VALUES ('tok', 'a')",
+            [],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest() {
+        let conn = open();
+        run(&conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), LATEST);
+        for table in [
+            "ClipboardItem",
+            "ShareLink",
+            "Tag",
+            "ItemRevision",
+            "ItemFile",
+        ] {
+            assert!(!columns(&conn, table).is_empty(), "{table} missing");
+        }
+    }
+
+    #[test]
+    fn legacy_database_upgrades_and_keeps_data() {
+        let conn = open();
+        legacy(&conn);
+        assert_eq!(version(&conn).unwrap(), 0);
+        run(&conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), LATEST);
+        let cols = columns(&conn, "ClipboardItem");
+        for c in [
+            "contentHash",
+            "extractedText",
+            "favorite",
+            "deletedAt",
+            "expiresAt",
+        ] {
+            assert!(cols.contains(&c.to_string()), "{c} missing");
+        }
+        assert!(columns(&conn, "ShareLink").contains(&"passwordPlain".to_string()));
+        let (content, favorite): (String, i64) = conn
+            .query_row(
+                "SELECT content, favorite FROM ClipboardItem WHERE id='a'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((content.as_str(), favorite), ("hello world", 0));
+        // existing rows are indexed for search
+        let hit: String = conn
+            .query_row(
+                "SELECT itemId FROM ClipboardSearch WHERE ClipboardSearch MATCH 'world'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(hit, "a");
+    }
+
+    #[test]
+    fn inline_data_moves_to_blob() {
+        let conn = open();
+        legacy(&conn);
+        conn.execute(
+            "INSERT INTO ClipboardItem (id, type, fileName, inlineData) VALUES ('f', 'FILE', 'a.bin', x'00ff10')",
+            [],
+        )
+        .unwrap();
+        run(&conn).unwrap();
+        assert!(!columns(&conn, "ClipboardItem").contains(&"inlineData".to_string()));
+        assert!(!columns(&conn, "ItemFile").contains(&"inlineData".to_string()));
+        assert_eq!(blob::get(&conn, "f"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(blob::get(&conn, "a"), None);
+        // blobs go with their item
+        conn.execute_batch("PRAGMA foreign_keys = ON; DELETE FROM ClipboardItem WHERE id='f';")
+            .unwrap();
+        assert_eq!(blob::get(&conn, "f"), None);
+    }
+
+    #[test]
+    fn partially_patched_database_upgrades() {
+        // a database the old ad hoc ALTER TABLE checks had already half migrated
+        let conn = open();
+        legacy(&conn);
+        conn.execute_batch(
+            "ALTER TABLE ShareLink ADD COLUMN passwordPlain TEXT;
+             ALTER TABLE ClipboardItem ADD COLUMN contentHash TEXT;",
+        )
+        .unwrap();
+        run(&conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), LATEST);
+    }
+
+    #[test]
+    fn running_again_is_a_no_op() {
+        let conn = open();
+        run(&conn).unwrap();
+        run(&conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), LATEST);
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let conn = open();
+        conn.pragma_update(None, "user_version", LATEST + 1)
+            .unwrap();
+        let err = run(&conn).unwrap_err().to_string();
+        assert!(err.contains("newer"), "{err}");
+        assert_eq!(version(&conn).unwrap(), LATEST + 1);
+    }
+
+    #[test]
+    fn failed_migration_leaves_version_unchanged() {
+        let conn = open();
+        run(&conn).unwrap();
+        // pretend migration 12 (inline data moved to Blob) is pending while its table is
+        // unusable
+        conn.pragma_update(None, "user_version", 11).unwrap();
+        conn.execute_batch("DROP TABLE ItemFile; CREATE VIEW ItemFile AS SELECT 1 AS id;")
+            .unwrap();
+        assert!(run(&conn).is_err());
+        assert_eq!(version(&conn).unwrap(), 11);
+    }
+}
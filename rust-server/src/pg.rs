@@ -0,0 +1,457 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+
+use anyhow::Context;
+use bytes::BytesMut;
+use postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+use postgres::{Client, Config, GenericClient, NoTls, Statement};
+
+use crate::store::{Conn, Dialect, Result, Row, Value};
+use crate::{crypt, search};
+
+// -------------------- PostgreSQL --------------------
+//
+// Used when DATABASE_URL is a postgres:// URL. Integers are BIGINT, blobs BYTEA and flags
+// stay 0/1 integers so statements read the same as on SQLite; full-text search is a
+// generated tsvector column (see search.rs). Each connection keeps its prepared
+// statements, and reconnects on next use if the server dropped it.
+
+// Prepared statements kept per connection before the cache is cleared
+const STATEMENT_CACHE: usize = 256;
+
+pub(crate) struct PgConn {
+    config: Config,
+    client: RefCell<Client>,
+    statements: RefCell<HashMap<String, Statement>>,
+}
+
+impl PgConn {
+    pub(crate) fn connect(config: &Config) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            client: RefCell::new(config.connect(NoTls)?),
+            statements: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn statement(&self, client: &mut Client, sql: &str) -> Result<Statement> {
+        let mut cache = self.statements.borrow_mut();
+        if let Some(st) = cache.get(sql) {
+            return Ok(st.clone());
+        }
+        if cache.len() >= STATEMENT_CACHE {
+            cache.clear();
+        }
+        let st = client.prepare(&numbered(sql))?;
+        cache.insert(sql.to_string(), st.clone());
+        Ok(st)
+    }
+
+    fn client(&self) -> Result<std::cell::RefMut<'_, Client>> {
+        let mut client = self.client.borrow_mut();
+        if client.is_closed() {
+            tracing::warn!("postgres connection lost; reconnecting");
+            *client = self.config.connect(NoTls)?;
+            self.statements.borrow_mut().clear();
+        }
+        Ok(client)
+    }
+}
+
+impl Conn for PgConn {
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    fn exec(&self, sql: &str, params: &[Value]) -> Result<usize> {
+        let mut client = self.client()?;
+        let st = self.statement(&mut client, sql)?;
+        let args: Vec<Param> = params.iter().map(Param).collect();
+        let refs: Vec<&(dyn ToSql + Sync)> = args.iter().map(|p| p as _).collect();
+        Ok(client.execute(&st, &refs)? as usize)
+    }
+
+    fn fetch(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        let mut client = self.client()?;
+        let st = self.statement(&mut client, sql)?;
+        let args: Vec<Param> = params.iter().map(Param).collect();
+        let refs: Vec<&(dyn ToSql + Sync)> = args.iter().map(|p| p as _).collect();
+        client
+            .query(&st, &refs)?
+            .iter()
+            .map(|r| {
+                (0..r.len())
+                    .map(|i| Ok(r.try_get::<_, Cell>(i)?.0))
+                    .collect::<Result<_>>()
+                    .map(Row)
+            })
+            .collect()
+    }
+
+    fn exec_batch(&self, sql: &str) -> Result<()> {
+        Ok(self.client()?.batch_execute(sql)?)
+    }
+}
+
+/// `?` / `?N` placeholders to `$N`, leaving quoted strings and identifiers alone.
+fn numbered(sql: &str) -> Cow<'_, str> {
+    if !sql.contains('?') {
+        return Cow::Borrowed(sql);
+    }
+    let mut out = String::with_capacity(sql.len() + 8);
+    let mut next = 0;
+    let mut quote: Option<char> = None;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '?') => {
+                let digits: String =
+                    std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+                if digits.is_empty() {
+                    next += 1;
+                    out.push_str(&format!("${next}"));
+                } else {
+                    out.push('$');
+                    out.push_str(&digits);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    Cow::Owned(out)
+}
+
+// A bound value, encoded as whatever type the server inferred for its placeholder.
+#[derive(Debug)]
+struct Param<'a>(&'a Value);
+
+type BoxError = Box<dyn StdError + Sync + Send>;
+
+impl ToSql for Param<'_> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        match (self.0, ty) {
+            (Value::Null, _) => Ok(IsNull::Yes),
+            (Value::Integer(i), &Type::INT8) => i.to_sql(ty, out),
+            (Value::Integer(i), &Type::INT4) => i32::try_from(*i)?.to_sql(ty, out),
+            (Value::Integer(i), &Type::INT2) => i16::try_from(*i)?.to_sql(ty, out),
+            (Value::Integer(i), &Type::BOOL) => (*i != 0).to_sql(ty, out),
+            (Value::Integer(i), &Type::FLOAT8) => (*i as f64).to_sql(ty, out),
+            (Value::Integer(i), &Type::FLOAT4) => (*i as f32).to_sql(ty, out),
+            (Value::Real(f), &Type::FLOAT8) => f.to_sql(ty, out),
+            (Value::Real(f), &Type::FLOAT4) => (*f as f32).to_sql(ty, out),
+            (Value::Integer(i), _) => i.to_string().to_sql(&Type::TEXT, out),
+            (Value::Real(f), _) => f.to_string().to_sql(&Type::TEXT, out),
+            (Value::Text(s), &Type::INT8) => s.parse::<i64>()?.to_sql(ty, out),
+            (Value::Text(s), _) => s.to_sql(&Type::TEXT, out),
+            (Value::Blob(b), _) => b.to_sql(&Type::BYTEA, out),
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+// A column value read back as the SQLite value it stands for.
+struct Cell(Value);
+
+impl<'a> FromSql<'a> for Cell {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        Ok(Self(match *ty {
+            Type::INT8 => Value::Integer(i64::from_sql(ty, raw)?),
+            Type::INT4 => Value::Integer(i32::from_sql(ty, raw)?.into()),
+            Type::INT2 => Value::Integer(i16::from_sql(ty, raw)?.into()),
+            Type::BOOL => Value::Integer(bool::from_sql(ty, raw)?.into()),
+            Type::FLOAT8 => Value::Real(f64::from_sql(ty, raw)?),
+            Type::FLOAT4 => Value::Real(f32::from_sql(ty, raw)?.into()),
+            Type::NUMERIC => numeric(raw)?,
+            Type::BYTEA => Value::Blob(Vec::<u8>::from_sql(ty, raw)?),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                Value::Text(String::from_sql(ty, raw)?)
+            }
+            _ => return Err(format!("column type {ty}").into()),
+        }))
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Self, BoxError> {
+        Ok(Self(Value::Null))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+// NUMERIC (what SUM over BIGINT gives) in binary form: digit count, weight, sign and
+// scale, then base-10000 digits.
+fn numeric(raw: &[u8]) -> Result<Value, BoxError> {
+    let word = |i: usize| -> Result<u16, BoxError> {
+        Ok(u16::from_be_bytes(
+            raw.get(i * 2..i * 2 + 2)
+                .ok_or("short numeric")?
+                .try_into()?,
+        ))
+    };
+    let (ndigits, weight, sign, scale) = (word(0)?, word(1)? as i16, word(2)?, word(3)?);
+    if sign == 0xC000 {
+        return Ok(Value::Real(f64::NAN));
+    }
+    let mut v = 0f64;
+    for i in 0..ndigits {
+        let exp = i32::from(weight) - i32::from(i);
+        v += f64::from(word(4 + usize::from(i))?) * 10000f64.powi(exp);
+    }
+    if sign == 0x4000 {
+        v = -v;
+    }
+    Ok(if scale == 0 && v.abs() < 9.0e15 {
+        Value::Integer(v as i64)
+    } else {
+        Value::Real(v)
+    })
+}
+
+// -------------------- Schema --------------------
+//
+// Postgres keeps its own migration list, applied under an advisory lock so several
+// instances starting together don't race. Version 1 is the schema SQLite reaches at its
+// migration 12; a schema change needs a migration in both lists.
+
+struct Migration {
+    name: &'static str,
+    sql: fn() -> String,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "initial schema",
+        sql: initial,
+    },
+    Migration {
+        name: "encryption at rest",
+        sql: encryption,
+    },
+    Migration {
+        name: "end-to-end encrypted items",
+        sql: e2ee,
+    },
+    Migration {
+        name: "keyed content hashes",
+        sql: || crypt::CLEAR_SEALED_HASHES.into(),
+    },
+];
+
+const LATEST: i64 = MIGRATIONS.len() as i64;
+
+// Any constant works; it only has to be the same for every instance
+const MIGRATION_LOCK: i64 = 0x636c_6970_7265_6c61;
+
+fn initial() -> String {
+    let now = "(extract(epoch FROM now())::bigint)";
+    format!(
+        r"
+        CREATE TABLE ClipboardItem (
+          id TEXT PRIMARY KEY,
+          type TEXT NOT NULL,
+          content TEXT,
+          fileName TEXT,
+          fileSize BIGINT,
+          sortWeight BIGINT NOT NULL DEFAULT 0,
+          contentType TEXT,
+          filePath TEXT,
+          createdAt BIGINT NOT NULL DEFAULT {now},
+          updatedAt BIGINT NOT NULL DEFAULT {now},
+          contentHash TEXT,
+          extractedText TEXT,
+          extractStatus TEXT,
+          extractError TEXT,
+          pinnedAt BIGINT,
+          favorite BIGINT NOT NULL DEFAULT 0,
+          archivedAt BIGINT,
+          deletedAt BIGINT,
+          expiresAt BIGINT,
+          searchVector tsvector GENERATED ALWAYS AS ({vector}) STORED
+        );
+        CREATE INDEX clipboard_created_idx ON ClipboardItem (createdAt, id);
+        CREATE INDEX clipboard_search_idx ON ClipboardItem USING GIN (searchVector);
+
+        CREATE TABLE ShareLink (
+          token TEXT PRIMARY KEY,
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          expiresAt BIGINT,
+          maxDownloads BIGINT,
+          downloadCount BIGINT NOT NULL DEFAULT 0,
+          revoked BIGINT NOT NULL DEFAULT 0,
+          passwordHash TEXT,
+          passwordPlain TEXT,
+          createdAt BIGINT NOT NULL DEFAULT {now},
+          updatedAt BIGINT NOT NULL DEFAULT {now}
+        );
+        CREATE INDEX share_item_idx ON ShareLink (itemId);
+        CREATE INDEX share_created_idx ON ShareLink (createdAt);
+
+        CREATE TABLE Tag (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL,
+          color TEXT,
+          createdAt BIGINT NOT NULL DEFAULT {now},
+          updatedAt BIGINT NOT NULL DEFAULT {now}
+        );
+        CREATE UNIQUE INDEX tag_name_idx ON Tag (lower(name));
+        CREATE TABLE ItemTag (
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          tagId TEXT NOT NULL REFERENCES Tag(id) ON DELETE CASCADE,
+          createdAt BIGINT NOT NULL DEFAULT {now},
+          PRIMARY KEY (itemId, tagId)
+        );
+        CREATE INDEX item_tag_tag_idx ON ItemTag (tagId);
+
+        CREATE TABLE Collection (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL,
+          description TEXT,
+          createdAt BIGINT NOT NULL DEFAULT {now},
+          updatedAt BIGINT NOT NULL DEFAULT {now}
+        );
+        CREATE UNIQUE INDEX collection_name_idx ON Collection (lower(name));
+        CREATE TABLE CollectionItem (
+          collectionId TEXT NOT NULL REFERENCES Collection(id) ON DELETE CASCADE,
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          addedAt BIGINT NOT NULL DEFAULT {now},
+          PRIMARY KEY (collectionId, itemId)
+        );
+        CREATE INDEX collection_item_item_idx ON CollectionItem (itemId);
+
+        CREATE TABLE ItemRevision (
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          rev BIGINT NOT NULL,
+          content TEXT,
+          fileName TEXT,
+          contentType TEXT,
+          createdAt BIGINT NOT NULL DEFAULT {now},
+          PRIMARY KEY (itemId, rev)
+        );
+
+        CREATE TABLE ItemFile (
+          id TEXT PRIMARY KEY,
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          position BIGINT NOT NULL,
+          path TEXT NOT NULL,
+          fileSize BIGINT NOT NULL,
+          contentType TEXT,
+          contentHash TEXT,
+          filePath TEXT,
+          createdAt BIGINT NOT NULL
+        );
+        CREATE INDEX ItemFile_item ON ItemFile (itemId, position);
+
+        CREATE TABLE Blob (
+          id TEXT PRIMARY KEY,
+          itemId TEXT NOT NULL REFERENCES ClipboardItem(id) ON DELETE CASCADE,
+          data BYTEA NOT NULL
+        );
+        CREATE INDEX Blob_item ON Blob (itemId);
+        ",
+        vector = search::pg_vector("content")
+    )
+}
+
+// keyId / dataKey as in SQLite's migration 13; sealed content leaves the search vector.
+fn encryption() -> String {
+    format!(
+        r"
+        ALTER TABLE ClipboardItem ADD COLUMN keyId TEXT, ADD COLUMN dataKey BYTEA;
+        ALTER TABLE ClipboardItem DROP COLUMN searchVector;
+        ALTER TABLE ClipboardItem ADD COLUMN searchVector tsvector GENERATED ALWAYS AS ({vector}) STORED;
+        CREATE INDEX clipboard_search_idx ON ClipboardItem USING GIN (searchVector);
+        ",
+        vector = search::pg_vector(search::PLAIN_CONTENT)
+    )
+}
+
+fn e2ee() -> String {
+    "ALTER TABLE ClipboardItem ADD COLUMN e2ee TEXT;".into()
+}
+
+fn version(client: &mut impl GenericClient) -> Result<i64> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS SchemaMigration (version BIGINT PRIMARY KEY, name TEXT NOT NULL, appliedAt BIGINT NOT NULL)",
+    )?;
+    let row = client.query_one("SELECT COALESCE(MAX(version), 0) FROM SchemaMigration", &[])?;
+    Ok(row.get(0))
+}
+
+/// Bring the database up to LATEST, like `migrations::run` does for SQLite.
+pub(crate) fn migrate(config: &Config) -> anyhow::Result<()> {
+    let mut client = config.connect(NoTls)?;
+    // full-text search only finds non-ASCII words in a UTF8 database
+    let encoding: String = client.query_one("SHOW server_encoding", &[])?.get(0);
+    if encoding != "UTF8" {
+        anyhow::bail!("database encoding is {encoding}; clip-relay needs a UTF8 database (CREATE DATABASE ... ENCODING 'UTF8' TEMPLATE template0)");
+    }
+    let mut tx = client.transaction()?;
+    tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+    let current = version(&mut tx)?;
+    if current > LATEST {
+        anyhow::bail!(
+            "database schema version {current} is newer than this build supports ({LATEST}); upgrade clip-relay"
+        );
+    }
+    for (v, m) in (1i64..).zip(MIGRATIONS).skip(current as usize) {
+        tx.batch_execute(&(m.sql)())
+            .with_context(|| format!("migration {v} ({}) failed", m.name))?;
+        tx.execute(
+            "INSERT INTO SchemaMigration (version, name, appliedAt) VALUES ($1, $2, extract(epoch FROM now())::bigint)",
+            &[&v, &m.name],
+        )?;
+        tracing::info!(version = v, name = m.name, "applied schema migration");
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// `clip-relay migrate [--status]` against Postgres.
+pub(crate) fn run_cli(args: &[String], config: &Config) -> anyhow::Result<()> {
+    let mut client = config.connect(NoTls)?;
+    let before = version(&mut client)?;
+    if args.get(1).map(String::as_str) == Some("--status") {
+        println!("schema version {before} (this build: {LATEST})");
+        for (v, m) in (1..).zip(MIGRATIONS) {
+            let state = if v <= before { "applied" } else { "pending" };
+            println!("{v:>4}  {state:<8} {}", m.name);
+        }
+        if before > LATEST {
+            println!("database is newer than this build");
+        }
+        return Ok(());
+    }
+    drop(client);
+    migrate(config)?;
+    println!("schema version {before} -> {LATEST}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::numbered;
+
+    #[test]
+    fn placeholders_are_numbered_outside_quotes() {
+        assert_eq!(
+            numbered("SELECT '?', \"a?\" FROM t WHERE a=? AND b IN (?,?)"),
+            "SELECT '?', \"a?\" FROM t WHERE a=$1 AND b IN ($2,$3)"
+        );
+        assert_eq!(
+            numbered("CASE WHEN ?1 THEN ?2 END"),
+            "CASE WHEN $1 THEN $2 END"
+        );
+    }
+}
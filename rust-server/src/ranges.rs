@@ -0,0 +1,521 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::crypt::{self, ItemKey};
+
+// -------------------- Range requests & conditional GET --------------------
+
+/// Upper bound on ranges honored in one request; more than this is served as a plain 200.
+const MAX_RANGES: usize = 16;
+
+pub(crate) enum FileSource {
+    Path(PathBuf),
+    Inline(Vec<u8>),
+    /// A file sealed by encryption at rest; served decrypted.
+    Sealed(PathBuf, ItemKey),
+}
+
+impl FileSource {
+    /// A stored file, sealed when its item has a key.
+    pub(crate) fn file(path: PathBuf, key: Option<ItemKey>) -> Self {
+        match key {
+            Some(k) => Self::Sealed(path, k),
+            None => Self::Path(path),
+        }
+    }
+
+    /// An item's content: its file under `data_dir`, else its inline blob (decrypted
+    /// here). None when the item has neither.
+    pub(crate) fn stored(
+        data_dir: &Path,
+        file_path: Option<String>,
+        blob: Option<Vec<u8>>,
+        key: Option<ItemKey>,
+    ) -> crypt::Result<Option<Self>> {
+        Ok(match (file_path, blob) {
+            (Some(rel), _) => Some(Self::file(data_dir.join(rel), key)),
+            (None, Some(buf)) => crypt::open_bytes(key.as_ref(), Some(buf))?.map(Self::Inline),
+            (None, None) => None,
+        })
+    }
+
+    pub(crate) fn key(&self) -> Option<&ItemKey> {
+        match self {
+            Self::Sealed(_, k) => Some(k),
+            _ => None,
+        }
+    }
+}
+
+/// Validators for a stored file: strong ETag (content hash) and modification time.
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<i64>,
+}
+
+pub(crate) struct Served {
+    pub(crate) response: Response,
+    /// True when this response delivers the first byte of the content (full 200, or ranges
+    /// starting at 0). Share handlers only count those as a download: every transfer has to
+    /// fetch byte 0 once, while seeks and resumed chunks further in don't count again.
+    pub(crate) counts_as_download: bool,
+}
+
+pub(crate) fn strong_etag(hex: &str) -> String {
+    format!("\"{hex}\"")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeSpec {
+    /// Missing, malformed or not worth honoring => full response.
+    Full,
+    Unsatisfiable,
+    /// Inclusive byte ranges.
+    Ranges(Vec<(u64, u64)>),
+}
+
+fn parse_range(value: &str, len: u64) -> RangeSpec {
+    let Some(set) = value.trim().strip_prefix("bytes=") else {
+        return RangeSpec::Full;
+    };
+    if len == 0 {
+        return RangeSpec::Full;
+    }
+    let mut out = vec![];
+    for spec in set.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let Some((a, b)) = spec.split_once('-') else {
+            return RangeSpec::Full;
+        };
+        let (a, b) = (a.trim(), b.trim());
+        let range = if a.is_empty() {
+            // suffix range: last N bytes
+            let Ok(n) = b.parse::<u64>() else {
+                return RangeSpec::Full;
+            };
+            if n == 0 {
+                continue;
+            }
+            (len.saturating_sub(n), len - 1)
+        } else {
+            let Ok(start) = a.parse::<u64>() else {
+                return RangeSpec::Full;
+            };
+            let end = if b.is_empty() {
+                len - 1
+            } else {
+                match b.parse::<u64>() {
+                    Ok(e) if e >= start => e.min(len - 1),
+                    _ => return RangeSpec::Full,
+                }
+            };
+            if start >= len {
+                continue;
+            }
+            (start, end)
+        };
+        out.push(range);
+    }
+    if out.is_empty() {
+        return RangeSpec::Unsatisfiable;
+    }
+    if out.len() > MAX_RANGES {
+        return RangeSpec::Full;
+    }
+    RangeSpec::Ranges(out)
+}
+
+// every transfer of the content starts with its first byte
+fn starts_transfer(ranges: &[(u64, u64)]) -> bool {
+    ranges.iter().any(|&(start, _)| start == 0)
+}
+
+fn etag_matches(list: &str, etag: &str, weak: bool) -> bool {
+    let norm = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    list.split(',').any(|t| {
+        let t = t.trim();
+        t == "*"
+            || if weak {
+                norm(t) == norm(etag)
+            } else {
+                !t.starts_with("W/") && t == etag
+            }
+    })
+}
+
+fn http_date(ts: i64) -> Option<String> {
+    let secs = u64::try_from(ts).ok()?;
+    Some(httpdate::fmt_http_date(
+        UNIX_EPOCH + Duration::from_secs(secs),
+    ))
+}
+
+fn parse_http_date(s: &str) -> Option<i64> {
+    let t = httpdate::parse_http_date(s.trim()).ok()?;
+    Some(t.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+// If-Range: only honor Range when the validator still matches (strong comparison)
+fn if_range_ok(req: &HeaderMap, v: &Validators) -> bool {
+    match header_str(req, header::IF_RANGE) {
+        None => true,
+        Some(ir) if ir.trim_start().starts_with('"') => v
+            .etag
+            .as_deref()
+            .is_some_and(|t| etag_matches(ir, t, false)),
+        Some(ir) => parse_http_date(ir)
+            .zip(v.last_modified)
+            .is_some_and(|(d, lm)| d == lm),
+    }
+}
+
+fn header_str(h: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    h.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Serve `src` honoring `If-None-Match`, `If-Modified-Since`, `Range` and `If-Range`.
+/// `hm` carries the representation headers (Content-Type, Content-Disposition, ...).
+pub(crate) async fn serve(
+    req: &HeaderMap,
+    mut hm: HeaderMap,
+    src: FileSource,
+    v: &Validators,
+) -> Served {
+    hm.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(tag) = v.etag.as_deref() {
+        if let Ok(h) = HeaderValue::from_str(tag) {
+            hm.insert(header::ETAG, h);
+        }
+    }
+    if let Some(lm) = v.last_modified.and_then(http_date) {
+        if let Ok(h) = HeaderValue::from_str(&lm) {
+            hm.insert(header::LAST_MODIFIED, h);
+        }
+    }
+
+    // Conditional GET: If-None-Match takes precedence over If-Modified-Since
+    let not_modified = if let Some(inm) = header_str(req, header::IF_NONE_MATCH) {
+        v.etag
+            .as_deref()
+            .is_some_and(|t| etag_matches(inm, t, true))
+    } else if let (Some(ims), Some(lm)) = (
+        header_str(req, header::IF_MODIFIED_SINCE).and_then(parse_http_date),
+        v.last_modified,
+    ) {
+        lm <= ims
+    } else {
+        false
+    };
+    if not_modified {
+        hm.remove(header::CONTENT_DISPOSITION);
+        return Served {
+            response: (StatusCode::NOT_MODIFIED, hm).into_response(),
+            counts_as_download: false,
+        };
+    }
+
+    let len = match &src {
+        FileSource::Inline(b) => b.len() as u64,
+        FileSource::Path(p) => match tokio::fs::metadata(p).await {
+            Ok(m) => m.len(),
+            Err(_) => return missing(),
+        },
+        FileSource::Sealed(p, _) => match tokio::fs::metadata(p).await {
+            Ok(m) => crypt::plain_len(m.len()),
+            Err(_) => return missing(),
+        },
+    };
+
+    let spec = match header_str(req, header::RANGE) {
+        Some(r) if if_range_ok(req, v) => parse_range(r, len),
+        _ => RangeSpec::Full,
+    };
+
+    match spec {
+        RangeSpec::Full => {
+            hm.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+            let body = match src {
+                FileSource::Inline(b) => Body::from(b),
+                FileSource::Path(p) => match tokio::fs::File::open(&p).await {
+                    Ok(f) => Body::from_stream(ReaderStream::new(f)),
+                    Err(_) => return missing(),
+                },
+                FileSource::Sealed(p, key) => Body::from_stream(crypt::stream(p, key, 0, len)),
+            };
+            Served {
+                response: (StatusCode::OK, hm, body).into_response(),
+                counts_as_download: true,
+            }
+        }
+        RangeSpec::Unsatisfiable => {
+            hm.remove(header::CONTENT_DISPOSITION);
+            hm.remove(header::CONTENT_TYPE);
+            hm.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{len}")).unwrap(),
+            );
+            Served {
+                response: (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    hm,
+                    Json(serde_json::json!({"error":"range not satisfiable"})),
+                )
+                    .into_response(),
+                counts_as_download: false,
+            }
+        }
+        RangeSpec::Ranges(ranges) => {
+            let counts = starts_transfer(&ranges);
+            let response = if ranges.len() == 1 {
+                let (start, end) = ranges[0];
+                hm.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes {start}-{end}/{len}")).unwrap(),
+                );
+                hm.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+                let body = match read_segment(&src, start, end).await {
+                    Some(b) => b,
+                    None => return missing(),
+                };
+                (StatusCode::PARTIAL_CONTENT, hm, body).into_response()
+            } else {
+                multipart_response(hm, src, ranges, len)
+            };
+            Served {
+                response,
+                counts_as_download: counts,
+            }
+        }
+    }
+}
+
+fn missing() -> Served {
+    Served {
+        response: (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"missing"})),
+        )
+            .into_response(),
+        counts_as_download: false,
+    }
+}
+
+async fn read_segment(src: &FileSource, start: u64, end: u64) -> Option<Body> {
+    match src {
+        FileSource::Inline(b) => Some(Body::from(b[start as usize..=end as usize].to_vec())),
+        FileSource::Path(p) => {
+            let mut f = tokio::fs::File::open(p).await.ok()?;
+            f.seek(SeekFrom::Start(start)).await.ok()?;
+            Some(Body::from_stream(ReaderStream::new(
+                f.take(end - start + 1),
+            )))
+        }
+        FileSource::Sealed(p, key) => Some(Body::from_stream(crypt::stream(
+            p.clone(),
+            key.clone(),
+            start,
+            end - start + 1,
+        ))),
+    }
+}
+
+fn multipart_response(
+    mut hm: HeaderMap,
+    src: FileSource,
+    ranges: Vec<(u64, u64)>,
+    len: u64,
+) -> Response {
+    let mut rnd = [0u8; 12];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut rnd);
+    let boundary: String = rnd.iter().map(|b| format!("{b:02x}")).collect();
+    let ctype = hm
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let part_heads: Vec<String> = ranges
+        .iter()
+        .map(|(s, e)| {
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: {ctype}\r\nContent-Range: bytes {s}-{e}/{len}\r\n\r\n"
+            )
+        })
+        .collect();
+    let tail = format!("\r\n--{boundary}--\r\n");
+    let total: u64 = part_heads.iter().map(|h| h.len() as u64).sum::<u64>()
+        + ranges.iter().map(|(s, e)| e - s + 1).sum::<u64>()
+        + tail.len() as u64;
+
+    hm.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&format!("multipart/byteranges; boundary={boundary}")).unwrap(),
+    );
+    hm.insert(header::CONTENT_LENGTH, HeaderValue::from(total));
+
+    let stream = async_stream::stream! {
+        let mut file = match &src {
+            FileSource::Path(p) => match tokio::fs::File::open(p).await {
+                Ok(f) => Some(f),
+                Err(e) => { yield Err(e); return; }
+            },
+            FileSource::Inline(_) | FileSource::Sealed(..) => None,
+        };
+        for ((start, end), head) in ranges.into_iter().zip(part_heads) {
+            yield Ok::<Bytes, std::io::Error>(Bytes::from(head));
+            match (&src, file.as_mut()) {
+                (FileSource::Inline(b), _) => {
+                    yield Ok(Bytes::copy_from_slice(&b[start as usize..=end as usize]));
+                }
+                (FileSource::Sealed(p, key), _) => {
+                    let part = crypt::stream(p.clone(), key.clone(), start, end - start + 1);
+                    futures_util::pin_mut!(part);
+                    while let Some(chunk) = part.next().await {
+                        let failed = chunk.is_err();
+                        yield chunk;
+                        if failed {
+                            return;
+                        }
+                    }
+                }
+                (FileSource::Path(_), Some(f)) => {
+                    if let Err(e) = f.seek(SeekFrom::Start(start)).await {
+                        yield Err(e);
+                        return;
+                    }
+                    let mut remaining = end - start + 1;
+                    let mut buf = vec![0u8; 64 * 1024];
+                    while remaining > 0 {
+                        let want = remaining.min(buf.len() as u64) as usize;
+                        match f.read(&mut buf[..want]).await {
+                            Ok(0) => return,
+                            Ok(n) => {
+                                remaining -= n as u64;
+                                yield Ok(Bytes::copy_from_slice(&buf[..n]));
+                            }
+                            Err(e) => { yield Err(e); return; }
+                        }
+                    }
+                }
+                _ => return,
+            }
+        }
+        yield Ok(Bytes::from(tail));
+    };
+    (StatusCode::PARTIAL_CONTENT, hm, Body::from_stream(stream)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(v: &[(u64, u64)]) -> RangeSpec {
+        RangeSpec::Ranges(v.to_vec())
+    }
+
+    #[test]
+    fn range_headers_are_parsed() {
+        assert_eq!(parse_range("bytes=0-9", 100), ranges(&[(0, 9)]));
+        // suffix and open-ended ranges, ends clamped to the length
+        assert_eq!(parse_range("bytes=-10", 100), ranges(&[(90, 99)]));
+        assert_eq!(parse_range("bytes=-500", 100), ranges(&[(0, 99)]));
+        assert_eq!(parse_range("bytes=40-", 100), ranges(&[(40, 99)]));
+        assert_eq!(parse_range("bytes=90-200", 100), ranges(&[(90, 99)]));
+        // overlapping ranges are served as asked
+        assert_eq!(
+            parse_range("bytes=0-49, 40-59", 100),
+            ranges(&[(0, 49), (40, 59)])
+        );
+        // unsatisfiable ranges are dropped; none left => 416
+        assert_eq!(parse_range("bytes=100-, 5-6", 100), ranges(&[(5, 6)]));
+        assert_eq!(parse_range("bytes=100-200", 100), RangeSpec::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 100), RangeSpec::Unsatisfiable);
+        // malformed, other units, empty files and too many ranges => full response
+        assert_eq!(parse_range("bytes=9-1", 100), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=a-b", 100), RangeSpec::Full);
+        assert_eq!(parse_range("items=0-1", 100), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=0-1", 0), RangeSpec::Full);
+        let many: Vec<String> = (0..=MAX_RANGES).map(|i| format!("{i}-{i}")).collect();
+        assert_eq!(
+            parse_range(&format!("bytes={}", many.join(",")), 100),
+            RangeSpec::Full
+        );
+        let max: Vec<String> = (0..MAX_RANGES).map(|i| format!("{i}-{i}")).collect();
+        assert!(matches!(
+            parse_range(&format!("bytes={}", max.join(",")), 100),
+            RangeSpec::Ranges(r) if r.len() == MAX_RANGES
+        ));
+    }
+
+    #[test]
+    fn if_range_needs_a_strong_match() {
+        let v = Validators {
+            etag: Some(strong_etag("abc")),
+            last_modified: Some(1_700_000_000),
+        };
+        let req = |value: &str| {
+            let mut h = HeaderMap::new();
+            h.insert(header::IF_RANGE, HeaderValue::from_str(value).unwrap());
+            h
+        };
+        assert!(if_range_ok(&HeaderMap::new(), &v));
+        assert!(if_range_ok(&req("\"abc\""), &v));
+        assert!(!if_range_ok(&req("\"def\""), &v));
+        assert!(!if_range_ok(&req("W/\"abc\""), &v));
+        assert!(if_range_ok(&req(&http_date(1_700_000_000).unwrap()), &v));
+        assert!(!if_range_ok(&req(&http_date(1_700_000_001).unwrap()), &v));
+        assert!(!if_range_ok(&req("yesterday"), &v));
+        // If-None-Match compares weakly
+        assert!(etag_matches("W/\"abc\", \"x\"", "\"abc\"", true));
+        assert!(!etag_matches("W/\"abc\"", "\"abc\"", false));
+    }
+
+    #[test]
+    fn downloads_count_when_the_first_byte_is_served() {
+        assert!(starts_transfer(&[(0, 99)]));
+        assert!(starts_transfer(&[(0, 98)]));
+        assert!(starts_transfer(&[(50, 99), (0, 9)]));
+        assert!(!starts_transfer(&[(50, 99)]));
+        assert!(!starts_transfer(&[(1, 50), (60, 99)]));
+    }
+
+    async fn counts(range: Option<&str>) -> bool {
+        let mut req = HeaderMap::new();
+        if let Some(r) = range {
+            req.insert(header::RANGE, HeaderValue::from_str(r).unwrap());
+        }
+        let v = Validators {
+            etag: None,
+            last_modified: None,
+        };
+        let src = FileSource::Inline(vec![7; 100]);
+        serve(&req, HeaderMap::new(), src, &v)
+            .await
+            .counts_as_download
+    }
+
+    #[tokio::test]
+    async fn served_ranges_count_once_per_transfer() {
+        assert!(counts(None).await);
+        assert!(counts(Some("bytes=0-")).await);
+        // all but the last byte is still a download
+        assert!(counts(Some("bytes=0-98")).await);
+        assert!(counts(Some("bytes=-100")).await);
+        // a player seeking or resuming further in doesn't count again
+        assert!(!counts(Some("bytes=50-")).await);
+        assert!(!counts(Some("bytes=1-10,-5")).await);
+        assert!(!counts(Some("bytes=-99")).await);
+        assert!(!counts(Some("bytes=200-")).await);
+    }
+}
@@ -0,0 +1,275 @@
+use std::env;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+use crate::store::{self, Conn};
+use crate::{epoch_to_iso, limits, listing, now_unix, trash, AppState, ServerEvent};
+
+// -------------------- Retention --------------------
+//
+// Optional rules that keep the clipboard from growing without bound:
+//   RETENTION_MAX_AGE    remove items created longer ago than this (`30d`, `12h`, ...)
+//   RETENTION_MAX_ITEMS  keep only the newest N items
+//   RETENTION_MAX_BYTES  remove the oldest items until the total size fits
+// Pinned and favorited items are never removed and don't count towards MAX_ITEMS (their
+// bytes do count towards MAX_BYTES). Items removed for age or count go to the trash like a
+// manual delete. The trash takes up space too, so its bytes count towards MAX_BYTES, and
+// items over the cap are deleted for good, the trash's oldest first.
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Policy {
+    /// Seconds
+    pub(crate) max_age: Option<i64>,
+    pub(crate) max_items: Option<u64>,
+    pub(crate) max_bytes: Option<u64>,
+}
+
+impl Policy {
+    pub(crate) fn from_env() -> Self {
+        Self {
+            max_age: env::var("RETENTION_MAX_AGE")
+                .ok()
+                .and_then(|v| limits::parse_duration(&v))
+                .filter(|s| *s > 0),
+            max_items: env::var("RETENTION_MAX_ITEMS")
+                .ok()
+                .and_then(|v| v.trim().parse().ok()),
+            max_bytes: env::var("RETENTION_MAX_BYTES")
+                .ok()
+                .and_then(|v| limits::parse_bytes(&v))
+                .filter(|n| *n > 0),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.max_age.is_some() || self.max_items.is_some() || self.max_bytes.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Reason {
+    Age,
+    Count,
+    Size,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    id: String,
+    #[serde(rename = "type")]
+    item_type: String,
+    file_name: Option<String>,
+    size: i64,
+    #[serde(serialize_with = "iso")]
+    created_at: i64,
+    #[serde(skip)]
+    exempt: bool,
+    /// Already in the trash; only MAX_BYTES removes these.
+    trashed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<Reason>,
+}
+
+fn iso<S: serde::Serializer>(ts: &i64, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&epoch_to_iso(*ts))
+}
+
+// Items the policy would remove: live ones oldest first, then trashed ones (which only
+// the size rule touches) in the order they were trashed.
+fn plan(conn: &dyn Conn, policy: &Policy, now: i64) -> Vec<Candidate> {
+    let load = |filter: &str, order: &str, trashed: bool| -> Vec<Candidate> {
+        let sql = format!(
+            "SELECT c.id, c.type, c.fileName, {}, c.createdAt, (c.pinnedAt IS NOT NULL OR c.favorite != 0) FROM ClipboardItem c WHERE {filter} ORDER BY {order}",
+            listing::SIZE_EXPR
+        );
+        let mut stmt = conn.prepare(&sql).unwrap();
+        stmt.query_map([], |r| {
+            Ok(Candidate {
+                id: r.get(0)?,
+                item_type: r.get(1)?,
+                file_name: r.get(2)?,
+                size: r.get(3)?,
+                created_at: r.get(4)?,
+                exempt: r.get(5)?,
+                trashed,
+                reason: None,
+            })
+        })
+        .unwrap()
+        .flatten()
+        .collect()
+    };
+    // newest first
+    let mut items = load("c.deletedAt IS NULL", "c.createdAt DESC, c.id DESC", false);
+    if let Some(age) = policy.max_age {
+        for it in items.iter_mut().filter(|it| !it.exempt) {
+            if it.created_at < now - age {
+                it.reason = Some(Reason::Age);
+            }
+        }
+    }
+    if let Some(n) = policy.max_items {
+        let mut kept = 0u64;
+        for it in items
+            .iter_mut()
+            .filter(|it| !it.exempt && it.reason.is_none())
+        {
+            if kept < n {
+                kept += 1;
+            } else {
+                it.reason = Some(Reason::Count);
+            }
+        }
+    }
+    items.reverse();
+    let mut trashed = vec![];
+    if let Some(cap) = policy.max_bytes {
+        trashed = load("c.deletedAt IS NOT NULL", "c.deletedAt ASC, c.id ASC", true);
+        let size = |it: &Candidate| it.size.max(0) as u64;
+        let mut total: u64 = items.iter().chain(&trashed).map(size).sum();
+        let mut over = |it: &mut Candidate| {
+            if total <= cap {
+                return false;
+            }
+            total -= size(it);
+            it.reason = Some(Reason::Size);
+            true
+        };
+        // the trash, then what age and count would add to it, then the oldest live items
+        let fits = trashed.iter_mut().any(|it| !over(it))
+            || items
+                .iter_mut()
+                .filter(|it| it.reason.is_some())
+                .any(|it| !over(it));
+        if !fits {
+            for it in items
+                .iter_mut()
+                .filter(|it| it.reason.is_none() && !it.exempt)
+            {
+                if !over(it) {
+                    break;
+                }
+            }
+        }
+    }
+    items.extend(trashed);
+    items.retain(|it| it.reason.is_some());
+    items
+}
+
+/// Apply the policy once, broadcasting clipboard:deleted for every removed item. Items over
+/// the byte cap are purged (announced as trash:purged).
+pub(crate) async fn run_once(state: &AppState) -> usize {
+    let policy = state.retention;
+    let removed = state
+        .db
+        .write(move |conn| {
+            let victims = plan(conn, &policy, now_unix());
+            let tx = conn.transaction()?;
+            let removed: Vec<(String, Reason, bool)> = victims
+                .into_iter()
+                .filter(|v| v.trashed || trash::soft_delete(&tx, &v.id))
+                .map(|v| (v.id, v.reason.unwrap(), v.trashed))
+                .collect();
+            tx.commit()?;
+            Ok::<_, store::Error>(removed)
+        })
+        .await;
+    let removed = removed.unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "retention: removing items failed");
+        vec![]
+    });
+    let mut purge = vec![];
+    for (id, reason, trashed) in &removed {
+        if !trashed {
+            let _ = state.tx.send(ServerEvent {
+                name: "clipboard:deleted".into(),
+                data: serde_json::json!({"id": id, "trashed": true, "reason": reason}),
+            });
+        }
+        if *reason == Reason::Size {
+            purge.push(id.clone());
+        }
+    }
+    if !purge.is_empty() {
+        trash::purge(state, purge).await;
+    }
+    removed.len()
+}
+
+/// Background task applying the retention policy every RETENTION_INTERVAL (default 10m).
+pub(crate) async fn run_task(state: AppState) {
+    if !state.retention.is_active() {
+        return;
+    }
+    let interval = env::var("RETENTION_INTERVAL")
+        .ok()
+        .and_then(|v| limits::parse_duration(&v))
+        .filter(|s| *s > 0)
+        .map(|s| Duration::from_secs(s as u64))
+        .unwrap_or(DEFAULT_INTERVAL);
+    tracing::info!(policy = ?state.retention, "retention policy enabled");
+    let mut tick = tokio::time::interval(interval);
+    loop {
+        tick.tick().await;
+        let n = run_once(&state).await;
+        if n > 0 {
+            tracing::info!(count = n, "retention policy moved items to trash");
+        }
+    }
+}
+
+// GET /api/retention/preview — what the policy would remove right now. maxAge, maxItems
+// and maxBytes override the configured rules to try out a policy before enabling it.
+pub(crate) async fn preview(State(state): State<AppState>, uri: Uri) -> Response {
+    let mut policy = state.retention;
+    for (k, v) in form_urlencoded::parse(uri.query().unwrap_or("").as_bytes()) {
+        let v = v.trim();
+        let off = v.is_empty() || v == "off";
+        let ok = match k.as_ref() {
+            "maxAge" => {
+                policy.max_age = if off { None } else { limits::parse_duration(v) };
+                off || policy.max_age.is_some()
+            }
+            "maxItems" => {
+                policy.max_items = if off { None } else { v.parse().ok() };
+                off || policy.max_items.is_some()
+            }
+            "maxBytes" => {
+                policy.max_bytes = if off { None } else { limits::parse_bytes(v) };
+                off || policy.max_bytes.is_some()
+            }
+            _ => true,
+        };
+        if !ok {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": format!("invalid {k}: {v}")})),
+            )
+                .into_response();
+        }
+    }
+    let items = state
+        .db
+        .read(move |conn| plan(conn, &policy, now_unix()))
+        .await;
+    let bytes: i64 = items.iter().map(|it| it.size.max(0)).sum();
+    Json(serde_json::json!({
+        "policy": policy,
+        "enabled": state.retention.is_active(),
+        "count": items.len(),
+        "bytes": bytes,
+        "items": items,
+    }))
+    .into_response()
+}
@@ -0,0 +1,507 @@
+use axum::extract::{Path, State};
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rusqlite::params;
+use serde::Deserialize;
+
+use crate::crypt::{self, ItemKey, Keyring};
+use crate::store::{self, Conn, OptionalExtension};
+use crate::{epoch_to_iso, now_unix, too_large_response, AppState, ServerEvent};
+
+// -------------------- Edits & revisions --------------------
+//
+// Text content, file names and the content type of text items can be edited. Every edit
+// stores a full snapshot in ItemRevision; the first edit also records the item as it was
+// created, so revision 1 is always the original. Restoring a revision is itself an edit and
+// adds a new revision on top. Revisions of an encrypted item are sealed with its data key.
+
+const MAX_FILE_NAME_LEN: usize = 255;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Snapshot {
+    content: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+}
+
+struct Current {
+    id: String,
+    key: Option<ItemKey>,
+    // end-to-end encrypted: the server can't change what it can't read
+    e2ee: bool,
+    item_type: String,
+    snapshot: Snapshot,
+    updated_at: i64,
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({"error":"Not found"})),
+    )
+        .into_response()
+}
+
+// Why an item or one of its revisions can't be read.
+enum Miss {
+    NotFound,
+    Undecryptable(String, crypt::Error),
+}
+
+impl IntoResponse for Miss {
+    fn into_response(self) -> Response {
+        match self {
+            Miss::NotFound => not_found(),
+            Miss::Undecryptable(id, e) => crypt::failure(&id, e),
+        }
+    }
+}
+
+fn bad_request(msg: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": msg })),
+    )
+        .into_response()
+}
+
+// The live item with its content decrypted.
+fn load_current(conn: &dyn Conn, keys: Option<&Keyring>, id: &str) -> Result<Current, Miss> {
+    let row = conn
+        .query_row(
+            "SELECT type, content, fileName, contentType, updatedAt, keyId, dataKey, e2ee IS NOT NULL FROM ClipboardItem WHERE id=? AND deletedAt IS NULL",
+            [id],
+            |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    Snapshot {
+                        content: r.get(1)?,
+                        file_name: r.get(2)?,
+                        content_type: r.get(3)?,
+                    },
+                    r.get::<_, i64>(4)?,
+                    r.get::<_, Option<String>>(5)?,
+                    r.get::<_, Option<Vec<u8>>>(6)?,
+                    r.get::<_, bool>(7)?,
+                ))
+            },
+        )
+        .optional()
+        .ok()
+        .flatten();
+    let Some((item_type, mut snapshot, updated_at, key_id, wrapped, e2ee)) = row else {
+        return Err(Miss::NotFound);
+    };
+    let undecryptable = |e| Miss::Undecryptable(id.to_string(), e);
+    let key = crypt::item_key(keys, id, key_id, wrapped).map_err(undecryptable)?;
+    snapshot.content = crypt::open_text(key.as_ref(), snapshot.content).map_err(undecryptable)?;
+    Ok(Current {
+        id: id.to_string(),
+        key,
+        e2ee,
+        item_type,
+        snapshot,
+        updated_at,
+    })
+}
+
+fn load_revision(
+    conn: &dyn Conn,
+    cur: &Current,
+    rev: i64,
+) -> Result<Option<(Snapshot, i64)>, Miss> {
+    let row = conn
+        .query_row(
+            "SELECT content, fileName, contentType, createdAt FROM ItemRevision WHERE itemId=? AND rev=?",
+            params![cur.id, rev],
+            |r| {
+                Ok((
+                    Snapshot {
+                        content: r.get(0)?,
+                        file_name: r.get(1)?,
+                        content_type: r.get(2)?,
+                    },
+                    r.get(3)?,
+                ))
+            },
+        )
+        .optional()
+        .ok()
+        .flatten();
+    let Some((mut s, at)) = row else {
+        return Ok(None);
+    };
+    s.content = crypt::open_text(cur.key.as_ref(), s.content)
+        .map_err(|e| Miss::Undecryptable(cur.id.clone(), e))?;
+    Ok(Some((s, at)))
+}
+
+fn latest_rev(conn: &dyn Conn, id: &str) -> i64 {
+    conn.query_row(
+        "SELECT COALESCE(MAX(rev), 0) FROM ItemRevision WHERE itemId=?",
+        [id],
+        |r| r.get(0),
+    )
+    .unwrap_or(0)
+}
+
+fn insert_revision(conn: &dyn Conn, cur: &Current, rev: i64, s: &Snapshot, at: i64) {
+    let content = crypt::seal_text(cur.key.as_ref(), s.content.clone());
+    let _ = conn.execute(
+        "INSERT INTO ItemRevision (itemId, rev, content, fileName, contentType, createdAt) VALUES (?,?,?,?,?,?)",
+        params![cur.id, rev, content, s.file_name, s.content_type, at],
+    );
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EditReq {
+    content: Option<String>,
+    file_name: Option<String>,
+    /// Text items only; an empty string clears it
+    content_type: Option<String>,
+}
+
+// Validate an edit against the item's type and upload policy and apply it to `next`.
+// Returns the error response when the edit is rejected.
+fn apply(state: &AppState, cur: &Current, req: EditReq, next: &mut Snapshot) -> Option<Response> {
+    let is_text = cur.item_type == "TEXT";
+    if cur.e2ee && (req.content.is_some() || req.content_type.is_some()) {
+        return Some(bad_request(
+            "end-to-end encrypted items can only be renamed",
+        ));
+    }
+    if let Some(content) = req.content {
+        if !is_text {
+            return Some(bad_request("content can only be edited on text items"));
+        }
+        if content.is_empty() {
+            return Some(bad_request("content must not be empty"));
+        }
+        let max_file = state.limits.max_file_bytes;
+        if max_file.is_some_and(|m| content.len() as u64 > m) {
+            return Some(too_large_response("Item too large", max_file));
+        }
+        next.content = Some(content);
+    }
+    if let Some(name) = req.file_name {
+        if is_text {
+            return Some(bad_request("text items have no file name"));
+        }
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_FILE_NAME_LEN {
+            return Some(bad_request("file name must be 1-255 characters"));
+        }
+        if name.contains(['/', '\\']) || name.chars().any(char::is_control) {
+            return Some(bad_request("file name contains invalid characters"));
+        }
+        // Renaming must not sneak a file past the extension policy
+        if let Err(reason) = state
+            .limits
+            .check_type(cur.snapshot.content_type.as_deref(), Some(name))
+        {
+            return Some(
+                (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    Json(serde_json::json!({"error":"Unsupported media type","detail": reason})),
+                )
+                    .into_response(),
+            );
+        }
+        next.file_name = Some(name.to_string());
+    }
+    if let Some(ct) = req.content_type {
+        // Uploads keep their sniffed type; text items may carry a hint such as text/markdown
+        if !is_text {
+            return Some(bad_request("content type can only be set on text items"));
+        }
+        let ct = ct.trim().to_ascii_lowercase();
+        if ct.is_empty() {
+            next.content_type = None;
+        } else if ct.starts_with("text/") && !ct.contains(char::is_whitespace) {
+            next.content_type = Some(ct);
+        } else {
+            return Some(bad_request("content type must be text/*"));
+        }
+    }
+    None
+}
+
+// Store `next` as the item's state plus a new revision, and broadcast the change.
+async fn commit(state: &AppState, id: &str, cur: Current, next: Snapshot) -> Response {
+    let now = now_unix();
+    let id = id.to_string();
+    let item = state
+        .db
+        .write(move |conn| {
+            let id = id.as_str();
+            let tx = conn.transaction()?;
+            let mut rev = latest_rev(&tx, id);
+            if rev == 0 {
+                rev = 1;
+                insert_revision(&tx, &cur, rev, &cur.snapshot, cur.updated_at);
+            }
+            rev += 1;
+            insert_revision(&tx, &cur, rev, &next, now);
+            let content = crypt::seal_text(cur.key.as_ref(), next.content.clone());
+            // sealed text keeps its plaintext length in fileSize for quotas and listings
+            let sealed_len = match (&cur.key, &next.content) {
+                (Some(_), Some(c)) => Some(c.len() as i64),
+                _ => None,
+            };
+            tx.execute(
+                "UPDATE ClipboardItem SET content=?, fileName=?, contentType=?, fileSize=COALESCE(?, fileSize), updatedAt=? WHERE id=?",
+                params![content, next.file_name, next.content_type, sealed_len, now, id],
+            )?;
+            tx.commit()?;
+            Ok::<_, store::Error>(serde_json::json!({
+                "id": id,
+                "type": cur.item_type,
+                "content": next.content,
+                "fileName": next.file_name,
+                "contentType": next.content_type,
+                "updatedAt": epoch_to_iso(now),
+                "rev": rev,
+            }))
+        })
+        .await;
+    let item = match item {
+        Ok(item) => item,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error":"db write failed","detail": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    let _ = state.tx.send(ServerEvent {
+        name: "clipboard:updated".into(),
+        data: item.clone(),
+    });
+    Json(item).into_response()
+}
+
+// PATCH /api/clipboard/:id
+pub(crate) async fn update_clipboard(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<EditReq>,
+) -> Response {
+    let cur = state
+        .db
+        .read({
+            let (id, keys) = (id.clone(), state.keys.clone());
+            move |conn| load_current(conn, keys.as_deref(), &id)
+        })
+        .await;
+    let cur = match cur {
+        Ok(cur) => cur,
+        Err(miss) => return miss.into_response(),
+    };
+    let mut next = cur.snapshot.clone();
+    if let Some(resp) = apply(&state, &cur, req, &mut next) {
+        return resp;
+    }
+    if next == cur.snapshot {
+        // Nothing changed: no revision, no event
+        let rev = state
+            .db
+            .read({
+                let id = id.clone();
+                move |conn| latest_rev(conn, &id)
+            })
+            .await;
+        return Json(serde_json::json!({
+            "id": id,
+            "type": cur.item_type,
+            "content": cur.snapshot.content,
+            "fileName": cur.snapshot.file_name,
+            "contentType": cur.snapshot.content_type,
+            "updatedAt": epoch_to_iso(cur.updated_at),
+            "rev": rev.max(1),
+        }))
+        .into_response();
+    }
+    commit(&state, &id, cur, next).await
+}
+
+fn size_of(s: &Snapshot) -> Option<usize> {
+    s.content.as_ref().map(String::len)
+}
+
+// GET /api/clipboard/:id/revisions — newest first, without content
+pub(crate) async fn list_revisions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let keys = state.keys.clone();
+    state.db.read(move |conn| {
+        let cur = match load_current(conn, keys.as_deref(), &id) {
+            Ok(cur) => cur,
+            Err(miss) => return miss.into_response(),
+        };
+        let mut stmt = conn
+            .prepare("SELECT rev, content, fileName, contentType, createdAt FROM ItemRevision WHERE itemId=? ORDER BY rev DESC")
+            .unwrap();
+        let mut revisions: Vec<serde_json::Value> = stmt
+            .query_map([&id], |r| {
+                let s = Snapshot {
+                    content: crypt::open_text(cur.key.as_ref(), r.get(1)?).unwrap_or_default(),
+                    file_name: r.get(2)?,
+                    content_type: r.get(3)?,
+                };
+                Ok(serde_json::json!({
+                    "rev": r.get::<_, i64>(0)?,
+                    "fileName": s.file_name,
+                    "contentType": s.content_type,
+                    "size": size_of(&s),
+                    "createdAt": epoch_to_iso(r.get::<_, i64>(4)?),
+                }))
+            })
+            .unwrap()
+            .flatten()
+            .collect();
+        if revisions.is_empty() {
+            // Never edited: the item itself is revision 1
+            revisions.push(serde_json::json!({
+                "rev": 1,
+                "fileName": cur.snapshot.file_name,
+                "contentType": cur.snapshot.content_type,
+                "size": size_of(&cur.snapshot),
+                "createdAt": epoch_to_iso(cur.updated_at),
+            }));
+        }
+        Json(serde_json::json!({ "revisions": revisions })).into_response()
+    })
+    .await
+}
+
+// Revision `rev` of an item, treating an unedited item as its own revision 1.
+fn revision_or_current(
+    conn: &dyn Conn,
+    cur: &Current,
+    rev: i64,
+) -> Result<Option<(Snapshot, i64)>, Miss> {
+    let found = load_revision(conn, cur, rev)?;
+    if found.is_some() || rev != 1 || latest_rev(conn, &cur.id) != 0 {
+        return Ok(found);
+    }
+    Ok(Some((cur.snapshot.clone(), cur.updated_at)))
+}
+
+// GET /api/clipboard/:id/revisions/:rev
+pub(crate) async fn get_revision(
+    State(state): State<AppState>,
+    Path((id, rev)): Path<(String, i64)>,
+) -> Response {
+    let keys = state.keys.clone();
+    state
+        .db
+        .read(move |conn| {
+            let found = load_current(conn, keys.as_deref(), &id)
+                .and_then(|cur| revision_or_current(conn, &cur, rev));
+            match found {
+                Ok(Some((s, at))) => Json(serde_json::json!({
+                    "rev": rev,
+                    "content": s.content,
+                    "fileName": s.file_name,
+                    "contentType": s.content_type,
+                    "createdAt": epoch_to_iso(at),
+                }))
+                .into_response(),
+                Ok(None) => not_found(),
+                Err(miss) => miss.into_response(),
+            }
+        })
+        .await
+}
+
+// GET /api/clipboard/:id/revisions/diff?from=&to= — unified diff of the content; `to`
+// defaults to the latest revision and `from` to the one before it
+pub(crate) async fn diff_revisions(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    uri: Uri,
+) -> Response {
+    let mut from: Option<i64> = None;
+    let mut to: Option<i64> = None;
+    for (k, v) in form_urlencoded::parse(uri.query().unwrap_or("").as_bytes()) {
+        let n = match v.parse::<i64>() {
+            Ok(n) => n,
+            Err(_) => return bad_request("from and to must be revision numbers"),
+        };
+        match k.as_ref() {
+            "from" => from = Some(n),
+            "to" => to = Some(n),
+            _ => {}
+        }
+    }
+    let keys = state.keys.clone();
+    state
+        .db
+        .read(move |conn| {
+            let cur = match load_current(conn, keys.as_deref(), &id) {
+                Ok(cur) => cur,
+                Err(miss) => return miss.into_response(),
+            };
+            let to = to.unwrap_or_else(|| latest_rev(conn, &id).max(1));
+            let from = from.unwrap_or((to - 1).max(1));
+            let pair = revision_or_current(conn, &cur, from)
+                .and_then(|a| Ok((a, revision_or_current(conn, &cur, to)?)));
+            let (a, b) = match pair {
+                Ok((Some((a, _)), Some((b, _)))) => (a, b),
+                Ok(_) => return not_found(),
+                Err(miss) => return miss.into_response(),
+            };
+            let old = a.content.clone().unwrap_or_default();
+            let new = b.content.clone().unwrap_or_default();
+            let text_diff = similar::TextDiff::from_lines(&old, &new);
+            let diff = text_diff
+                .unified_diff()
+                .context_radius(3)
+                .header(&format!("rev {from}"), &format!("rev {to}"))
+                .to_string();
+            let field = |x: &Option<String>, y: &Option<String>| {
+                (x != y).then(|| serde_json::json!({ "from": x, "to": y }))
+            };
+            Json(serde_json::json!({
+                "from": from,
+                "to": to,
+                "diff": diff,
+                "fileName": field(&a.file_name, &b.file_name),
+                "contentType": field(&a.content_type, &b.content_type),
+            }))
+            .into_response()
+        })
+        .await
+}
+
+// POST /api/clipboard/:id/revisions/:rev/restore
+pub(crate) async fn restore_revision(
+    State(state): State<AppState>,
+    Path((id, rev)): Path<(String, i64)>,
+) -> Response {
+    let found = state
+        .db
+        .read({
+            let (id, keys) = (id.clone(), state.keys.clone());
+            move |conn| {
+                let cur = load_current(conn, keys.as_deref(), &id)?;
+                match load_revision(conn, &cur, rev)? {
+                    Some((target, _)) => Ok((cur, target)),
+                    None => Err(Miss::NotFound),
+                }
+            }
+        })
+        .await;
+    let (cur, target) = match found {
+        Ok(found) => found,
+        Err(miss) => return miss.into_response(),
+    };
+    if target == cur.snapshot {
+        return bad_request("revision matches the current state");
+    }
+    commit(&state, &id, cur, target).await
+}
@@ -0,0 +1,431 @@
+use std::path::{Path, PathBuf};
+
+use image::metadata::Orientation;
+
+use crate::crypt::{self, ItemKey};
+
+// -------------------- Metadata stripping --------------------
+//
+// Share recipients get a copy of JPEG/PNG/WebP images without EXIF (GPS, camera serials,
+// timestamps), XMP, IPTC or text chunks. Pixel data is copied verbatim, so this is
+// lossless. If the original carried an EXIF orientation we write back a minimal EXIF
+// block holding only that tag, so the picture still displays upright.
+//
+// Stripped copies of on-disk files are cached in data/scrubbed/<itemId> (bundle files in
+// data/scrubbed/<itemId>/<fileId>), sealed like the original when the item is encrypted.
+
+pub(crate) fn is_strippable(content_type: &str) -> bool {
+    matches!(content_type, "image/jpeg" | "image/png" | "image/webp")
+}
+
+pub(crate) fn scrubbed_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("scrubbed")
+}
+
+pub(crate) fn cached_path(data_dir: &Path, id: &str) -> PathBuf {
+    scrubbed_dir(data_dir).join(id)
+}
+
+/// Write the stripped copy of `src` to the cache. Blocking; run on a blocking thread.
+pub(crate) fn write_cached(
+    data_dir: &Path,
+    id: &str,
+    src: &Path,
+    content_type: &str,
+    key: Option<&ItemKey>,
+) -> anyhow::Result<PathBuf> {
+    let original = crypt::read(src, key)?;
+    let stripped = strip(&original, content_type)?;
+    let dest = cached_path(data_dir, id);
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = dest.with_extension("tmp");
+    crypt::write(&tmp, key, &stripped)?;
+    std::fs::rename(&tmp, &dest)?;
+    Ok(dest)
+}
+
+pub(crate) fn remove(data_dir: &Path, id: &str) {
+    let path = cached_path(data_dir, id);
+    if std::fs::remove_file(&path).is_err() {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
+
+/// Return `data` without privacy-sensitive metadata.
+pub(crate) fn strip(data: &[u8], content_type: &str) -> anyhow::Result<Vec<u8>> {
+    match content_type {
+        "image/jpeg" => strip_jpeg(data),
+        "image/png" => strip_png(data),
+        "image/webp" => strip_webp(data),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+fn strip_jpeg(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(data.starts_with(&[0xFF, 0xD8]), "not a JPEG");
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut orientation = None;
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        anyhow::ensure!(data[i] == 0xFF, "corrupt JPEG marker");
+        let marker = data[i + 1];
+        if marker == 0xFF {
+            // fill byte
+            i += 1;
+            continue;
+        }
+        if marker == 0xD8 || marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            out.extend_from_slice(&data[i..i + 2]);
+            i += 2;
+            continue;
+        }
+        let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        anyhow::ensure!(
+            len >= 2 && i + 2 + len <= data.len(),
+            "truncated JPEG segment"
+        );
+        let payload = &data[i + 4..i + 2 + len];
+        let drop = match marker {
+            // APP1: EXIF / XMP
+            0xE1 => {
+                if let Some(tiff) = payload.strip_prefix(b"Exif\0\0") {
+                    orientation = orientation.or_else(|| Orientation::from_exif_chunk(tiff));
+                }
+                true
+            }
+            // APP2 keeps ICC profiles (needed for correct colors) but drops FlashPix/MPF
+            0xE2 => !payload.starts_with(b"ICC_PROFILE\0"),
+            // APP3..APP13 (Meta, Picture Info, IPTC/Photoshop, ...), APP15 and comments
+            0xE3..=0xED | 0xEF | 0xFE => true,
+            _ => false,
+        };
+        if marker == 0xDA {
+            // Start of scan: insert the orientation block before the image data, then the
+            // rest of the file (entropy-coded data, further scans, EOI) is copied as-is.
+            insert_jpeg_orientation(&mut out, orientation);
+            out.extend_from_slice(&data[i..]);
+            return Ok(out);
+        }
+        if !drop {
+            out.extend_from_slice(&data[i..i + 2 + len]);
+        }
+        i += 2 + len;
+    }
+    anyhow::bail!("JPEG without image data")
+}
+
+fn insert_jpeg_orientation(out: &mut Vec<u8>, orientation: Option<Orientation>) {
+    let Some(tiff) = orientation.and_then(minimal_exif) else {
+        return;
+    };
+    // After SOI and any APP0 (JFIF) segment, which must come first
+    let mut at = 2;
+    if out.len() >= 6 && out[2..4] == [0xFF, 0xE0] {
+        at = 4 + u16::from_be_bytes([out[4], out[5]]) as usize;
+    }
+    let mut seg = vec![0xFF, 0xE1];
+    seg.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+    seg.extend_from_slice(b"Exif\0\0");
+    seg.extend_from_slice(&tiff);
+    out.splice(at..at, seg);
+}
+
+const PNG_SIG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn strip_png(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(data.starts_with(PNG_SIG), "not a PNG");
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIG);
+    let mut orientation = None;
+    let mut i = PNG_SIG.len();
+    while i + 12 <= data.len() {
+        let len = u32::from_be_bytes(data[i..i + 4].try_into().unwrap()) as usize;
+        let end = i + 12 + len;
+        anyhow::ensure!(end <= data.len(), "truncated PNG chunk");
+        let kind = &data[i + 4..i + 8];
+        match kind {
+            b"eXIf" => orientation = Orientation::from_exif_chunk(&data[i + 8..i + 8 + len]),
+            b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" => {}
+            b"IDAT" => {
+                if let Some(tiff) = orientation.take().and_then(minimal_exif) {
+                    push_png_chunk(&mut out, b"eXIf", &tiff);
+                }
+                out.extend_from_slice(&data[i..end]);
+            }
+            _ => out.extend_from_slice(&data[i..end]),
+        }
+        if kind == b"IEND" {
+            return Ok(out);
+        }
+        i = end;
+    }
+    anyhow::bail!("PNG without IEND")
+}
+
+fn push_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    let mut crc = crc32(0, kind);
+    crc = crc32(crc, body);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn strip_webp(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP",
+        "not a WebP"
+    );
+    let mut chunks: Vec<(&[u8], Vec<u8>)> = vec![];
+    let mut orientation = None;
+    let mut i = 12;
+    while i + 8 <= data.len() {
+        let kind = &data[i..i + 4];
+        let len = u32::from_le_bytes(data[i + 4..i + 8].try_into().unwrap()) as usize;
+        let end = i + 8 + len;
+        anyhow::ensure!(end <= data.len(), "truncated WebP chunk");
+        let body = &data[i + 8..end];
+        match kind {
+            b"EXIF" => {
+                let tiff = body.strip_prefix(b"Exif\0\0").unwrap_or(body);
+                orientation = Orientation::from_exif_chunk(tiff);
+            }
+            b"XMP " => {}
+            _ => chunks.push((kind, body.to_vec())),
+        }
+        // chunks are padded to an even size
+        i = end + (len & 1);
+    }
+    anyhow::ensure!(i >= data.len(), "truncated WebP chunk");
+    let exif = orientation.and_then(minimal_exif);
+    if let Some((_, vp8x)) = chunks.iter_mut().find(|(k, _)| *k == b"VP8X") {
+        if let Some(flags) = vp8x.first_mut() {
+            // clear XMP (0x04) and EXIF (0x08) flags, set EXIF again if we keep orientation
+            *flags &= !0x0C;
+            if exif.is_some() {
+                *flags |= 0x08;
+            }
+        }
+    }
+    let mut body = Vec::with_capacity(data.len());
+    body.extend_from_slice(b"WEBP");
+    for (kind, chunk) in &chunks {
+        push_riff_chunk(&mut body, kind, chunk);
+    }
+    // The EXIF chunk is only valid in the extended format (VP8X present)
+    if let Some(tiff) = exif.filter(|_| chunks.iter().any(|(k, _)| *k == b"VP8X")) {
+        push_riff_chunk(&mut body, b"EXIF", &tiff);
+    }
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn push_riff_chunk(out: &mut Vec<u8>, kind: &[u8], body: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if body.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+/// A little-endian TIFF structure whose IFD0 holds only the Orientation tag.
+/// Nothing is written for the default orientation.
+pub(crate) fn minimal_exif(orientation: Orientation) -> Option<Vec<u8>> {
+    let value = orientation.to_exif();
+    if value == 1 {
+        return None;
+    }
+    let mut t = Vec::with_capacity(26);
+    t.extend_from_slice(b"II*\0");
+    t.extend_from_slice(&8u32.to_le_bytes()); // IFD0 offset
+    t.extend_from_slice(&1u16.to_le_bytes()); // entry count
+    t.extend_from_slice(&0x0112u16.to_le_bytes()); // Orientation
+    t.extend_from_slice(&3u16.to_le_bytes()); // SHORT
+    t.extend_from_slice(&1u32.to_le_bytes()); // count
+    t.extend_from_slice(&u16::from(value).to_le_bytes());
+    t.extend_from_slice(&[0, 0]); // value padding
+    t.extend_from_slice(&0u32.to_le_bytes()); // no next IFD
+    Some(t)
+}
+
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut c = !crc;
+    for b in bytes {
+        c ^= u32::from(*b);
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+    }
+    !c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut seg = vec![0xFF, marker];
+        seg.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        seg.extend_from_slice(payload);
+        seg
+    }
+
+    fn sample_jpeg() -> Vec<u8> {
+        let exif = [
+            b"Exif\0\0".as_slice(),
+            &minimal_exif(Orientation::Rotate90).unwrap(),
+        ]
+        .concat();
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+        data.extend(jpeg_segment(0xE1, &exif));
+        data.extend(jpeg_segment(
+            0xE1,
+            b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>",
+        ));
+        data.extend(jpeg_segment(0xE2, b"ICC_PROFILE\0\x01\x01icc"));
+        data.extend(jpeg_segment(0xFE, b"shot on my phone"));
+        data.extend(jpeg_segment(0xDA, b"\x01\x01\0"));
+        data.extend_from_slice(b"\x12\x34\xFF\x00\x56\xFF\xD9");
+        data
+    }
+
+    fn sample_png() -> Vec<u8> {
+        let mut data = PNG_SIG.to_vec();
+        push_png_chunk(&mut data, b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]);
+        push_png_chunk(&mut data, b"tEXt", b"Author\0someone");
+        push_png_chunk(
+            &mut data,
+            b"eXIf",
+            &minimal_exif(Orientation::Rotate180).unwrap(),
+        );
+        push_png_chunk(&mut data, b"IDAT", b"pixels");
+        push_png_chunk(&mut data, b"IEND", b"");
+        data
+    }
+
+    fn sample_webp() -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        push_riff_chunk(&mut body, b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        push_riff_chunk(&mut body, b"VP8L", b"pixels!");
+        let exif = [
+            b"Exif\0\0".as_slice(),
+            &minimal_exif(Orientation::Rotate270).unwrap(),
+        ]
+        .concat();
+        push_riff_chunk(&mut body, b"EXIF", &exif);
+        push_riff_chunk(&mut body, b"XMP ", b"<x:xmpmeta/>");
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    // orientation of the EXIF (TIFF) block that follows `prefix`
+    fn orientation_after(data: &[u8], prefix: &[u8]) -> Option<Orientation> {
+        let at = data.windows(prefix.len()).position(|w| w == prefix)? + prefix.len();
+        Orientation::from_exif_chunk(&data[at..])
+    }
+
+    #[test]
+    fn jpeg_keeps_pixels_icc_and_orientation_only() {
+        let out = strip(&sample_jpeg(), "image/jpeg").unwrap();
+        assert!(!contains(&out, b"xmpmeta"));
+        assert!(!contains(&out, b"my phone"));
+        assert!(contains(&out, b"ICC_PROFILE"));
+        assert!(out.ends_with(b"\x12\x34\xFF\x00\x56\xFF\xD9"));
+        // the orientation block goes right after JFIF
+        assert_eq!(&out[20..22], [0xFF, 0xE1]);
+        assert_eq!(
+            orientation_after(&out, b"Exif\0\0"),
+            Some(Orientation::Rotate90)
+        );
+    }
+
+    #[test]
+    fn png_drops_text_chunks_and_keeps_orientation() {
+        let out = strip(&sample_png(), "image/png").unwrap();
+        assert!(!contains(&out, b"tEXt"));
+        assert!(!contains(&out, b"someone"));
+        assert!(contains(&out, b"IDAT"));
+        assert_eq!(
+            orientation_after(&out, b"eXIf"),
+            Some(Orientation::Rotate180)
+        );
+        // every chunk carries a valid CRC
+        let mut i = PNG_SIG.len();
+        while i < out.len() {
+            let len = u32::from_be_bytes(out[i..i + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(out[i + 8 + len..i + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(0, &out[i + 4..i + 8 + len]), crc);
+            i += 12 + len;
+        }
+        assert_eq!(i, out.len());
+    }
+
+    #[test]
+    fn webp_drops_xmp_and_rewrites_flags() {
+        let out = strip(&sample_webp(), "image/webp").unwrap();
+        assert!(!contains(&out, b"XMP "));
+        assert!(!contains(&out, b"xmpmeta"));
+        assert!(contains(&out, b"pixels!"));
+        assert_eq!(
+            u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize,
+            out.len() - 8
+        );
+        // VP8X flags: XMP cleared, EXIF kept for the orientation
+        assert_eq!(out[20], 0x08);
+        assert_eq!(
+            orientation_after(&out, b"EXIF\x1a\0\0\0"),
+            Some(Orientation::Rotate270)
+        );
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_values() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(0, b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn truncated_images_are_errors() {
+        // cut inside the EXIF segment, inside IHDR, inside the VP8L chunk header
+        for (data, ctype, cut) in [
+            (sample_jpeg(), "image/jpeg", 30),
+            (sample_png(), "image/png", 30),
+            (sample_webp(), "image/webp", 35),
+        ] {
+            // no prefix panics
+            for n in 0..data.len() {
+                let _ = strip(&data[..n], ctype);
+            }
+            assert!(strip(&data[..cut], ctype).is_err(), "{ctype}");
+        }
+        // a length running past the end of the data
+        let mut png = sample_png();
+        png[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(strip(&png, "image/png").is_err());
+        let mut webp = sample_webp();
+        webp[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(strip(&webp, "image/webp").is_err());
+        let mut jpeg = sample_jpeg();
+        jpeg[4..6].copy_from_slice(&[0xFF, 0xFF]);
+        assert!(strip(&jpeg, "image/jpeg").is_err());
+    }
+}
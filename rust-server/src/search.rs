@@ -0,0 +1,303 @@
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+
+use crate::store::Dialect;
+
+// -------------------- Full-text search --------------------
+//
+// ClipboardSearch is an FTS5 table kept in sync with ClipboardItem by triggers. The
+// unicode61 tokenizer splits on whitespace/punctuation but would treat a run of Chinese
+// or Japanese characters as one token, so indexed text goes through fts_segment(), which
+// fences every CJK character with a zero-width space. Each CJK character becomes its own
+// token and a CJK word is searched as a phrase of consecutive characters.
+//
+// On Postgres the same text feeds a generated tsvector column (`searchVector`, 'simple'
+// configuration, CJK characters fenced with spaces by regexp_replace) and queries become
+// tsquery expressions. The helpers below give each dialect's spelling of the FROM clause,
+// match condition, rank and snippet; the query text is always the first parameter.
+//
+// Content sealed by encryption at rest (crypt.rs) is not indexed; such items are found by
+// file name only.
+
+const SEP: char = '\u{200B}';
+// Highlight markers used inside snippet(); replaced by <mark> once the text is escaped.
+const MARK_OPEN: char = '\u{2}';
+const MARK_CLOSE: char = '\u{3}';
+
+/// Columns: content, fileName, extractedText. File names weigh more than body text.
+const BM25: &str = "bm25(ClipboardSearch, 1.0, 2.0, 1.0)";
+const SNIPPET: &str = "snippet(ClipboardSearch, -1, char(2), char(3), '…', 16)";
+
+// Weights of the D, C, B and A labels; fileName is A, the rest D. Negated so that, as with
+// bm25, lower sorts first.
+const PG_RANK: &str = "-ts_rank('{0.5, 0.5, 0.5, 1.0}', c.searchVector, fq)";
+const PG_SNIPPET: &str = "ts_headline('simple', concat_ws(' ', c.fileName, CASE WHEN c.dataKey IS NULL THEN c.content END, c.extractedText), fq, 'MaxFragments=1, MaxWords=16, MinWords=6, StartSel=' || chr(2) || ', StopSel=' || chr(3))";
+// tsvector caps out at 1 MB; very long extracted text is indexed up to here
+const PG_MAX_CHARS: usize = 200_000;
+
+const CJK_RANGES: &[(char, char)] = &[
+    ('\u{3040}', '\u{30FF}'),   // Hiragana, Katakana
+    ('\u{31F0}', '\u{31FF}'),   // Katakana extensions
+    ('\u{3400}', '\u{4DBF}'),   // CJK extension A
+    ('\u{4E00}', '\u{9FFF}'),   // CJK unified ideographs
+    ('\u{F900}', '\u{FAFF}'),   // compatibility ideographs
+    ('\u{20000}', '\u{2FA1F}'), // extensions B and later
+];
+
+/// Items joined with their index entry, as `c`.
+pub(crate) fn source(d: Dialect) -> &'static str {
+    match d {
+        Dialect::Sqlite => "ClipboardSearch JOIN ClipboardItem c ON c.id = ClipboardSearch.itemId",
+        Dialect::Postgres => "ClipboardItem c CROSS JOIN to_tsquery('simple', ?) AS fq",
+    }
+}
+
+/// WHERE condition selecting matches; on SQLite it takes the query parameter.
+pub(crate) fn condition(d: Dialect) -> &'static str {
+    match d {
+        Dialect::Sqlite => "ClipboardSearch MATCH ?",
+        Dialect::Postgres => "c.searchVector @@ fq",
+    }
+}
+
+/// Relevance, lower is better.
+pub(crate) fn rank(d: Dialect) -> &'static str {
+    match d {
+        Dialect::Sqlite => BM25,
+        Dialect::Postgres => PG_RANK,
+    }
+}
+
+/// Matched text with \u{2} / \u{3} around hits, for `snippet_html`.
+pub(crate) fn snippet(d: Dialect) -> &'static str {
+    match d {
+        Dialect::Sqlite => SNIPPET,
+        Dialect::Postgres => PG_SNIPPET,
+    }
+}
+
+/// The indexed text of an item: its content unless that is sealed.
+pub(crate) const PLAIN_CONTENT: &str = "CASE WHEN dataKey IS NULL THEN content END";
+
+/// Expression of the generated `searchVector` column on Postgres, indexing `content`.
+pub(crate) fn pg_vector(content: &str) -> String {
+    let class: String = CJK_RANGES
+        .iter()
+        .map(|(a, b)| format!("\\U{:08X}-\\U{:08X}", u32::from(*a), u32::from(*b)))
+        .collect();
+    let column = |name: &str, weight: char| {
+        format!("setweight(to_tsvector('simple', regexp_replace(left(COALESCE({name}, ''), {PG_MAX_CHARS}), '([{class}])', ' \\1 ', 'g')), '{weight}')")
+    };
+    [
+        column(content, 'D'),
+        column("fileName", 'A'),
+        column("extractedText", 'D'),
+    ]
+    .join(" || ")
+}
+
+/// Register SQL functions used by the search triggers. Must run on every connection
+/// that writes to ClipboardItem.
+pub(crate) fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "fts_segment",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|s| segment(&s))),
+    )
+}
+
+/// Create the index and triggers, (re)building it from ClipboardItem when it is new or
+/// predates the current column layout.
+pub(crate) fn init(conn: &Connection) -> rusqlite::Result<()> {
+    let exists: bool = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type='table' AND name='ClipboardSearch'",
+            [],
+            |_| Ok(true),
+        )
+        .unwrap_or(false);
+    let current = exists
+        && conn
+            .prepare("SELECT extractedText FROM ClipboardSearch LIMIT 0")
+            .is_ok();
+    if exists && !current {
+        // FTS5 tables can't gain columns; drop and rebuild
+        conn.execute_batch(
+            r"
+            DROP TRIGGER IF EXISTS clipboard_search_ai;
+            DROP TRIGGER IF EXISTS clipboard_search_ad;
+            DROP TRIGGER IF EXISTS clipboard_search_au;
+            DROP TABLE ClipboardSearch;
+            ",
+        )?;
+    }
+    conn.execute_batch(
+        r"
+        CREATE VIRTUAL TABLE IF NOT EXISTS ClipboardSearch USING fts5(
+          content,
+          fileName,
+          extractedText,
+          itemId UNINDEXED,
+          tokenize = 'unicode61 remove_diacritics 2'
+        );
+        CREATE TRIGGER IF NOT EXISTS clipboard_search_ai AFTER INSERT ON ClipboardItem BEGIN
+          INSERT INTO ClipboardSearch (content, fileName, extractedText, itemId)
+          VALUES (fts_segment(new.content), fts_segment(new.fileName), fts_segment(new.extractedText), new.id);
+        END;
+        CREATE TRIGGER IF NOT EXISTS clipboard_search_ad AFTER DELETE ON ClipboardItem BEGIN
+          DELETE FROM ClipboardSearch WHERE itemId = old.id;
+        END;
+        CREATE TRIGGER IF NOT EXISTS clipboard_search_au AFTER UPDATE OF content, fileName, extractedText ON ClipboardItem BEGIN
+          DELETE FROM ClipboardSearch WHERE itemId = old.id;
+          INSERT INTO ClipboardSearch (content, fileName, extractedText, itemId)
+          VALUES (fts_segment(new.content), fts_segment(new.fileName), fts_segment(new.extractedText), new.id);
+        END;
+        ",
+    )?;
+    if !current {
+        conn.execute(
+            "INSERT INTO ClipboardSearch (content, fileName, extractedText, itemId) SELECT fts_segment(content), fts_segment(fileName), fts_segment(extractedText), id FROM ClipboardItem",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Migration: replace the insert and update triggers so sealed content stays out of the
+/// index, and re-index when an item is sealed or unsealed.
+pub(crate) fn skip_sealed_content(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r"
+        DROP TRIGGER clipboard_search_ai;
+        DROP TRIGGER clipboard_search_au;
+        CREATE TRIGGER clipboard_search_ai AFTER INSERT ON ClipboardItem BEGIN
+          INSERT INTO ClipboardSearch (content, fileName, extractedText, itemId)
+          VALUES (fts_segment(CASE WHEN new.dataKey IS NULL THEN new.content END), fts_segment(new.fileName), fts_segment(new.extractedText), new.id);
+        END;
+        CREATE TRIGGER clipboard_search_au AFTER UPDATE OF content, fileName, extractedText, dataKey ON ClipboardItem BEGIN
+          DELETE FROM ClipboardSearch WHERE itemId = old.id;
+          INSERT INTO ClipboardSearch (content, fileName, extractedText, itemId)
+          VALUES (fts_segment(CASE WHEN new.dataKey IS NULL THEN new.content END), fts_segment(new.fileName), fts_segment(new.extractedText), new.id);
+        END;
+        ",
+    )
+}
+
+fn is_cjk(c: char) -> bool {
+    CJK_RANGES.iter().any(|(a, b)| (*a..=*b).contains(&c))
+}
+
+/// Fence every CJK character with separators so the tokenizer indexes it on its own.
+pub(crate) fn segment(text: &str) -> String {
+    fence(text, SEP)
+}
+
+fn fence(text: &str, sep: char) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / 2);
+    for c in text.chars() {
+        if is_cjk(c) {
+            out.push(sep);
+            out.push(c);
+            out.push(sep);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Translate a search box string into an FTS5 MATCH expression, or a tsquery on Postgres.
+///
+/// - `"exact phrase"` matches the words in order
+/// - `term*` is a prefix query; the last bare term is also matched as a prefix so results
+///   follow the user while typing
+/// - everything else is matched as whole words, all of which must be present
+///
+/// FTS5 / tsquery operators in user input are never interpreted; every term is quoted.
+pub(crate) fn match_query(d: Dialect, input: &str) -> Option<String> {
+    let mut parts: Vec<(String, bool, bool)> = vec![]; // (text, prefix, quoted)
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '"' {
+            chars.next();
+            let phrase: String = chars.by_ref().take_while(|c| *c != '"').collect();
+            let prefix = chars.next_if_eq(&'*').is_some();
+            parts.push((phrase, prefix, true));
+            continue;
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '"' {
+                break;
+            }
+            word.push(c);
+            chars.next();
+        }
+        let prefix = word.ends_with('*');
+        parts.push((word.trim_end_matches('*').to_string(), prefix, false));
+    }
+    parts.retain(|(t, _, _)| t.chars().any(char::is_alphanumeric));
+    if let Some(last) = parts.last_mut() {
+        if !last.2 {
+            last.1 = true;
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    let expr = match d {
+        Dialect::Sqlite => parts
+            .iter()
+            .map(|(text, prefix, _)| {
+                let quoted = format!("\"{}\"", segment(text).replace('"', "\"\""));
+                if *prefix {
+                    quoted + "*"
+                } else {
+                    quoted
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        // 'a b' is the phrase a <-> b; :* makes every word of it a prefix
+        Dialect::Postgres => parts
+            .iter()
+            .map(|(text, prefix, _)| {
+                let quoted = format!(
+                    "'{}'",
+                    fence(text, ' ').replace('\\', "\\\\").replace('\'', "''")
+                );
+                if *prefix {
+                    quoted + ":*"
+                } else {
+                    quoted
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" & "),
+    };
+    Some(expr)
+}
+
+/// Turn a raw snippet() result into HTML: escaped text with matches wrapped in <mark>.
+pub(crate) fn snippet_html(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len() + 16);
+    for c in raw.chars() {
+        match c {
+            SEP => {}
+            MARK_OPEN => out.push_str("<mark>"),
+            MARK_CLOSE => out.push_str("</mark>"),
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,345 @@
+use mime_guess::from_path as guess_mime;
+
+// -------------------- Content-type sniffing --------------------
+
+/// Number of leading bytes kept from an upload for sniffing.
+pub(crate) const SNIFF_LEN: usize = 8192;
+
+/// Determine the real content type of an upload from its leading bytes.
+///
+/// Magic numbers are checked first, then markup (HTML/SVG/XML) so that active content
+/// can never be disguised as something harmless. Plain text falls back to the type
+/// implied by the file extension when that type is itself a passive text type.
+pub(crate) fn detect(head: &[u8], file_name: Option<&str>) -> String {
+    if let Some(m) = magic(head) {
+        if m == "application/zip" {
+            // OOXML/ODF/EPUB/JAR containers are zip files; trust the extension to refine them
+            if let Some(g) = file_name.and_then(|n| guess_mime(n).first()) {
+                let g = g.essence_str().to_string();
+                if g.starts_with("application/") && !is_active(&g) {
+                    return g;
+                }
+            }
+        }
+        return m.to_string();
+    }
+    if let Some(m) = markup(head) {
+        return m.to_string();
+    }
+    if looks_like_text(head) {
+        if let Some(g) = file_name.and_then(|n| guess_mime(n).first()) {
+            let g = g.essence_str().to_string();
+            let textual = g.starts_with("text/")
+                || matches!(
+                    g.as_str(),
+                    "application/json" | "application/javascript" | "application/x-sh"
+                );
+            if textual && !is_active(&g) {
+                return g;
+            }
+        }
+        return "text/plain".to_string();
+    }
+    "application/octet-stream".to_string()
+}
+
+/// Types a browser would execute or render as a document when navigated to directly.
+pub(crate) fn is_active(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    matches!(
+        essence.as_str(),
+        "text/html"
+            | "application/xhtml+xml"
+            | "image/svg+xml"
+            | "text/xml"
+            | "application/xml"
+            | "text/xsl"
+            | "application/xslt+xml"
+            | "application/x-shockwave-flash"
+            | "application/vnd.wap.xhtml+xml"
+            | "multipart/x-mixed-replace"
+    ) || essence.ends_with("+xml")
+}
+
+/// Image types browsers can preview inline; everything else is stored as a FILE item.
+pub(crate) fn is_previewable_image(content_type: &str) -> bool {
+    matches!(
+        content_type,
+        "image/png"
+            | "image/jpeg"
+            | "image/gif"
+            | "image/webp"
+            | "image/bmp"
+            | "image/avif"
+            | "image/svg+xml"
+            | "image/x-icon"
+    )
+}
+
+fn magic(b: &[u8]) -> Option<&'static str> {
+    let starts = |sig: &[u8]| b.starts_with(sig);
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if starts(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg");
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if b.len() >= 12 && starts(b"RIFF") {
+        match &b[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    if b.len() >= 12 && &b[4..8] == b"ftyp" {
+        return Some(match &b[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        });
+    }
+    if b.len() >= 26 && starts(b"BM") && b[6..10] == [0, 0, 0, 0] {
+        return Some("image/bmp");
+    }
+    if starts(&[0, 0, 1, 0]) && b.len() >= 6 && b[4] > 0 {
+        return Some("image/x-icon");
+    }
+    if starts(b"II*\0") || starts(b"MM\0*") {
+        return Some("image/tiff");
+    }
+    if starts(b"%PDF-") {
+        return Some("application/pdf");
+    }
+    if starts(b"PK\x03\x04") || starts(b"PK\x05\x06") {
+        return Some("application/zip");
+    }
+    if starts(&[0x1F, 0x8B]) {
+        return Some("application/gzip");
+    }
+    if starts(b"BZh") && b.len() >= 4 && b[3].is_ascii_digit() {
+        return Some("application/x-bzip2");
+    }
+    if starts(&[0xFD, b'7', b'z', b'X', b'Z', 0]) {
+        return Some("application/x-xz");
+    }
+    if starts(&[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C]) {
+        return Some("application/x-7z-compressed");
+    }
+    if starts(b"Rar!\x1a\x07") {
+        return Some("application/vnd.rar");
+    }
+    if starts(b"OggS") {
+        return Some("audio/ogg");
+    }
+    if starts(b"fLaC") {
+        return Some("audio/flac");
+    }
+    if starts(b"ID3") || (b.len() >= 2 && b[0] == 0xFF && matches!(b[1], 0xFB | 0xF3 | 0xF2)) {
+        return Some("audio/mpeg");
+    }
+    if starts(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        let webm = b.windows(4).take(64).any(|w| w == b"webm");
+        return Some(if webm {
+            "video/webm"
+        } else {
+            "video/x-matroska"
+        });
+    }
+    if starts(b"\0asm") {
+        return Some("application/wasm");
+    }
+    if starts(b"\x7fELF") {
+        return Some("application/x-executable");
+    }
+    if starts(b"MZ") {
+        return Some("application/x-msdownload");
+    }
+    if starts(b"SQLite format 3\0") {
+        return Some("application/vnd.sqlite3");
+    }
+    if starts(b"\xca\xfe\xba\xbe") {
+        return Some("application/java-vm");
+    }
+    None
+}
+
+// Markup detection loosely follows the WHATWG MIME sniffing rules for HTML.
+fn markup(b: &[u8]) -> Option<&'static str> {
+    let b = b.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(b);
+    let start = b.iter().position(|c| !c.is_ascii_whitespace())?;
+    let body = &b[start..];
+    let lower: Vec<u8> = body.iter().take(1024).map(u8::to_ascii_lowercase).collect();
+    const HTML_TAGS: &[&[u8]] = &[
+        b"<!doctype html",
+        b"<html",
+        b"<head",
+        b"<body",
+        b"<script",
+        b"<iframe",
+        b"<h1",
+        b"<div",
+        b"<font",
+        b"<table",
+        b"<a",
+        b"<style",
+        b"<title",
+        b"<b",
+        b"<br",
+        b"<p",
+        b"<!--",
+        b"<object",
+        b"<embed",
+        b"<meta",
+        b"<form",
+    ];
+    for tag in HTML_TAGS {
+        if lower.starts_with(tag) {
+            // tag must be terminated by a space or '>' (except comments)
+            let next = lower.get(tag.len()).copied();
+            if *tag == b"<!--" || matches!(next, Some(b' ' | b'>' | b'\t' | b'\n' | b'\r' | b'/')) {
+                return Some("text/html");
+            }
+        }
+    }
+    if lower.starts_with(b"<svg") {
+        return Some("image/svg+xml");
+    }
+    if lower.starts_with(b"<?xml") {
+        let has = |needle: &[u8]| lower.windows(needle.len()).any(|w| w == needle);
+        if has(b"<svg") {
+            return Some("image/svg+xml");
+        }
+        if has(b"<html") {
+            return Some("application/xhtml+xml");
+        }
+        return Some("application/xml");
+    }
+    None
+}
+
+fn looks_like_text(b: &[u8]) -> bool {
+    if b.is_empty() {
+        return true;
+    }
+    if b.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(b) {
+        Ok(_) => true,
+        // the sniff window may cut a multi-byte sequence in half
+        Err(e) => e.error_len().is_none() && e.valid_up_to() + 4 >= b.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_decide_the_type() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(detect(png, Some("a.png")), "image/png");
+        assert_eq!(
+            detect(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10], None),
+            "image/jpeg"
+        );
+        assert_eq!(
+            detect(b"%PDF-1.7\n%\xe2\xe3", Some("doc.txt")),
+            "application/pdf"
+        );
+        assert_eq!(
+            detect(b"PK\x03\x04\x14\0", Some("a.zip")),
+            "application/zip"
+        );
+        // zip containers take their type from the extension, unless it's active content
+        assert_eq!(
+            detect(b"PK\x03\x04\x14\0", Some("report.docx")),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+        assert_eq!(
+            detect(b"PK\x03\x04\x14\0", Some("page.xhtml")),
+            "application/zip"
+        );
+    }
+
+    #[test]
+    fn markup_is_detected_whatever_the_name() {
+        // a page sent as image/png with a .png name is still HTML
+        assert_eq!(
+            detect(b"\xEF\xBB\xBF  <!DOCTYPE html><p>hi", Some("photo.png")),
+            "text/html"
+        );
+        assert_eq!(detect(b"<script>alert(1)</script>", None), "text/html");
+        assert_eq!(detect(b"<!-- x -->", Some("notes.txt")), "text/html");
+        assert_eq!(
+            detect(
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                Some("a.txt")
+            ),
+            "image/svg+xml"
+        );
+        assert_eq!(
+            detect(b"<?xml version=\"1.0\"?>\n<svg/>", None),
+            "image/svg+xml"
+        );
+        assert_eq!(
+            detect(b"<?xml version=\"1.0\"?><a/>", None),
+            "application/xml"
+        );
+        // `<bold` is not `<b`
+        assert_eq!(detect(b"<bold text", None), "text/plain");
+    }
+
+    #[test]
+    fn text_and_binary_are_told_apart() {
+        assert_eq!(detect("naïve café".as_bytes(), None), "text/plain");
+        assert_eq!(detect(b"a,b\n1,2\n", Some("t.csv")), "text/csv");
+        assert_eq!(detect(b"{\"a\":1}", Some("a.json")), "application/json");
+        // a passive name can't pass text off as active content
+        assert_eq!(detect(b"plain words", Some("x.html")), "text/plain");
+        assert_eq!(
+            detect(b"\x01\x02\0\x03", Some("a.txt")),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            detect(b"\xff\xfe\xfd plain", None),
+            "application/octet-stream"
+        );
+        // a multi-byte character cut off by the sniff window
+        let cut = &"日本".as_bytes()[..4];
+        assert!(looks_like_text(cut));
+        assert!(looks_like_text(b""));
+    }
+
+    #[test]
+    fn active_types_include_html_svg_and_xml() {
+        for t in [
+            "text/html; charset=utf-8",
+            "IMAGE/SVG+XML",
+            "application/xml",
+            "application/atom+xml",
+            "multipart/x-mixed-replace",
+        ] {
+            assert!(is_active(t), "{t}");
+        }
+        for t in [
+            "text/plain",
+            "image/png",
+            "application/pdf",
+            "application/json",
+        ] {
+            assert!(!is_active(t), "{t}");
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::fmt;
+use std::ops::Deref;
+
+use rusqlite::types::{FromSql, FromSqlError, ToSqlOutput, ValueRef};
+use rusqlite::ToSql;
+
+pub(crate) use rusqlite::types::Value;
+
+// -------------------- Storage backends --------------------
+//
+// Handlers talk to the database through `Conn`, implemented by a SQLite connection and by
+// a PostgreSQL client (pg.rs). Statements are written once in the SQL both understand:
+// `?` placeholders, `ON CONFLICT` instead of `INSERT OR ...`, `lower()` instead of
+// `COLLATE NOCASE`. What differs (full-text search, the schema and its migrations) asks
+// `dialect()`. Parameters are bound with rusqlite's `params!` / `ToSql` and columns read
+// with its `FromSql`; the Postgres side converts to and from the same values.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// `query_row` found no row.
+    NoRows,
+    Sqlite(rusqlite::Error),
+    Postgres(postgres::Error),
+    /// A column value that doesn't convert to the requested type.
+    Column(usize, FromSqlError),
+    /// A parameter or column type the backend can't carry.
+    Unsupported(String),
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRows => f.write_str("query returned no rows"),
+            Self::Sqlite(e) => e.fmt(f),
+            Self::Postgres(e) => e.fmt(f),
+            Self::Column(i, e) => write!(f, "column {i}: {e}"),
+            Self::Unsupported(what) => write!(f, "unsupported {what}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => Self::NoRows,
+            e => Self::Sqlite(e),
+        }
+    }
+}
+
+impl From<postgres::Error> for Error {
+    fn from(e: postgres::Error) -> Self {
+        Self::Postgres(e)
+    }
+}
+
+pub(crate) trait OptionalExtension<T> {
+    /// `Ok(None)` instead of `Err(NoRows)`.
+    fn optional(self) -> Result<Option<T>>;
+}
+
+impl<T> OptionalExtension<T> for Result<T> {
+    fn optional(self) -> Result<Option<T>> {
+        match self {
+            Ok(v) => Ok(Some(v)),
+            Err(Error::NoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// One result row.
+pub(crate) struct Row(pub(crate) Vec<Value>);
+
+/// Column positions; only `usize`, so a literal index needs no annotation.
+pub(crate) trait ColumnIndex {
+    fn index(self) -> usize;
+}
+
+impl ColumnIndex for usize {
+    fn index(self) -> usize {
+        self
+    }
+}
+
+impl Row {
+    pub(crate) fn get<I: ColumnIndex, T: FromSql>(&self, idx: I) -> Result<T> {
+        let i = idx.index();
+        let v = self
+            .0
+            .get(i)
+            .ok_or(Error::Column(i, FromSqlError::InvalidType))?;
+        T::column_result(ValueRef::from(v)).map_err(|e| Error::Column(i, e))
+    }
+}
+
+/// Statement parameters: what `params!`, `[a, b]`, `[]` or a `Vec<Value>` give.
+pub(crate) trait Params {
+    fn values(self) -> Result<Vec<Value>>;
+}
+
+fn to_value(p: &dyn ToSql) -> Result<Value> {
+    Ok(match p.to_sql()? {
+        ToSqlOutput::Borrowed(v) => v.into(),
+        ToSqlOutput::Owned(v) => v,
+        _ => return Err(Error::Unsupported("parameter".into())),
+    })
+}
+
+impl Params for [&(dyn ToSql + Send + Sync); 0] {
+    fn values(self) -> Result<Vec<Value>> {
+        Ok(vec![])
+    }
+}
+
+impl Params for &[&dyn ToSql] {
+    fn values(self) -> Result<Vec<Value>> {
+        self.iter().map(|p| to_value(*p)).collect()
+    }
+}
+
+impl Params for Vec<Value> {
+    fn values(self) -> Result<Vec<Value>> {
+        Ok(self)
+    }
+}
+
+/// Parameters from an iterator, like `rusqlite::params_from_iter`.
+pub(crate) struct ParamsFromIter<I>(I);
+
+pub(crate) fn params_from_iter<I>(iter: I) -> ParamsFromIter<I>
+where
+    I: IntoIterator,
+    I::Item: ToSql,
+{
+    ParamsFromIter(iter)
+}
+
+impl<I> Params for ParamsFromIter<I>
+where
+    I: IntoIterator,
+    I::Item: ToSql,
+{
+    fn values(self) -> Result<Vec<Value>> {
+        self.0.into_iter().map(|p| to_value(&p)).collect()
+    }
+}
+
+macro_rules! array_params {
+    ($($n:literal)*) => {$(
+        impl<T: ToSql> Params for [T; $n] {
+            fn values(self) -> Result<Vec<Value>> {
+                self.iter().map(|p| to_value(p)).collect()
+            }
+        }
+    )*};
+}
+
+array_params!(1 2 3 4 5 6 7 8);
+
+/// A connection to one of the backends. Callers use the generic helpers on `dyn Conn`
+/// (`execute`, `query_row`, `prepare`, `transaction`), which mirror rusqlite's.
+pub(crate) trait Conn {
+    fn dialect(&self) -> Dialect;
+
+    /// Run one statement; the number of rows it changed.
+    fn exec(&self, sql: &str, params: &[Value]) -> Result<usize>;
+
+    /// Run one query and collect its rows.
+    fn fetch(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>>;
+
+    /// Run statements without parameters, separated by `;`.
+    fn exec_batch(&self, sql: &str) -> Result<()>;
+
+    /// The SQLite connection underneath, for what only exists there (export, backups).
+    fn as_sqlite(&self) -> Option<&rusqlite::Connection> {
+        None
+    }
+
+    fn as_sqlite_mut(&mut self) -> Option<&mut rusqlite::Connection> {
+        None
+    }
+}
+
+impl dyn Conn + '_ {
+    pub(crate) fn execute<P: Params>(&self, sql: &str, params: P) -> Result<usize> {
+        self.exec(sql, &params.values()?)
+    }
+
+    pub(crate) fn query_row<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<T>
+    where
+        P: Params,
+        F: FnOnce(&Row) -> Result<T>,
+    {
+        let rows = self.fetch(sql, &params.values()?)?;
+        f(rows.first().ok_or(Error::NoRows)?)
+    }
+
+    pub(crate) fn prepare<'c>(&'c self, sql: &str) -> Result<Statement<'c>> {
+        Ok(Statement {
+            conn: self,
+            sql: sql.to_string(),
+        })
+    }
+
+    /// Start a transaction; it rolls back when dropped without `commit`.
+    pub(crate) fn transaction(&self) -> Result<Transaction<'_>> {
+        self.exec_batch("BEGIN")?;
+        Ok(Transaction {
+            conn: self,
+            done: false,
+        })
+    }
+}
+
+/// A query to run one or more times, as with rusqlite's prepared statements.
+pub(crate) struct Statement<'c> {
+    conn: &'c dyn Conn,
+    sql: String,
+}
+
+impl Statement<'_> {
+    pub(crate) fn query_map<T, P, F>(
+        &mut self,
+        params: P,
+        mut f: F,
+    ) -> Result<std::vec::IntoIter<Result<T>>>
+    where
+        P: Params,
+        F: FnMut(&Row) -> Result<T>,
+    {
+        let rows = self.conn.fetch(&self.sql, &params.values()?)?;
+        Ok(rows.iter().map(&mut f).collect::<Vec<_>>().into_iter())
+    }
+
+    pub(crate) fn query_row<T, P, F>(&mut self, params: P, f: F) -> Result<T>
+    where
+        P: Params,
+        F: FnOnce(&Row) -> Result<T>,
+    {
+        self.conn.query_row(&self.sql, params, f)
+    }
+}
+
+pub(crate) struct Transaction<'c> {
+    conn: &'c dyn Conn,
+    done: bool,
+}
+
+impl<'c> Deref for Transaction<'c> {
+    type Target = dyn Conn + 'c;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+    }
+}
+
+// so that `&tx` passes wherever a `&dyn Conn` is expected
+impl Conn for Transaction<'_> {
+    fn dialect(&self) -> Dialect {
+        self.conn.dialect()
+    }
+
+    fn exec(&self, sql: &str, params: &[Value]) -> Result<usize> {
+        self.conn.exec(sql, params)
+    }
+
+    fn fetch(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        self.conn.fetch(sql, params)
+    }
+
+    fn exec_batch(&self, sql: &str) -> Result<()> {
+        self.conn.exec_batch(sql)
+    }
+}
+
+impl Transaction<'_> {
+    pub(crate) fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.conn.exec_batch("COMMIT")
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.conn.exec_batch("ROLLBACK");
+        }
+    }
+}
+
+// -------------------- SQLite --------------------
+
+impl Conn for rusqlite::Connection {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn exec(&self, sql: &str, params: &[Value]) -> Result<usize> {
+        Ok(self
+            .prepare_cached(sql)?
+            .execute(rusqlite::params_from_iter(params))?)
+    }
+
+    fn fetch(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        let mut stmt = self.prepare_cached(sql)?;
+        let n = stmt.column_count();
+        let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            out.push(Row((0..n).map(|i| r.get(i)).collect::<Result<_, _>>()?));
+        }
+        Ok(out)
+    }
+
+    fn exec_batch(&self, sql: &str) -> Result<()> {
+        Ok(self.execute_batch(sql)?)
+    }
+
+    fn as_sqlite(&self) -> Option<&rusqlite::Connection> {
+        Some(self)
+    }
+
+    fn as_sqlite_mut(&mut self) -> Option<&mut rusqlite::Connection> {
+        Some(self)
+    }
+}
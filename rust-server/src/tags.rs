@@ -0,0 +1,692 @@
+use std::collections::HashMap;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::store::{self, Conn, OptionalExtension};
+use crate::{epoch_to_iso, now_unix, AppState, ServerEvent};
+
+// -------------------- Tags & collections --------------------
+//
+// Tags are free-form labels (many per item); collections are named groups of items. Both
+// are many-to-many with ClipboardItem through ItemTag / CollectionItem, whose rows go away
+// with the item (ON DELETE CASCADE).
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct TagRef {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) color: Option<String>,
+}
+
+fn bad_request(msg: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": msg })),
+    )
+        .into_response()
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({"error":"Not found"})),
+    )
+        .into_response()
+}
+
+// A refused write, returned from inside a DB closure where a whole Response is too large
+struct Refusal(StatusCode, &'static str);
+
+impl From<store::Error> for Refusal {
+    fn from(e: store::Error) -> Self {
+        tracing::warn!(error = %e, "tags: write failed");
+        Self(StatusCode::INTERNAL_SERVER_ERROR, "db write failed")
+    }
+}
+
+impl IntoResponse for Refusal {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn broadcast(state: &AppState, name: &str, data: serde_json::Value) {
+    let _ = state.tx.send(ServerEvent {
+        name: name.into(),
+        data,
+    });
+}
+
+// Names are used in comma-separated list filters, so commas are not allowed.
+fn clean_name(name: &str) -> Result<String, &'static str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("name is required");
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err("name is too long");
+    }
+    if name.contains(',') {
+        return Err("name must not contain commas");
+    }
+    Ok(name.to_string())
+}
+
+fn clean_color(color: Option<&str>) -> Result<Option<String>, &'static str> {
+    match color.map(str::trim).filter(|c| !c.is_empty()) {
+        None => Ok(None),
+        Some(c)
+            if c.len() == 7
+                && c.starts_with('#')
+                && c[1..].chars().all(|ch| ch.is_ascii_hexdigit()) =>
+        {
+            Ok(Some(c.to_ascii_lowercase()))
+        }
+        Some(_) => Err("color must look like #rrggbb"),
+    }
+}
+
+pub(crate) fn item_exists(conn: &dyn Conn, id: &str) -> bool {
+    conn.query_row(
+        "SELECT 1 FROM ClipboardItem WHERE id=? AND deletedAt IS NULL",
+        [id],
+        |_| Ok(()),
+    )
+    .is_ok()
+}
+
+/// Tags of the given items, keyed by item id.
+pub(crate) fn tags_for_items(conn: &dyn Conn, ids: &[String]) -> HashMap<String, Vec<TagRef>> {
+    let mut out: HashMap<String, Vec<TagRef>> = HashMap::new();
+    if ids.is_empty() {
+        return out;
+    }
+    let marks = vec!["?"; ids.len()].join(",");
+    let sql = format!(
+        "SELECT it.itemId, t.id, t.name, t.color FROM ItemTag it JOIN Tag t ON t.id = it.tagId WHERE it.itemId IN ({marks}) ORDER BY lower(t.name)"
+    );
+    let Ok(mut stmt) = conn.prepare(&sql) else {
+        return out;
+    };
+    let rows = stmt.query_map(store::params_from_iter(ids.iter()), |r| {
+        Ok((
+            r.get::<_, String>(0)?,
+            TagRef {
+                id: r.get(1)?,
+                name: r.get(2)?,
+                color: r.get(3)?,
+            },
+        ))
+    });
+    if let Ok(rows) = rows {
+        for (item_id, tag) in rows.flatten() {
+            out.entry(item_id).or_default().push(tag);
+        }
+    }
+    out
+}
+
+/// Collections an item belongs to.
+pub(crate) fn collections_for_item(conn: &dyn Conn, id: &str) -> Vec<serde_json::Value> {
+    let Ok(mut stmt) = conn.prepare(
+        "SELECT c.id, c.name FROM CollectionItem ci JOIN Collection c ON c.id = ci.collectionId WHERE ci.itemId = ? ORDER BY lower(c.name)",
+    ) else {
+        return vec![];
+    };
+    stmt.query_map([id], |r| {
+        Ok(serde_json::json!({"id": r.get::<_, String>(0)?, "name": r.get::<_, String>(1)?}))
+    })
+    .map(|rows| rows.flatten().collect())
+    .unwrap_or_default()
+}
+
+// -------- Tags --------
+
+// GET /api/tags
+pub(crate) async fn list_tags(State(state): State<AppState>) -> Response {
+    state.db.read(move |conn| {
+        let mut stmt = conn
+            .prepare("SELECT t.id, t.name, t.color, t.createdAt, (SELECT COUNT(*) FROM ItemTag it WHERE it.tagId = t.id) FROM Tag t ORDER BY lower(t.name)")
+            .unwrap();
+        let tags: Vec<serde_json::Value> = stmt
+            .query_map([], |r| {
+                Ok(serde_json::json!({
+                    "id": r.get::<_, String>(0)?,
+                    "name": r.get::<_, String>(1)?,
+                    "color": r.get::<_, Option<String>>(2)?,
+                    "createdAt": epoch_to_iso(r.get::<_, i64>(3)?),
+                    "itemCount": r.get::<_, i64>(4)?,
+                }))
+            })
+            .unwrap()
+            .flatten()
+            .collect();
+        Json(serde_json::json!({ "tags": tags })).into_response()
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TagReq {
+    name: Option<String>,
+    color: Option<String>,
+}
+
+pub(crate) fn find_tag_by_name(conn: &dyn Conn, name: &str) -> Option<TagRef> {
+    conn.query_row(
+        "SELECT id, name, color FROM Tag WHERE lower(name) = lower(?)",
+        [name],
+        |r| {
+            Ok(TagRef {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                color: r.get(2)?,
+            })
+        },
+    )
+    .optional()
+    .ok()
+    .flatten()
+}
+
+fn insert_tag(conn: &dyn Conn, name: &str, color: Option<String>) -> store::Result<TagRef> {
+    let id = Uuid::new_v4().to_string();
+    let now = now_unix();
+    conn.execute(
+        "INSERT INTO Tag (id, name, color, createdAt, updatedAt) VALUES (?,?,?,?,?)",
+        params![id, name, color, now, now],
+    )?;
+    Ok(TagRef {
+        id,
+        name: name.to_string(),
+        color,
+    })
+}
+
+// POST /api/tags
+pub(crate) async fn create_tag(State(state): State<AppState>, Json(req): Json<TagReq>) -> Response {
+    let name = match clean_name(req.name.as_deref().unwrap_or("")) {
+        Ok(n) => n,
+        Err(e) => return bad_request(e),
+    };
+    let color = match clean_color(req.color.as_deref()) {
+        Ok(c) => c,
+        Err(e) => return bad_request(e),
+    };
+    let tag = match state
+        .db
+        .write(move |conn| {
+            if find_tag_by_name(conn, &name).is_some() {
+                return Err(Refusal(
+                    StatusCode::CONFLICT,
+                    "a tag with this name already exists",
+                ));
+            }
+            insert_tag(conn, &name, color)
+                .map_err(|_| Refusal(StatusCode::CONFLICT, "a tag with this name already exists"))
+        })
+        .await
+    {
+        Ok(v) => v,
+        Err(refusal) => return refusal.into_response(),
+    };
+    broadcast(&state, "tag:created", serde_json::json!(tag));
+    (StatusCode::CREATED, Json(tag)).into_response()
+}
+
+// PATCH /api/tags/:id
+pub(crate) async fn update_tag(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<TagReq>,
+) -> Response {
+    let tag = match state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| {
+                let Some(mut tag) = conn
+                    .query_row("SELECT id, name, color FROM Tag WHERE id=?", [&id], |r| {
+                        Ok(TagRef {
+                            id: r.get(0)?,
+                            name: r.get(1)?,
+                            color: r.get(2)?,
+                        })
+                    })
+                    .optional()
+                    .ok()
+                    .flatten()
+                else {
+                    return Err(Refusal(StatusCode::NOT_FOUND, "Not found"));
+                };
+                if let Some(n) = req.name.as_deref() {
+                    let name = match clean_name(n) {
+                        Ok(n) => n,
+                        Err(e) => return Err(Refusal(StatusCode::BAD_REQUEST, e)),
+                    };
+                    if find_tag_by_name(conn, &name).is_some_and(|t| t.id != id) {
+                        return Err(Refusal(
+                            StatusCode::CONFLICT,
+                            "a tag with this name already exists",
+                        ));
+                    }
+                    tag.name = name;
+                }
+                if req.color.is_some() {
+                    tag.color = match clean_color(req.color.as_deref()) {
+                        Ok(c) => c,
+                        Err(e) => return Err(Refusal(StatusCode::BAD_REQUEST, e)),
+                    };
+                }
+                let _ = conn.execute(
+                    "UPDATE Tag SET name=?, color=?, updatedAt=? WHERE id=?",
+                    params![tag.name, tag.color, now_unix(), id],
+                );
+                Ok(tag)
+            }
+        })
+        .await
+    {
+        Ok(v) => v,
+        Err(refusal) => return refusal.into_response(),
+    };
+    broadcast(&state, "tag:updated", serde_json::json!(tag));
+    Json(tag).into_response()
+}
+
+// DELETE /api/tags/:id
+pub(crate) async fn delete_tag(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let removed = state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| {
+                conn.execute("DELETE FROM Tag WHERE id=?", [&id])
+                    .unwrap_or(0)
+            }
+        })
+        .await;
+    if removed == 0 {
+        return not_found();
+    }
+    broadcast(&state, "tag:deleted", serde_json::json!({ "id": id }));
+    Json(serde_json::json!({"ok": true})).into_response()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ItemTagsReq {
+    /// Tag names; unknown names are created on the fly.
+    tags: Vec<String>,
+}
+
+/// Validate tag names from a request, dropping case-insensitive duplicates.
+pub(crate) fn clean_names(raw: &[String]) -> Result<Vec<String>, &'static str> {
+    let mut names: Vec<String> = vec![];
+    for n in raw {
+        let n = clean_name(n)?;
+        if !names.iter().any(|x| x.eq_ignore_ascii_case(&n)) {
+            names.push(n);
+        }
+    }
+    Ok(names)
+}
+
+/// Look up tags by name, creating missing ones. Returns (all tags, newly created tags).
+pub(crate) fn ensure_tags(conn: &dyn Conn, names: &[String]) -> (Vec<TagRef>, Vec<TagRef>) {
+    let mut tags = vec![];
+    let mut created = vec![];
+    for n in names {
+        let tag = match find_tag_by_name(conn, n) {
+            Some(t) => t,
+            None => match insert_tag(conn, n, None) {
+                Ok(t) => {
+                    created.push(t.clone());
+                    t
+                }
+                Err(_) => continue,
+            },
+        };
+        tags.push(tag);
+    }
+    (tags, created)
+}
+
+pub(crate) fn announce_created(state: &AppState, created: Vec<TagRef>) {
+    for t in created {
+        broadcast(state, "tag:created", serde_json::json!(t));
+    }
+}
+
+// PUT /api/clipboard/:id/tags — replace the item's tag set
+pub(crate) async fn set_item_tags(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<ItemTagsReq>,
+) -> Response {
+    let names = match clean_names(&req.tags) {
+        Ok(n) => n,
+        Err(e) => return bad_request(e),
+    };
+    let (tags, created) = match state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| {
+                if !item_exists(conn, &id) {
+                    return Err(Refusal(StatusCode::NOT_FOUND, "Not found"));
+                }
+                let tx = conn.transaction()?;
+                let (tags, created) = ensure_tags(&tx, &names);
+                let _ = tx.execute("DELETE FROM ItemTag WHERE itemId=?", [&id]);
+                for t in &tags {
+                    let _ = tx.execute(
+                        "INSERT INTO ItemTag (itemId, tagId, createdAt) VALUES (?,?,?) ON CONFLICT DO NOTHING",
+                        params![id, t.id, now_unix()],
+                    );
+                }
+                tx.commit()?;
+                Ok((tags, created))
+            }
+        })
+        .await
+    {
+        Ok(v) => v,
+        Err(refusal) => return refusal.into_response(),
+    };
+    announce_created(&state, created);
+    let data = serde_json::json!({ "id": id, "tags": tags });
+    broadcast(&state, "clipboard:tags", data.clone());
+    Json(data).into_response()
+}
+
+// -------- Collections --------
+
+// GET /api/collections
+pub(crate) async fn list_collections(State(state): State<AppState>) -> Response {
+    state.db.read(move |conn| {
+        let mut stmt = conn
+            .prepare("SELECT c.id, c.name, c.description, c.createdAt, c.updatedAt, (SELECT COUNT(*) FROM CollectionItem ci WHERE ci.collectionId = c.id) FROM Collection c ORDER BY lower(c.name)")
+            .unwrap();
+        let collections: Vec<serde_json::Value> = stmt
+            .query_map([], |r| collection_json(r, Some(r.get::<_, i64>(5)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        Json(serde_json::json!({ "collections": collections })).into_response()
+    })
+    .await
+}
+
+fn collection_json(r: &store::Row, item_count: Option<i64>) -> store::Result<serde_json::Value> {
+    let mut v = serde_json::json!({
+        "id": r.get::<_, String>(0)?,
+        "name": r.get::<_, String>(1)?,
+        "description": r.get::<_, Option<String>>(2)?,
+        "createdAt": epoch_to_iso(r.get::<_, i64>(3)?),
+        "updatedAt": epoch_to_iso(r.get::<_, i64>(4)?),
+    });
+    if let Some(n) = item_count {
+        v["itemCount"] = serde_json::json!(n);
+    }
+    Ok(v)
+}
+
+fn load_collection(conn: &dyn Conn, id: &str) -> Option<serde_json::Value> {
+    conn.query_row(
+        "SELECT id, name, description, createdAt, updatedAt FROM Collection WHERE id=?",
+        [id],
+        |r| collection_json(r, None),
+    )
+    .optional()
+    .ok()
+    .flatten()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CollectionReq {
+    name: Option<String>,
+    description: Option<String>,
+}
+
+fn collection_name_taken(conn: &dyn Conn, name: &str, except: Option<&str>) -> bool {
+    conn.query_row(
+        "SELECT id FROM Collection WHERE lower(name) = lower(?)",
+        [name],
+        |r| r.get::<_, String>(0),
+    )
+    .optional()
+    .ok()
+    .flatten()
+    .is_some_and(|id| Some(id.as_str()) != except)
+}
+
+// POST /api/collections
+pub(crate) async fn create_collection(
+    State(state): State<AppState>,
+    Json(req): Json<CollectionReq>,
+) -> Response {
+    let name = match clean_name(req.name.as_deref().unwrap_or("")) {
+        Ok(n) => n,
+        Err(e) => return bad_request(e),
+    };
+    let description = req
+        .description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+    let collection = match state.db.write(move |conn| {
+        if collection_name_taken(conn, &name, None) {
+            return Err(Refusal(StatusCode::CONFLICT, "a collection with this name already exists"));
+        }
+        let id = Uuid::new_v4().to_string();
+        let now = now_unix();
+        if conn
+            .execute(
+                "INSERT INTO Collection (id, name, description, createdAt, updatedAt) VALUES (?,?,?,?,?)",
+                params![id, name, description, now, now],
+            )
+            .is_err()
+        {
+            return Err(Refusal(StatusCode::CONFLICT, "a collection with this name already exists"));
+        }
+        Ok(load_collection(conn, &id))
+    }).await {
+        Ok(v) => v,
+        Err(refusal) => return refusal.into_response(),
+    };
+    broadcast(&state, "collection:created", serde_json::json!(collection));
+    (StatusCode::CREATED, Json(collection)).into_response()
+}
+
+// GET /api/collections/:id — the collection plus its item ids, most recently added first
+pub(crate) async fn get_collection(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    state.db.read(move |conn| {
+        let Some(mut collection) = load_collection(conn, &id) else {
+            return not_found();
+        };
+        let mut stmt = conn
+            .prepare(
+                "SELECT itemId FROM CollectionItem WHERE collectionId=? ORDER BY addedAt DESC, itemId",
+            )
+            .unwrap();
+        let ids: Vec<String> = stmt
+            .query_map([&id], |r| r.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+        collection["itemIds"] = serde_json::json!(ids);
+        Json(collection).into_response()
+    })
+    .await
+}
+
+// PATCH /api/collections/:id
+pub(crate) async fn update_collection(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<CollectionReq>,
+) -> Response {
+    let collection = match state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| {
+                if load_collection(conn, &id).is_none() {
+                    return Err(Refusal(StatusCode::NOT_FOUND, "Not found"));
+                }
+                if let Some(n) = req.name.as_deref() {
+                    let name = match clean_name(n) {
+                        Ok(n) => n,
+                        Err(e) => return Err(Refusal(StatusCode::BAD_REQUEST, e)),
+                    };
+                    if collection_name_taken(conn, &name, Some(&id)) {
+                        return Err(Refusal(
+                            StatusCode::CONFLICT,
+                            "a collection with this name already exists",
+                        ));
+                    }
+                    let _ = conn.execute(
+                        "UPDATE Collection SET name=?, updatedAt=? WHERE id=?",
+                        params![name, now_unix(), id],
+                    );
+                }
+                if let Some(d) = req.description.as_deref() {
+                    let d = Some(d.trim()).filter(|d| !d.is_empty());
+                    let _ = conn.execute(
+                        "UPDATE Collection SET description=?, updatedAt=? WHERE id=?",
+                        params![d, now_unix(), id],
+                    );
+                }
+                Ok(load_collection(conn, &id))
+            }
+        })
+        .await
+    {
+        Ok(v) => v,
+        Err(refusal) => return refusal.into_response(),
+    };
+    broadcast(&state, "collection:updated", serde_json::json!(collection));
+    Json(collection).into_response()
+}
+
+// DELETE /api/collections/:id — items themselves are kept
+pub(crate) async fn delete_collection(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let removed = state
+        .db
+        .write({
+            let id = id.clone();
+            move |conn| {
+                conn.execute("DELETE FROM Collection WHERE id=?", [&id])
+                    .unwrap_or(0)
+            }
+        })
+        .await;
+    if removed == 0 {
+        return not_found();
+    }
+    broadcast(
+        &state,
+        "collection:deleted",
+        serde_json::json!({ "id": id }),
+    );
+    Json(serde_json::json!({"ok": true})).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CollectionItemsReq {
+    item_ids: Vec<String>,
+}
+
+// POST /api/collections/:id/items
+pub(crate) async fn add_collection_items(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<CollectionItemsReq>,
+) -> Response {
+    let added = match state.db.write({
+        let id = id.clone();
+        move |conn| {
+            if load_collection(conn, &id).is_none() {
+                return Err(Refusal(StatusCode::NOT_FOUND, "Not found"));
+            }
+            let now = now_unix();
+            let tx = conn.transaction()?;
+            let mut added = vec![];
+            for item_id in &req.item_ids {
+                if !item_exists(&tx, item_id) {
+                    continue;
+                }
+                let n = tx
+                    .execute(
+                        "INSERT INTO CollectionItem (collectionId, itemId, addedAt) VALUES (?,?,?) ON CONFLICT DO NOTHING",
+                        params![id, item_id, now],
+                    )
+                    .unwrap_or(0);
+                if n > 0 {
+                    added.push(item_id.clone());
+                }
+            }
+            let _ = tx.execute(
+                "UPDATE Collection SET updatedAt=? WHERE id=?",
+                params![now, id],
+            );
+            tx.commit()?;
+            Ok(added)
+        }
+    }).await {
+        Ok(v) => v,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let data = serde_json::json!({ "id": id, "added": added, "removed": [] });
+    broadcast(&state, "collection:items", data.clone());
+    Json(data).into_response()
+}
+
+// DELETE /api/collections/:id/items/:item_id
+pub(crate) async fn remove_collection_item(
+    State(state): State<AppState>,
+    Path((id, item_id)): Path<(String, String)>,
+) -> Response {
+    let removed = state
+        .db
+        .write({
+            let id = id.clone();
+            let item_id = item_id.clone();
+            move |conn| {
+                let n = conn
+                    .execute(
+                        "DELETE FROM CollectionItem WHERE collectionId=? AND itemId=?",
+                        params![id, item_id],
+                    )
+                    .unwrap_or(0);
+                if n > 0 {
+                    let _ = conn.execute(
+                        "UPDATE Collection SET updatedAt=? WHERE id=?",
+                        params![now_unix(), id],
+                    );
+                }
+                n
+            }
+        })
+        .await;
+    if removed == 0 {
+        return not_found();
+    }
+    let data = serde_json::json!({ "id": id, "added": [], "removed": [item_id] });
+    broadcast(&state, "collection:items", data.clone());
+    Json(data).into_response()
+}
@@ -0,0 +1,1299 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use serde_json::{json, Value as Json};
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, mpsc};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::crypt::Keyring;
+use crate::limits::{QuotaPolicy, UploadLimits};
+use crate::store::Dialect;
+use crate::{backup, db, expiry, retention, router, AppState};
+
+// -------------------- Backend tests --------------------
+//
+// HTTP-level tests that run once per storage backend: SQLite in a temporary directory,
+// and PostgreSQL when TEST_DATABASE_URL is set (each test gets a schema of its own,
+// dropped afterwards). scripts/test-postgres.sh starts a throwaway server and sets it.
+
+const PASSWORD: &str = "pw";
+
+struct Backend {
+    name: &'static str,
+    db: Arc<db::Db>,
+    dir: PathBuf,
+    // declared after `db` so its connections close first
+    _schema: Option<DropSchema>,
+}
+
+impl Backend {
+    fn sqlite() -> Self {
+        let dir = scratch_dir();
+        let db = db::Db::open(&db::Target::Sqlite(dir.join("custom.db"))).unwrap();
+        Self {
+            name: "sqlite",
+            db: Arc::new(db),
+            dir,
+            _schema: None,
+        }
+    }
+
+    fn postgres(url: &str) -> Self {
+        let base: postgres::Config = url.parse().expect("TEST_DATABASE_URL");
+        let schema = format!("test_{}", Uuid::new_v4().simple());
+        let mut client = base.connect(postgres::NoTls).unwrap();
+        client
+            .batch_execute(&format!("CREATE SCHEMA {schema}"))
+            .unwrap();
+        // dropped with the schema even if opening fails
+        let cleanup = DropSchema(base.clone(), schema.clone());
+        let mut config = base;
+        config.options(&format!("-c search_path={schema}"));
+        let db = db::Db::open(&db::Target::Postgres(Box::new(config))).unwrap();
+        Self {
+            name: "postgres",
+            db: Arc::new(db),
+            dir: scratch_dir(),
+            _schema: Some(cleanup),
+        }
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A test schema, and where to drop it from.
+struct DropSchema(postgres::Config, String);
+
+impl Drop for DropSchema {
+    fn drop(&mut self) {
+        if let Ok(mut client) = self.0.connect(postgres::NoTls) {
+            let _ = client.batch_execute(&format!("DROP SCHEMA {} CASCADE", self.1));
+        }
+    }
+}
+
+fn scratch_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("clip-relay-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("uploads")).unwrap();
+    dir
+}
+
+/// Run `test` against every configured backend. Postgres clients block on a runtime of
+/// their own, so the backends are set up and torn down outside the test's runtime.
+fn each_backend<F, Fut>(test: F)
+where
+    F: Fn(App) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    each_backend_with(None, test)
+}
+
+/// Like each_backend, with encryption at rest under `key` (base64).
+fn each_backend_with<F, Fut>(key: Option<&str>, test: F)
+where
+    F: Fn(App) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let keys = key.map(|k| Arc::new(Keyring::parse(k).unwrap()));
+    let mut backends = vec![Backend::sqlite()];
+    match std::env::var("TEST_DATABASE_URL") {
+        Ok(url) if !url.is_empty() => backends.push(Backend::postgres(&url)),
+        _ => {}
+    }
+    for backend in backends {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (tx, _) = broadcast::channel(16);
+        let (extract_tx, _) = mpsc::unbounded_channel();
+        let state = AppState {
+            tx,
+            password: Some(PASSWORD.into()),
+            db: backend.db.clone(),
+            data_dir: backend.dir.clone(),
+            limits: Arc::new(UploadLimits::default()),
+            strip_share_metadata: true,
+            extract_tx,
+            trash_retention: None,
+            retention: retention::Policy::default(),
+            expiry_wake: Arc::new(tokio::sync::Notify::new()),
+            backup: None,
+            keys: keys.clone(),
+        };
+        let app = App {
+            router: router(state.clone()),
+            dialect: backend.db.dialect(),
+            db: backend.db.clone(),
+            dir: backend.dir.clone(),
+            state,
+        };
+        let run = std::panic::AssertUnwindSafe(|| rt.block_on(test(app)));
+        if let Err(panic) = std::panic::catch_unwind(run) {
+            let msg = panic
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| panic.downcast_ref::<&str>().copied())
+                .unwrap_or("panic");
+            panic!("{} backend: {msg}", backend.name);
+        }
+        drop(rt);
+    }
+}
+
+/// POST /api/clipboard request with multipart `fields` (name, file name, value).
+fn upload(fields: &[(&str, Option<&str>, &[u8])]) -> Request<Body> {
+    let boundary = "test-boundary";
+    let mut body = vec![];
+    for (name, file, value) in fields {
+        body.extend(
+            format!("--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"").as_bytes(),
+        );
+        if let Some(f) = file {
+            body.extend(
+                format!("; filename=\"{f}\"\r\nContent-Type: application/octet-stream").as_bytes(),
+            );
+        }
+        body.extend(b"\r\n\r\n");
+        body.extend(*value);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{boundary}--\r\n").as_bytes());
+    Request::post("/api/clipboard")
+        .header("authorization", format!("Bearer {PASSWORD}"))
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+struct App {
+    router: Router,
+    dialect: Dialect,
+    db: Arc<db::Db>,
+    dir: PathBuf,
+    // for driving background tasks and watching events
+    state: AppState,
+}
+
+impl App {
+    async fn send(&self, req: Request<Body>) -> (StatusCode, Vec<u8>) {
+        let res = self.router.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (status, body.to_vec())
+    }
+
+    async fn call(&self, method: Method, uri: &str, body: Option<Json>) -> (StatusCode, Json) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {PASSWORD}"));
+        let req = match body {
+            Some(b) => req
+                .header("content-type", "application/json")
+                .body(Body::from(b.to_string())),
+            None => req.body(Body::empty()),
+        };
+        let (status, body) = self.send(req.unwrap()).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Json::Null))
+    }
+
+    async fn get(&self, uri: &str) -> Json {
+        let (status, body) = self.call(Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::OK, "GET {uri}: {body}");
+        body
+    }
+
+    /// POST /api/clipboard as multipart; `fields` are (name, file name, value).
+    async fn create(&self, fields: &[(&str, Option<&str>, &[u8])]) -> Json {
+        let (status, body) = self.try_create(fields).await;
+        assert_eq!(status, StatusCode::CREATED, "create: {body}");
+        body
+    }
+
+    async fn try_create(&self, fields: &[(&str, Option<&str>, &[u8])]) -> (StatusCode, Json) {
+        let (status, body) = self.send(upload(fields)).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn text(&self, content: &str) -> String {
+        let item = self
+            .create(&[
+                ("type", None, b"TEXT"),
+                ("content", None, content.as_bytes()),
+            ])
+            .await;
+        item["id"].as_str().unwrap().to_string()
+    }
+
+    /// Ids of a listing, in order.
+    async fn ids(&self, query: &str) -> Vec<String> {
+        self.get(&format!("/api/clipboard?{query}")).await["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["id"].as_str().unwrap().to_string())
+            .collect()
+    }
+}
+
+#[test]
+fn items_are_created_listed_edited_and_trashed() {
+    each_backend(|app| async move {
+        let first = app.text("first note").await;
+        let second = app.text("second note").await;
+        assert_eq!(app.ids("").await, [second.clone(), first.clone()]);
+
+        let item = app.get(&format!("/api/clipboard/{first}")).await;
+        assert_eq!(item["content"], "first note");
+
+        let (status, _) = app
+            .call(
+                Method::PATCH,
+                &format!("/api/clipboard/{first}"),
+                Some(json!({"content": "first note, edited"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let item = app.get(&format!("/api/clipboard/{first}")).await;
+        assert_eq!(item["content"], "first note, edited");
+        let revs = app.get(&format!("/api/clipboard/{first}/revisions")).await;
+        assert!(!revs["revisions"].as_array().unwrap().is_empty(), "{revs}");
+
+        let (status, _) = app
+            .call(Method::DELETE, &format!("/api/clipboard/{second}"), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.ids("").await, [first.as_str()]);
+        let trash = app.get("/api/trash").await;
+        assert_eq!(trash["items"][0]["id"], second.as_str(), "{trash}");
+
+        let (status, _) = app
+            .call(Method::POST, &format!("/api/trash/{second}/restore"), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.ids("").await.len(), 2);
+    })
+}
+
+#[test]
+fn search_matches_words_prefixes_and_cjk() {
+    each_backend(|app| async move {
+        let hello = app.text("hello world").await;
+        let moon = app.text("goodbye moon").await;
+        let cjk = app.text("今天天气很好").await;
+
+        let res = app.get("/api/clipboard?search=hello").await;
+        assert_eq!(res["items"].as_array().unwrap().len(), 1, "{res}");
+        assert_eq!(res["items"][0]["id"], hello.as_str());
+        let snippet = res["items"][0]["snippet"].as_str().unwrap();
+        assert!(snippet.contains("<mark>hello</mark>"), "{snippet}");
+
+        assert_eq!(app.ids("search=good").await, [moon.as_str()]);
+        assert_eq!(app.ids("search=HELLO").await, [hello.as_str()]);
+        assert_eq!(app.ids("search=天气").await, [cjk.as_str()]);
+        assert!(app.ids("search=nothing").await.is_empty());
+        // punctuation-only input falls back to a substring match
+        assert!(app.ids("search=%2B%2B").await.is_empty());
+        // ... where LIKE wildcards in the term are literal characters
+        let percent = app.text("100% done").await;
+        let snake = app.text("snake_case").await;
+        assert_eq!(app.ids("search=%25").await, [percent.as_str()]);
+        assert_eq!(app.ids("search=_").await, [snake.as_str()]);
+    })
+}
+
+#[test]
+fn relevance_ranks_file_names_above_content() {
+    each_backend(|app| async move {
+        let content = app.text("a report about the budget").await;
+        let named = app
+            .create(&[("file", Some("budget.txt"), b"numbers")])
+            .await["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            app.ids("search=budget&sort=relevance").await,
+            [named, content],
+            "{:?}",
+            app.dialect
+        );
+    })
+}
+
+#[test]
+fn documents_are_extracted_for_search() {
+    each_backend(|app| async move {
+        let mut zw = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zw.start_file(
+            "word/document.xml",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        std::io::Write::write_all(&mut zw, b"<w:p><w:t>zanzibar minutes</w:t></w:p>").unwrap();
+        let docx = zw.finish().unwrap().into_inner();
+        let mut ids = vec![];
+        for (name, data) in [
+            ("notes.docx", docx.as_slice()),
+            ("broken.pdf", b"%PDF-1.4\ngarbage".as_slice()),
+            ("raw.bin", b"\0\x01\x02".as_slice()),
+        ] {
+            let item = app.create(&[("file", Some(name), data)]).await;
+            let id = item["id"].as_str().unwrap().to_string();
+            crate::extract_item_text(&app.state, &id).await;
+            ids.push(id);
+        }
+        let mut details = vec![];
+        for id in &ids {
+            details.push(app.get(&format!("/api/clipboard/{id}")).await);
+        }
+        let [docx, pdf, raw] = &details[..] else {
+            unreachable!()
+        };
+        assert_eq!(docx["extractStatus"], "done", "{docx}");
+        assert_eq!(app.ids("search=zanzibar").await, [ids[0].as_str()]);
+        assert_eq!(pdf["extractStatus"], "failed", "{pdf}");
+        assert!(pdf["extractError"].as_str().unwrap().starts_with("pdf:"));
+        assert_eq!(raw["extractStatus"], "skipped", "{raw}");
+    })
+}
+
+#[test]
+fn flags_order_and_pagination() {
+    each_backend(|app| async move {
+        let mut ids = vec![];
+        for i in 0..5 {
+            ids.push(app.text(&format!("item {i}")).await);
+        }
+        let (status, flags) = app
+            .call(
+                Method::PUT,
+                &format!("/api/clipboard/{}/flags", ids[0]),
+                Some(json!({"pinned": true, "favorite": true})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            flags,
+            json!({"id": ids[0], "pinned": true, "favorite": true, "archived": false})
+        );
+        assert_eq!(app.ids("").await[0], ids[0]);
+        assert_eq!(app.ids("favorite=1").await, [ids[0].clone()]);
+
+        // walk the manual order one item per page
+        let mut seen = vec![];
+        let mut query = "take=1".to_string();
+        loop {
+            let page = app.get(&format!("/api/clipboard?{query}")).await;
+            seen.push(page["items"][0]["id"].as_str().unwrap().to_string());
+            let Some(cur) = page["nextCursor"].as_object() else {
+                break;
+            };
+            query = format!(
+                "take=1&cursorId={}&cursorCreatedAt={}&cursorKey={}&cursorPinned={}",
+                cur["id"].as_str().unwrap(),
+                urlencoding::encode(cur["createdAt"].as_str().unwrap()),
+                cur["key"],
+                cur["pinned"],
+            );
+        }
+        assert_eq!(seen, app.ids("").await);
+        assert_eq!(seen.len(), 5);
+
+        let (status, _) = app
+            .call(
+                Method::PUT,
+                &format!("/api/clipboard/{}/flags", ids[1]),
+                Some(json!({"archived": true})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.ids("").await.len(), 4);
+        assert_eq!(app.ids("archived=1").await, [ids[1].clone()]);
+    })
+}
+
+#[test]
+fn sizes_sort_and_files_download() {
+    each_backend(|app| async move {
+        let small = app.text("x").await;
+        let big = app.create(&[("file", Some("big.bin"), &[7u8; 3000])]).await["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(app.ids("sort=largest").await, [big.clone(), small.clone()]);
+        assert_eq!(app.ids("sort=smallest").await, [small, big.clone()]);
+
+        let req = Request::get(format!("/api/files/{big}"))
+            .header("authorization", format!("Bearer {PASSWORD}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = app.send(req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, vec![7u8; 3000]);
+
+        let bundle = app
+            .create(&[
+                ("file", Some("a.txt"), b"aa"),
+                ("file", Some("b.txt"), b"bbb"),
+            ])
+            .await;
+        assert_eq!(bundle["type"], "BUNDLE");
+        assert_eq!(bundle["fileSize"], 5);
+        assert_eq!(
+            app.ids("type=bundle").await,
+            [bundle["id"].as_str().unwrap()]
+        );
+        assert_eq!(app.ids("type=FILE").await, [big.as_str()]);
+
+        // trashed files aren't served
+        let (status, _) = app
+            .call(Method::DELETE, &format!("/api/clipboard/{big}"), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app
+            .call(Method::GET, &format!("/api/files/{big}"), None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    })
+}
+
+#[test]
+fn tags_and_collections_ignore_case() {
+    each_backend(|app| async move {
+        let a = app.text("alpha").await;
+        let b = app.text("beta").await;
+        let (status, _) = app
+            .call(
+                Method::PUT,
+                &format!("/api/clipboard/{a}/tags"),
+                Some(json!({"tags": ["Work", "work", "home"]})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let tags = app.get("/api/tags").await;
+        let names: Vec<&str> = tags["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["home", "Work"]);
+        assert_eq!(app.ids("tag=WORK").await, [a.as_str()]);
+
+        let (status, _) = app
+            .call(Method::POST, "/api/tags", Some(json!({"name": "HOME"})))
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, coll) = app
+            .call(
+                Method::POST,
+                "/api/collections",
+                Some(json!({"name": "Reading"})),
+            )
+            .await;
+        assert!(status.is_success(), "{coll}");
+        let cid = coll["id"].as_str().unwrap().to_string();
+        for _ in 0..2 {
+            let (status, _) = app
+                .call(
+                    Method::POST,
+                    &format!("/api/collections/{cid}/items"),
+                    Some(json!({"itemIds": [a, b]})),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert_eq!(app.ids("collection=reading").await.len(), 2);
+
+        let (status, res) = app
+            .call(
+                Method::POST,
+                "/api/clipboard/bulk",
+                Some(json!({"action": "tag", "ids": [a, b], "tags": ["work"]})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{res}");
+        assert_eq!(app.ids("tag=work").await, [b, a]);
+    })
+}
+
+#[test]
+fn shares_serve_content_without_auth() {
+    each_backend(|app| async move {
+        let item = app
+            .create(&[("type", None, b"TEXT"), ("content", None, b"shared text")])
+            .await;
+        let token = item["share"]["token"].as_str().unwrap().to_string();
+        let anon = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+
+        let (status, meta) = app.send(anon(format!("/api/share/{token}"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let meta: Json = serde_json::from_slice(&meta).unwrap();
+        assert_eq!(meta["requiresPassword"], false, "{meta}");
+
+        let (status, body) = app.send(anon(format!("/api/share/{token}/download"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"shared text");
+
+        let (status, _) = app
+            .call(
+                Method::PUT,
+                &format!("/api/clipboard/{}/share", item["id"].as_str().unwrap()),
+                Some(json!({"password": "secret"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.send(anon(format!("/api/share/{token}/download"))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // a one-time share of a file on disk: the transfer using it up can still seek and
+        // resume, a new one can't, and the item is deleted once the grace period is over
+        let data = vec![5u8; 300 * 1024];
+        let item = app
+            .create(&[
+                ("shareMaxDownloads", None, b"1"),
+                ("file", Some("big.bin"), &data),
+            ])
+            .await;
+        let id = item["id"].as_str().unwrap().to_string();
+        let token = item["share"]["token"].as_str().unwrap().to_string();
+        let uploads = || std::fs::read_dir(app.dir.join("uploads")).unwrap().count();
+        assert_eq!(uploads(), 1);
+        let range = |r: &str| {
+            Request::get(format!("/api/share/{token}/file"))
+                .header("range", r)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, head) = app.send(range("bytes=0-1023")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        let (status, tail) = app.send(range("bytes=1024-")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!([head, tail].concat(), data);
+        let (status, _) = app.send(range("bytes=2048-4095")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        let (status, _) = app.send(range("bytes=0-")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = app.send(anon(format!("/api/share/{token}/file"))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let expires: Option<i64> = app
+            .db
+            .read(move |c| {
+                c.query_row(
+                    "SELECT expiresAt FROM ClipboardItem WHERE id=?",
+                    [id],
+                    |r| r.get(0),
+                )
+                .unwrap()
+            })
+            .await;
+        assert!(expires.is_some());
+        assert_eq!(uploads(), 1);
+
+        // seeking around a file counts once; stopping one byte short still counts
+        let item = app
+            .create(&[
+                ("shareMaxDownloads", None, b"2"),
+                ("file", Some("clip.bin"), &data),
+            ])
+            .await;
+        let token = item["share"]["token"].as_str().unwrap().to_string();
+        let downloads = || async {
+            let (_, meta) = app.send(anon(format!("/api/share/{token}"))).await;
+            serde_json::from_slice::<Json>(&meta).unwrap()["downloadCount"].clone()
+        };
+        let range = |r: String| {
+            Request::get(format!("/api/share/{token}/file"))
+                .header("range", r)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _) = app.send(range("bytes=0-".into())).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        for at in [4096, 100_000, 8192, 250_000] {
+            let (status, _) = app.send(range(format!("bytes={at}-"))).await;
+            assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        }
+        assert_eq!(downloads().await, 1);
+        let (status, _) = app.send(range(format!("bytes=0-{}", data.len() - 2))).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        let (status, _) = app.send(anon(format!("/api/share/{token}"))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    })
+}
+
+#[test]
+fn shared_bundles_strip_image_metadata() {
+    each_backend(|app| async move {
+        // a JPEG big enough to be stored on disk, with a comment naming where it was taken
+        let mut photo = vec![0xFF, 0xD8, 0xFF, 0xFE, 0x00, 0x0E];
+        photo.extend(b"GPS 48.8584N");
+        photo.extend([0xFF, 0xDA, 0x00, 0x05, 0x01, 0x01, 0x00]);
+        photo.extend(vec![0x42; 300 * 1024]);
+        photo.extend([0xFF, 0xD9]);
+        let has_gps = |data: &[u8]| data.windows(3).any(|w| w == b"GPS");
+        let item = app
+            .create(&[
+                ("file", Some("photo.jpg"), &photo),
+                ("file", Some("note.txt"), b"hello"),
+            ])
+            .await;
+        let id = item["id"].as_str().unwrap().to_string();
+        let token = app.get(&format!("/api/clipboard/{id}/share")).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let files = app.get(&format!("/api/clipboard/{id}/files")).await;
+        let file = files["files"][0]["id"].as_str().unwrap().to_string();
+        let anon = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+
+        let (status, body) = app
+            .send(anon(format!("/api/share/{token}/files/{file}")))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!has_gps(&body));
+        assert_eq!(body.len(), photo.len() - 16);
+        // served from the cached copy the second time
+        let (_, again) = app
+            .send(anon(format!("/api/share/{token}/files/{file}")))
+            .await;
+        assert_eq!(again, body);
+
+        let (status, zip) = app.send(anon(format!("/api/share/{token}/download"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(zip)).unwrap();
+        let mut entry = zip.by_name("photo.jpg").unwrap();
+        let mut data = vec![];
+        std::io::Read::read_to_end(&mut entry, &mut data).unwrap();
+        assert_eq!(data, body);
+
+        // the owner still gets the original
+        let req = Request::get(format!("/api/clipboard/{id}/files/{file}"))
+            .header("authorization", format!("Bearer {PASSWORD}"))
+            .body(Body::empty())
+            .unwrap();
+        let (_, own) = app.send(req).await;
+        assert_eq!(own, photo);
+
+        // the cached copy goes with the item
+        assert!(app.dir.join("scrubbed").join(&id).is_dir());
+        for uri in [format!("/api/clipboard/{id}"), format!("/api/trash/{id}")] {
+            let (status, _) = app.call(Method::DELETE, &uri, None).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert!(!app.dir.join("scrubbed").join(&id).exists());
+    })
+}
+
+#[test]
+fn items_expire_on_schedule() {
+    each_backend(|app| async move {
+        let keep = app.text("keep").await;
+        let gone = app.text("gone").await;
+        for body in [
+            json!({"expiresIn": "9223372036854775807"}),
+            json!({"expiresAt": "9223372036854775807"}),
+            json!({"expiresAt": "2001-01-01"}),
+        ] {
+            let (status, _) = app
+                .call(
+                    Method::PUT,
+                    &format!("/api/clipboard/{gone}/expiry"),
+                    Some(body),
+                )
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+        let (status, _) = app
+            .call(
+                Method::POST,
+                "/api/clipboard/bulk",
+                Some(
+                    json!({"action": "expire", "ids": [gone], "expiresIn": "9223372036854775807s"}),
+                ),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let mut events = app.state.tx.subscribe();
+        let task = tokio::spawn(expiry::run_task(app.state.clone()));
+        let (status, _) = app
+            .call(
+                Method::PUT,
+                &format!("/api/clipboard/{gone}/expiry"),
+                Some(json!({"expiresIn": "1s"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let expired = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if event.name == "clipboard:expired" {
+                    return event.data;
+                }
+            }
+        })
+        .await
+        .expect("no clipboard:expired event");
+        task.abort();
+        assert_eq!(expired["id"], gone.as_str());
+        assert_eq!(app.ids("").await, [keep]);
+        let (status, _) = app
+            .call(Method::GET, &format!("/api/clipboard/{gone}"), None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    })
+}
+
+#[test]
+fn retention_rules_spare_pinned_and_favorite_items() {
+    each_backend(|app| async move {
+        // oldest first: 3 bytes, 6 bytes pinned, files of k, k favorited and 2k bytes, 3 bytes
+        let k = 300 * 1024;
+        let old = app.text("old").await;
+        let pinned = app.text("pinned").await;
+        let mut files = vec![];
+        for (name, size) in [("a.bin", k), ("fav.bin", k), ("b.bin", 2 * k)] {
+            let item = app.create(&[("file", Some(name), &vec![1u8; size])]).await;
+            files.push(item["id"].as_str().unwrap().to_string());
+        }
+        let (a, fav, b) = (files[0].clone(), files[1].clone(), files[2].clone());
+        let new = app.text("new").await;
+        for (id, flag) in [(&pinned, "pinned"), (&fav, "favorite")] {
+            let (status, _) = app
+                .call(
+                    Method::PUT,
+                    &format!("/api/clipboard/{id}/flags"),
+                    Some(json!({ flag: true })),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let ages: Vec<(String, i64)> = [&old, &pinned, &a, &fav, &b, &new]
+            .iter()
+            .zip([100, 90, 50, 40, 20, 0])
+            .map(|(id, days)| (id.to_string(), crate::now_unix() - days * 86_400))
+            .collect();
+        app.db
+            .write(move |c| {
+                for (id, at) in ages {
+                    c.execute(
+                        "UPDATE ClipboardItem SET createdAt=? WHERE id=?",
+                        rusqlite::params![at, id],
+                    )
+                    .unwrap();
+                }
+            })
+            .await;
+        // 4k + 12 bytes in all; the pinned and favorited k + 6 count but stay
+        for (query, want) in [
+            ("maxAge=60d".to_string(), vec![&old]),
+            ("maxItems=2".to_string(), vec![&old, &a]),
+            (format!("maxBytes={}", 3 * k + 10), vec![&old, &a]),
+            (format!("maxBytes={}", 2 * k), vec![&old, &a, &b]),
+            (format!("maxBytes={}", 5 * k), vec![]),
+        ] {
+            let res = app.get(&format!("/api/retention/preview?{query}")).await;
+            let ids: Vec<&str> = res["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| i["id"].as_str().unwrap())
+                .collect();
+            assert_eq!(ids, want, "{query}");
+        }
+
+        // age and count move items to the trash
+        let state = AppState {
+            retention: retention::Policy {
+                max_age: Some(60 * 86_400),
+                ..Default::default()
+            },
+            ..app.state.clone()
+        };
+        assert_eq!(retention::run_once(&state).await, 1);
+        let trash = app.get("/api/trash").await;
+        assert_eq!(trash["items"][0]["id"], old.as_str());
+
+        // the byte cap counts the trash and frees the space for good
+        let (status, _) = app
+            .call(Method::DELETE, &format!("/api/clipboard/{a}"), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            std::fs::read_dir(app.dir.join("uploads")).unwrap().count(),
+            3
+        );
+        let state = AppState {
+            retention: retention::Policy {
+                max_bytes: Some(3 * k as u64 + 10),
+                ..Default::default()
+            },
+            ..app.state.clone()
+        };
+        assert_eq!(retention::run_once(&state).await, 2);
+        assert!(app.get("/api/trash").await["items"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            std::fs::read_dir(app.dir.join("uploads")).unwrap().count(),
+            2
+        );
+        assert_eq!(app.ids("").await, [pinned.clone(), new, b, fav.clone()]);
+        let state = AppState {
+            retention: retention::Policy {
+                max_bytes: Some(k as u64),
+                ..Default::default()
+            },
+            ..app.state.clone()
+        };
+        assert_eq!(retention::run_once(&state).await, 2);
+        assert_eq!(app.ids("").await, [pinned, fav]);
+    })
+}
+
+#[test]
+fn quota_rejects_or_evicts_atomically() {
+    each_backend(|app| async move {
+        let with_quota = |policy| {
+            let mut limits = UploadLimits::default();
+            limits.quota_bytes = Some(10);
+            limits.quota_policy = policy;
+            router(AppState {
+                limits: Arc::new(limits),
+                ..app.state.clone()
+            })
+        };
+        let (evict, reject) = (
+            with_quota(QuotaPolicy::EvictOldest),
+            with_quota(QuotaPolicy::Reject),
+        );
+        let app = App {
+            router: evict,
+            ..app
+        };
+        let oldest = app.text("aaaa").await;
+        let pinned = app.text("bbbb").await;
+        let (status, _) = app
+            .call(
+                Method::PUT,
+                &format!("/api/clipboard/{pinned}/flags"),
+                Some(json!({"pinned": true})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let newest = app.text("cccccc").await;
+        assert_eq!(app.ids("").await, [pinned.clone(), newest.clone()]);
+        assert_ne!(oldest, newest);
+        // only the pinned item would be left to evict
+        let (status, _) = app
+            .try_create(&[("type", None, b"TEXT"), ("content", None, b"ddddddddd")])
+            .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(app.ids("").await, [pinned.clone(), newest.clone()]);
+
+        // uploads racing for the last bytes: checking and inserting is one step
+        let app = App {
+            router: reject,
+            ..app
+        };
+        let (status, _) = app
+            .call(Method::DELETE, &format!("/api/clipboard/{newest}"), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.call(Method::DELETE, "/api/trash", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let uploads: Vec<_> = (0..16)
+            .map(|_| {
+                let router = app.router.clone();
+                tokio::spawn(async move {
+                    let req = upload(&[("type", None, b"TEXT"), ("content", None, b"xxx")]);
+                    let res = router.oneshot(req).await.unwrap();
+                    let status = res.status();
+                    let body = res.into_body().collect().await.unwrap().to_bytes();
+                    (status, serde_json::from_slice::<Json>(&body).unwrap())
+                })
+            })
+            .collect();
+        let mut created = 0;
+        for upload in uploads {
+            let (status, body) = upload.await.unwrap();
+            if status == StatusCode::CREATED {
+                created += 1;
+            } else {
+                assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+                assert_eq!(body["limit"], 10);
+            }
+        }
+        assert_eq!(created, 2);
+        assert_eq!(app.ids("").await.len(), 3);
+    })
+}
+
+#[test]
+fn encrypted_items_are_sealed_at_rest() {
+    let key = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    each_backend_with(Some(key), |app| async move {
+        let text = app.text("top secret note").await;
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let item = app.create(&[("file", Some("big.bin"), &data)]).await;
+        let file = item["id"].as_str().unwrap().to_string();
+
+        assert!(app.ids("search=secret").await.is_empty());
+        let item = app.get(&format!("/api/clipboard/{text}")).await;
+        assert_eq!(item["content"], "top secret note");
+        assert_eq!(item["fileSize"], 15);
+        let (status, _) = app
+            .call(
+                Method::PATCH,
+                &format!("/api/clipboard/{text}"),
+                Some(json!({"content": "top secret note, edited"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let rev = app.get(&format!("/api/clipboard/{text}/revisions/1")).await;
+        assert_eq!(rev["content"], "top secret note");
+
+        // ranges across chunk boundaries come back decrypted
+        let req = Request::get(format!("/api/files/{file}"))
+            .header("authorization", format!("Bearer {PASSWORD}"))
+            .header("range", "bytes=65000-140000")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = app.send(req).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body, &data[65000..=140000]);
+
+        let (content, (path, hash)): (String, (String, String)) = app
+            .db
+            .read(move |conn| {
+                let content = conn.query_row(
+                    "SELECT content FROM ClipboardItem WHERE id=?",
+                    [&text],
+                    |r| r.get(0),
+                );
+                let file = conn.query_row(
+                    "SELECT filePath, contentHash FROM ClipboardItem WHERE id=?",
+                    [&file],
+                    |r| Ok((r.get(0)?, r.get(1)?)),
+                );
+                (content.unwrap(), file.unwrap())
+            })
+            .await;
+        assert!(!content.contains("secret"), "{content}");
+        // the stored hash doesn't give the content away
+        assert_ne!(hash, format!("{:x}", Sha256::digest(&data)));
+        let stored = std::fs::read(app.dir.join(path)).unwrap();
+        assert!(stored.len() > data.len());
+        assert!(!stored.windows(1000).any(|w| w == &data[..1000]));
+    })
+}
+
+#[test]
+fn e2ee_items_keep_ciphertext_opaque() {
+    each_backend(|app| async move {
+        let e2ee = json!({
+            "alg": "XChaCha20-Poly1305",
+            "nonce": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYX",
+            "wrappedKey": "c2VhbGVkIGtleQ==",
+        })
+        .to_string();
+        // looks like a PNG, but must not be treated as one
+        let cipher = b"\x89PNG\r\n\x1a\n opaque ciphertext";
+        let item = app
+            .create(&[
+                ("type", None, b"TEXT"),
+                ("e2ee", None, e2ee.as_bytes()),
+                ("file", Some("note"), cipher),
+            ])
+            .await;
+        assert_eq!(item["type"], "TEXT");
+        assert_eq!(item["fileSize"], cipher.len());
+        assert_eq!(item["e2ee"]["alg"], "XChaCha20-Poly1305");
+        let id = item["id"].as_str().unwrap().to_string();
+        let token = item["share"]["token"].as_str().unwrap().to_string();
+
+        let listed = app.get(&format!("/api/clipboard/{id}")).await;
+        assert_eq!(listed["contentType"], "application/octet-stream");
+        assert_eq!(listed["e2ee"]["wrappedKey"], "c2VhbGVkIGtleQ==");
+
+        let anon = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+        let (status, meta) = app.send(anon(format!("/api/share/{token}"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let meta: Json = serde_json::from_slice(&meta).unwrap();
+        assert_eq!(meta["item"]["content"], Json::Null, "{meta}");
+        assert_eq!(
+            meta["item"]["e2ee"]["nonce"],
+            "AAECAwQFBgcICQoLDA0ODxAREhMUFRYX"
+        );
+        assert_eq!(meta["downloadCount"], 0);
+        let (status, body) = app.send(anon(format!("/api/share/{token}/file"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, cipher);
+
+        let (status, _) = app
+            .call(
+                Method::PATCH,
+                &format!("/api/clipboard/{id}"),
+                Some(json!({"content": "plaintext"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let bad = json!({"alg": "XChaCha20-Poly1305", "nonce": "AAEC"}).to_string();
+        let (status, _) = app
+            .try_create(&[("e2ee", None, bad.as_bytes()), ("file", Some("x"), cipher)])
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = app
+            .try_create(&[("e2ee", None, e2ee.as_bytes()), ("content", None, b"plain")])
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    })
+}
+
+#[test]
+fn export_needs_sqlite() {
+    each_backend(|app| async move {
+        let (status, _) = app.call(Method::GET, "/api/admin/export", None).await;
+        let expected = match app.dialect {
+            Dialect::Sqlite => StatusCode::OK,
+            Dialect::Postgres => StatusCode::NOT_IMPLEMENTED,
+        };
+        assert_eq!(status, expected);
+    })
+}
+
+#[test]
+fn export_imports_back_and_checks_keys() {
+    let key = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    each_backend_with(Some(key), |app| async move {
+        if app.dialect != Dialect::Sqlite {
+            return;
+        }
+        let text = app.text("exported note").await;
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let item = app.create(&[("file", Some("big.bin"), &data)]).await;
+        let file = item["id"].as_str().unwrap().to_string();
+        let authed = |req: axum::http::request::Builder| {
+            req.header("authorization", format!("Bearer {PASSWORD}"))
+        };
+        let (status, archive) = app
+            .send(
+                authed(Request::get("/api/admin/export"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        for id in [&text, &file] {
+            let (status, _) = app
+                .call(Method::DELETE, &format!("/api/clipboard/{id}"), None)
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            let (status, _) = app
+                .call(Method::DELETE, &format!("/api/trash/{id}"), None)
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert!(app.ids("").await.is_empty());
+
+        let import = |router: Router| {
+            let req = authed(Request::post("/api/admin/import?mode=merge"))
+                .body(Body::from(archive.clone()))
+                .unwrap();
+            async move {
+                let res = router.oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = res.into_body().collect().await.unwrap().to_bytes();
+                (status, serde_json::from_slice::<Json>(&body).unwrap())
+            }
+        };
+        let (status, report) = import(app.router.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{report}");
+        assert_eq!(report["imported"], 2);
+        assert_eq!(report["files"], 1);
+        let item = app.get(&format!("/api/clipboard/{text}")).await;
+        assert_eq!(item["content"], "exported note");
+        let (status, body) = app
+            .send(
+                authed(Request::get(format!("/api/files/{file}")))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, data);
+        // items already present are skipped
+        let (_, report) = import(app.router.clone()).await;
+        assert_eq!(
+            (report["imported"].clone(), report["skipped"].clone()),
+            (json!(0), json!(2))
+        );
+
+        // a server without the master key refuses the archive rather than taking in
+        // items it could never open
+        let key_id = Keyring::parse(key).unwrap().current_id().to_string();
+        let other = Keyring::parse("ICEiIyQlJicoKSorLC0uLzAxMjM0NTY3ODk6Ozw9Pj8=").unwrap();
+        for keys in [None, Some(Arc::new(other))] {
+            let router = router(AppState {
+                keys,
+                ..app.state.clone()
+            });
+            let (status, body) = import(router).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body["error"].as_str().unwrap().contains(&key_id), "{body}");
+        }
+    })
+}
+
+#[test]
+fn archives_stream_items_as_zip_and_tar_gz() {
+    each_backend(|app| async move {
+        let text = app.text("plain words").await;
+        let big: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let mut ids = vec![text.clone()];
+        for (name, data) in [
+            ("big.bin", big.as_slice()),
+            ("notes.md", b"# one".as_slice()),
+            ("NOTES.md", b"# two".as_slice()),
+            ("../up.md", b"# three".as_slice()),
+        ] {
+            let item = app.create(&[("file", Some(name), data)]).await;
+            ids.push(item["id"].as_str().unwrap().to_string());
+        }
+        let expected: Vec<(String, Vec<u8>)> = vec![
+            (format!("text-{}.txt", &text[..8]), b"plain words".to_vec()),
+            ("big.bin".into(), big.clone()),
+            ("notes.md".into(), b"# one".to_vec()),
+            ("NOTES (2).md".into(), b"# two".to_vec()),
+            ("_up.md".into(), b"# three".to_vec()),
+        ];
+        let fetch = |format: &str| {
+            let req = Request::get(format!(
+                "/api/clipboard/archive?ids={}&format={format}",
+                ids.join(",")
+            ))
+            .header("authorization", format!("Bearer {PASSWORD}"))
+            .body(Body::empty())
+            .unwrap();
+            app.send(req)
+        };
+
+        let (status, zip) = fetch("zip").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(zip)).unwrap();
+        let mut got = vec![];
+        for i in 0..zip.len() {
+            let mut f = zip.by_index(i).unwrap();
+            let mut data = vec![];
+            std::io::Read::read_to_end(&mut f, &mut data).unwrap();
+            got.push((f.name().unwrap().into_owned(), data));
+        }
+        assert_eq!(got, expected);
+
+        let (status, tgz) = fetch("tar.gz").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(tgz.as_slice()));
+        let got: Vec<(String, Vec<u8>)> = tar
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let mut e = e.unwrap();
+                let name = e.path().unwrap().to_string_lossy().into_owned();
+                let mut data = vec![];
+                std::io::Read::read_to_end(&mut e, &mut data).unwrap();
+                (name, data)
+            })
+            .collect();
+        assert_eq!(got, expected);
+
+        let (status, _) = fetch("rar").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    })
+}
+
+#[test]
+fn backups_share_uploads_and_rotate() {
+    each_backend(|app| async move {
+        if app.dialect != Dialect::Sqlite {
+            return;
+        }
+        let dir = scratch_dir();
+        let backups = backup::Backups::new(backup::Config {
+            dir: dir.clone(),
+            interval: 3600,
+            keep: 2,
+        });
+        let run = |n: usize| {
+            let status = serde_json::to_value(backup::run_once(&app.state, &backups)).unwrap();
+            assert_eq!(status["ok"], true, "{status}");
+            // names have one-second resolution; date this one back so the next run's is new and
+            // sorts after it
+            let name = status["name"].as_str().unwrap();
+            std::fs::rename(dir.join(name), dir.join(format!("backup-{n:04}"))).unwrap();
+            status
+        };
+        let names = || {
+            let mut names: Vec<String> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+
+        app.text("kept in every backup").await;
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let item = app.create(&[("file", Some("big.bin"), &data)]).await;
+        let file = item["id"].as_str().unwrap().to_string();
+        let status = run(1);
+        assert_eq!(
+            (status["items"].clone(), status["files"].clone()),
+            (json!(2), json!(1))
+        );
+        assert_eq!(status["copied"], 1);
+        let conn = rusqlite::Connection::open(dir.join("backup-0001/custom.db")).unwrap();
+        let n: i64 = conn
+            .query_row("SELECT COUNT(*) FROM ClipboardItem", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(n, 2);
+        drop(conn);
+
+        // the upload is already in the shared store
+        assert_eq!(run(2)["copied"], 0);
+        for uri in [
+            format!("/api/clipboard/{file}"),
+            format!("/api/trash/{file}"),
+        ] {
+            let (status, _) = app.call(Method::DELETE, &uri, None).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        // the second backup still needs the file after the first is rotated out ...
+        assert_eq!(run(3)["files"], 0);
+        assert_eq!(names(), ["backup-0002", "backup-0003", "uploads"]);
+        assert_eq!(std::fs::read_dir(dir.join("uploads")).unwrap().count(), 1);
+        // ... and once the second goes too, so does the file
+        run(4);
+        assert_eq!(names(), ["backup-0003", "backup-0004", "uploads"]);
+        assert_eq!(std::fs::read_dir(dir.join("uploads")).unwrap().count(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    })
+}
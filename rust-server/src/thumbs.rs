@@ -0,0 +1,242 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageDecoder, ImageReader, Limits};
+
+use crate::crypt::{self, ItemKey};
+
+// -------------------- Image thumbnails --------------------
+//
+// Thumbnails live in data/thumbs/<itemId>-<size>.<ext>. They are re-encoded from decoded
+// pixels, so no EXIF (GPS, camera serials, ...) survives; orientation is baked in first.
+// Thumbnails of encrypted items are sealed with the item's key.
+
+/// Bounding boxes (px) of the renditions we generate.
+pub(crate) const THUMB_SIZES: [u32; 2] = [256, 1024];
+
+const MAX_DIMENSION: u32 = 16_384;
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 82;
+
+pub(crate) enum ImageSource<'a> {
+    Path(&'a Path),
+    Bytes(&'a [u8]),
+}
+
+/// Snap a requested size to the smallest rendition that covers it.
+pub(crate) fn snap_size(requested: Option<u32>) -> u32 {
+    let want = requested.unwrap_or(THUMB_SIZES[0]);
+    THUMB_SIZES
+        .iter()
+        .copied()
+        .find(|s| *s >= want)
+        .unwrap_or(THUMB_SIZES[THUMB_SIZES.len() - 1])
+}
+
+pub(crate) fn thumbs_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("thumbs")
+}
+
+/// Locate an existing rendition, returning its path and content type.
+pub(crate) fn find(data_dir: &Path, id: &str, size: u32) -> Option<(PathBuf, &'static str)> {
+    let dir = thumbs_dir(data_dir);
+    [("jpg", "image/jpeg"), ("webp", "image/webp")]
+        .into_iter()
+        .map(|(ext, ct)| (dir.join(format!("{id}-{size}.{ext}")), ct))
+        .find(|(p, _)| p.is_file())
+}
+
+/// Decode the source once and write every rendition. Blocking; run on a blocking thread.
+pub(crate) fn generate(
+    data_dir: &Path,
+    id: &str,
+    src: ImageSource<'_>,
+    key: Option<&ItemKey>,
+) -> anyhow::Result<()> {
+    let img = decode_oriented(src)?;
+    let dir = thumbs_dir(data_dir);
+    std::fs::create_dir_all(&dir)?;
+    for size in THUMB_SIZES {
+        let scaled = if img.width() > size || img.height() > size {
+            img.thumbnail(size, size)
+        } else {
+            img.clone()
+        };
+        let (ext, bytes) = encode(&scaled)?;
+        let dest = dir.join(format!("{id}-{size}.{ext}"));
+        let tmp = dir.join(format!("{id}-{size}.{ext}.tmp"));
+        crypt::write(&tmp, key, &bytes)?;
+        std::fs::rename(&tmp, &dest)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn remove(data_dir: &Path, id: &str) {
+    let dir = thumbs_dir(data_dir);
+    for size in THUMB_SIZES {
+        for ext in ["jpg", "webp"] {
+            let _ = std::fs::remove_file(dir.join(format!("{id}-{size}.{ext}")));
+        }
+    }
+}
+
+pub(crate) fn decode_oriented(src: ImageSource<'_>) -> anyhow::Result<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut decoder = match src {
+        ImageSource::Path(p) => {
+            let mut r = ImageReader::open(p)?.with_guessed_format()?;
+            r.limits(limits);
+            Box::new(r.into_decoder()?) as Box<dyn ImageDecoder>
+        }
+        ImageSource::Bytes(b) => {
+            let mut r = ImageReader::new(Cursor::new(b)).with_guessed_format()?;
+            r.limits(limits);
+            Box::new(r.into_decoder()?) as Box<dyn ImageDecoder>
+        }
+    };
+    let orientation = decoder
+        .orientation()
+        .unwrap_or(image::metadata::Orientation::NoTransforms);
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+// JPEG for opaque images; lossless WebP keeps transparency for the rest.
+fn encode(img: &DynamicImage) -> anyhow::Result<(&'static str, Vec<u8>)> {
+    let mut out = Vec::new();
+    if img.color().has_alpha() {
+        let rgba = img.to_rgba8();
+        WebPEncoder::new_lossless(&mut out).encode(
+            rgba.as_raw(),
+            rgba.width(),
+            rgba.height(),
+            image::ExtendedColorType::Rgba8,
+        )?;
+        Ok(("webp", out))
+    } else {
+        let rgb = img.to_rgb8();
+        JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&rgb)?;
+        Ok(("jpg", out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::metadata::Orientation;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    use crate::crypt::Keyring;
+    use crate::scrub;
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("clip-relay-thumbs-{}", uuid::Uuid::new_v4()))
+    }
+
+    // a JPEG of `img` whose EXIF says to display it rotated by `orientation`
+    fn jpeg_with_orientation(img: &RgbImage, orientation: Orientation) -> Vec<u8> {
+        let mut plain = vec![];
+        JpegEncoder::new_with_quality(&mut plain, 95)
+            .encode_image(img)
+            .unwrap();
+        let exif = [
+            b"Exif\0\0".as_slice(),
+            &scrub::minimal_exif(orientation).unwrap(),
+        ]
+        .concat();
+        let mut out = plain[..2].to_vec();
+        out.extend_from_slice(&[0xFF, 0xE1]);
+        out.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        out.extend(exif);
+        out.extend_from_slice(&plain[2..]);
+        out
+    }
+
+    #[test]
+    fn sizes_snap_to_a_covering_rendition() {
+        assert_eq!(snap_size(None), 256);
+        assert_eq!(snap_size(Some(1)), 256);
+        assert_eq!(snap_size(Some(257)), 1024);
+        assert_eq!(snap_size(Some(5000)), 1024);
+    }
+
+    #[test]
+    fn orientation_is_applied_before_scaling() {
+        // red top half, blue bottom half; turned 90° clockwise the red ends up on the right
+        let img = RgbImage::from_fn(40, 20, |_, y| {
+            if y < 10 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let data = jpeg_with_orientation(&img, Orientation::Rotate90);
+        let out = decode_oriented(ImageSource::Bytes(&data))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(out.dimensions(), (20, 40));
+        assert!(
+            out.get_pixel(15, 20)[0] > 200,
+            "{:?}",
+            out.get_pixel(15, 20)
+        );
+        assert!(out.get_pixel(4, 20)[2] > 200, "{:?}", out.get_pixel(4, 20));
+    }
+
+    #[test]
+    fn renditions_fit_their_box_and_keep_alpha() {
+        let dir = scratch();
+        let wide = RgbImage::from_pixel(2000, 500, Rgb([10, 200, 30]));
+        let mut png = vec![];
+        DynamicImage::ImageRgb8(wide)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        generate(&dir, "wide", ImageSource::Bytes(&png), None).unwrap();
+        for (size, dims) in [(256, (256, 64)), (1024, (1024, 256))] {
+            let (path, ctype) = find(&dir, "wide", size).unwrap();
+            assert_eq!(ctype, "image/jpeg");
+            let thumb = image::open(&path).unwrap();
+            assert_eq!((thumb.width(), thumb.height()), dims);
+        }
+
+        // small images aren't scaled up; transparent ones become WebP
+        let small = RgbaImage::from_pixel(30, 20, Rgba([0, 0, 0, 0]));
+        let path = dir.join("small.png");
+        small.save(&path).unwrap();
+        generate(&dir, "small", ImageSource::Path(&path), None).unwrap();
+        let (thumb, ctype) = find(&dir, "small", 1024).unwrap();
+        assert_eq!(ctype, "image/webp");
+        let thumb = image::open(thumb).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (30, 20));
+        assert!(thumb.color().has_alpha());
+
+        remove(&dir, "wide");
+        assert!(find(&dir, "wide", 256).is_none());
+        assert!(find(&dir, "small", 256).is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn renditions_of_sealed_items_are_sealed() {
+        let dir = scratch();
+        let keys = Keyring::parse("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").unwrap();
+        let (key, _, _) = keys.new_key("item");
+        let mut png = vec![];
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(300, 300, Rgb([1, 2, 3])))
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        generate(&dir, "item", ImageSource::Bytes(&png), Some(&key)).unwrap();
+        let (path, _) = find(&dir, "item", 256).unwrap();
+        assert!(image::open(&path).is_err());
+        let plain = crypt::read(&path, Some(&key)).unwrap();
+        let thumb = image::load_from_memory(&plain).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (256, 256));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use std::env;
+use std::path::Path as StdPath;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use rusqlite::params;
+
+use crate::store::{self, Conn};
+use crate::{
+    bundle, crypt, e2ee, epoch_to_iso, limits, now_unix, scrub, thumbs, AppState, ServerEvent,
+};
+
+// -------------------- Trash --------------------
+//
+// Deleting an item only sets deletedAt. Trashed items are left out of the list, search and
+// all item endpoints, and their share links answer 404 right away. Files stay on disk until
+// the item is purged: explicitly from the trash, or by the background task once it has
+// been in the trash longer than TRASH_RETENTION.
+
+const DEFAULT_RETENTION: i64 = 30 * 86_400;
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Condition on a ShareLink row (alias `s`) that its item is not in the trash.
+pub(crate) const SHARE_ITEM_LIVE: &str =
+    "s.itemId NOT IN (SELECT id FROM ClipboardItem WHERE deletedAt IS NOT NULL)";
+
+/// TRASH_RETENTION: how long trashed items are kept (`30d`, `12h`, ...); `off` keeps them
+/// until purged by hand. Defaults to 30 days.
+pub(crate) fn retention_from_env() -> Option<i64> {
+    let Ok(v) = env::var("TRASH_RETENTION") else {
+        return Some(DEFAULT_RETENTION);
+    };
+    match v.trim().to_ascii_lowercase().as_str() {
+        "off" | "never" | "none" => None,
+        s => limits::parse_duration(s).or_else(|| {
+            tracing::warn!(value = %v, "invalid TRASH_RETENTION, using 30d");
+            Some(DEFAULT_RETENTION)
+        }),
+    }
+}
+
+/// Remove an item's files: the upload itself (or a bundle's files) plus cached thumbnails
+/// and stripped copies.
+pub(crate) fn remove_files(data_dir: &StdPath, id: &str, file_path: Option<&str>) {
+    if let Some(rel) = file_path {
+        let _ = std::fs::remove_file(data_dir.join(rel));
+    }
+    thumbs::remove(data_dir, id);
+    scrub::remove(data_dir, id);
+    let _ = std::fs::remove_dir_all(bundle::dir(data_dir, id));
+}
+
+/// Move an item to the trash. Returns false when it doesn't exist or is already there.
+pub(crate) fn soft_delete(conn: &dyn Conn, id: &str) -> bool {
+    conn.execute(
+        "UPDATE ClipboardItem SET deletedAt=? WHERE id=? AND deletedAt IS NULL",
+        params![now_unix(), id],
+    )
+    .unwrap_or(0)
+        > 0
+}
+
+/// Permanently delete trashed items and their files.
+pub(crate) async fn purge(state: &AppState, ids: Vec<String>) -> Vec<String> {
+    let removed = state
+        .db
+        .write(move |conn| {
+            let tx = conn.transaction()?;
+            let mut removed = vec![];
+            for id in &ids {
+                let fp: Option<Option<String>> = tx
+                    .query_row(
+                        "SELECT filePath FROM ClipboardItem WHERE id=? AND deletedAt IS NOT NULL",
+                        [id],
+                        |r| r.get(0),
+                    )
+                    .ok();
+                if let Some(fp) = fp {
+                    tx.execute("DELETE FROM ClipboardItem WHERE id=?", [id])?;
+                    removed.push((id.clone(), fp));
+                }
+            }
+            tx.commit()?;
+            Ok::<_, store::Error>(removed)
+        })
+        .await;
+    let removed: Vec<(String, Option<String>)> = removed.unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "purging trashed items failed");
+        vec![]
+    });
+    for (id, fp) in &removed {
+        remove_files(&state.data_dir, id, fp.as_deref());
+    }
+    let ids: Vec<String> = removed.into_iter().map(|(id, _)| id).collect();
+    if !ids.is_empty() {
+        let _ = state.tx.send(ServerEvent {
+            name: "trash:purged".into(),
+            data: serde_json::json!({ "ids": ids }),
+        });
+    }
+    ids
+}
+
+fn trashed_ids(conn: &dyn Conn, before: Option<i64>) -> Vec<String> {
+    let mut stmt = conn
+        .prepare("SELECT id FROM ClipboardItem WHERE deletedAt IS NOT NULL AND deletedAt <= ?")
+        .unwrap();
+    stmt.query_map([before.unwrap_or(i64::MAX)], |r| r.get(0))
+        .unwrap()
+        .flatten()
+        .collect()
+}
+
+/// Purge items whose retention ran out, hourly.
+pub(crate) async fn run_purge_task(state: AppState) {
+    let Some(retention) = state.trash_retention else {
+        return;
+    };
+    let mut tick = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        tick.tick().await;
+        let before = now_unix() - retention;
+        let ids = state
+            .db
+            .read(move |conn| trashed_ids(conn, Some(before)))
+            .await;
+        if ids.is_empty() {
+            continue;
+        }
+        let purged = purge(&state, ids).await;
+        tracing::info!(count = purged.len(), "purged expired items from trash");
+    }
+}
+
+// GET /api/trash — most recently deleted first
+pub(crate) async fn list_trash(State(state): State<AppState>) -> Response {
+    let (retention, keys) = (state.trash_retention, state.keys.clone());
+    state.db.read(move |conn| {
+        let mut stmt = conn
+            .prepare("SELECT id, type, content, fileName, fileSize, contentType, createdAt, deletedAt, keyId, dataKey, e2ee FROM ClipboardItem WHERE deletedAt IS NOT NULL ORDER BY deletedAt DESC, id DESC")
+            .unwrap();
+        let items: Vec<serde_json::Value> = stmt
+            .query_map([], |r| {
+                let deleted_at: i64 = r.get(7)?;
+                let id: String = r.get(0)?;
+                let content = crypt::open_listed(keys.as_deref(), &id, r.get(8)?, r.get(9)?, r.get(2)?);
+                Ok(serde_json::json!({
+                    "id": id,
+                    "type": r.get::<_, String>(1)?,
+                    "content": content,
+                    "fileName": r.get::<_, Option<String>>(3)?,
+                    "fileSize": r.get::<_, Option<i64>>(4)?,
+                    "contentType": r.get::<_, Option<String>>(5)?,
+                    "createdAt": epoch_to_iso(r.get::<_, i64>(6)?),
+                    "deletedAt": epoch_to_iso(deleted_at),
+                    "purgeAt": retention.map(|s| epoch_to_iso(deleted_at + s)),
+                    "e2ee": e2ee::json(r.get(10)?),
+                }))
+            })
+            .unwrap()
+            .flatten()
+            .collect();
+        Json(serde_json::json!({ "items": items, "retentionSeconds": retention })).into_response()
+    })
+    .await
+}
+
+// POST /api/trash/:id/restore
+pub(crate) async fn restore_item(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let keys = state.keys.clone();
+    let item = state
+        .db
+        .write(move |conn| {
+            let n = conn
+                .execute(
+                    "UPDATE ClipboardItem SET deletedAt=NULL WHERE id=? AND deletedAt IS NOT NULL",
+                    [&id],
+                )
+                .unwrap_or(0);
+            if n == 0 {
+                return None;
+            }
+            // Same shape as the clipboard:created payload so clients can insert it directly
+            conn.query_row(
+                "SELECT type, content, fileName, fileSize, sortWeight, createdAt, updatedAt, pinnedAt IS NOT NULL, favorite, archivedAt IS NOT NULL, keyId, dataKey, e2ee FROM ClipboardItem WHERE id=?",
+                [&id],
+                |r| {
+                    let content = crypt::open_listed(keys.as_deref(), &id, r.get(10)?, r.get(11)?, r.get(1)?);
+                    Ok(serde_json::json!({
+                        "id": id,
+                        "type": r.get::<_, String>(0)?,
+                        "content": content,
+                        "fileName": r.get::<_, Option<String>>(2)?,
+                        "fileSize": r.get::<_, Option<i64>>(3)?,
+                        "sortWeight": r.get::<_, i64>(4)?,
+                        "createdAt": epoch_to_iso(r.get::<_, i64>(5)?),
+                        "updatedAt": epoch_to_iso(r.get::<_, i64>(6)?),
+                        "pinned": r.get::<_, bool>(7)?,
+                        "favorite": r.get::<_, bool>(8)?,
+                        "archived": r.get::<_, bool>(9)?,
+                        "e2ee": e2ee::json(r.get(12)?),
+                    }))
+                },
+            )
+            .ok()
+        })
+        .await;
+    let Some(item) = item else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"Not found"})),
+        )
+            .into_response();
+    };
+    let _ = state.tx.send(ServerEvent {
+        name: "clipboard:restored".into(),
+        data: item.clone(),
+    });
+    Json(item).into_response()
+}
+
+// DELETE /api/trash/:id — permanent
+pub(crate) async fn purge_item(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    if purge(&state, vec![id]).await.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error":"Not found"})),
+        )
+            .into_response();
+    }
+    Json(serde_json::json!({"ok": true})).into_response()
+}
+
+// DELETE /api/trash — empty the trash
+pub(crate) async fn empty_trash(State(state): State<AppState>) -> Response {
+    let ids = state.db.read(|conn| trashed_ids(conn, None)).await;
+    let purged = purge(&state, ids).await;
+    Json(serde_json::json!({ "ok": true, "purged": purged.len() })).into_response()
+}
@@ -19,11 +19,26 @@ import {
 } from '@/components/ui/alert-dialog';
 import { Dialog, DialogContent, DialogHeader, DialogTitle } from '@/components/ui/dialog';
 import { useToast } from '@/hooks/use-toast';
+import { ToastAction } from '@/components/ui/toast';
 import { safeCopyText } from '@/lib/copy';
 import { authFetch, verifyPassword, getStoredPassword, logout } from '@/lib/auth';
 import ThemeSelect from '@/components/ThemeSelect';
 import { Sheet, SheetContent, SheetFooter, SheetHeader } from '@/components/ui/sheet';
-import { CLIPBOARD_CREATED_EVENT, CLIPBOARD_DELETED_EVENT, CLIPBOARD_REORDERED_EVENT } from '@/lib/socket-events';
+import {
+  CLIPBOARD_BULK_EVENT,
+  CLIPBOARD_CREATED_EVENT,
+  CLIPBOARD_DELETED_EVENT,
+  CLIPBOARD_EXPIRED_EVENT,
+  CLIPBOARD_EXPIRY_EVENT,
+  CLIPBOARD_FLAGS_EVENT,
+  CLIPBOARD_IMPORTED_EVENT,
+  CLIPBOARD_REORDERED_EVENT,
+  CLIPBOARD_RESTORED_EVENT,
+  CLIPBOARD_TAGS_EVENT,
+  CLIPBOARD_UPDATED_EVENT,
+  TAG_DELETED_EVENT,
+  TAG_UPDATED_EVENT,
+} from '@/lib/socket-events';
 import type { ClipboardItem as GridItem } from '@/components/clipboard/ClipboardGrid';
 
 const ClipboardGrid = dynamic(() => import('@/components/clipboard/ClipboardGrid'), { ssr: false });
@@ -35,6 +50,20 @@ const CreateShareDialog = dynamic(() => import('@/components/clipboard/CreateSha
 
 type ClipboardItem = GridItem;
 
+// Server's manual order: pinned first, then sortWeight desc, createdAt desc, id desc
+function compareManual(x: ClipboardItem, y: ClipboardItem) {
+  if (!!x.pinned !== !!y.pinned) return x.pinned ? -1 : 1;
+  const kx0 = typeof x.sortWeight === 'number' ? x.sortWeight : 0;
+  const ky0 = typeof y.sortWeight === 'number' ? y.sortWeight : 0;
+  if (kx0 !== ky0) return ky0 - kx0;
+  const kx1 = new Date(x.createdAt).getTime();
+  const ky1 = new Date(y.createdAt).getTime();
+  if (kx1 !== ky1) return ky1 - kx1;
+  if (y.id > x.id) return 1;
+  if (y.id < x.id) return -1;
+  return 0;
+}
+
 export default function Home() {
   const API_BASE = (process.env.NEXT_PUBLIC_API_BASE || '').replace(/\/$/, '');
   const REPO_URL = 'https://github.com/paopaoandlingyia/clip-relay';
@@ -58,7 +87,7 @@ export default function Home() {
   const [shareInitialTab, setShareInitialTab] = useState<'status'|'settings'>('status');
   const [qrOpen, setQrOpen] = useState(false);
   const [qrShare, setQrShare] = useState<{ token: string; url: string } | null>(null);
-  const [nextCursor, setNextCursor] = useState<{ id: string; createdAt: string; sortWeight?: number } | null>(null);
+  const [nextCursor, setNextCursor] = useState<{ id: string; createdAt: string; sortWeight?: number; key?: number; pinned?: boolean } | null>(null);
   const [loadingMore, setLoadingMore] = useState(false);
   const [viewMode, setViewMode] = useState<'grid' | 'list'>('grid');
   const { toast } = useToast();
@@ -151,7 +180,8 @@ export default function Home() {
       params.set('take', '24');
       params.set('cursorCreatedAt', nextCursor.createdAt);
       params.set('cursorId', nextCursor.id);
-      if (typeof nextCursor.sortWeight === 'number') params.set('cursorSortWeight', String(nextCursor.sortWeight));
+      if (typeof nextCursor.key === 'number') params.set('cursorKey', String(nextCursor.key));
+      if (typeof nextCursor.pinned === 'boolean') params.set('cursorPinned', String(nextCursor.pinned));
       if (searchTermRef.current) params.set('search', searchTermRef.current);
       const res = await authFetch(`/api/clipboard?${params.toString()}`);
       const data = await res.json();
@@ -226,37 +256,94 @@ export default function Home() {
       const pwd = getStoredPassword();
       const url = useQueryAuth && pwd ? `${API_BASE}/api/events?auth=${encodeURIComponent(pwd)}` : `${API_BASE}/api/events`;
       es = new EventSource(url, { withCredentials: true } as any);
-      es.addEventListener(CLIPBOARD_CREATED_EVENT, (ev: MessageEvent) => {
+      const insertItem = (ev: MessageEvent) => {
         const newItem = JSON.parse((ev as MessageEvent).data) as ClipboardItem;
         if (searchTermRef.current) {
           fetchItems(searchTermRef.current);
         } else {
           setItems(prev => {
-            // Insert by sortWeight desc, then createdAt desc, then id desc
             if (prev.find(i => i.id === newItem.id)) return prev;
             const arr = [...prev];
-            const keyOf = (x: ClipboardItem) => [x.sortWeight ?? 0, new Date(x.createdAt).getTime(), x.id] as const;
-            const newKey = keyOf(newItem);
             let idx = 0;
-            while (idx < arr.length) {
-              const k = keyOf(arr[idx]);
-              if (
-                newKey[0] > (k[0] ?? 0) ||
-                (newKey[0] === (k[0] ?? 0) && (newKey[1] > k[1] || (newKey[1] === k[1] && newKey[2] > k[2])))
-              ) {
-                break;
-              }
-              idx++;
-            }
+            while (idx < arr.length && compareManual(arr[idx], newItem) < 0) idx++;
             arr.splice(idx, 0, newItem);
             return arr;
           });
         }
+      };
+      es.addEventListener(CLIPBOARD_CREATED_EVENT, insertItem);
+      // Restored from the trash: same payload as a newly created item
+      es.addEventListener(CLIPBOARD_RESTORED_EVENT, (ev: MessageEvent) => {
+        const item = JSON.parse((ev as MessageEvent).data) as ClipboardItem;
+        if (!item.archived) insertItem(ev);
       });
       es.addEventListener(CLIPBOARD_DELETED_EVENT, (ev: MessageEvent) => {
         const { id } = JSON.parse((ev as MessageEvent).data) as { id: string };
         setItems(prev => prev.filter(i => i.id !== id));
       });
+      // Item reached its own expiresAt and was deleted for good
+      es.addEventListener(CLIPBOARD_EXPIRED_EVENT, (ev: MessageEvent) => {
+        const { id } = JSON.parse((ev as MessageEvent).data) as { id: string };
+        setItems(prev => prev.filter(i => i.id !== id));
+        setSelectedItem(prev => (prev && prev.id === id ? null : prev));
+      });
+      es.addEventListener(CLIPBOARD_EXPIRY_EVENT, (ev: MessageEvent) => {
+        const { id, expiresAt } = JSON.parse((ev as MessageEvent).data) as { id: string; expiresAt: string | null };
+        setItems(prev => prev.map(i => (i.id === id ? { ...i, expiresAt: expiresAt ?? undefined } : i)));
+      });
+      // An import can change anything; just reload
+      es.addEventListener(CLIPBOARD_IMPORTED_EVENT, () => {
+        fetchItems(searchTermRef.current || '');
+      });
+      // One event for a whole POST /api/clipboard/bulk call
+      es.addEventListener(CLIPBOARD_BULK_EVENT, (ev: MessageEvent) => {
+        const bulk = JSON.parse((ev as MessageEvent).data) as {
+          action: string;
+          ids: string[];
+          tags?: Record<string, NonNullable<ClipboardItem['tags']>>;
+          expiresAt?: string | null;
+        };
+        const ids = new Set(bulk.ids);
+        setItems(prev => {
+          switch (bulk.action) {
+            case 'delete':
+            case 'archive':
+              return prev.filter(i => !ids.has(i.id));
+            case 'tag':
+            case 'untag':
+              return prev.map(i => (bulk.tags?.[i.id] ? { ...i, tags: bulk.tags[i.id] } : i));
+            case 'expire':
+              return prev.map(i => (ids.has(i.id) ? { ...i, expiresAt: bulk.expiresAt ?? undefined } : i));
+            default:
+              return prev;
+          }
+        });
+      });
+      es.addEventListener(CLIPBOARD_UPDATED_EVENT, (ev: MessageEvent) => {
+        const patch = JSON.parse((ev as MessageEvent).data) as Partial<ClipboardItem> & { id: string };
+        setItems(prev => prev.map(i => (i.id === patch.id ? { ...i, ...patch } : i)));
+        setSelectedItem(prev => (prev && prev.id === patch.id ? { ...prev, ...patch } : prev));
+      });
+      es.addEventListener(CLIPBOARD_FLAGS_EVENT, (ev: MessageEvent) => {
+        const flags = JSON.parse((ev as MessageEvent).data) as Pick<ClipboardItem, 'id' | 'pinned' | 'favorite' | 'archived'>;
+        setItems(prev => {
+          // Archived items are not part of the default list
+          if (flags.archived) return prev.filter(i => i.id !== flags.id);
+          return prev.map(i => (i.id === flags.id ? { ...i, ...flags } : i)).sort(compareManual);
+        });
+      });
+      es.addEventListener(CLIPBOARD_TAGS_EVENT, (ev: MessageEvent) => {
+        const { id, tags } = JSON.parse((ev as MessageEvent).data) as Pick<ClipboardItem, 'id' | 'tags'>;
+        setItems(prev => prev.map(i => (i.id === id ? { ...i, tags } : i)));
+      });
+      es.addEventListener(TAG_UPDATED_EVENT, (ev: MessageEvent) => {
+        const tag = JSON.parse((ev as MessageEvent).data) as NonNullable<ClipboardItem['tags']>[number];
+        setItems(prev => prev.map(i => (i.tags?.some(t => t.id === tag.id) ? { ...i, tags: i.tags.map(t => (t.id === tag.id ? tag : t)) } : i)));
+      });
+      es.addEventListener(TAG_DELETED_EVENT, (ev: MessageEvent) => {
+        const { id } = JSON.parse((ev as MessageEvent).data) as { id: string };
+        setItems(prev => prev.map(i => (i.tags?.some(t => t.id === id) ? { ...i, tags: i.tags.filter(t => t.id !== id) } : i)));
+      });
       es.addEventListener(CLIPBOARD_REORDERED_EVENT, (ev: MessageEvent) => {
         const data = JSON.parse((ev as MessageEvent).data) as { ids: string[]; weights?: Record<string, number> };
         const order = data?.ids || [];
@@ -268,17 +355,7 @@ export default function Home() {
           if (hasWeights) {
             // Update local sortWeight and resort to match server ordering logic
             a = a.map(it => (weights && Object.prototype.hasOwnProperty.call(weights, it.id) ? { ...it, sortWeight: weights![it.id] } : it));
-            a.sort((x, y) => {
-              const kx0 = typeof x.sortWeight === 'number' ? x.sortWeight : 0;
-              const ky0 = typeof y.sortWeight === 'number' ? y.sortWeight : 0;
-              if (kx0 !== ky0) return ky0 - kx0; // sortWeight desc
-              const kx1 = new Date(x.createdAt).getTime();
-              const ky1 = new Date(y.createdAt).getTime();
-              if (kx1 !== ky1) return ky1 - kx1; // createdAt desc
-              if (y.id > x.id) return 1; // id desc
-              if (y.id < x.id) return -1;
-              return 0;
-            });
+            a.sort(compareManual);
             return a;
           }
           // Fallback for older servers: use provided ids order only
@@ -316,8 +393,13 @@ export default function Home() {
       if (response.ok) {
         setItems(prev => prev.filter(i => i.id !== id));
         toast({
-          title: "已删除",
-          description: "条目已成功删除",
+          title: "已移入回收站",
+          description: "条目可在回收站中恢复",
+          action: (
+            <ToastAction altText="撤销删除" onClick={() => { authFetch(`/api/trash/${id}/restore`, { method: 'POST' }).catch(() => {}); }}>
+              撤销
+            </ToastAction>
+          ),
         });
       } else {
         toast({
@@ -532,7 +614,7 @@ export default function Home() {
         <AlertDialogContent>
           <AlertDialogHeader>
             <AlertDialogTitle>确认删除</AlertDialogTitle>
-            <AlertDialogDescription>删除后将无法恢复。确定要删除该条目吗？</AlertDialogDescription>
+            <AlertDialogDescription>条目将移入回收站，可在保留期内恢复。确定要删除该条目吗？</AlertDialogDescription>
           </AlertDialogHeader>
           <AlertDialogFooter>
             <AlertDialogCancel>取消</AlertDialogCancel>
@@ -12,11 +12,12 @@ type ShareMeta = {
   token: string;
   item: {
     id: string;
-    type: 'TEXT' | 'IMAGE' | 'FILE';
+    type: 'TEXT' | 'IMAGE' | 'FILE' | 'BUNDLE';
     fileName?: string | null;
     fileSize?: number | null;
     contentType?: string | null;
     content?: string | null;
+    files?: { id: string; path: string; fileSize: number }[] | null; // BUNDLE, once authorized
     createdAt: string;
     updatedAt: string;
   };
@@ -34,6 +35,8 @@ export default function ShareClient({ token }: { token: string }) {
   const [meta, setMeta] = useState<ShareMeta | null>(null);
   const [pwd, setPwd] = useState('');
   const [submitting, setSubmitting] = useState(false);
+  // BUNDLE: ids of the files picked for download; none picked downloads everything
+  const [picked, setPicked] = useState<string[]>([]);
 
   const fetchMeta = async () => {
     if (!token) return;
@@ -168,7 +171,12 @@ export default function ShareClient({ token }: { token: string }) {
           {meta.item.type === 'IMAGE' && (
             <div>
               <img
-                src={`${API_BASE}/api/share/${token}/file`}
+                src={`${API_BASE}/api/share/${token}/thumb?size=1024`}
+                onError={(e) => {
+                  // 缩略图不可用时回退到原图
+                  const img = e.currentTarget;
+                  if (!img.src.endsWith('/file')) img.src = `${API_BASE}/api/share/${token}/file`;
+                }}
                 alt={meta.item.fileName || 'image'}
                 className="max-h-[60vh] rounded"
                 loading="lazy"
@@ -183,6 +191,33 @@ export default function ShareClient({ token }: { token: string }) {
             </div>
           )}
 
+          {meta.item.type === 'BUNDLE' && (
+            <div>
+              <div className="text-sm">{meta.item.fileName} {fileSize(meta.item.fileSize)}</div>
+              <div className="mt-3 border rounded divide-y max-h-[50vh] overflow-y-auto">
+                {(meta.item.files || []).map((f) => (
+                  <label key={f.id} className="flex items-center gap-3 px-3 py-2 text-sm cursor-pointer">
+                    <input
+                      type="checkbox"
+                      checked={picked.includes(f.id)}
+                      onChange={(e) => setPicked((p) => (e.target.checked ? [...p, f.id] : p.filter((x) => x !== f.id)))}
+                    />
+                    <span className="truncate flex-1" title={f.path}>{f.path}</span>
+                    <span className="text-muted-foreground shrink-0">{fileSize(f.fileSize)}</span>
+                  </label>
+                ))}
+              </div>
+              <div className="mt-3">
+                <a
+                  className="underline"
+                  href={`${API_BASE}/api/share/${token}/download${picked.length ? `?files=${picked.map(encodeURIComponent).join(',')}` : ''}`}
+                >
+                  {picked.length ? `下载所选（${picked.length}）` : '下载全部'}
+                </a>
+              </div>
+            </div>
+          )}
+
           {meta.item.type === 'FILE' && (
             <div>
               <div className="text-sm">{meta.item.fileName} {fileSize(meta.item.fileSize)}</div>
@@ -10,13 +10,14 @@ import { useToast } from "@/hooks/use-toast";
 import { getAuthHeaders } from "@/lib/auth";
 import { safeCopyText, isSecure } from "@/lib/copy";
 import axios from "axios";
-import { File as FileIcon, Plus } from "lucide-react";
+import { File as FileIcon, Folder, Plus } from "lucide-react";
 // unified add-and-share flow: configure share params here and show result after creation
 
 export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemAdded: () => void; onShareCreated?: (share: { token: string; url: string; id?: string }) => void }) {
   const [open, setOpen] = useState(false);
   const [content, setContent] = useState("");
-  const [file, setFile] = useState<File | null>(null);
+  // more than one file (or a folder) is uploaded as a single BUNDLE item
+  const [files, setFiles] = useState<File[]>([]);
   const [isDragging, setIsDragging] = useState(false);
   const [uploadProgress, setUploadProgress] = useState<number | null>(null);
   const abortControllerRef = useRef<AbortController | null>(null);
@@ -24,20 +25,26 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
 
   // Share params for creation
   const [shareExpiresIn, setShareExpiresIn] = useState<string>("0"); // 0 = 永不过期
+  // Item expiry: the item itself is deleted after this, independent of the share
+  const [expiresIn, setExpiresIn] = useState<string>("0");
   const [shareMaxDownloads, setShareMaxDownloads] = useState<string>("");
   const [sharePassword, setSharePassword] = useState<string>("");
   const [shareResult, setShareResult] = useState<{ token: string; url: string } | null>(null);
 
-  const handleFileSelect = (selectedFile: File) => {
-    setFile(selectedFile);
+  const handleFileSelect = (selected: FileList | File[] | null | undefined) => {
+    const list = Array.from(selected || []);
+    if (list.length) setFiles(list);
   };
+  const file = files[0] ?? null;
+  const totalSize = files.reduce((n, f) => n + f.size, 0);
 
   const resetForm = () => {
     setContent("");
-    setFile(null);
+    setFiles([]);
     setIsDragging(false);
     setUploadProgress(null);
     setShareExpiresIn("0");
+    setExpiresIn("0");
     setShareMaxDownloads("");
     setSharePassword("");
     setShareResult(null);
@@ -46,8 +53,7 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
   const handleDrop = (e: React.DragEvent) => {
     e.preventDefault();
     setIsDragging(false);
-    const droppedFile = e.dataTransfer.files[0];
-    if (droppedFile) handleFileSelect(droppedFile);
+    handleFileSelect(e.dataTransfer.files);
   };
 
   const handleDragOver = (e: React.DragEvent) => {
@@ -69,7 +75,7 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
         if (it.kind === "file" || (it.type && it.type.startsWith("image/"))) {
           const pastedFile = it.getAsFile?.();
           if (pastedFile) {
-            setFile(pastedFile);
+            setFiles([pastedFile]);
             toast({ title: "已获取粘贴的图片", description: `${pastedFile.name || "image"} (${(pastedFile.size / 1024 / 1024).toFixed(2)} MB)` });
             break;
           }
@@ -90,12 +96,17 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
     try {
       const formData = new FormData();
       formData.append("content", content);
-      let itemType: "TEXT" | "IMAGE" | "FILE" = "TEXT";
-      if (file) {
+      let itemType: "TEXT" | "IMAGE" | "FILE" | "BUNDLE" = "TEXT";
+      if (files.length > 1) {
+        itemType = "BUNDLE";
+        // the filename carries the relative path so folder structure survives
+        for (const f of files) formData.append("file", f, f.webkitRelativePath || f.name);
+      } else if (file) {
         itemType = file.type.startsWith("image/") ? "IMAGE" : "FILE";
         formData.append("file", file);
       }
       formData.append("type", itemType);
+      if (expiresIn !== "0") formData.append("expiresIn", expiresIn);
       // share params
       formData.append("shareExpiresIn", shareExpiresIn || "0");
       if (shareMaxDownloads.trim()) formData.append("shareMaxDownloads", shareMaxDownloads.trim());
@@ -127,7 +138,7 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
         toast({ title: "已添加并生成分享" });
         // 清空输入，以便继续添加；保留结果区域
         setContent("");
-        setFile(null);
+        setFiles([]);
       } else {
         toast({ title: "添加成功" });
       }
@@ -195,28 +206,38 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
               {file ? (
                 <div className="space-y-2">
                   <FileIcon className="h-8 w-8 text-green-600 mx-auto" />
-                  <p className="text-sm font-medium">{file.name}</p>
-                  <p className="text-xs text-muted-foreground">{(file.size / 1024 / 1024).toFixed(2)} MB</p>
-                  <Button variant="outline" size="sm" onClick={() => setFile(null)}>
+                  <p className="text-sm font-medium">{files.length > 1 ? `${files.length} 个文件` : file.name}</p>
+                  <p className="text-xs text-muted-foreground">{(totalSize / 1024 / 1024).toFixed(2)} MB</p>
+                  <Button variant="outline" size="sm" onClick={() => setFiles([])}>
                     重新选择
                   </Button>
                 </div>
               ) : (
                 <div className="space-y-2">
                   <FileIcon className="h-8 w-8 text-muted-foreground mx-auto" />
-                  <p className="text-sm text-muted-foreground">拖拽文件到此处、点击选择，或在此处按 Ctrl+V 粘贴图片</p>
+                  <p className="text-sm text-muted-foreground">拖拽文件到此处、点击选择，或在此处按 Ctrl+V 粘贴图片；多个文件会合并为一个条目</p>
                   <input
                     type="file"
-                    onChange={(e) => {
-                      const selectedFile = e.target.files?.[0];
-                      if (selectedFile) handleFileSelect(selectedFile);
-                    }}
+                    multiple
+                    onChange={(e) => handleFileSelect(e.target.files)}
                     className="hidden"
                     id="file-upload"
                   />
-                  <Button variant="outline" size="sm" onClick={() => document.getElementById("file-upload")?.click()}>
-                    选择文件
-                  </Button>
+                  <input
+                    type="file"
+                    {...({ webkitdirectory: "" } as Record<string, string>)}
+                    onChange={(e) => handleFileSelect(e.target.files)}
+                    className="hidden"
+                    id="folder-upload"
+                  />
+                  <div className="flex justify-center gap-2">
+                    <Button variant="outline" size="sm" onClick={() => document.getElementById("file-upload")?.click()}>
+                      选择文件
+                    </Button>
+                    <Button variant="outline" size="sm" onClick={() => document.getElementById("folder-upload")?.click()}>
+                      <Folder className="h-4 w-4 mr-1" /> 选择文件夹
+                    </Button>
+                  </div>
                 </div>
               )}
             </div>
@@ -230,6 +251,18 @@ export default function AddItemDialog({ onItemAdded, onShareCreated }: { onItemA
             )}
           </div>
 
+          {/* 自动删除 */}
+          <div className="pt-4 border-t">
+            <label className="text-sm font-medium mb-1 block">自动删除</label>
+            <select className="w-full rounded border px-2 py-1 text-sm bg-background" value={expiresIn} onChange={(e) => setExpiresIn(e.target.value)}>
+              <option value="0">不自动删除</option>
+              <option value="10m">10 分钟后</option>
+              <option value="1h">1 小时后</option>
+              <option value="1d">1 天后</option>
+              <option value="7d">7 天后</option>
+            </select>
+          </div>
+
           {/* 分享设置 */}
           <div className="pt-4 border-t">
             <div className="text-sm font-medium mb-2">分享设置</div>
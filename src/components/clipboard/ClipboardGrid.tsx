@@ -7,7 +7,7 @@ import { CSS } from "@dnd-kit/utilities";
 import { Card, CardContent, CardHeader } from "@/components/ui/card";
 import { Badge } from "@/components/ui/badge";
 import { Button } from "@/components/ui/button";
-import { Copy, Share2, Trash2, FileText, Image as ImageIcon, File as FileIcon, QrCode } from "lucide-react";
+import { Copy, Share2, Trash2, FileText, Image as ImageIcon, File as FileIcon, QrCode, Pin, Star, Archive, Timer, Folder } from "lucide-react";
 import { authFetch } from "@/lib/auth";
 import { safeCopyText, isSecure } from "@/lib/copy";
 import { useToast } from "@/hooks/use-toast";
@@ -15,11 +15,17 @@ import { formatDate, formatFileSize } from "@/lib/format";
 
 export type ClipboardItem = {
   id: string;
-  type: "TEXT" | "IMAGE" | "FILE";
+  type: "TEXT" | "IMAGE" | "FILE" | "BUNDLE"; // BUNDLE: several files uploaded as one item
   content?: string;
   fileName?: string;
   fileSize?: number;
   sortWeight?: number;
+  snippet?: string; // search hit context, server-escaped HTML with <mark> highlights
+  tags?: { id: string; name: string; color?: string | null }[];
+  pinned?: boolean;
+  favorite?: boolean;
+  archived?: boolean;
+  expiresAt?: string; // item is deleted for good at this time
   createdAt: string;
   updatedAt: string;
 };
@@ -40,6 +46,8 @@ function getTypeIcon(type: string) {
       return <FileText className="h-4 w-4" />;
     case "IMAGE":
       return <ImageIcon className="h-4 w-4" />;
+    case "BUNDLE":
+      return <Folder className="h-4 w-4" />;
     case "FILE":
     default:
       return <FileIcon className="h-4 w-4" />;
@@ -131,6 +139,20 @@ const SortableItem = React.memo(function SortableItem({ id, item, onSelectItem,
     } catch {}
   };
 
+  const setFlags = async (flags: { pinned?: boolean; favorite?: boolean; archived?: boolean }) => {
+    // The list updates from the clipboard:flags event
+    try {
+      const res = await authFetch(`/api/clipboard/${item.id}/flags`, {
+        method: 'PUT',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify(flags),
+      });
+      if (!res.ok) throw new Error('failed');
+    } catch {
+      toast({ title: '操作失败', variant: 'destructive' });
+    }
+  };
+
   const style = {
     transform: CSS.Transform.toString(transform),
     transition,
@@ -150,8 +172,50 @@ const SortableItem = React.memo(function SortableItem({ id, item, onSelectItem,
             <div className="flex items-center gap-2">
               {getTypeIcon(item.type)}
               <Badge variant="secondary">{item.type}</Badge>
+              {item.pinned && <Pin className="h-3 w-3 text-primary" aria-label="已置顶" />}
+              {item.favorite && <Star className="h-3 w-3 fill-yellow-400 text-yellow-400" aria-label="已收藏" />}
+              {item.expiresAt && (
+                <span title={`将于 ${formatDate(item.expiresAt)} 自动删除`}>
+                  <Timer className="h-3 w-3 text-destructive" aria-label="定时删除" />
+                </span>
+              )}
+              {item.tags?.slice(0, 3).map(t => (
+                <Badge key={t.id} variant="outline" className="max-w-24 truncate" style={t.color ? { borderColor: t.color, color: t.color } : undefined}>
+                  {t.name}
+                </Badge>
+              ))}
             </div>
             <div className="flex gap-1 items-center opacity-100 md:opacity-0 md:group-hover:opacity-100 transition-opacity">
+              <Button
+                size="sm"
+                variant="ghost"
+                className="cursor-pointer"
+                onClick={(e) => { e.stopPropagation(); setFlags({ pinned: !item.pinned }); }}
+                aria-label={item.pinned ? "取消置顶" : "置顶"}
+                title={item.pinned ? "取消置顶" : "置顶"}
+              >
+                <Pin className="h-3 w-3" />
+              </Button>
+              <Button
+                size="sm"
+                variant="ghost"
+                className="cursor-pointer"
+                onClick={(e) => { e.stopPropagation(); setFlags({ favorite: !item.favorite }); }}
+                aria-label={item.favorite ? "取消收藏" : "收藏"}
+                title={item.favorite ? "取消收藏" : "收藏"}
+              >
+                <Star className="h-3 w-3" />
+              </Button>
+              <Button
+                size="sm"
+                variant="ghost"
+                className="cursor-pointer"
+                onClick={(e) => { e.stopPropagation(); setFlags({ archived: true }); }}
+                aria-label="归档"
+                title="归档"
+              >
+                <Archive className="h-3 w-3" />
+              </Button>
               <Button
                 size="sm"
                 variant="ghost"
@@ -202,10 +266,24 @@ const SortableItem = React.memo(function SortableItem({ id, item, onSelectItem,
         </CardHeader>
         <CardContent className="pb-12">
           <div className="space-y-2">
-            {(item.type === "FILE" || item.type === "IMAGE") && (
+            {item.type === "IMAGE" && (
+              <img
+                src={`/api/files/${item.id}/thumb?size=256`}
+                alt={item.fileName || "图片"}
+                className="h-24 w-full object-cover rounded"
+                loading="lazy"
+                decoding="async"
+                onError={(e) => { e.currentTarget.style.display = "none"; }}
+              />
+            )}
+            {(item.type === "FILE" || item.type === "IMAGE" || item.type === "BUNDLE") && (
               <p className="text-sm font-medium truncate">{item.fileName || (item.type === "IMAGE" ? "图片" : "文件")}</p>
             )}
-            {item.content && <p className="text-sm text-muted-foreground line-clamp-3">{item.content}</p>}
+            {item.snippet ? (
+              <p className="text-sm text-muted-foreground line-clamp-3" dangerouslySetInnerHTML={{ __html: item.snippet }} />
+            ) : (
+              item.content && <p className="text-sm text-muted-foreground line-clamp-3">{item.content}</p>
+            )}
           </div>
           <div className="absolute bottom-3 right-3 text-right text-xs text-muted-foreground">
             {typeof item.fileSize === "number" && item.fileSize > 0 && <div>大小: {formatFileSize(item.fileSize)}</div>}
@@ -27,6 +27,7 @@ import {
   FileText,
   Image as ImageIcon,
   File as FileIcon,
+  Folder,
   QrCode,
 } from "lucide-react";
 import { formatDate, formatFileSize } from "@/lib/format";
@@ -53,6 +54,8 @@ function getTypeIcon(type: string) {
       return <FileText className="h-4 w-4" />;
     case "IMAGE":
       return <ImageIcon className="h-4 w-4" />;
+    case "BUNDLE":
+      return <Folder className="h-4 w-4" />;
     case "FILE":
     default:
       return <FileIcon className="h-4 w-4" />;
@@ -177,7 +180,7 @@ const SortableRow = React.memo(function SortableRow({
   } as React.CSSProperties;
 
   const primaryText = (() => {
-    if (item.type === "FILE" || item.type === "IMAGE") {
+    if (item.type === "FILE" || item.type === "IMAGE" || item.type === "BUNDLE") {
       return item.fileName || (item.type === "IMAGE" ? "图片" : "文件");
     }
     if (item.content) {
@@ -208,10 +211,17 @@ const SortableRow = React.memo(function SortableRow({
               <span className={"truncate max-w-full " + (isText ? "text-sm" : "font-medium")}>{primaryText}</span>
               <Badge variant="secondary">{item.type}</Badge>
             </div>
-            {!isText && item.content && (
-              <p className="text-xs text-muted-foreground truncate max-w-full min-w-0 mt-1 break-all">
-                {item.content}
-              </p>
+            {item.snippet ? (
+              <p
+                className="text-xs text-muted-foreground truncate max-w-full min-w-0 mt-1 break-all"
+                dangerouslySetInnerHTML={{ __html: item.snippet }}
+              />
+            ) : (
+              !isText && item.content && (
+                <p className="text-xs text-muted-foreground truncate max-w-full min-w-0 mt-1 break-all">
+                  {item.content}
+                </p>
+              )
             )}
           </div>
         </div>
@@ -3,6 +3,7 @@
 import React, { useEffect, useState } from "react";
 import { Badge } from "@/components/ui/badge";
 import { Button } from "@/components/ui/button";
+import { Textarea } from "@/components/ui/textarea";
 import { Dialog, DialogContent, DialogHeader, DialogTitle } from "@/components/ui/dialog";
 import {
   AlertDialog,
@@ -17,15 +18,19 @@ import {
 } from "@/components/ui/alert-dialog";
 import { useToast } from "@/hooks/use-toast";
 import { safeCopyText, safeCopyBlob } from "@/lib/copy";
-import { File as FileIcon, FileText, Image as ImageIcon, Copy } from "lucide-react";
+import { File as FileIcon, FileText, Folder, Image as ImageIcon, Copy, Pencil, Download } from "lucide-react";
+import { authFetch } from "@/lib/auth";
 import { formatFileSize } from "@/lib/format";
 
 type ClipboardItem = {
   id: string;
-  type: "TEXT" | "IMAGE" | "FILE";
+  type: "TEXT" | "IMAGE" | "FILE" | "BUNDLE";
   content?: string;
   fileName?: string;
   fileSize?: number;
+  files?: { id: string; path: string; fileSize: number; contentType?: string }[]; // BUNDLE only
+  extractStatus?: "pending" | "done" | "failed" | "skipped";
+  extractError?: string;
   createdAt: string;
   updatedAt: string;
 };
@@ -44,6 +49,11 @@ export default function ItemDetailDialog({
   const { toast } = useToast();
   const [imgBlob, setImgBlob] = useState<Blob | null>(null);
   const [imgMime, setImgMime] = useState<string | null>(null);
+  const [editing, setEditing] = useState(false);
+  const [draft, setDraft] = useState("");
+  const [saving, setSaving] = useState(false);
+
+  useEffect(() => { setEditing(false); }, [item?.id, open]);
 
   // Prefetch image blob when dialog opens to speed up copy
   useEffect(() => {
@@ -96,21 +106,45 @@ export default function ItemDetailDialog({
     }
   };
 
-  const downloadFile = () => {
+  const saveEdit = async () => {
+    try {
+      setSaving(true);
+      const res = await authFetch(`/api/clipboard/${item.id}`, {
+        method: "PATCH",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ content: draft }),
+      });
+      const data = await res.json().catch(() => ({}));
+      if (!res.ok) throw new Error(data?.error || "failed");
+      // The dialog picks up the new content from the clipboard:updated event
+      setEditing(false);
+      toast({ title: "已保存", description: `版本 ${data.rev}` });
+    } catch (e: any) {
+      toast({ title: "保存失败", description: e?.message, variant: "destructive" });
+    } finally {
+      setSaving(false);
+    }
+  };
+
+  const downloadUrl = (href: string, name: string) => {
     const link = document.createElement("a");
-    link.href = `/api/files/${item.id}?download=1`;
-    link.download = item.fileName || "download";
+    link.href = href;
+    link.download = name;
     document.body.appendChild(link);
     link.click();
     document.body.removeChild(link);
   };
 
+  const downloadFile = () => downloadUrl(`/api/files/${item.id}?download=1`, item.fileName || "download");
+
   const getTypeIcon = (type: string) => {
     switch (type) {
       case "TEXT":
         return <FileText className="h-5 w-5" />;
       case "IMAGE":
         return <ImageIcon className="h-5 w-5" />;
+      case "BUNDLE":
+        return <Folder className="h-5 w-5" />;
       case "FILE":
       default:
         return <FileIcon className="h-5 w-5" />;
@@ -140,7 +174,18 @@ export default function ItemDetailDialog({
             <div>
               <h3 className="text-sm font-medium mb-2">内容</h3>
               <div className="relative bg-muted p-4 rounded-lg group">
-                {item.type === "TEXT" && (
+                {item.type === "TEXT" && !editing && (
+                  <Button
+                    variant="ghost"
+                    size="sm"
+                    onClick={() => { setDraft(item.content || ""); setEditing(true); }}
+                    className="absolute top-2 right-12 h-8 opacity-100 md:opacity-0 md:group-hover:opacity-100 transition-opacity"
+                    title="编辑"
+                  >
+                    <Pencil className="h-4 w-4" />
+                  </Button>
+                )}
+                {item.type === "TEXT" && !editing && (
                   <Button
                     variant="ghost"
                     size="sm"
@@ -151,7 +196,21 @@ export default function ItemDetailDialog({
                     <Copy className="h-4 w-4" />
                   </Button>
                 )}
-                <pre className="whitespace-pre-wrap break-words text-sm pr-12">{item.content}</pre>
+                {editing ? (
+                  <div className="space-y-2">
+                    <Textarea value={draft} onChange={(e) => setDraft(e.target.value)} className="min-h-40 font-mono text-sm" />
+                    <div className="flex justify-end gap-2">
+                      <Button variant="outline" size="sm" onClick={() => setEditing(false)} disabled={saving}>
+                        取消
+                      </Button>
+                      <Button size="sm" onClick={saveEdit} disabled={saving || !draft || draft === item.content}>
+                        保存
+                      </Button>
+                    </div>
+                  </div>
+                ) : (
+                  <pre className="whitespace-pre-wrap break-words text-sm pr-24">{item.content}</pre>
+                )}
               </div>
             </div>
           )}
@@ -161,7 +220,12 @@ export default function ItemDetailDialog({
               <h3 className="text-sm font-medium mb-2">图片预览</h3>
               <div className="bg-muted p-4 rounded-lg flex justify-center min-h-[24rem]">
                 <img
-                  src={`/api/files/${item.id}`}
+                  src={`/api/files/${item.id}/thumb?size=1024`}
+                  onError={(e) => {
+                    // 缩略图不可用（例如 SVG）时回退到原图
+                    const img = e.currentTarget;
+                    if (!img.src.endsWith(`/api/files/${item.id}`)) img.src = `/api/files/${item.id}`;
+                  }}
                   alt={item.fileName || "图片"}
                   className="max-w-full max-h-96 object-contain rounded"
                   loading="eager"
@@ -196,6 +260,12 @@ export default function ItemDetailDialog({
                     <span className="text-sm font-medium">类型:</span>
                     <span className="text-sm">{item.type}</span>
                   </div>
+                  {item.extractStatus === "failed" && (
+                    <div className="flex justify-between gap-4">
+                      <span className="text-sm font-medium shrink-0">全文索引:</span>
+                      <span className="text-sm text-destructive break-all">提取失败{item.extractError ? `：${item.extractError}` : ""}</span>
+                    </div>
+                  )}
                 </div>
               </div>
               <Button variant="outline" size="sm" className="mt-2" onClick={downloadFile}>
@@ -204,6 +274,39 @@ export default function ItemDetailDialog({
             </div>
           )}
 
+          {item.type === "BUNDLE" && (
+            <div>
+              <h3 className="text-sm font-medium mb-2">文件列表（{item.files?.length ?? 0}）</h3>
+              <div className="bg-muted p-2 rounded-lg max-h-72 overflow-y-auto custom-scrollbar">
+                {(item.files || []).map((f) => (
+                  <div key={f.id} className="flex items-center justify-between gap-3 px-2 py-1.5 rounded hover:bg-background/60">
+                    <span className="text-sm truncate" title={f.path}>{f.path}</span>
+                    <div className="flex items-center gap-2 shrink-0">
+                      <span className="text-xs text-muted-foreground">{formatFileSize(f.fileSize)}</span>
+                      <Button
+                        variant="ghost"
+                        size="sm"
+                        className="h-7"
+                        title="下载"
+                        onClick={() => downloadUrl(`/api/clipboard/${item.id}/files/${f.id}?download=1`, f.path.split("/").pop() || "download")}
+                      >
+                        <Download className="h-4 w-4" />
+                      </Button>
+                    </div>
+                  </div>
+                ))}
+              </div>
+              <Button
+                variant="outline"
+                size="sm"
+                className="mt-2"
+                onClick={() => downloadUrl(`/api/clipboard/${item.id}/archive`, `${item.fileName || "files"}.zip`)}
+              >
+                <Download className="h-4 w-4 mr-2" /> 打包下载全部
+              </Button>
+            </div>
+          )}
+
           {/* 分享功能已迁移到条目外的分享图标和全局对话框，这里不再展示 */}
 
           <div>
@@ -236,7 +339,7 @@ export default function ItemDetailDialog({
               <AlertDialogContent>
                 <AlertDialogHeader>
                   <AlertDialogTitle>确认删除</AlertDialogTitle>
-                  <AlertDialogDescription>删除后将无法恢复。确定要删除该条目吗？</AlertDialogDescription>
+                  <AlertDialogDescription>条目将移入回收站，可在保留期内恢复。确定要删除该条目吗？</AlertDialogDescription>
                 </AlertDialogHeader>
                 <AlertDialogFooter>
                   <AlertDialogCancel>取消</AlertDialogCancel>
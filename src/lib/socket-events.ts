@@ -1,3 +1,13 @@
 export const CLIPBOARD_CREATED_EVENT = 'clipboard:created';
 export const CLIPBOARD_DELETED_EVENT = 'clipboard:deleted';
 export const CLIPBOARD_REORDERED_EVENT = 'clipboard:reordered';
+export const CLIPBOARD_RESTORED_EVENT = 'clipboard:restored';
+export const CLIPBOARD_UPDATED_EVENT = 'clipboard:updated';
+export const CLIPBOARD_FLAGS_EVENT = 'clipboard:flags';
+export const CLIPBOARD_TAGS_EVENT = 'clipboard:tags';
+export const CLIPBOARD_EXPIRY_EVENT = 'clipboard:expiry';
+export const CLIPBOARD_EXPIRED_EVENT = 'clipboard:expired';
+export const CLIPBOARD_BULK_EVENT = 'clipboard:bulk';
+export const CLIPBOARD_IMPORTED_EVENT = 'clipboard:imported';
+export const TAG_UPDATED_EVENT = 'tag:updated';
+export const TAG_DELETED_EVENT = 'tag:deleted';